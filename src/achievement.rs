@@ -0,0 +1,94 @@
+use crate::level::MAX_STARS;
+use crate::profile::Profile;
+
+// レベルのメダルから解ける実績。プロフィールには id だけを残す
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Achievement {
+    FirstClear,
+    PerfectLevel,
+    AllCleared,
+    AllPerfect,
+}
+
+const ACHIEVEMENTS: [Achievement; 4] = [
+    Achievement::FirstClear,
+    Achievement::PerfectLevel,
+    Achievement::AllCleared,
+    Achievement::AllPerfect,
+];
+
+impl Achievement {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Achievement::FirstClear => "first_clear",
+            Achievement::PerfectLevel => "perfect_level",
+            Achievement::AllCleared => "all_cleared",
+            Achievement::AllPerfect => "all_perfect",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Achievement::FirstClear => "First Clear",
+            Achievement::PerfectLevel => "Three Stars",
+            Achievement::AllCleared => "Every Level Cleared",
+            Achievement::AllPerfect => "Every Level Perfect",
+        }
+    }
+
+    // level_count はいま並んでいるレベルの数。0 のときは「全部」の実績を解かない
+    fn earned(&self, profile: &Profile, level_count: usize) -> bool {
+        let perfect = profile
+            .medals
+            .values()
+            .filter(|stars| **stars >= MAX_STARS)
+            .count();
+        match self {
+            Achievement::FirstClear => !profile.medals.is_empty(),
+            Achievement::PerfectLevel => perfect > 0,
+            Achievement::AllCleared => level_count > 0 && profile.medals.len() >= level_count,
+            Achievement::AllPerfect => level_count > 0 && perfect >= level_count,
+        }
+    }
+}
+
+// 新しく解けた実績をプロフィールに足して返す
+pub fn award(profile: &mut Profile, level_count: usize) -> Vec<Achievement> {
+    let unlocked: Vec<Achievement> = ACHIEVEMENTS
+        .iter()
+        .filter(|achievement| !profile.achievements.iter().any(|id| id == achievement.id()))
+        .filter(|achievement| achievement.earned(profile, level_count))
+        .copied()
+        .collect();
+    profile.achievements.extend(
+        unlocked
+            .iter()
+            .map(|achievement| achievement.id().to_string()),
+    );
+    unlocked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn achievements_unlock_once_as_medals_come_in() {
+        let mut profile = Profile::default();
+        assert!(award(&mut profile, 2).is_empty());
+
+        profile.record_medal("Morning Stroll", 1);
+        assert_eq!(award(&mut profile, 2), vec![Achievement::FirstClear]);
+        assert!(award(&mut profile, 2).is_empty());
+
+        profile.record_medal("Park Loop", MAX_STARS);
+        assert_eq!(
+            award(&mut profile, 2),
+            vec![Achievement::PerfectLevel, Achievement::AllCleared]
+        );
+
+        profile.record_medal("Morning Stroll", MAX_STARS);
+        assert_eq!(award(&mut profile, 2), vec![Achievement::AllPerfect]);
+        assert_eq!(profile.achievements.len(), 4);
+    }
+}
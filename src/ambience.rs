@@ -0,0 +1,117 @@
+use crate::assets::{AssetManager, PendingSound};
+use crate::engine::{Audio, Sound};
+
+// 景色が変わったときに環境音を重ねながら入れ替える秒数
+const CROSSFADE: f64 = 3.0;
+
+// 走った距離 (m) で移り変わる景色と、そこで流す環境音。草原では鳥、高原では風
+pub struct Biome {
+    pub from: u32,
+    pub track: &'static str,
+}
+
+pub const BIOMES: [Biome; 2] = [
+    Biome {
+        from: 0,
+        track: "ambience_birds.wav",
+    },
+    Biome {
+        from: 1000,
+        track: "ambience_wind.wav",
+    },
+];
+
+// BIOMES の番号
+pub fn biome_at(distance: u32) -> usize {
+    BIOMES
+        .iter()
+        .rposition(|biome| distance >= biome.from)
+        .unwrap_or(0)
+}
+
+enum Track {
+    Loading(PendingSound),
+    Ready(Sound),
+    Missing,
+}
+
+impl Track {
+    fn poll(&mut self) {
+        let Track::Loading(pending) = self else {
+            return;
+        };
+        match pending.take() {
+            Some(Ok(sound)) => *self = Track::Ready(sound),
+            Some(Err(err)) => {
+                log!("Could not load ambience {:#?}", err);
+                *self = Track::Missing;
+            }
+            None => {}
+        }
+    }
+}
+
+// BGM とは別に、景色に合わせた風や鳥の声をループで流す
+pub struct Ambience {
+    tracks: Vec<Track>,
+    playing: Option<usize>,
+}
+
+impl Ambience {
+    // 環境音はなくても走れるので、読み込みは待たない
+    pub fn load(assets: &AssetManager, audio: &Audio) -> Self {
+        Ambience {
+            tracks: BIOMES
+                .iter()
+                .map(|biome| Track::Loading(assets.sound_in_background(audio, biome.track)))
+                .collect(),
+            playing: None,
+        }
+    }
+
+    // 今いる景色の音が読み終わっていて、まだ流れていなければ入れ替える
+    pub fn update(&mut self, audio: &Audio, distance: u32) {
+        self.tracks.iter_mut().for_each(Track::poll);
+        let biome = biome_at(distance);
+        if self.playing == Some(biome) {
+            return;
+        }
+        if let Some(Track::Ready(sound)) = self.tracks.get(biome) {
+            if let Err(err) = audio.play_ambience(sound, CROSSFADE) {
+                log!("Could not play ambience {:#?}", err);
+            }
+            self.playing = Some(biome);
+        }
+    }
+
+    pub fn stop(&mut self, audio: &Audio) {
+        if self.playing.take().is_some() {
+            if let Err(err) = audio.stop_ambience(CROSSFADE) {
+                log!("Could not stop ambience {:#?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn biomes_change_with_distance() {
+        assert_eq!(biome_at(0), 0);
+        assert_eq!(biome_at(BIOMES[1].from - 1), 0);
+        assert_eq!(biome_at(BIOMES[1].from), 1);
+        assert_eq!(biome_at(u32::MAX), BIOMES.len() - 1);
+    }
+
+    #[test]
+    fn every_biome_track_is_bundled() {
+        BIOMES.iter().for_each(|biome| {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("static")
+                .join(biome.track);
+            assert!(path.exists(), "{} is missing from static", biome.track);
+        });
+    }
+}
@@ -0,0 +1,59 @@
+use crate::browser;
+use crate::experiment::Assignment;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct AnalyticsEvent<'a> {
+    event: &'a str,
+    // JSON.stringify と同じく、値がなければ項目ごと省く
+    #[serde(skip_serializing_if = "Option::is_none")]
+    experiment: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<&'a str>,
+    frames: u32,
+}
+
+// 実験のバリアントを全イベントに付与して送信する
+pub struct Analytics {
+    endpoint: Option<String>,
+    assignment: Option<Assignment>,
+}
+
+impl Analytics {
+    pub fn new(endpoint: Option<String>, assignment: Option<Assignment>) -> Self {
+        Analytics {
+            endpoint,
+            assignment,
+        }
+    }
+
+    pub fn track(&self, event: &str, frames: u32) {
+        let payload = AnalyticsEvent {
+            event,
+            experiment: self
+                .assignment
+                .as_ref()
+                .map(|assignment| assignment.experiment.as_str()),
+            variant: self
+                .assignment
+                .as_ref()
+                .map(|assignment| assignment.variant.as_str()),
+            frames,
+        };
+        let body = serde_json::to_string(&payload).ok();
+
+        match (&self.endpoint, body) {
+            (Some(endpoint), Some(body)) => {
+                if let Err(err) = browser::send_beacon(endpoint, &body) {
+                    log!("Could not send analytics event {:#?}", err);
+                }
+            }
+            (None, Some(body)) => {
+                log!("analytics: {}", body);
+            }
+            (_, None) => {
+                log!("Could not serialize analytics event {}", event);
+            }
+        }
+    }
+}
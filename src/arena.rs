@@ -0,0 +1,151 @@
+// 上限付きのコンテナ。長時間のプレイ中に Vec が伸びて再確保されるのを防ぐ
+#[derive(Clone, Copy)]
+pub enum OverflowPolicy {
+    DropOldest,
+    DropNewest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArenaStats {
+    pub len: usize,
+    pub capacity: usize,
+    pub peak: usize,
+    pub dropped: u32,
+}
+
+pub struct Arena<T> {
+    items: Vec<T>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    peak: usize,
+    dropped: u32,
+}
+
+impl<T> Arena<T> {
+    pub fn with_capacity(capacity: usize, policy: OverflowPolicy) -> Self {
+        Arena {
+            items: Vec::with_capacity(capacity),
+            capacity,
+            policy,
+            peak: 0,
+            dropped: 0,
+        }
+    }
+
+    // 満杯のときはポリシーに従って捨てる。追加できたら true
+    pub fn push(&mut self, item: T) -> bool {
+        if self.items.len() >= self.capacity {
+            self.dropped += 1;
+            match self.policy {
+                OverflowPolicy::DropOldest if self.capacity > 0 => {
+                    self.items.remove(0);
+                }
+                _ => return false,
+            }
+        }
+        self.items.push(item);
+        self.peak = self.peak.max(self.items.len());
+        true
+    }
+
+    pub fn extend(&mut self, items: impl IntoIterator<Item = T>) {
+        items.into_iter().for_each(|item| {
+            self.push(item);
+        });
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.items.retain(f);
+    }
+
+    // f が false を返したものを取り除き、捨てずに removed へ渡す
+    pub fn retain_with(&mut self, mut f: impl FnMut(&T) -> bool, mut removed: impl FnMut(T)) {
+        let mut index = 0;
+        while index < self.items.len() {
+            if f(&self.items[index]) {
+                index += 1;
+            } else {
+                removed(self.items.remove(index));
+            }
+        }
+    }
+
+    pub fn drain(&mut self) -> std::vec::Drain<'_, T> {
+        self.items.drain(..)
+    }
+
+    pub fn remove_first(&mut self) -> Option<T> {
+        if self.items.is_empty() {
+            None
+        } else {
+            Some(self.items.remove(0))
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index)
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.items.first()
+    }
+
+    pub fn first_mut(&mut self) -> Option<&mut T> {
+        self.items.first_mut()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.items.iter_mut()
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        ArenaStats {
+            len: self.items.len(),
+            capacity: self.capacity,
+            peak: self.peak,
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_follows_policy_without_growing() {
+        let mut oldest = Arena::with_capacity(2, OverflowPolicy::DropOldest);
+        oldest.extend(vec![1, 2, 3]);
+        assert_eq!(oldest.iter().copied().collect::<Vec<_>>(), vec![2, 3]);
+
+        let mut newest = Arena::with_capacity(2, OverflowPolicy::DropNewest);
+        assert!(newest.push(1));
+        assert!(newest.push(2));
+        assert!(!newest.push(3));
+        assert_eq!(newest.iter().copied().collect::<Vec<_>>(), vec![1, 2]);
+
+        assert_eq!(
+            newest.stats(),
+            ArenaStats {
+                len: 2,
+                capacity: 2,
+                peak: 2,
+                dropped: 1
+            }
+        );
+        assert_eq!(oldest.items.capacity(), 2);
+
+        let mut removed = vec![];
+        oldest.retain_with(|item| *item > 2, |item| removed.push(item));
+        assert_eq!(removed, vec![2]);
+        assert_eq!(oldest.iter().copied().collect::<Vec<_>>(), vec![3]);
+    }
+}
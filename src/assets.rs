@@ -0,0 +1,241 @@
+use crate::browser;
+use crate::draw::Bitmap;
+use crate::engine::{self, Audio, Sound};
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// デコード後の画像は 1 ピクセル 4 バイトとして数える
+const BYTES_PER_PIXEL: usize = 4;
+
+struct Entry {
+    bytes: usize,
+    essential: bool,
+    last_used: u64,
+}
+
+// どの画像がどれだけメモリを使っているかの台帳。予算を超えたら
+// しばらく使っていない、消してもよい画像から順に追い出す
+#[derive(Default)]
+struct Ledger {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+impl Ledger {
+    fn touch(&mut self, source: &str) -> bool {
+        self.clock += 1;
+        match self.entries.get_mut(source) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, source: &str, bytes: usize, essential: bool) {
+        self.clock += 1;
+        self.entries.insert(
+            source.into(),
+            Entry {
+                bytes,
+                essential,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.entries.values().map(|entry| entry.bytes).sum()
+    }
+
+    // 起動に必要な画像は予算を超えていても残す
+    fn evict(&mut self, budget: usize) -> Vec<String> {
+        let mut evicted = vec![];
+        while self.used_bytes() > budget {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.essential)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(source, _)| source.clone());
+            match oldest {
+                Some(source) => {
+                    self.entries.remove(&source);
+                    evicted.push(source);
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+}
+
+// 読み込んだ画像を URL ごとに使い回す。追い出した画像は次に要求されたときに読み直す
+pub struct AssetManager {
+    images: HashMap<String, Bitmap>,
+    ledger: Ledger,
+    budget: usize,
+}
+
+impl AssetManager {
+    pub fn new(budget: usize) -> Self {
+        AssetManager {
+            images: HashMap::new(),
+            ledger: Ledger::default(),
+            budget,
+        }
+    }
+
+    // ゲーム中ずっと使う画像。追い出さない
+    pub async fn essential_image(&mut self, source: &str) -> Result<Bitmap> {
+        self.load(source, true).await
+    }
+
+    // 季節のスキンや背景など、使い終わったら捨ててよい画像
+    pub async fn image(&mut self, source: &str) -> Result<Bitmap> {
+        self.load(source, false).await
+    }
+
+    async fn load(&mut self, source: &str, essential: bool) -> Result<Bitmap> {
+        if self.ledger.touch(source) {
+            if let Some(image) = self.images.get(source) {
+                return Ok(image.clone());
+            }
+        }
+
+        let image = engine::load_image(source).await?;
+        let bytes =
+            image.natural_width() as usize * image.natural_height() as usize * BYTES_PER_PIXEL;
+        self.ledger.insert(source, bytes, essential);
+        self.images.insert(source.into(), image.clone());
+        self.ledger.evict(self.budget).iter().for_each(|evicted| {
+            log!("Evicted {} to stay within the texture budget", evicted);
+            self.images.remove(evicted);
+        });
+        Ok(image)
+    }
+
+    // 大きな音楽ファイルのデコードで起動を止めないよう、裏で読み込む
+    pub fn sound_in_background(&self, audio: &Audio, source: &str) -> PendingSound {
+        let state = Rc::new(RefCell::new(PendingState::default()));
+        let pending = PendingSound {
+            source: source.into(),
+            state: state.clone(),
+        };
+        let audio = audio.clone();
+        let source = source.to_string();
+        browser::spawn_local(async move {
+            let array_buffer = match browser::fetch_array_buffer(&source).await {
+                Ok(array_buffer) => array_buffer,
+                Err(err) => {
+                    state.borrow_mut().finish(Err(err));
+                    return;
+                }
+            };
+            if !state.borrow_mut().fetched() {
+                return;
+            }
+            let result = audio
+                .decode_sound(&array_buffer)
+                .await
+                .map_err(|err| anyhow!("Could not decode {} {:#?}", source, err));
+            state.borrow_mut().finish(result);
+        });
+        pending
+    }
+}
+
+// 取得が終わったら半分、デコードが終わったら完了とみなす
+const FETCHED_PROGRESS: f32 = 0.5;
+
+#[derive(Default)]
+struct PendingState {
+    progress: f32,
+    result: Option<Result<Sound>>,
+    cancelled: bool,
+}
+
+impl PendingState {
+    fn fetched(&mut self) -> bool {
+        self.progress = FETCHED_PROGRESS;
+        !self.cancelled
+    }
+
+    fn finish(&mut self, result: Result<Sound>) {
+        if !self.cancelled {
+            self.progress = 1.0;
+            self.result = Some(result);
+        }
+    }
+}
+
+// 裏で読み込んでいる音。待たずに先へ進み、進み具合を見たり途中でやめたりできる。
+// 手放したときもやめる
+pub struct PendingSound {
+    source: String,
+    state: Rc<RefCell<PendingState>>,
+}
+
+impl PendingSound {
+    pub fn progress(&self) -> f32 {
+        self.state.borrow().progress
+    }
+
+    // 読み込みが終わったときに一度だけ Some を返す
+    pub fn take(&mut self) -> Option<Result<Sound>> {
+        self.state.borrow_mut().result.take()
+    }
+
+    pub fn cancel(&self) {
+        let mut state = self.state.borrow_mut();
+        if !state.cancelled && state.progress < 1.0 {
+            log!("Cancelled loading {}", self.source);
+        }
+        state.cancelled = true;
+    }
+}
+
+impl Drop for PendingSound {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ledger_evicts_the_least_recently_used_optional_image() {
+        let mut ledger = Ledger::default();
+        ledger.insert("rhb.png", 400, true);
+        ledger.insert("winter.png", 300, false);
+        ledger.insert("summer.png", 300, false);
+        ledger.touch("winter.png");
+
+        assert_eq!(ledger.evict(800), vec!["summer.png".to_string()]);
+        assert_eq!(ledger.used_bytes(), 700);
+        assert_eq!(ledger.evict(100), vec!["winter.png".to_string()]);
+        assert_eq!(ledger.used_bytes(), 400);
+        assert!(!ledger.touch("summer.png"));
+    }
+
+    #[test]
+    fn cancelled_sounds_stop_after_the_fetch() {
+        let state = Rc::new(RefCell::new(PendingState::default()));
+        let pending = PendingSound {
+            source: "music.mp3".into(),
+            state: state.clone(),
+        };
+
+        assert_eq!(pending.progress(), 0.0);
+        drop(pending);
+        assert!(!state.borrow_mut().fetched());
+        assert_eq!(state.borrow().progress, FETCHED_PROGRESS);
+        state.borrow_mut().finish(Err(anyhow!("late")));
+        assert!(state.borrow().result.is_none());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::config::Physics;
+use crate::engine::Rect;
+
+// 足元よりこれだけ上に底があるものまでは地面の障害物とみなす
+const GROUND_TOLERANCE: i16 = 8;
+// 1 フレームで流れる分を見逃さないよう、少しだけ早めに跳ぶ
+const JUMP_MARGIN: i16 = 16;
+
+// 跳んでから足元が clearance の高さを越えるまでに障害物が近づく距離。
+// これより近づいてから跳んでも間に合わない。頂点でも届かなければ None
+pub fn last_safe_jump_distance(physics: &Physics, speed: i16, clearance: i16) -> Option<i16> {
+    let mut velocity = physics.jump_speed;
+    let mut height = 0;
+    let mut frames = 0;
+    while height < clearance {
+        velocity = (velocity + physics.gravity).min(physics.terminal_speed);
+        if velocity >= 0 {
+            return None;
+        }
+        height -= velocity;
+        frames += 1;
+    }
+    Some(speed * frames)
+}
+
+// 跳んだ足元が頂点で届く高さ
+pub fn jump_apex(physics: &Physics) -> i16 {
+    let mut velocity = physics.jump_speed;
+    let mut height = 0;
+    loop {
+        velocity = (velocity + physics.gravity).min(physics.terminal_speed);
+        if velocity >= 0 {
+            return height;
+        }
+        height -= velocity;
+    }
+}
+
+// 跳んでから、足元が rise の高さまで下りてきて着地するまでに進む距離。頂点でも届かなければ None
+pub fn jump_reach(physics: &Physics, speed: i16, rise: i16) -> Option<i16> {
+    if jump_apex(physics) < rise {
+        return None;
+    }
+    let mut velocity = physics.jump_speed;
+    let mut height = 0;
+    let mut frames = 0;
+    loop {
+        velocity = (velocity + physics.gravity).min(physics.terminal_speed);
+        if velocity > 0 && height - velocity < rise {
+            return Some(speed * frames);
+        }
+        height -= velocity;
+        frames += 1;
+    }
+}
+
+// 地面の障害物が跳ぶべき距離まで来たら true を返す。台に乗るかどうかは決めない
+pub fn should_jump(
+    boy: &Rect,
+    physics: &Physics,
+    speed: i16,
+    mut hazards: impl Iterator<Item = Rect>,
+) -> bool {
+    hazards.any(|hazard| {
+        let on_ground =
+            hazard.bottom() >= boy.bottom() - GROUND_TOLERANCE && hazard.y() < boy.bottom();
+        let gap = hazard.x() - boy.right();
+        on_ground
+            && gap >= 0
+            && last_safe_jump_distance(physics, speed, boy.bottom() - hazard.y())
+                .is_some_and(|distance| gap <= distance + JUMP_MARGIN)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHYSICS: Physics = Physics {
+        gravity: 1,
+        jump_speed: -25,
+        running_speed: 4,
+        terminal_speed: 20,
+    };
+
+    #[test]
+    fn jumps_once_a_ground_hazard_reaches_the_last_safe_distance() {
+        assert_eq!(last_safe_jump_distance(&PHYSICS, 4, 48), Some(12));
+        assert_eq!(last_safe_jump_distance(&PHYSICS, 4, 1000), None);
+        assert!(jump_reach(&PHYSICS, 4, 0) > jump_reach(&PHYSICS, 4, 200));
+        assert_eq!(jump_reach(&PHYSICS, 4, 1000), None);
+
+        let boy = Rect::new_from_x_y(100, 450, 60, 150);
+        let stone = |x: i16| Rect::new_from_x_y(x, 550, 90, 50);
+        let platform = Rect::new_from_x_y(170, 300, 300, 40);
+
+        assert!(!should_jump(
+            &boy,
+            &PHYSICS,
+            4,
+            vec![stone(300)].into_iter()
+        ));
+        assert!(should_jump(&boy, &PHYSICS, 4, vec![stone(180)].into_iter()));
+        assert!(!should_jump(
+            &boy,
+            &PHYSICS,
+            4,
+            vec![stone(140)].into_iter()
+        ));
+        assert!(!should_jump(&boy, &PHYSICS, 4, vec![platform].into_iter()));
+    }
+}
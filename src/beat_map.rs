@@ -0,0 +1,196 @@
+use crate::assist::{jump_apex, jump_reach};
+use crate::config::Physics;
+use crate::segment::BEAT_PLATFORM_WIDTH;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+// 自動で作るときに並べる足場の数と、足場どうしの拍の間隔
+const GENERATED_STEPS: u32 = 5;
+const GENERATED_BEATS_PER_STEP: f64 = 2.0;
+// 地面からの高さ。低い足場、高い足場、足場の上のコインの順
+const LOW_STEP: i16 = 180;
+const HIGH_STEP: i16 = 225;
+const COIN_ABOVE_STEP: i16 = 40;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BeatEventKind {
+    Platform,
+    Coin,
+}
+
+// time はリズム区間の頭の拍からの秒数。その時刻に少年の足元へ来るように置く
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BeatEvent {
+    pub time: f64,
+    pub kind: BeatEventKind,
+    // 地面からの高さ。足場は上面、コインは中心
+    pub height: i16,
+}
+
+// リズム区間の譜面。music の曲の bpm に合わせて書く
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BeatMap {
+    pub music: String,
+    pub bpm: f64,
+    pub events: Vec<BeatEvent>,
+}
+
+impl BeatMap {
+    // 譜面がないときは、曲のテンポから低い足場と高い足場を交互に並べる
+    pub fn from_bpm(music: &str, bpm: f64) -> Self {
+        let seconds_per_beat = 60.0 / bpm;
+        let events = (0..GENERATED_STEPS)
+            .flat_map(|step| {
+                let time = f64::from(step) * GENERATED_BEATS_PER_STEP * seconds_per_beat;
+                let height = if step % 2 == 0 { LOW_STEP } else { HIGH_STEP };
+                [
+                    BeatEvent {
+                        time,
+                        kind: BeatEventKind::Platform,
+                        height,
+                    },
+                    BeatEvent {
+                        time,
+                        kind: BeatEventKind::Coin,
+                        height: height + COIN_ABOVE_STEP,
+                    },
+                ]
+            })
+            .collect();
+        BeatMap {
+            music: music.into(),
+            bpm,
+            events,
+        }
+    }
+
+    pub fn seconds_per_beat(&self) -> f64 {
+        60.0 / self.bpm
+    }
+
+    // 流れている曲と組になっていて、時刻が順に並び、どの足場も直前に立っている高さから跳んで届くか。
+    // 地面から届かない高さのものは、直前の足場の端から跳んで、走る速さ × 滞空時間で横にも届くか
+    pub fn validate(&self, music: &str, physics: &Physics) -> Result<()> {
+        if self.music != music {
+            return Err(anyhow!(
+                "Beat map is written for {} but {} is playing",
+                self.music,
+                music
+            ));
+        }
+        if self.bpm.is_nan() || self.bpm <= 0.0 {
+            return Err(anyhow!("Beat map has an invalid bpm {}", self.bpm));
+        }
+        let apex = jump_apex(physics);
+        let speed = physics.running_speed.max(1);
+        let pixels_per_second = f64::from(speed) * 60.0;
+        let mut previous_time = 0.0;
+        let mut standing = 0;
+        let mut standing_since: Option<f64> = None;
+        self.events.iter().try_for_each(|event| {
+            if event.time < previous_time {
+                return Err(anyhow!("Beat map event at {}s is out of order", event.time));
+            }
+            previous_time = event.time;
+            if event.height - standing > apex {
+                return Err(anyhow!(
+                    "{:?} at {}s is {} above where the boy stands, but a jump only reaches {}",
+                    event.kind,
+                    event.time,
+                    event.height - standing,
+                    apex
+                ));
+            }
+            if let (true, Some(since)) = (event.height > apex, standing_since) {
+                let left = match event.kind {
+                    BeatEventKind::Platform => 0,
+                    BeatEventKind::Coin => BEAT_PLATFORM_WIDTH / 2,
+                };
+                let gap = ((event.time - since) * pixels_per_second).round() as i16 + left
+                    - BEAT_PLATFORM_WIDTH;
+                let reach = jump_reach(physics, speed, event.height - standing).unwrap_or(0);
+                if gap > reach {
+                    return Err(anyhow!(
+                        "{:?} at {}s is {} past the edge of the last platform, but a jump only carries {}",
+                        event.kind,
+                        event.time,
+                        gap,
+                        reach
+                    ));
+                }
+            }
+            if event.kind == BeatEventKind::Platform {
+                standing = event.height;
+                standing_since = Some(event.time);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHYSICS: Physics = Physics {
+        gravity: 1,
+        jump_speed: -25,
+        running_speed: 4,
+        terminal_speed: 20,
+    };
+
+    #[test]
+    fn beat_maps_are_checked_against_the_jump_height() {
+        let generated = BeatMap::from_bpm("song.mp3", 120.0);
+        assert_eq!(generated.events.len(), GENERATED_STEPS as usize * 2);
+        assert_eq!(generated.events.last().unwrap().time, 4.0);
+        assert!(generated.validate("song.mp3", &PHYSICS).is_ok());
+        assert!(generated.validate("other.mp3", &PHYSICS).is_err());
+
+        let map: BeatMap = serde_json::from_str(
+            r#"{"music": "song.mp3", "bpm": 90, "events": [
+                {"time": 0, "kind": "platform", "height": 200},
+                {"time": 0.75, "kind": "platform", "height": 450},
+                {"time": 0.75, "kind": "coin", "height": 700}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(map.validate("song.mp3", &PHYSICS).is_ok());
+
+        let too_high = BeatMap {
+            events: vec![BeatEvent {
+                time: 0.0,
+                kind: BeatEventKind::Platform,
+                height: 400,
+            }],
+            ..map
+        };
+        assert!(too_high.validate("song.mp3", &PHYSICS).is_err());
+    }
+
+    #[test]
+    fn platforms_out_of_the_ground_jump_must_be_within_horizontal_reach() {
+        // 地面からは届かない 450 の足場へは、200 の足場の端から跳んで横にも届かなければならない
+        let map = |gap_seconds: f64| BeatMap {
+            music: "song.mp3".into(),
+            bpm: 90.0,
+            events: vec![
+                BeatEvent {
+                    time: 0.0,
+                    kind: BeatEventKind::Platform,
+                    height: 200,
+                },
+                BeatEvent {
+                    time: gap_seconds,
+                    kind: BeatEventKind::Platform,
+                    height: 450,
+                },
+            ],
+        };
+        assert!(map(0.75).validate("song.mp3", &PHYSICS).is_ok());
+        assert!(map(4.0).validate("song.mp3", &PHYSICS).is_err());
+    }
+}
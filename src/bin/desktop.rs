@@ -0,0 +1,6 @@
+use anyhow::Result;
+
+// ブラウザ版と同じ WalkTheDog を、ウィンドウと CPU 描画のバックエンドで動かす
+fn main() -> Result<()> {
+    rust_webpack_template::main_desktop()
+}
@@ -1,25 +1,42 @@
+use crate::draw::{Context, Surface};
+use crate::platform;
 use anyhow::{anyhow, Result};
+use std::cell::RefCell;
 use std::future::Future;
-use wasm_bindgen::closure::{IntoWasmClosure, WasmClosure, WasmClosureFnOnce};
+use std::rc::Rc;
+#[cfg(not(feature = "desktop"))]
+use wasm_bindgen::closure::WasmClosureFnOnce;
+use wasm_bindgen::closure::{IntoWasmClosure, WasmClosure};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
+#[cfg(not(feature = "desktop"))]
 use web_sys::js_sys::ArrayBuffer;
-use web_sys::{
-    CanvasRenderingContext2d, Document, HtmlCanvasElement, HtmlElement, Response, Window,
-};
-use web_sys::{Element, HtmlImageElement};
+use web_sys::Element;
+use web_sys::{Document, HtmlCanvasElement, HtmlElement, RequestInit, Response, Window};
+#[cfg(not(feature = "desktop"))]
+use web_sys::{HtmlImageElement, UrlSearchParams};
+
+pub mod socket;
+pub mod storage;
 
 macro_rules! log{
     ($($t:tt)*) => {
-        web_sys::console::log_1(&format!($($t)*).into());
+        $crate::platform::current().log(&format!($($t)*));
     }
 }
 
+#[cfg(not(feature = "desktop"))]
 pub fn window() -> Result<Window> {
     web_sys::window().ok_or_else(|| anyhow!("No Window Found"))
 }
 
+// デスクトップには DOM がないので、ページに触る処理はここで失敗させる
+#[cfg(feature = "desktop")]
+pub fn window() -> Result<Window> {
+    Err(anyhow!("No Window on the desktop"))
+}
+
 pub fn document() -> Result<Document> {
     window()?
         .document()
@@ -34,20 +51,20 @@ pub fn canvas() -> Result<HtmlCanvasElement> {
         .map_err(|element| anyhow!("Error converting {:#?} to HtmlCanvasElement", element))
 }
 
-pub fn context() -> Result<CanvasRenderingContext2d> {
-    canvas()?
-        .get_context("2d")
-        .map_err(|js_value| anyhow!("Error getting 2d context {:#?}", js_value))?
-        .ok_or_else(|| anyhow!("No 2d context found"))?
-        .dyn_into::<CanvasRenderingContext2d>()
-        .map_err(|element| {
-            anyhow!(
-                "Error converting {:#?} to CanvasRenderingContext2d",
-                element
-            )
-        })
+// 画面と描画先は Platform が用意する
+pub fn context() -> Result<Context> {
+    platform::current().context()
+}
+
+pub fn create_canvas(width: u32, height: u32) -> Result<Surface> {
+    platform::current().create_surface(width, height)
 }
 
+pub fn context_of(canvas: &Surface) -> Result<Context> {
+    platform::current().context_of(canvas)
+}
+
+#[cfg(not(feature = "desktop"))]
 pub fn spawn_local<F>(future: F)
 where
     F: Future<Output = ()> + 'static,
@@ -55,6 +72,14 @@ where
     wasm_bindgen_futures::spawn_local(future);
 }
 
+#[cfg(feature = "desktop")]
+pub fn spawn_local<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    crate::desktop::spawn(future);
+}
+
 pub async fn fetch_with_str(resource: &str) -> Result<JsValue> {
     JsFuture::from(window()?.fetch_with_str(resource))
         .await
@@ -68,6 +93,25 @@ pub async fn fetch_response(resource: &str) -> Result<Response> {
         .map_err(|err| anyhow!("error converting fetch to Response {:#?}", err))
 }
 
+pub async fn fetch_response_with_init(resource: &str, init: &RequestInit) -> Result<Response> {
+    JsFuture::from(window()?.fetch_with_str_and_init(resource, init))
+        .await
+        .map_err(|err| anyhow!("error fetching {:?}", err))?
+        .dyn_into()
+        .map_err(|err| anyhow!("error converting fetch to Response {:#?}", err))
+}
+
+pub async fn response_text(resp: &Response) -> Result<String> {
+    JsFuture::from(
+        resp.text()
+            .map_err(|err| anyhow!("Could not get text from response {:#?}", err))?,
+    )
+    .await
+    .map_err(|err| anyhow!("error reading response text {:#?}", err))?
+    .as_string()
+    .ok_or_else(|| anyhow!("Response text is not a string"))
+}
+
 pub async fn fetch_json(json_path: &str) -> Result<JsValue> {
     let resp = fetch_response(json_path).await?;
 
@@ -79,6 +123,7 @@ pub async fn fetch_json(json_path: &str) -> Result<JsValue> {
     .map_err(|err| anyhow!("error fetching JSON {:#?}", err))
 }
 
+#[cfg(not(feature = "desktop"))]
 pub async fn fetch_array_buffer(resource: &str) -> Result<ArrayBuffer> {
     let array_buffer = fetch_response(resource)
         .await?
@@ -92,10 +137,18 @@ pub async fn fetch_array_buffer(resource: &str) -> Result<ArrayBuffer> {
         .map_err(|err| anyhow!("Error converting raw JSValue to ArrayBuffer {:#?}", err))
 }
 
+// デスクトップの音はデコードしないので、読んだバイト列をそのまま渡す
+#[cfg(feature = "desktop")]
+pub async fn fetch_array_buffer(resource: &str) -> Result<Vec<u8>> {
+    platform::current().fetch_bytes(resource).await
+}
+
+#[cfg(not(feature = "desktop"))]
 pub fn new_image() -> Result<HtmlImageElement> {
     HtmlImageElement::new().map_err(|err| anyhow!("Could not create HtmlImageElement: {:#?}", err))
 }
 
+#[cfg(not(feature = "desktop"))]
 pub fn closure_once<F, A, R>(fn_once: F) -> Closure<F::FnMut>
 where
     F: 'static + WasmClosureFnOnce<A, R>,
@@ -103,11 +156,14 @@ where
     Closure::once(fn_once)
 }
 
+#[cfg(not(feature = "desktop"))]
 pub type LoopClosure = Closure<dyn FnMut(f64)>;
+// デスクトップではウィンドウの次の描画で呼ぶ
+#[cfg(feature = "desktop")]
+pub type LoopClosure = Rc<RefCell<dyn FnMut(f64)>>;
+
 pub fn request_animation_frame(callback: &LoopClosure) -> Result<i32> {
-    window()?
-        .request_animation_frame(callback.as_ref().unchecked_ref())
-        .map_err(|err| anyhow!("Cannot request animation frame {:#?}", err))
+    platform::current().request_animation_frame(callback)
 }
 
 pub fn closure_wrap<F: IntoWasmClosure<T> + 'static, T: ?Sized + WasmClosure>(
@@ -116,15 +172,113 @@ pub fn closure_wrap<F: IntoWasmClosure<T> + 'static, T: ?Sized + WasmClosure>(
     Closure::new(data)
 }
 
+// タブが隠れたら true、また見えるようになったら false を渡す
+pub fn on_visibility_change(callback: impl FnMut(bool) + 'static) -> Result<()> {
+    platform::current().on_visibility_change(Box::new(callback))
+}
+
+// ページを離れる直前に呼ぶ
+pub fn on_unload(callback: impl FnMut() + 'static) -> Result<()> {
+    platform::current().on_unload(Box::new(callback))
+}
+
+// navigator.onLine。false ならネットワークにつながっていない
+pub fn is_online() -> bool {
+    window().map_or(true, |window| window.navigator().on_line())
+}
+
+// online と offline のイベントを、つながっているかどうかにして渡す
+pub fn on_connectivity_change(callback: impl FnMut(bool) + 'static) -> Result<()> {
+    let window = window()?;
+    let callback = Rc::new(RefCell::new(callback));
+    [("online", true), ("offline", false)]
+        .iter()
+        .try_for_each(|(event, online)| {
+            let callback = callback.clone();
+            let online = *online;
+            let listener =
+                closure_wrap(Box::new(move || (callback.borrow_mut())(online)) as Box<dyn FnMut()>);
+            window
+                .add_event_listener_with_callback(event, listener.as_ref().unchecked_ref())
+                .map_err(|err| anyhow!("Could not listen to {} {:#?}", event, err))?;
+            listener.forget();
+            Ok(())
+        })
+}
+
+#[cfg(not(feature = "desktop"))]
 pub fn create_raf_closure(f: impl FnMut(f64) + 'static) -> LoopClosure {
     closure_wrap(f)
 }
 
+#[cfg(feature = "desktop")]
+pub fn create_raf_closure(f: impl FnMut(f64) + 'static) -> LoopClosure {
+    Rc::new(RefCell::new(f))
+}
+
+pub fn send_beacon(url: &str, body: &str) -> Result<bool> {
+    window()?
+        .navigator()
+        .send_beacon_with_opt_str(url, Some(body))
+        .map_err(|err| anyhow!("Could not send beacon {:#?}", err))
+}
+
+// つながっているすべてのゲームパッドで、押されているボタンの番号を返す
+pub fn pressed_gamepad_buttons() -> Result<Vec<u32>> {
+    let gamepads = window()?
+        .navigator()
+        .get_gamepads()
+        .map_err(|err| anyhow!("Could not get gamepads {:#?}", err))?;
+    Ok(gamepads
+        .iter()
+        .filter_map(|gamepad| gamepad.dyn_into::<web_sys::Gamepad>().ok())
+        .flat_map(|gamepad| {
+            gamepad
+                .buttons()
+                .iter()
+                .enumerate()
+                .filter_map(|(index, button)| {
+                    button
+                        .dyn_into::<web_sys::GamepadButton>()
+                        .ok()
+                        .filter(|button| button.pressed())
+                        .map(|_| index as u32)
+                })
+                .collect::<Vec<u32>>()
+        })
+        .collect())
+}
+
+pub fn timestamp() -> f64 {
+    platform::current().timestamp()
+}
+
+// 月は 1 始まりで返す
+pub fn month_and_day() -> (u8, u8) {
+    platform::current().month_and_day()
+}
+
+#[cfg(not(feature = "desktop"))]
+pub fn query_param(name: &str) -> Option<String> {
+    let search = window().ok()?.location().search().ok()?;
+    UrlSearchParams::new_with_str(&search).ok()?.get(name)
+}
+
+// デスクトップでは ?name=value の代わりに --name=value で渡す
+#[cfg(feature = "desktop")]
+pub fn query_param(name: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| match arg.strip_prefix(&flag) {
+            Some("") => Some(String::new()),
+            Some(value) => value.strip_prefix('=').map(String::from),
+            None => None,
+        })
+}
+
 pub fn now() -> Result<f64> {
-    Ok(window()?
-        .performance()
-        .ok_or_else(|| anyhow!("Performance object not found"))?
-        .now())
+    platform::current().now()
 }
 
 fn find_ui() -> Result<Element> {
@@ -169,3 +323,11 @@ pub fn find_html_element_by_id(id: &str) -> Result<HtmlElement> {
                 .map_err(|err| anyhow!("Could not cast into HtmlElement {:#?}", err))
         })
 }
+
+pub fn set_text_by_id(id: &str, text: &str) -> Result<()> {
+    find_html_element_by_id(id).map(|element| element.set_inner_text(text))
+}
+
+pub fn set_class_by_id(id: &str, class: &str) -> Result<()> {
+    find_html_element_by_id(id).map(|element| element.set_class_name(class))
+}
@@ -0,0 +1,65 @@
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use wasm_bindgen::JsCast;
+use web_sys::{MessageEvent, WebSocket};
+
+// 文字列だけをやり取りする WebSocket。受け取ったメッセージは update でまとめて取り出す
+pub struct Socket {
+    socket: WebSocket,
+    receiver: UnboundedReceiver<String>,
+}
+
+impl Socket {
+    pub fn connect(url: &str) -> Result<Self> {
+        let socket = WebSocket::new(url)
+            .map_err(|err| anyhow!("Could not connect to {} {:#?}", url, err))?;
+        let (sender, receiver) = unbounded();
+        let onmessage = super::closure_wrap(Box::new(move |event: MessageEvent| {
+            if let Some(text) = event.data().as_string() {
+                let _ = sender.unbounded_send(text);
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        onmessage.forget();
+
+        Ok(Socket { socket, receiver })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.socket.ready_state() == WebSocket::OPEN
+    }
+
+    // つながらなかったか、途中で切れた
+    pub fn is_closed(&self) -> bool {
+        self.socket.ready_state() == WebSocket::CLOSED
+    }
+
+    pub fn send(&self, text: &str) -> Result<()> {
+        self.socket
+            .send_with_str(text)
+            .map_err(|err| anyhow!("Could not send {} {:#?}", text, err))
+    }
+
+    pub fn try_receive(&mut self) -> Option<String> {
+        self.receiver.try_next().ok().flatten()
+    }
+
+    // 別れの挨拶を送ってから閉じる
+    pub fn close(&self, farewell: &str) {
+        if self.is_open() {
+            let _ = self.socket.send_with_str(farewell);
+        }
+        let _ = self.socket.close();
+    }
+
+    // ページを離れるときも、閉じる前に farewell を送る
+    pub fn close_on_unload(&self, farewell: String) -> Result<()> {
+        let socket = self.socket.clone();
+        super::on_unload(move || {
+            if socket.ready_state() == WebSocket::OPEN {
+                let _ = socket.send_with_str(&farewell);
+            }
+            let _ = socket.close();
+        })
+    }
+}
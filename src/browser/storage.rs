@@ -0,0 +1,10 @@
+use crate::platform;
+use anyhow::Result;
+
+pub fn get_item(key: &str) -> Result<Option<String>> {
+    platform::current().get_item(key)
+}
+
+pub fn set_item(key: &str, value: &str) -> Result<()> {
+    platform::current().set_item(key, value)
+}
@@ -0,0 +1,198 @@
+use crate::browser::{self, storage};
+use crate::connectivity;
+use crate::profile::{self, Profile};
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use wasm_bindgen::JsValue;
+use web_sys::{Headers, RequestInit};
+
+const TOKEN_KEY: &str = "walk_the_dog.cloud_save_token";
+const NOT_FOUND: u16 = 404;
+// 通知とオフライン表示に使う名前
+pub const SERVICE: &str = "Cloud save";
+
+thread_local! {
+    static RESTORED: RefCell<Option<(usize, Profile)>> = const { RefCell::new(None) };
+}
+
+// やり直した同期でサーバーの方が新しかったときに一度だけ Some を返す。次のフレームでゲームが拾う
+pub fn take_restored() -> Option<(usize, Profile)> {
+    RESTORED.with(|restored| restored.take())
+}
+
+pub fn set_token(token: &str) {
+    if let Err(err) = storage::set_item(TOKEN_KEY, token) {
+        log!("Could not store cloud save token {:#?}", err);
+    }
+}
+
+#[derive(Clone)]
+pub struct CloudSave {
+    url: String,
+    token: String,
+}
+
+impl CloudSave {
+    // エンドポイントとトークンの両方が揃っている場合のみ有効
+    pub fn new(url: Option<String>) -> Option<Self> {
+        let token = storage::get_item(TOKEN_KEY).ok().flatten()?;
+        url.map(|url| CloudSave { url, token })
+    }
+
+    // アカウントはサーバーがトークンから決める。端末ごとの player_id を使うと
+    // 別の端末から同じセーブに届かない
+    fn profile_url(&self, slot: usize) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), slot)
+    }
+
+    fn request_init(&self, method: &str, body: Option<&str>) -> Result<RequestInit> {
+        let headers =
+            Headers::new().map_err(|err| anyhow!("Could not create headers {:#?}", err))?;
+        headers
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .and_then(|_| headers.set("Content-Type", "application/json"))
+            .map_err(|err| anyhow!("Could not set headers {:#?}", err))?;
+
+        let init = RequestInit::new();
+        init.set_method(method);
+        init.set_headers(&headers);
+        if let Some(body) = body {
+            init.set_body(&JsValue::from_str(body));
+        }
+        Ok(init)
+    }
+
+    async fn download(&self, slot: usize) -> Result<Option<Profile>> {
+        let resp = browser::fetch_response_with_init(
+            &self.profile_url(slot),
+            &self.request_init("GET", None)?,
+        )
+        .await?;
+        if resp.status() == NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.ok() {
+            return Err(anyhow!("Cloud save download failed {}", resp.status()));
+        }
+
+        let text = browser::response_text(&resp).await?;
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+
+    async fn upload(&self, slot: usize, profile: &Profile) -> Result<()> {
+        let body = serde_json::to_string(profile)?;
+        let resp = browser::fetch_response_with_init(
+            &self.profile_url(slot),
+            &self.request_init("PUT", Some(&body))?,
+        )
+        .await?;
+        if resp.ok() {
+            Ok(())
+        } else {
+            Err(anyhow!("Cloud save upload failed {}", resp.status()))
+        }
+    }
+
+    // 更新時刻の新しい方を残す
+    pub async fn sync(&self, slot: usize, local: Profile) -> Profile {
+        match self.download(slot).await {
+            Ok(Some(remote)) if is_newer(&remote, &local) => {
+                connectivity::record_success(SERVICE);
+                profile::store(slot, &remote);
+                remote
+            }
+            Ok(_) => {
+                connectivity::record_success(SERVICE);
+                if let Err(err) = self.upload(slot, &local).await {
+                    log!("Could not upload profile {:#?}", err);
+                    self.record_upload_failure(slot, local.clone());
+                }
+                local
+            }
+            Err(err) => {
+                log!("Could not sync profile {:#?}", err);
+                self.record_sync_failure(slot);
+                local
+            }
+        }
+    }
+
+    // 端末に残っている最新の内容で突き合わせ直す
+    pub fn sync_in_background(&self, slot: usize) {
+        let cloud_save = self.clone();
+        browser::spawn_local(async move {
+            let local = profile::load(slot);
+            let synced = cloud_save.sync(slot, local.clone()).await;
+            if is_newer(&synced, &local) {
+                RESTORED.with(|restored| restored.replace(Some((slot, synced))));
+            }
+        });
+    }
+
+    // つながったらその枠の同期をもう一度やる
+    fn record_sync_failure(&self, slot: usize) {
+        let cloud_save = self.clone();
+        connectivity::record_failure(
+            SERVICE,
+            Some(Box::new(move || cloud_save.sync_in_background(slot))),
+        );
+    }
+
+    pub fn upload_in_background(&self, slot: usize, profile: Profile) {
+        let cloud_save = self.clone();
+        browser::spawn_local(async move {
+            match cloud_save.upload(slot, &profile).await {
+                Ok(()) => connectivity::record_success(SERVICE),
+                Err(err) => {
+                    log!("Could not upload profile {:#?}", err);
+                    cloud_save.record_upload_failure(slot, profile);
+                }
+            }
+        });
+    }
+
+    // つながったら同じ内容をもう一度送る
+    fn record_upload_failure(&self, slot: usize, profile: Profile) {
+        let cloud_save = self.clone();
+        connectivity::record_failure(
+            SERVICE,
+            Some(Box::new(move || {
+                cloud_save.upload_in_background(slot, profile)
+            })),
+        );
+    }
+}
+
+fn is_newer(remote: &Profile, local: &Profile) -> bool {
+    remote.updated_at > local.updated_at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_recent_profile_wins() {
+        let local = Profile {
+            updated_at: 100.0,
+            ..Profile::default()
+        };
+        let remote = Profile {
+            updated_at: 200.0,
+            ..Profile::default()
+        };
+
+        assert!(is_newer(&remote, &local));
+        assert!(!is_newer(&local, &remote));
+        assert!(!is_newer(&local, &local));
+    }
+
+    #[test]
+    fn saves_are_keyed_by_the_token_account_not_the_device() {
+        let cloud_save = CloudSave {
+            url: "https://example.com/saves/".into(),
+            token: "token".into(),
+        };
+        assert_eq!(cloud_save.profile_url(2), "https://example.com/saves/2");
+    }
+}
@@ -0,0 +1,67 @@
+use crate::engine::Rect;
+
+// 障害物の横の広がり。index は障害物の並びでの位置
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    left: i16,
+    right: i16,
+    index: usize,
+}
+
+// 障害物を左端の順に並べておき、少年と横に重なるものだけを当たり判定に回す。
+// バッファは毎フレーム使い回す
+#[derive(Default)]
+pub struct BroadPhase {
+    spans: Vec<Span>,
+    candidates: Vec<usize>,
+}
+
+impl BroadPhase {
+    // (左端, 右端) を障害物の並びの順に渡す
+    pub fn rebuild(&mut self, spans: impl Iterator<Item = (i16, i16)>) {
+        self.spans.clear();
+        self.spans
+            .extend(
+                spans
+                    .enumerate()
+                    .map(|(index, (left, right))| Span { left, right, index }),
+            );
+        self.spans.sort_unstable_by_key(|span| span.left);
+    }
+
+    // area と横に重なる障害物の番号。当たり判定の順が変わらないよう、元の並びの順で返す
+    pub fn query(&mut self, area: &Rect) -> &[usize] {
+        let (left, right) = (area.x(), area.right());
+        let end = self.spans.partition_point(|span| span.left <= right);
+        self.candidates.clear();
+        self.candidates.extend(
+            self.spans[..end]
+                .iter()
+                .filter(|span| span.right >= left)
+                .map(|span| span.index),
+        );
+        self.candidates.sort_unstable();
+        &self.candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_obstacles_overlapping_the_area_are_candidates() {
+        let mut broad_phase = BroadPhase::default();
+        broad_phase.rebuild(
+            [(300, 400), (0, 50), (90, 120), (150, 160), (40, 700)]
+                .iter()
+                .copied(),
+        );
+
+        let area = Rect::new_from_x_y(100, 0, 60, 100);
+        assert_eq!(broad_phase.query(&area), &[2, 3, 4]);
+
+        let far_right = Rect::new_from_x_y(800, 0, 10, 10);
+        assert!(broad_phase.query(&far_right).is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::browser::{self, storage};
+use crate::experiment::Experiment;
+use crate::ramp::RampCurve;
+use crate::tween::Easing;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use web_sys::js_sys::{Object, JSON};
+
+const REMOTE_CONFIG_URL: &str = "remote_config.json";
+const REMOTE_CONFIG_CACHE_KEY: &str = "walk_the_dog.remote_config";
+
+#[derive(Clone, Copy)]
+pub struct Physics {
+    pub gravity: i16,
+    pub jump_speed: i16,
+    pub running_speed: i16,
+    pub terminal_speed: i16,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameConfig {
+    pub gravity: i16,
+    pub jump_speed: i16,
+    pub running_speed: i16,
+    pub terminal_speed: i16,
+    pub obstacle_buffer: i16,
+    pub timeline_minimum: i16,
+    // 走った距離に応じて走る速さに足す量と、障害物の間隔から引く量
+    pub speed_ramp: RampCurve,
+    pub spacing_ramp: RampCurve,
+    // 石にぶつかってから何 update の間にジャンプすれば、ぶつからなかったことにするか
+    pub forgiveness_ticks: u8,
+    // false にすると障害物にぶつかっても倒れず、よろけて少し遅くなるだけになる
+    pub knockouts: bool,
+    // スタミナが減り続け、コインで回復する夜のマラソン
+    pub marathon: bool,
+    // 倒れてもゲームオーバーにせず、しおりの場面からやり直す
+    pub practice: bool,
+    // 500m ごとにランダムな変化が 30 秒だけ効く
+    pub mutators: bool,
+    // 変化が効いている間だけ書き換わる値
+    pub inverted_controls: bool,
+    pub boy_scale: f64,
+    // スピードランの区切りを置く距離 (m)
+    pub split_distances: Vec<u32>,
+    // デコード済みの画像に使ってよいメモリ (MB)。超えたら季節のスキンなどから捨てる
+    pub texture_budget_mb: u32,
+    // BGM のテンポ。リズム区間の足場はこの拍に合わせて現れる
+    pub music_bpm: f64,
+    pub seasonal_events: bool,
+    pub message_of_the_day: Option<String>,
+    pub message_of_the_day_link: Option<String>,
+    pub experiment: Option<Experiment>,
+    pub analytics_url: Option<String>,
+    pub cloud_save_url: Option<String>,
+    pub lobby_url: Option<String>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            gravity: 1,
+            jump_speed: -25,
+            running_speed: 4,
+            terminal_speed: 20,
+            obstacle_buffer: 20,
+            timeline_minimum: 1000,
+            speed_ramp: RampCurve {
+                start: 200,
+                full: 3000,
+                max: 4,
+                easing: Easing::Linear,
+            },
+            spacing_ramp: RampCurve {
+                start: 200,
+                full: 2000,
+                max: 20,
+                easing: Easing::EaseOut,
+            },
+            forgiveness_ticks: 3,
+            knockouts: true,
+            marathon: false,
+            practice: false,
+            mutators: false,
+            inverted_controls: false,
+            boy_scale: 1.0,
+            split_distances: vec![100, 250, 500, 1000],
+            texture_budget_mb: 96,
+            music_bpm: 120.0,
+            seasonal_events: true,
+            message_of_the_day: None,
+            message_of_the_day_link: None,
+            experiment: None,
+            analytics_url: None,
+            cloud_save_url: None,
+            lobby_url: None,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn physics(&self) -> Physics {
+        Physics {
+            gravity: self.gravity,
+            jump_speed: self.jump_speed,
+            running_speed: self.running_speed,
+            terminal_speed: self.terminal_speed,
+        }
+    }
+
+    // リモートの値で上書きする。書かれていない項目はローカルの値のまま
+    fn merge(&self, remote: &JsValue) -> Result<GameConfig> {
+        let local: Object = serde_wasm_bindgen::to_value(self)
+            .map_err(|err| anyhow!("Could not serialize GameConfig {:#?}", err))?
+            .dyn_into()
+            .map_err(|err| anyhow!("GameConfig is not an object {:#?}", err))?;
+        let remote: Object = remote
+            .clone()
+            .dyn_into()
+            .map_err(|err| anyhow!("Remote config is not an object {:#?}", err))?;
+
+        serde_wasm_bindgen::from_value(Object::assign(&local, &remote).into())
+            .map_err(|err| anyhow!("Could not convert remote config {:#?}", err))
+    }
+}
+
+// 取得できなければ前回キャッシュした設定、それも無ければローカルの設定を使う
+pub async fn load_game_config() -> GameConfig {
+    let local = GameConfig::default();
+    let remote = match fetch_remote_config().await {
+        Ok(remote) => Some(remote),
+        Err(err) => {
+            log!("Could not fetch remote config, using cache {:#?}", err);
+            cached_remote_config()
+        }
+    };
+
+    match remote.map(|remote| local.merge(&remote)) {
+        Some(Ok(config)) => config,
+        Some(Err(err)) => {
+            log!("Could not merge remote config {:#?}", err);
+            local
+        }
+        None => local,
+    }
+}
+
+async fn fetch_remote_config() -> Result<JsValue> {
+    let remote = browser::fetch_json(REMOTE_CONFIG_URL).await?;
+    if let Some(text) = JSON::stringify(&remote)
+        .ok()
+        .and_then(|text| text.as_string())
+    {
+        if let Err(err) = storage::set_item(REMOTE_CONFIG_CACHE_KEY, &text) {
+            log!("Could not cache remote config {:#?}", err);
+        }
+    }
+    Ok(remote)
+}
+
+fn cached_remote_config() -> Option<JsValue> {
+    storage::get_item(REMOTE_CONFIG_CACHE_KEY)
+        .ok()
+        .flatten()
+        .and_then(|text| JSON::parse(&text).ok())
+}
@@ -0,0 +1,176 @@
+use crate::browser;
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{Point, Renderer};
+use crate::notifications;
+use std::cell::RefCell;
+
+const BADGE_POSITION: Point = Point { x: 300, y: 590 };
+const BADGE_COLOR: &str = "#FF9F43";
+
+thread_local! {
+    static CONNECTIVITY: RefCell<Connectivity> = RefCell::new(Connectivity::default());
+}
+
+// つながったときにやり直す処理
+pub type Retry = Box<dyn FnOnce()>;
+
+// navigator.onLine を見張り、変わったら知らせる。オフラインからつながったら失敗したリクエストをやり直す
+pub fn watch() {
+    let online = browser::is_online();
+    CONNECTIVITY.with(|connectivity| connectivity.borrow_mut().browser_offline = !online);
+    if let Err(err) = browser::on_connectivity_change(set_online) {
+        log!("Could not watch connectivity {:#?}", err);
+    }
+}
+
+fn set_online(online: bool) {
+    let message = CONNECTIVITY.with(|connectivity| connectivity.borrow_mut().set_online(online));
+    if let Some(message) = message {
+        notifications::push(&message);
+    }
+    if online {
+        retry();
+    }
+}
+
+// service へのリクエストが失敗した。初めて失敗したときだけ知らせる
+pub fn record_failure(service: &'static str, retry: Option<Retry>) {
+    let message =
+        CONNECTIVITY.with(|connectivity| connectivity.borrow_mut().record_failure(service, retry));
+    if let Some(message) = message {
+        notifications::push(&message);
+    }
+}
+
+pub fn record_success(service: &'static str) {
+    CONNECTIVITY.with(|connectivity| connectivity.borrow_mut().record_success(service));
+}
+
+// service をオフラインとして扱う画面にするか
+pub fn is_degraded(service: &str) -> bool {
+    CONNECTIVITY.with(|connectivity| connectivity.borrow().is_degraded(service))
+}
+
+// やり直す処理は借用を返してから呼ぶ。中でまた失敗を記録してもよい
+pub fn retry() {
+    let retries = CONNECTIVITY.with(|connectivity| connectivity.borrow_mut().take_retries());
+    retries.into_iter().for_each(|retry| retry());
+}
+
+// どこかがオフラインなら画面の下に出す
+pub fn draw(renderer: &Renderer) {
+    let offline = CONNECTIVITY.with(|connectivity| connectivity.borrow().is_offline());
+    if offline {
+        renderer.draw_text(
+            "OFFLINE",
+            &BADGE_POSITION,
+            TextStyle::new(BADGE_COLOR).align(TextAlign::Center),
+        );
+    }
+}
+
+#[derive(Default)]
+struct Connectivity {
+    // navigator.onLine が false の間はリクエストを待たずにすべてオフライン扱い
+    browser_offline: bool,
+    // 失敗したままのサービス
+    failed: Vec<(&'static str, Option<Retry>)>,
+}
+
+impl Connectivity {
+    fn set_online(&mut self, online: bool) -> Option<String> {
+        if self.browser_offline != online {
+            return None;
+        }
+        self.browser_offline = !online;
+        Some(
+            if online {
+                "Back online"
+            } else {
+                "You are offline"
+            }
+            .to_string(),
+        )
+    }
+
+    fn record_failure(&mut self, service: &'static str, retry: Option<Retry>) -> Option<String> {
+        match self
+            .failed
+            .iter_mut()
+            .find(|(failed, _)| *failed == service)
+        {
+            Some(failure) => {
+                failure.1 = retry;
+                None
+            }
+            None => {
+                self.failed.push((service, retry));
+                Some(format!("{} offline", service))
+            }
+        }
+    }
+
+    fn record_success(&mut self, service: &str) {
+        self.failed.retain(|(failed, _)| *failed != service);
+    }
+
+    fn is_degraded(&self, service: &str) -> bool {
+        self.browser_offline || self.failed.iter().any(|(failed, _)| *failed == service)
+    }
+
+    fn is_offline(&self) -> bool {
+        self.browser_offline || !self.failed.is_empty()
+    }
+
+    // やり直すサービスはいったん失敗の一覧から外す。また失敗すれば改めて知らせる
+    fn take_retries(&mut self) -> Vec<Retry> {
+        self.failed
+            .drain(..)
+            .filter_map(|(_, retry)| retry)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn failures_are_announced_once_and_retried_when_back_online() {
+        let mut connectivity = Connectivity::default();
+        let retried = Rc::new(std::cell::Cell::new(0));
+        let counter = retried.clone();
+
+        assert_eq!(
+            connectivity.record_failure(
+                "Cloud save",
+                Some(Box::new(move || counter.set(counter.get() + 1)))
+            ),
+            Some("Cloud save offline".to_string())
+        );
+        assert_eq!(connectivity.record_failure("Cloud save", None), None);
+        assert!(connectivity.is_degraded("Cloud save"));
+        assert!(!connectivity.is_degraded("Lobby"));
+
+        assert_eq!(
+            connectivity.set_online(false),
+            Some("You are offline".into())
+        );
+        assert!(connectivity.is_degraded("Lobby"));
+        assert_eq!(connectivity.set_online(true), Some("Back online".into()));
+        assert_eq!(connectivity.set_online(true), None);
+
+        // 二度目の失敗で差し替えたので、最初のやり直しは呼ばれない
+        connectivity
+            .take_retries()
+            .into_iter()
+            .for_each(|retry| retry());
+        assert_eq!(retried.get(), 0);
+        assert!(!connectivity.is_offline());
+
+        connectivity.record_failure("Lobby", None);
+        connectivity.record_success("Lobby");
+        assert!(!connectivity.is_offline());
+    }
+}
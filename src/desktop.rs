@@ -0,0 +1,258 @@
+use crate::draw::software::Framebuffer;
+use crate::engine::{InputEvent, KeyPress, Point};
+use crate::platform;
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::executor::{LocalPool, LocalSpawner};
+use futures::task::LocalSpawnExt;
+use std::cell::RefCell;
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use winit::application::ApplicationHandler;
+use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::PhysicalKey;
+use winit::window::{Window, WindowId};
+
+pub mod audio;
+
+// ゲームの Canvas と同じ大きさ
+pub const WIDTH: usize = 600;
+pub const HEIGHT: usize = 600;
+// requestAnimationFrame と同じく 60 FPS を目安に描く
+const FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+
+type FrameCallback = Rc<RefCell<dyn FnMut(f64)>>;
+type VisibilityCallback = Box<dyn FnMut(bool)>;
+type UnloadCallback = Box<dyn FnMut()>;
+
+// ブラウザがページごとに持っているものを、ウィンドウのスレッドに 1 つずつ置く
+thread_local! {
+    static SCREEN: Rc<Framebuffer> = Rc::new(Framebuffer::new(WIDTH, HEIGHT));
+    static POOL: RefCell<LocalPool> = RefCell::new(LocalPool::new());
+    // 実行中のタスクからも積めるよう、POOL を借りずに使える spawner を別に持つ
+    static SPAWNER: LocalSpawner = POOL.with(|pool| pool.borrow().spawner());
+    static NEXT_FRAME: RefCell<Option<FrameCallback>> = RefCell::new(None);
+    static INPUT: RefCell<Option<UnboundedSender<InputEvent>>> = const { RefCell::new(None) };
+    static VISIBILITY: RefCell<Vec<VisibilityCallback>> = RefCell::new(vec![]);
+    static UNLOAD: RefCell<Vec<UnloadCallback>> = RefCell::new(vec![]);
+}
+
+// browser::context() の代わりにゲームが描く画面
+pub fn screen() -> Rc<Framebuffer> {
+    SCREEN.with(Rc::clone)
+}
+
+// wasm_bindgen_futures::spawn_local と同じく、描画の合間に少しずつ進める
+pub fn spawn(future: impl Future<Output = ()> + 'static) {
+    if let Err(err) = SPAWNER.with(|spawner| spawner.spawn_local(future)) {
+        log!("Could not spawn desktop task {:#?}", err);
+    }
+}
+
+// requestAnimationFrame と同じく、次の描画で 1 回だけ呼ぶ
+pub fn request_frame(callback: FrameCallback) {
+    NEXT_FRAME.with(|next| next.replace(Some(callback)));
+}
+
+// ウィンドウのキーとマウスの入力を、キャンバスのイベントと同じ形で受け取る
+pub fn input() -> UnboundedReceiver<InputEvent> {
+    let (sender, receiver) = unbounded();
+    INPUT.with(|input| input.replace(Some(sender)));
+    receiver
+}
+
+// ウィンドウが隠れたら true、また見えるようになったら false を渡す
+pub fn on_visibility_change(callback: impl FnMut(bool) + 'static) {
+    VISIBILITY.with(|callbacks| callbacks.borrow_mut().push(Box::new(callback)));
+}
+
+// ウィンドウを閉じる直前に呼ぶ
+pub fn on_unload(callback: impl FnMut() + 'static) {
+    UNLOAD.with(|callbacks| callbacks.borrow_mut().push(Box::new(callback)));
+}
+
+fn send(press: KeyPress) {
+    let at = platform::current().now().unwrap_or_default();
+    INPUT.with(|input| {
+        if let Some(sender) = input.borrow().as_ref() {
+            let _ = sender.unbounded_send((at, press));
+        }
+    });
+}
+
+struct Desktop {
+    window: Option<Rc<Window>>,
+    surface: Option<softbuffer::Surface<Rc<Window>, Rc<Window>>>,
+    next_frame: Instant,
+    pointer: Point,
+}
+
+impl Desktop {
+    fn open(&mut self, event_loop: &ActiveEventLoop) -> Result<()> {
+        let attributes = Window::default_attributes()
+            .with_title("Walk the Dog")
+            .with_inner_size(LogicalSize::new(WIDTH as f64, HEIGHT as f64))
+            .with_resizable(false);
+        let window = Rc::new(
+            event_loop
+                .create_window(attributes)
+                .map_err(|err| anyhow!("Could not create window {:#?}", err))?,
+        );
+        let context = softbuffer::Context::new(window.clone())
+            .map_err(|err| anyhow!("Could not create softbuffer context {:#?}", err))?;
+        let surface = softbuffer::Surface::new(&context, window.clone())
+            .map_err(|err| anyhow!("Could not create softbuffer surface {:#?}", err))?;
+        self.window = Some(window);
+        self.surface = Some(surface);
+        Ok(())
+    }
+
+    // 読み込みなどのタスクを進めてから、ゲームループの 1 フレームを呼ぶ
+    fn frame(&mut self) -> Result<()> {
+        POOL.with(|pool| pool.borrow_mut().run_until_stalled());
+        if let Some(callback) = NEXT_FRAME.with(|next| next.borrow_mut().take()) {
+            (callback.borrow_mut())(platform::current().now()?);
+        }
+        self.present()
+    }
+
+    fn present(&mut self) -> Result<()> {
+        let surface = self
+            .surface
+            .as_mut()
+            .ok_or_else(|| anyhow!("Desktop: Surface is None"))?;
+        let size = self
+            .window
+            .as_ref()
+            .ok_or_else(|| anyhow!("Desktop: Window is None"))?
+            .inner_size();
+        let (width, height) = match (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Ok(()),
+        };
+        surface
+            .resize(width, height)
+            .map_err(|err| anyhow!("Could not resize surface {:#?}", err))?;
+
+        let mut buffer = surface
+            .buffer_mut()
+            .map_err(|err| anyhow!("Could not get surface buffer {:#?}", err))?;
+        let screen = screen();
+        let pixels = screen.pixels();
+        // HiDPI ではウィンドウの方が大きいので、最近傍で引き伸ばす
+        let (surface_width, surface_height) = (width.get() as usize, height.get() as usize);
+        for y in 0..surface_height {
+            let source_y = y * screen.height() / surface_height;
+            for x in 0..surface_width {
+                let source_x = x * screen.width() / surface_width;
+                buffer[y * surface_width + x] = pixels[source_y * screen.width() + source_x];
+            }
+        }
+        buffer
+            .present()
+            .map_err(|err| anyhow!("Could not present surface {:#?}", err))
+    }
+
+    // ウィンドウのピクセルをゲームの座標に直す
+    fn to_game(&self, position: PhysicalPosition<f64>) -> Point {
+        let size = match &self.window {
+            Some(window) => window.inner_size(),
+            None => return self.pointer,
+        };
+        Point {
+            x: (position.x * WIDTH as f64 / f64::from(size.width.max(1))) as i16,
+            y: (position.y * HEIGHT as f64 / f64::from(size.height.max(1))) as i16,
+        }
+    }
+}
+
+impl ApplicationHandler for Desktop {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_none() {
+            if let Err(err) = self.open(event_loop) {
+                log!("Could not open desktop window {:#?}", err);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => {
+                UNLOAD.with(|callbacks| {
+                    callbacks
+                        .borrow_mut()
+                        .iter_mut()
+                        .for_each(|callback| callback())
+                });
+                event_loop.exit();
+            }
+            WindowEvent::RedrawRequested => {
+                if let Err(err) = self.frame() {
+                    log!("Could not draw desktop frame {:#?}", err);
+                }
+            }
+            // winit の KeyCode は KeyboardEvent.code と同じ名前なので、そのまま文字列にする
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    let code = format!("{:?}", code);
+                    send(match event.state {
+                        ElementState::Pressed => KeyPress::KeyDown(code),
+                        ElementState::Released => KeyPress::KeyUp(code),
+                    });
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer = self.to_game(position);
+                send(KeyPress::PointerMove(self.pointer));
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => send(match state {
+                ElementState::Pressed => KeyPress::PointerDown {
+                    position: self.pointer,
+                    touch: false,
+                },
+                ElementState::Released => KeyPress::PointerUp,
+            }),
+            WindowEvent::Occluded(hidden) => VISIBILITY.with(|callbacks| {
+                callbacks
+                    .borrow_mut()
+                    .iter_mut()
+                    .for_each(|callback| callback(hidden))
+            }),
+            _ => {}
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if Instant::now() >= self.next_frame {
+            self.next_frame = Instant::now() + FRAME_INTERVAL;
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+        }
+        event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+    }
+}
+
+// ウィンドウを開き、閉じられるまで spawn したタスクとゲームループを回す
+pub fn run() -> Result<()> {
+    let event_loop =
+        EventLoop::new().map_err(|err| anyhow!("Could not create event loop {:#?}", err))?;
+    let mut desktop = Desktop {
+        window: None,
+        surface: None,
+        next_frame: Instant::now(),
+        pointer: Point { x: 0, y: 0 },
+    };
+    event_loop
+        .run_app(&mut desktop)
+        .map_err(|err| anyhow!("Desktop event loop failed {:#?}", err))
+}
@@ -0,0 +1,92 @@
+use crate::browser;
+use crate::mixer::{Bus, DuckingRule};
+use crate::platform;
+use anyhow::Result;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// デスクトップにはまだ音の出力先がないので、ブラウザ版と同じ操作を受け付けて何も鳴らさない。
+// BGM の時計だけは進め、譜面に合わせた障害物がずれないようにする
+#[derive(Clone)]
+pub struct Audio {
+    music_started: Rc<Cell<Option<f64>>>,
+}
+
+#[derive(Clone)]
+pub struct Sound;
+
+impl Audio {
+    pub fn new() -> Result<Self> {
+        Ok(Audio {
+            music_started: Rc::new(Cell::new(None)),
+        })
+    }
+
+    pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
+        let bytes = browser::fetch_array_buffer(filename).await?;
+        self.decode_sound(&bytes).await
+    }
+
+    pub async fn decode_sound(&self, _bytes: &[u8]) -> Result<Sound> {
+        Ok(Sound)
+    }
+
+    pub fn play_loud_sound(&self, _sound: &Sound) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_ducking(&self, _rule: DuckingRule) {}
+
+    pub fn play_ambience(&self, _sound: &Sound, _fade: f64) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn stop_ambience(&self, _fade: f64) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn play_music(&self, _sound: &Sound, _fade: f64) -> Result<()> {
+        self.music_started.set(Some(self.current_time()));
+        Ok(())
+    }
+
+    // AudioContext の時計と同じく秒で返す
+    pub fn current_time(&self) -> f64 {
+        platform::current().now().unwrap_or_default() / 1000.0
+    }
+
+    pub fn music_time(&self) -> Option<f64> {
+        self.music_started
+            .get()
+            .map(|started| self.current_time() - started)
+    }
+
+    pub fn stop_music(&self, _fade: f64) -> Result<()> {
+        self.music_started.set(None);
+        Ok(())
+    }
+
+    pub fn is_music_playing(&self) -> bool {
+        self.music_started.get().is_some()
+    }
+
+    pub fn set_music_in_background(&self, _keep_playing: bool) {}
+
+    pub fn watch_visibility(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn play_tone(&self, _frequency: f32, _duration: f64) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn play_tone_on(&self, _bus: Bus, _frequency: f32, _duration: f64) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_bus_volume(&self, _bus: Bus, _volume: f32) {}
+
+    pub fn set_master_volume(&self, _volume: f32) {}
+
+    pub fn set_muted(&self, _muted: bool) {}
+}
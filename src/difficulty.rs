@@ -0,0 +1,121 @@
+use crate::config::GameConfig;
+use crate::ramp::RampCurve;
+use serde::{Deserialize, Serialize};
+
+// ラン開始時に選ぶ難易度。スコアはこの難易度ごとに比べる
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Difficulty {
+    // 小さい子ども向け。ぶつかってもゲームオーバーにならない
+    Kid,
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    // 夜のまま終わらないラン。スタミナが尽きると遅くなって倒れる
+    Marathon,
+    // 一時停止中にしおりを挟み、倒れたらそこから何度でもやり直せる
+    Practice,
+    // 500m ごとに重力や速さが 30 秒だけ変わる
+    Roulette,
+}
+
+pub const DIFFICULTIES: [Difficulty; 7] = [
+    Difficulty::Kid,
+    Difficulty::Easy,
+    Difficulty::Normal,
+    Difficulty::Hard,
+    Difficulty::Marathon,
+    Difficulty::Practice,
+    Difficulty::Roulette,
+];
+
+impl Difficulty {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Difficulty::Kid => "Kid",
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+            Difficulty::Marathon => "Marathon",
+            Difficulty::Practice => "Practice",
+            Difficulty::Roulette => "Roulette",
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        DIFFICULTIES
+            .iter()
+            .position(|difficulty| difficulty == self)
+            .unwrap_or_default()
+    }
+
+    // Normal は設定の値をそのまま使い、他の難易度はそこからずらす
+    pub fn apply(&self, base: &GameConfig) -> GameConfig {
+        match self {
+            // 小さい子どもには速くならない
+            Difficulty::Kid => GameConfig {
+                knockouts: false,
+                speed_ramp: RampCurve::FLAT,
+                spacing_ramp: RampCurve::FLAT,
+                ..Difficulty::Easy.apply(base)
+            },
+            Difficulty::Easy => GameConfig {
+                running_speed: (base.running_speed - 1).max(1),
+                obstacle_buffer: base.obstacle_buffer + 40,
+                forgiveness_ticks: base.forgiveness_ticks.max(6),
+                ..base.clone()
+            },
+            Difficulty::Normal => base.clone(),
+            // 重力を強くした分だけ踏み切りも強くして、素早い低いジャンプにする
+            Difficulty::Hard => GameConfig {
+                gravity: base.gravity * 2,
+                jump_speed: base.jump_speed * 6 / 5,
+                running_speed: base.running_speed + 1,
+                obstacle_buffer: (base.obstacle_buffer - 10).max(0),
+                forgiveness_ticks: 0,
+                ..base.clone()
+            },
+            Difficulty::Marathon => GameConfig {
+                marathon: true,
+                ..base.clone()
+            },
+            Difficulty::Practice => GameConfig {
+                practice: true,
+                ..base.clone()
+            },
+            Difficulty::Roulette => GameConfig {
+                mutators: true,
+                ..base.clone()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn difficulty_presets_adjust_the_base_config() {
+        let base = GameConfig::default();
+        let easy = Difficulty::Easy.apply(&base);
+        let hard = Difficulty::Hard.apply(&base);
+
+        assert!(easy.running_speed < base.running_speed);
+        assert!(easy.obstacle_buffer > base.obstacle_buffer);
+        assert!(hard.running_speed > base.running_speed);
+        assert_eq!(hard.forgiveness_ticks, 0);
+        assert!(!Difficulty::Kid.apply(&base).knockouts);
+        assert_eq!(Difficulty::Kid.apply(&base).speed_ramp, RampCurve::FLAT);
+        assert!(hard.knockouts);
+        assert!(Difficulty::Marathon.apply(&base).marathon);
+        assert!(!base.marathon);
+        assert!(Difficulty::Roulette.apply(&base).mutators);
+        assert_eq!(Difficulty::Normal.apply(&base).gravity, base.gravity);
+        assert_eq!(
+            serde_json::to_string(&Difficulty::Hard).unwrap(),
+            r#""hard""#
+        );
+    }
+}
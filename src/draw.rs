@@ -0,0 +1,466 @@
+use crate::engine::{Point, Rect};
+use std::borrow::Cow;
+#[cfg(not(feature = "desktop"))]
+use web_sys::CanvasRenderingContext2d;
+
+#[cfg(any(feature = "desktop", test))]
+mod font;
+#[cfg(any(feature = "desktop", test))]
+pub mod software;
+
+// 画像と描画先の型。ブラウザではキャンバス、デスクトップでは CPU で塗る Framebuffer を使う
+#[cfg(feature = "desktop")]
+pub use software::Bitmap;
+#[cfg(not(feature = "desktop"))]
+pub use web_sys::{
+    CanvasRenderingContext2d as Context, HtmlCanvasElement as Surface, HtmlImageElement as Bitmap,
+};
+#[cfg(feature = "desktop")]
+pub type Surface = std::rc::Rc<software::Framebuffer>;
+#[cfg(feature = "desktop")]
+pub type Context = Surface;
+
+// 描画先の (幅, 高さ)
+#[cfg(not(feature = "desktop"))]
+pub fn surface_size(surface: &Surface) -> (u32, u32) {
+    (surface.width(), surface.height())
+}
+
+#[cfg(feature = "desktop")]
+pub fn surface_size(surface: &Surface) -> (u32, u32) {
+    (surface.width() as u32, surface.height() as u32)
+}
+
+// キャンバスと同じく、大きさを変えると中身は消える
+#[cfg(not(feature = "desktop"))]
+pub fn resize_surface(surface: &Surface, width: u32, height: u32) {
+    surface.set_width(width);
+    surface.set_height(height);
+}
+
+#[cfg(feature = "desktop")]
+pub fn resize_surface(surface: &Surface, width: u32, height: u32) {
+    surface.resize(width as usize, height as usize);
+}
+
+#[cfg(not(feature = "desktop"))]
+pub fn surface_of(context: &Context) -> Option<Surface> {
+    context.canvas()
+}
+
+#[cfg(feature = "desktop")]
+pub fn surface_of(context: &Context) -> Option<Surface> {
+    Some(context.clone())
+}
+
+// デスクトップは組み込みのドットフォントで描くので、フォント名はキャンバスでだけ使う
+#[cfg_attr(feature = "desktop", allow(dead_code))]
+const UI_FONT_FAMILY: &str = "'Ken Future'";
+const DEFAULT_FONT_SIZE: u16 = 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TextAlign::Left => "left",
+            TextAlign::Center => "center",
+            TextAlign::Right => "right",
+        }
+    }
+}
+
+// 文字の色と大きさと揃え方。色だけ渡したときは 16px の左揃えになる
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub color: Color,
+    pub size: u16,
+    pub align: TextAlign,
+}
+
+impl TextStyle {
+    pub fn new(color: impl Into<Color>) -> Self {
+        TextStyle {
+            color: color.into(),
+            size: DEFAULT_FONT_SIZE,
+            align: TextAlign::default(),
+        }
+    }
+
+    pub fn size(self, size: u16) -> Self {
+        TextStyle { size, ..self }
+    }
+
+    pub fn align(self, align: TextAlign) -> Self {
+        TextStyle { align, ..self }
+    }
+
+    #[cfg_attr(feature = "desktop", allow(dead_code))]
+    fn font(&self) -> String {
+        format!("{}px {}", self.size, UI_FONT_FAMILY)
+    }
+
+    // 既定の大きさと揃え方なら色だけを書き、以前のゴールデンと同じ文字列にする
+    fn describe(&self) -> String {
+        if self.size == DEFAULT_FONT_SIZE && self.align == TextAlign::default() {
+            self.color.to_string()
+        } else {
+            format!("{} {}px {}", self.color, self.size, self.align.as_str())
+        }
+    }
+}
+
+impl From<&'static str> for TextStyle {
+    fn from(color: &'static str) -> Self {
+        TextStyle::new(color)
+    }
+}
+
+impl From<String> for TextStyle {
+    fn from(color: String) -> Self {
+        TextStyle::new(color)
+    }
+}
+
+const FLASH_TICKS: u8 = 8;
+// 点滅中はこのフレーム数ごとに薄くしたり戻したりする
+const BLINK_INTERVAL: u8 = 4;
+const BLINK_ALPHA: f64 = 0.3;
+
+// 絵一枚にかける描き方の変化。ぶつかった直後は白く光り、無敵の間は点滅する
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderModifiers {
+    flash_ticks: u8,
+    blink_ticks: u8,
+}
+
+impl RenderModifiers {
+    // 光ったあと、invulnerable_ticks の間は点滅を続ける
+    pub fn hit(invulnerable_ticks: u8) -> Self {
+        RenderModifiers {
+            flash_ticks: FLASH_TICKS,
+            blink_ticks: invulnerable_ticks,
+        }
+    }
+
+    pub fn blink(ticks: u8) -> Self {
+        RenderModifiers {
+            flash_ticks: 0,
+            blink_ticks: ticks,
+        }
+    }
+
+    pub fn update(self) -> Self {
+        RenderModifiers {
+            flash_ticks: self.flash_ticks.saturating_sub(1),
+            blink_ticks: self.blink_ticks.saturating_sub(1),
+        }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        if self.blink_ticks / BLINK_INTERVAL % 2 == 1 {
+            BLINK_ALPHA
+        } else {
+            1.0
+        }
+    }
+
+    // 光り始めが一番強く、だんだん弱くなる
+    pub fn flash(&self) -> f64 {
+        f64::from(self.flash_ticks) / f64::from(FLASH_TICKS)
+    }
+}
+
+// 色の名前。ほとんどは定数なので、毎フレームの命令ごとに文字列を作らないよう借りたまま持つ
+pub type Color = Cow<'static, str>;
+
+// Renderer が 1 フレーム分ためておく描画命令。実際の描画はバックエンドに任せる
+#[derive(Clone)]
+pub enum DrawCommand {
+    Clear(Rect),
+    Image {
+        image: Bitmap,
+        frame: Rect,
+        destination: Rect,
+    },
+    EntireImage {
+        image: Bitmap,
+        position: Point,
+    },
+    Canvas {
+        canvas: Surface,
+        position: Point,
+    },
+    FillRect {
+        rect: Rect,
+        color: Color,
+    },
+    FillCircle {
+        center: Point,
+        radius: i16,
+        color: Color,
+    },
+    FillEllipse {
+        center: Point,
+        radius_x: i16,
+        radius_y: i16,
+        color: Color,
+    },
+    StrokeRect {
+        rect: Rect,
+        color: Color,
+    },
+    Text {
+        text: String,
+        position: Point,
+        style: TextStyle,
+    },
+    Save,
+    Translate(Point),
+    Scale(f64),
+    // この後に描くものの後ろに付ける影。offset は変換に関係なく画面のピクセルでずらす
+    Shadow {
+        color: Color,
+        blur: i16,
+        offset: Point,
+    },
+    // この後に描くものの不透明度と重ね方
+    Blend {
+        alpha: f64,
+        operation: &'static str,
+    },
+    Restore,
+}
+
+impl DrawCommand {
+    // ゴールデンテストでハッシュする文字列。画像は参照先の URL で区別する
+    pub fn describe(&self) -> String {
+        match self {
+            DrawCommand::Clear(rect) => format!("clear {:?}", rect),
+            DrawCommand::Image {
+                image,
+                frame,
+                destination,
+            } => format!("image {} {:?} {:?}", image.src(), frame, destination),
+            DrawCommand::EntireImage { image, position } => {
+                format!("image {} {:?}", image.src(), position)
+            }
+            DrawCommand::Canvas { canvas, position } => format!(
+                "canvas {}x{} {:?}",
+                canvas.width(),
+                canvas.height(),
+                position
+            ),
+            DrawCommand::FillRect { rect, color } => format!("fill_rect {:?} {}", rect, color),
+            DrawCommand::FillCircle {
+                center,
+                radius,
+                color,
+            } => format!("fill_circle {:?} {} {}", center, radius, color),
+            DrawCommand::FillEllipse {
+                center,
+                radius_x,
+                radius_y,
+                color,
+            } => format!(
+                "fill_ellipse {:?} {} {} {}",
+                center, radius_x, radius_y, color
+            ),
+            DrawCommand::StrokeRect { rect, color } => format!("stroke_rect {:?} {}", rect, color),
+            DrawCommand::Text {
+                text,
+                position,
+                style,
+            } => format!("text {} {:?} {}", text, position, style.describe()),
+            DrawCommand::Save => "save".into(),
+            DrawCommand::Translate(offset) => format!("translate {:?}", offset),
+            DrawCommand::Scale(factor) => format!("scale {}", factor),
+            DrawCommand::Shadow {
+                color,
+                blur,
+                offset,
+            } => format!("shadow {} {} {:?}", color, blur, offset),
+            DrawCommand::Blend { alpha, operation } => format!("blend {} {}", alpha, operation),
+            DrawCommand::Restore => "restore".into(),
+        }
+    }
+}
+
+pub trait DrawBackend {
+    fn execute(&self, command: &DrawCommand);
+}
+
+#[cfg(not(feature = "desktop"))]
+impl DrawBackend for CanvasRenderingContext2d {
+    fn execute(&self, command: &DrawCommand) {
+        let result = match command {
+            DrawCommand::Clear(rect) => {
+                self.clear_rect(
+                    rect.x().into(),
+                    rect.y().into(),
+                    rect.width.into(),
+                    rect.height.into(),
+                );
+                Ok(())
+            }
+            DrawCommand::Image {
+                image,
+                frame,
+                destination,
+            } => self.draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                image,
+                frame.x().into(),
+                frame.y().into(),
+                frame.width.into(),
+                frame.height.into(),
+                destination.x().into(),
+                destination.y().into(),
+                destination.width.into(),
+                destination.height.into(),
+            ),
+            DrawCommand::EntireImage { image, position } => {
+                self.draw_image_with_html_image_element(image, position.x.into(), position.y.into())
+            }
+            DrawCommand::Canvas { canvas, position } => self.draw_image_with_html_canvas_element(
+                canvas,
+                position.x.into(),
+                position.y.into(),
+            ),
+            DrawCommand::FillRect { rect, color } => {
+                self.set_fill_style_str(color);
+                self.fill_rect(
+                    rect.x().into(),
+                    rect.y().into(),
+                    rect.width.into(),
+                    rect.height.into(),
+                );
+                Ok(())
+            }
+            DrawCommand::FillCircle {
+                center,
+                radius,
+                color,
+            } => {
+                self.set_fill_style_str(color);
+                self.begin_path();
+                let result = self.arc(
+                    center.x.into(),
+                    center.y.into(),
+                    (*radius).into(),
+                    0.0,
+                    std::f64::consts::PI * 2.0,
+                );
+                self.fill();
+                result
+            }
+            DrawCommand::FillEllipse {
+                center,
+                radius_x,
+                radius_y,
+                color,
+            } => {
+                self.set_fill_style_str(color);
+                self.begin_path();
+                let result = self.ellipse(
+                    center.x.into(),
+                    center.y.into(),
+                    (*radius_x).into(),
+                    (*radius_y).into(),
+                    0.0,
+                    0.0,
+                    std::f64::consts::PI * 2.0,
+                );
+                self.fill();
+                result
+            }
+            DrawCommand::StrokeRect { rect, color } => {
+                self.set_stroke_style_str(color);
+                self.stroke_rect(
+                    rect.x().into(),
+                    rect.y().into(),
+                    rect.width.into(),
+                    rect.height.into(),
+                );
+                Ok(())
+            }
+            DrawCommand::Text {
+                text,
+                position,
+                style,
+            } => {
+                self.set_font(&style.font());
+                self.set_fill_style_str(&style.color);
+                self.set_text_align(style.align.as_str());
+                self.set_text_baseline("middle");
+                self.fill_text(text, position.x.into(), position.y.into())
+            }
+            DrawCommand::Save => {
+                self.save();
+                Ok(())
+            }
+            DrawCommand::Translate(offset) => self.translate(offset.x.into(), offset.y.into()),
+            DrawCommand::Scale(factor) => self.scale(*factor, *factor),
+            DrawCommand::Shadow {
+                color,
+                blur,
+                offset,
+            } => {
+                self.set_shadow_color(color);
+                self.set_shadow_blur((*blur).into());
+                self.set_shadow_offset_x(offset.x.into());
+                self.set_shadow_offset_y(offset.y.into());
+                Ok(())
+            }
+            DrawCommand::Blend { alpha, operation } => {
+                self.set_global_alpha(*alpha);
+                self.set_global_composite_operation(operation)
+            }
+            DrawCommand::Restore => {
+                self.restore();
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            log!("Could not execute {} {:#?}", command.describe(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_style_defaults_keep_the_old_description() {
+        let plain: TextStyle = "#FFFFFF".into();
+        let title = TextStyle::new("#FFFFFF").size(32).align(TextAlign::Center);
+
+        assert_eq!(plain.font(), "16px 'Ken Future'");
+        assert_eq!(plain.describe(), "#FFFFFF");
+        assert_eq!(title.describe(), "#FFFFFF 32px center");
+    }
+
+    #[test]
+    fn hits_flash_and_then_blink_until_invulnerability_ends() {
+        let mut modifiers = RenderModifiers::hit(12);
+        assert_eq!(modifiers.flash(), 1.0);
+        assert_eq!(modifiers.alpha(), BLINK_ALPHA);
+
+        let alphas: Vec<f64> = (0..12)
+            .map(|_| {
+                modifiers = modifiers.update();
+                modifiers.alpha()
+            })
+            .collect();
+        assert_eq!(modifiers.flash(), 0.0);
+        assert!(alphas.contains(&1.0) && alphas.contains(&BLINK_ALPHA));
+        assert_eq!(modifiers, RenderModifiers::default());
+        assert_eq!(modifiers.alpha(), 1.0);
+    }
+}
@@ -0,0 +1,110 @@
+// 組み込みの 5x7 ドットのフォント。Framebuffer が文字を描くのに使う。
+// ' ' から '~' までの ASCII を並べ、1 行 5 ビットで上位ビットが左端
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+const FIRST: char = ' ';
+
+const GLYPHS: [[u8; GLYPH_HEIGHT]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+    [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00],
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+    [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04],
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+    [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],
+    [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+    [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00],
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08],
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02],
+    [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E],
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+    [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E],
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00],
+    [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E],
+    [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x0E, 0x01, 0x0F, 0x11, 0x0F],
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1E],
+    [0x00, 0x00, 0x0E, 0x10, 0x10, 0x11, 0x0E],
+    [0x01, 0x01, 0x0D, 0x13, 0x11, 0x11, 0x0F],
+    [0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E],
+    [0x06, 0x09, 0x08, 0x1C, 0x08, 0x08, 0x08],
+    [0x00, 0x0F, 0x11, 0x11, 0x0F, 0x01, 0x0E],
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11],
+    [0x04, 0x00, 0x0C, 0x04, 0x04, 0x04, 0x0E],
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0C],
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12],
+    [0x0C, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11],
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11],
+    [0x00, 0x00, 0x0E, 0x11, 0x11, 0x11, 0x0E],
+    [0x00, 0x00, 0x1E, 0x11, 0x1E, 0x10, 0x10],
+    [0x00, 0x00, 0x0D, 0x13, 0x0F, 0x01, 0x01],
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10],
+    [0x00, 0x00, 0x0E, 0x10, 0x0E, 0x01, 0x1E],
+    [0x08, 0x08, 0x1C, 0x08, 0x08, 0x09, 0x06],
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0D],
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0A, 0x04],
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0A],
+    [0x00, 0x00, 0x11, 0x0A, 0x04, 0x0A, 0x11],
+    [0x00, 0x00, 0x11, 0x11, 0x0F, 0x01, 0x0E],
+    [0x00, 0x00, 0x1F, 0x02, 0x04, 0x08, 0x1F],
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02],
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08],
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00],
+];
+
+// 表にない文字は None。呼び出し側で空白として扱う
+pub fn glyph(character: char) -> Option<&'static [u8; GLYPH_HEIGHT]> {
+    (character as usize)
+        .checked_sub(FIRST as usize)
+        .and_then(|index| GLYPHS.get(index))
+}
@@ -0,0 +1,813 @@
+use super::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::{DrawBackend, DrawCommand, TextAlign, TextStyle};
+use crate::engine::{Point, Rect};
+#[cfg(feature = "desktop")]
+use anyhow::{anyhow, Result};
+use std::cell::{Ref, RefCell};
+use std::rc::Rc;
+
+// ネイティブ向けに CPU でピクセルを塗るバックエンド。
+// 画像は Bitmap からピクセルを写し、文字は組み込みのドットフォントで描く
+pub struct Framebuffer {
+    plane: RefCell<Plane>,
+    states: RefCell<Vec<State>>,
+}
+
+// 色 (0x00RRGGBB) と不透明度を分けて持つピクセルの並び
+#[derive(Clone)]
+struct Plane {
+    width: usize,
+    height: usize,
+    rgb: Vec<u32>,
+    alpha: Vec<u8>,
+}
+
+impl Plane {
+    fn new(width: usize, height: usize) -> Self {
+        Plane {
+            width,
+            height,
+            rgb: vec![0; width * height],
+            alpha: vec![0; width * height],
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            None
+        } else {
+            Some(y as usize * self.width + x as usize)
+        }
+    }
+
+    // 画面外や、縮めて 1 ピクセルもないところは None
+    fn sample(&self, x: f64, y: f64) -> Option<(u32, f32)> {
+        let index = self.index(x.floor() as i32, y.floor() as i32)?;
+        Some((self.rgb[index], f32::from(self.alpha[index]) / 255.0))
+    }
+
+    fn plot(&mut self, x: i32, y: i32, color: u32, coverage: f32, additive: bool) {
+        let index = match self.index(x, y) {
+            Some(index) if coverage > 0.0 => index,
+            _ => return,
+        };
+        let below = f32::from(self.alpha[index]) / 255.0;
+        let (color, covered) = if additive {
+            (
+                add(self.rgb[index], color, coverage),
+                (below + coverage).min(1.0),
+            )
+        } else {
+            over(self.rgb[index], below, color, coverage)
+        };
+        self.rgb[index] = color;
+        self.alpha[index] = (covered * 255.0).round() as u8;
+    }
+
+    fn set(&mut self, index: usize, color: u32, alpha: u8) {
+        self.rgb[index] = color;
+        self.alpha[index] = alpha;
+    }
+
+    fn map_colors(&mut self, apply: impl Fn(u32) -> u32) {
+        self.rgb.iter_mut().for_each(|color| *color = apply(*color));
+    }
+}
+
+// Save ごとに積む描画の状態。画面上の位置は offset + 座標 * scale
+#[derive(Clone, Copy)]
+struct State {
+    x: f64,
+    y: f64,
+    scale: f64,
+    alpha: f32,
+    // "lighter" の間は色を足し合わせる
+    additive: bool,
+    shadow: Option<Shadow>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            x: 0.0,
+            y: 0.0,
+            scale: 1.0,
+            alpha: 1.0,
+            additive: false,
+            shadow: None,
+        }
+    }
+}
+
+impl State {
+    fn apply(&self, x: i16, y: i16) -> (i32, i32) {
+        (
+            (self.x + f64::from(x) * self.scale).round() as i32,
+            (self.y + f64::from(y) * self.scale).round() as i32,
+        )
+    }
+}
+
+// 影は変換に関係なく画面のピクセルでずらす。ぼかしは周りに薄く広げて近似する
+#[derive(Clone, Copy)]
+struct Shadow {
+    color: u32,
+    alpha: f32,
+    offset: (i32, i32),
+    blur: i32,
+}
+
+const BLUR_STRENGTH: f32 = 0.35;
+const BLUR_DIRECTIONS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+impl Shadow {
+    // (ずらす量, 濃さ)。ぼかしがなければずらした 1 か所だけに置く
+    fn spread(&self) -> Vec<(i32, i32, f32)> {
+        let (x, y) = self.offset;
+        if self.blur == 0 {
+            return vec![(x, y, 1.0)];
+        }
+        let radius = (self.blur / 2).max(1);
+        BLUR_DIRECTIONS
+            .iter()
+            .map(|(dx, dy)| (x + dx * radius, y + dy * radius, BLUR_STRENGTH))
+            .collect()
+    }
+}
+
+// デスクトップ版の画像。ブラウザの画像と同じ名前で URL と大きさを返し、複製してもピクセルは共有する
+#[derive(Clone)]
+pub struct Bitmap {
+    src: String,
+    plane: Rc<Plane>,
+}
+
+#[cfg_attr(not(feature = "desktop"), allow(dead_code))]
+impl Bitmap {
+    // 1 ピクセル 4 バイトの RGBA から作る
+    pub fn from_rgba(src: &str, width: usize, height: usize, rgba: &[u8]) -> Self {
+        let mut plane = Plane::new(width, height);
+        rgba.chunks(4).enumerate().for_each(|(index, pixel)| {
+            let color = rgb(pixel[0].into(), pixel[1].into(), pixel[2].into());
+            plane.set(index, color, pixel[3]);
+        });
+        Bitmap {
+            src: src.into(),
+            plane: Rc::new(plane),
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    pub fn decode(src: &str, bytes: &[u8]) -> Result<Self> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder
+            .read_info()
+            .map_err(|err| anyhow!("Could not read {} {:#?}", src, err))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|err| anyhow!("Could not decode {} {:#?}", src, err))?;
+        let pixels = &buffer[..info.buffer_size()];
+        let rgba: Vec<u8> = match info.color_type {
+            png::ColorType::Rgba => pixels.to_vec(),
+            png::ColorType::Rgb => pixels
+                .chunks(3)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks(2)
+                .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+                .collect(),
+            png::ColorType::Grayscale => pixels
+                .iter()
+                .flat_map(|gray| [*gray, *gray, *gray, 255])
+                .collect(),
+            png::ColorType::Indexed => {
+                return Err(anyhow!("Could not expand the palette of {}", src))
+            }
+        };
+        Ok(Bitmap::from_rgba(
+            src,
+            info.width as usize,
+            info.height as usize,
+            &rgba,
+        ))
+    }
+
+    pub fn src(&self) -> String {
+        self.src.clone()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.plane.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.plane.height as u32
+    }
+
+    pub fn natural_width(&self) -> u32 {
+        self.width()
+    }
+
+    pub fn natural_height(&self) -> u32 {
+        self.height()
+    }
+
+    // 各ピクセルの不透明度。当たり判定のマスクを作るのに使う
+    pub fn alpha(&self) -> &[u8] {
+        &self.plane.alpha
+    }
+}
+
+// 描画命令の画像とオフスクリーンからピクセルを読む。ブラウザの画像やキャンバスは読めないので何も写さない
+#[cfg(feature = "desktop")]
+fn image_plane(image: &super::Bitmap) -> Option<&Plane> {
+    Some(&image.plane)
+}
+
+#[cfg(not(feature = "desktop"))]
+fn image_plane(_image: &super::Bitmap) -> Option<&Plane> {
+    None
+}
+
+#[cfg(feature = "desktop")]
+fn surface_plane(surface: &super::Surface) -> Option<Ref<'_, Plane>> {
+    Some(surface.plane.borrow())
+}
+
+#[cfg(not(feature = "desktop"))]
+fn surface_plane(_surface: &super::Surface) -> Option<Ref<'_, Plane>> {
+    None
+}
+
+const PLACEHOLDER_COLOR: u32 = 0xFF00FF;
+// 16px の文字で 1 ドットが 2 ピクセルになる
+const DOTS_PER_EM: f64 = 8.0;
+
+// "#RRGGBB" と "rgb(...)" / "rgba(...)" を (0xRRGGBB, alpha) にする
+fn parse_color(color: &str) -> Option<(u32, f32)> {
+    if let Some(hex) = color.strip_prefix('#') {
+        return u32::from_str_radix(hex, 16).ok().map(|rgb| (rgb, 1.0));
+    }
+    let body = color
+        .strip_prefix("rgba(")
+        .or_else(|| color.strip_prefix("rgb("))?
+        .strip_suffix(')')?;
+    let parts: Vec<f32> = body
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match parts.as_slice() {
+        [r, g, b] => Some((rgb(*r, *g, *b), 1.0)),
+        [r, g, b, a] => Some((rgb(*r, *g, *b), *a)),
+        _ => None,
+    }
+}
+
+fn painted(color: &str) -> (u32, f32) {
+    parse_color(color).unwrap_or((PLACEHOLDER_COLOR, 1.0))
+}
+
+fn rgb(r: f32, g: f32, b: f32) -> u32 {
+    ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}
+
+fn channels(color: u32) -> [f32; 3] {
+    [16, 8, 0].map(|shift| ((color >> shift) & 0xFF) as f32)
+}
+
+fn from_channels([r, g, b]: [f32; 3]) -> u32 {
+    let channel = |value: f32| value.round().clamp(0.0, 255.0);
+    rgb(channel(r), channel(g), channel(b))
+}
+
+// 下の色と不透明度に src を coverage の濃さで重ねた (色, 不透明度)
+fn over(dst: u32, below: f32, src: u32, coverage: f32) -> (u32, f32) {
+    let covered = coverage + below * (1.0 - coverage);
+    if covered <= 0.0 {
+        return (dst, 0.0);
+    }
+    let [dr, dg, db] = channels(dst);
+    let [sr, sg, sb] = channels(src);
+    let mix = |d: f32, s: f32| (s * coverage + d * below * (1.0 - coverage)) / covered;
+    (
+        from_channels([mix(dr, sr), mix(dg, sg), mix(db, sb)]),
+        covered,
+    )
+}
+
+fn add(dst: u32, src: u32, coverage: f32) -> u32 {
+    let [dr, dg, db] = channels(dst);
+    let [sr, sg, sb] = channels(src);
+    from_channels([dr + sr * coverage, dg + sg * coverage, db + sb * coverage])
+}
+
+// 明るさは下の色のまま、色味を tone にする (合成の "color")
+fn with_luminosity(tone: [f32; 3], luminosity: f32) -> [f32; 3] {
+    let luminance = |[r, g, b]: [f32; 3]| 0.3 * r + 0.59 * g + 0.11 * b;
+    let shift = luminosity - luminance(tone);
+    let shifted = tone.map(|channel| channel + shift);
+    let (lowest, highest) = shifted
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), channel| {
+            (low.min(*channel), high.max(*channel))
+        });
+    let middle = luminance(shifted);
+    shifted.map(|channel| {
+        if lowest < 0.0 {
+            middle + (channel - middle) * middle / (middle - lowest)
+        } else if highest > 255.0 {
+            middle + (channel - middle) * (255.0 - middle) / (highest - middle)
+        } else {
+            channel
+        }
+    })
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            plane: RefCell::new(Plane::new(width, height)),
+            states: RefCell::new(vec![State::default()]),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.plane.borrow().width
+    }
+
+    pub fn height(&self) -> usize {
+        self.plane.borrow().height
+    }
+
+    // 0x00RRGGBB の並び。softbuffer にそのまま渡せる
+    pub fn pixels(&self) -> Ref<'_, Vec<u32>> {
+        Ref::map(self.plane.borrow(), |plane| &plane.rgb)
+    }
+
+    fn state(&self) -> State {
+        self.states.borrow().last().copied().unwrap_or_default()
+    }
+
+    fn update_state(&self, change: impl FnOnce(&mut State)) {
+        if let Some(state) = self.states.borrow_mut().last_mut() {
+            change(state);
+        }
+    }
+
+    // shape が塗るピクセルを今の状態で重ねる。影があれば先にずらした位置へ影の色で塗る
+    fn paint(&self, shape: impl Fn(&mut dyn FnMut(i32, i32, u32, f32))) {
+        let state = self.state();
+        let mut plane = self.plane.borrow_mut();
+        if let Some(shadow) = state.shadow {
+            for (dx, dy, strength) in shadow.spread() {
+                let opacity = shadow.alpha * strength * state.alpha;
+                shape(&mut |x, y, _, coverage| {
+                    plane.plot(
+                        x + dx,
+                        y + dy,
+                        shadow.color,
+                        coverage * opacity,
+                        state.additive,
+                    )
+                });
+            }
+        }
+        shape(&mut |x, y, color, coverage| {
+            plane.plot(x, y, color, coverage * state.alpha, state.additive)
+        });
+    }
+
+    // 画面上の範囲。画面の外にはみ出した分は切り詰める
+    fn visible(&self, from: i32, to: i32, size: usize) -> std::ops::Range<i32> {
+        from.min(to).max(0)..from.max(to).min(size as i32)
+    }
+
+    fn fill(&self, rect: &Rect, color: u32, alpha: f32) {
+        let state = self.state();
+        let (left, top) = state.apply(rect.x(), rect.y());
+        let (right, bottom) = state.apply(rect.right(), rect.bottom());
+        let (columns, rows) = (
+            self.visible(left, right, self.width()),
+            self.visible(top, bottom, self.height()),
+        );
+        self.paint(|plot| {
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    plot(x, y, color, alpha);
+                }
+            }
+        });
+    }
+
+    fn outline(&self, rect: &Rect, color: u32, alpha: f32) {
+        let edges = [
+            Rect::new_from_x_y(rect.x(), rect.y(), rect.width, 1),
+            Rect::new_from_x_y(rect.x(), rect.bottom() - 1, rect.width, 1),
+            Rect::new_from_x_y(rect.x(), rect.y() + 1, 1, rect.height - 2),
+            Rect::new_from_x_y(rect.right() - 1, rect.y() + 1, 1, rect.height - 2),
+        ];
+        edges.iter().for_each(|edge| self.fill(edge, color, alpha));
+    }
+
+    fn ellipse(&self, center: &Point, radius_x: i16, radius_y: i16, color: u32, alpha: f32) {
+        let state = self.state();
+        let (center_x, center_y) = state.apply(center.x, center.y);
+        let scaled = |radius: i16| (f64::from(radius) * state.scale).round() as i32;
+        let (radius_x, radius_y) = (scaled(radius_x), scaled(radius_y));
+        self.paint(|plot| {
+            for dy in -radius_y..=radius_y {
+                for dx in -radius_x..=radius_x {
+                    if dx * dx * radius_y * radius_y + dy * dy * radius_x * radius_x
+                        <= radius_x * radius_x * radius_y * radius_y
+                    {
+                        plot(center_x + dx, center_y + dy, color, alpha);
+                    }
+                }
+            }
+        });
+    }
+
+    // clearRect と同じく変換はかかるが、不透明度や影に関係なく透明にする
+    fn clear(&self, rect: &Rect) {
+        let state = self.state();
+        let (left, top) = state.apply(rect.x(), rect.y());
+        let (right, bottom) = state.apply(rect.right(), rect.bottom());
+        let (columns, rows) = (
+            self.visible(left, right, self.width()),
+            self.visible(top, bottom, self.height()),
+        );
+        let mut plane = self.plane.borrow_mut();
+        for y in rows {
+            for x in columns.clone() {
+                if let Some(index) = plane.index(x, y) {
+                    plane.set(index, 0, 0);
+                }
+            }
+        }
+    }
+
+    // source の frame の範囲を destination に引き伸ばして写す。幅や高さが負なら反転する
+    fn blit(&self, source: &Plane, frame: &Rect, destination: &Rect) {
+        let state = self.state();
+        let (left, top) = state.apply(destination.x(), destination.y());
+        let (right, bottom) = state.apply(destination.right(), destination.bottom());
+        if left == right || top == bottom {
+            return;
+        }
+        let x_step = f64::from(frame.width) / f64::from(right - left);
+        let y_step = f64::from(frame.height) / f64::from(bottom - top);
+        let (columns, rows) = (
+            self.visible(left, right, self.width()),
+            self.visible(top, bottom, self.height()),
+        );
+        self.paint(|plot| {
+            for y in rows.clone() {
+                let v = f64::from(frame.y()) + (f64::from(y - top) + 0.5) * y_step;
+                for x in columns.clone() {
+                    let u = f64::from(frame.x()) + (f64::from(x - left) + 0.5) * x_step;
+                    if let Some((color, opacity)) = source.sample(u, v) {
+                        plot(x, y, color, opacity);
+                    }
+                }
+            }
+        });
+    }
+
+    fn blit_entire(&self, source: &Plane, position: &Point) {
+        let (width, height) = (source.width as i16, source.height as i16);
+        self.blit(
+            source,
+            &Rect::new_from_x_y(0, 0, width, height),
+            &Rect::new_from_x_y(position.x, position.y, width, height),
+        );
+    }
+
+    // 組み込みのフォントで描く。キャンバスの textBaseline = "middle" と同じく position を縦の中心にする
+    fn text(&self, text: &str, position: &Point, style: &TextStyle) {
+        let (color, alpha) = painted(&style.color);
+        let state = self.state();
+        let dot = f64::from(style.size) / DOTS_PER_EM * state.scale;
+        let advance = (GLYPH_WIDTH + 1) as f64 * dot;
+        let width = (advance * text.chars().count() as f64 - dot).max(0.0);
+        let (x, y) = state.apply(position.x, position.y);
+        let left = f64::from(x)
+            - match style.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => width / 2.0,
+                TextAlign::Right => width,
+            };
+        let top = f64::from(y) - GLYPH_HEIGHT as f64 * dot / 2.0;
+        let span = |from: f64| (from.round() as i32)..((from + dot).round() as i32);
+        self.paint(|plot| {
+            for (index, character) in text.chars().enumerate() {
+                let glyph = match font::glyph(character) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                let origin = left + advance * index as f64;
+                for (row, bits) in glyph.iter().enumerate() {
+                    for column in 0..GLYPH_WIDTH {
+                        if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                            continue;
+                        }
+                        for y in span(top + row as f64 * dot) {
+                            for x in span(origin + column as f64 * dot) {
+                                plot(x, y, color, alpha);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Renderer がフレームの前後に画面全体へかける処理
+#[cfg_attr(not(feature = "desktop"), allow(dead_code))]
+impl Framebuffer {
+    // キャンバスの大きさを変えたときと同じく、中身と状態を捨てる
+    pub fn resize(&self, width: usize, height: usize) {
+        self.plane.replace(Plane::new(width, height));
+        self.reset(1.0);
+    }
+
+    // 積んだ状態を捨てて、scale 倍するだけの状態に戻す
+    pub fn reset(&self, scale: f64) {
+        self.states.replace(vec![State {
+            scale,
+            ..State::default()
+        }]);
+    }
+
+    // 解像度を落として描いた source を画面いっぱいに引き伸ばす
+    pub fn stretch(&self, source: &Framebuffer) {
+        let frame = Rect::new_from_x_y(0, 0, source.width() as i16, source.height() as i16);
+        let destination = Rect::new_from_x_y(0, 0, self.width() as i16, self.height() as i16);
+        self.blit(&source.plane.borrow(), &frame, &destination);
+    }
+
+    // 合成の "multiply" で color を全体に重ねる
+    pub fn multiply(&self, color: &str) {
+        let tone = channels(painted(color).0);
+        self.plane.borrow_mut().map_colors(|color| {
+            let [r, g, b] = channels(color);
+            from_channels([
+                r * tone[0] / 255.0,
+                g * tone[1] / 255.0,
+                b * tone[2] / 255.0,
+            ])
+        });
+    }
+
+    // 合成の "color" で、明るさはそのままに全体を color の色味にする
+    pub fn colorize(&self, color: &str) {
+        let tone = channels(painted(color).0);
+        self.plane.borrow_mut().map_colors(|color| {
+            let [r, g, b] = channels(color);
+            from_channels(with_luminosity(tone, 0.3 * r + 0.59 * g + 0.11 * b))
+        });
+    }
+
+    // 中心から離れるほど color を濃く重ねる。内側の半径は短い辺の半分の半分
+    pub fn vignette(&self, color: &str) {
+        let (tone, alpha) = painted(color);
+        let mut plane = self.plane.borrow_mut();
+        let (center_x, center_y) = (plane.width as f64 / 2.0, plane.height as f64 / 2.0);
+        let inner = center_x.min(center_y) * 0.5;
+        let outer = center_x.hypot(center_y);
+        for y in 0..plane.height {
+            for x in 0..plane.width {
+                let distance = (x as f64 + 0.5 - center_x).hypot(y as f64 + 0.5 - center_y);
+                let ratio = ((distance - inner) / (outer - inner)).clamp(0.0, 1.0) as f32;
+                plane.plot(x as i32, y as i32, tone, alpha * ratio, false);
+            }
+        }
+    }
+
+    // 今の中身を buffer に写し、slice_height の行ごとに中央ほど bulge だけ横に広げて描き直す
+    pub fn crt(&self, buffer: &Framebuffer, slice_height: usize, bulge: f64) {
+        buffer.plane.replace(self.plane.borrow().clone());
+        let source = buffer.plane.borrow();
+        let mut plane = self.plane.borrow_mut();
+        let (width, center_y) = (plane.width as f64, plane.height as f64 / 2.0);
+        for y in 0..plane.height {
+            let slice = (y / slice_height * slice_height) as f64;
+            let distance = (slice + slice_height as f64 / 2.0 - center_y) / center_y;
+            let slice_width = width * (1.0 + bulge * (1.0 - distance * distance));
+            let left = (width - slice_width) / 2.0;
+            for x in 0..plane.width {
+                let index = y * plane.width + x;
+                let u = (x as f64 + 0.5 - left) * width / slice_width;
+                match source.sample(u, y as f64) {
+                    Some((color, alpha)) => plane.set(index, color, (alpha * 255.0) as u8),
+                    None => plane.set(index, 0, 0),
+                }
+            }
+        }
+    }
+}
+
+impl DrawBackend for Framebuffer {
+    fn execute(&self, command: &DrawCommand) {
+        match command {
+            DrawCommand::Clear(rect) => self.clear(rect),
+            DrawCommand::Image {
+                image,
+                frame,
+                destination,
+            } => match image_plane(image) {
+                Some(plane) => self.blit(plane, frame, destination),
+                None => self.outline(destination, PLACEHOLDER_COLOR, 1.0),
+            },
+            DrawCommand::EntireImage { image, position } => {
+                if let Some(plane) = image_plane(image) {
+                    self.blit_entire(plane, position);
+                }
+            }
+            DrawCommand::Canvas { canvas, position } => {
+                if let Some(plane) = surface_plane(canvas) {
+                    self.blit_entire(&plane, position);
+                }
+            }
+            DrawCommand::FillRect { rect, color } => {
+                let (color, alpha) = painted(color);
+                self.fill(rect, color, alpha);
+            }
+            DrawCommand::FillCircle {
+                center,
+                radius,
+                color,
+            } => {
+                let (color, alpha) = painted(color);
+                self.ellipse(center, *radius, *radius, color, alpha);
+            }
+            DrawCommand::FillEllipse {
+                center,
+                radius_x,
+                radius_y,
+                color,
+            } => {
+                let (color, alpha) = painted(color);
+                self.ellipse(center, *radius_x, *radius_y, color, alpha);
+            }
+            DrawCommand::StrokeRect { rect, color } => {
+                let (color, alpha) = painted(color);
+                self.outline(rect, color, alpha);
+            }
+            DrawCommand::Text {
+                text,
+                position,
+                style,
+            } => self.text(text, position, style),
+            DrawCommand::Save => {
+                let state = self.state();
+                self.states.borrow_mut().push(state);
+            }
+            DrawCommand::Translate(delta) => self.update_state(|state| {
+                state.x += f64::from(delta.x) * state.scale;
+                state.y += f64::from(delta.y) * state.scale;
+            }),
+            DrawCommand::Scale(factor) => self.update_state(|state| state.scale *= factor),
+            DrawCommand::Shadow {
+                color,
+                blur,
+                offset,
+            } => self.update_state(|state| {
+                state.shadow =
+                    parse_color(color)
+                        .filter(|(_, alpha)| *alpha > 0.0)
+                        .map(|(color, alpha)| Shadow {
+                            color,
+                            alpha,
+                            offset: (offset.x.into(), offset.y.into()),
+                            blur: (*blur).into(),
+                        });
+            }),
+            DrawCommand::Blend { alpha, operation } => self.update_state(|state| {
+                state.alpha = *alpha as f32;
+                state.additive = *operation == "lighter";
+            }),
+            DrawCommand::Restore => {
+                let mut states = self.states.borrow_mut();
+                if states.len() > 1 {
+                    states.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framebuffer_fills_translated_and_blended_rects() {
+        let framebuffer = Framebuffer::new(4, 4);
+        framebuffer.execute(&DrawCommand::FillRect {
+            rect: Rect::new_from_x_y(0, 0, 4, 4),
+            color: "#FFFFFF".into(),
+        });
+        framebuffer.execute(&DrawCommand::Save);
+        framebuffer.execute(&DrawCommand::Translate(Point { x: 2, y: 2 }));
+        framebuffer.execute(&DrawCommand::FillRect {
+            rect: Rect::new_from_x_y(0, 0, 1, 1),
+            color: "rgba(0, 0, 0, 0.5)".into(),
+        });
+        framebuffer.execute(&DrawCommand::Restore);
+
+        let pixels = framebuffer.pixels();
+        assert_eq!(pixels.len(), framebuffer.width() * framebuffer.height());
+        assert_eq!(pixels[0], 0xFFFFFF);
+        assert_eq!(pixels[2 * 4 + 2], 0x808080);
+    }
+
+    #[test]
+    fn framebuffer_scales_rects_and_later_translations() {
+        let framebuffer = Framebuffer::new(4, 4);
+        framebuffer.execute(&DrawCommand::Save);
+        framebuffer.execute(&DrawCommand::Scale(0.5));
+        framebuffer.execute(&DrawCommand::Translate(Point { x: 4, y: 0 }));
+        framebuffer.execute(&DrawCommand::FillRect {
+            rect: Rect::new_from_x_y(0, 0, 4, 4),
+            color: "#FFFFFF".into(),
+        });
+        framebuffer.execute(&DrawCommand::Restore);
+
+        let pixels = framebuffer.pixels();
+        assert_eq!(pixels[1], 0);
+        assert_eq!(pixels[2], 0xFFFFFF);
+        assert_eq!(pixels[4 + 3], 0xFFFFFF);
+        assert_eq!(pixels[2 * 4 + 2], 0);
+    }
+
+    #[test]
+    fn framebuffer_blits_stretched_flipped_and_transparent_images() {
+        // 左が赤、右が透明な 2x1 の画像
+        let image = Bitmap::from_rgba("test.png", 2, 1, &[255, 0, 0, 255, 0, 0, 255, 0]);
+        let framebuffer = Framebuffer::new(4, 2);
+        framebuffer.execute(&DrawCommand::FillRect {
+            rect: Rect::new_from_x_y(0, 0, 4, 2),
+            color: "#00FF00".into(),
+        });
+        let frame = Rect::new_from_x_y(0, 0, 2, 1);
+        framebuffer.blit(&image.plane, &frame, &Rect::new_from_x_y(0, 0, 4, 1));
+        framebuffer.blit(&image.plane, &frame, &Rect::new_from_x_y(4, 1, -4, 1));
+
+        let pixels = framebuffer.pixels();
+        assert_eq!(&pixels[..4], &[0xFF0000, 0xFF0000, 0x00FF00, 0x00FF00]);
+        assert_eq!(&pixels[4..], &[0x00FF00, 0x00FF00, 0xFF0000, 0xFF0000]);
+    }
+
+    #[test]
+    fn framebuffer_draws_text_around_the_aligned_position() {
+        let framebuffer = Framebuffer::new(40, 20);
+        framebuffer.execute(&DrawCommand::Text {
+            text: "I".into(),
+            position: Point { x: 20, y: 10 },
+            style: TextStyle::new("#FFFFFF").align(TextAlign::Center),
+        });
+
+        let pixels = framebuffer.pixels();
+        let lit: Vec<(usize, usize)> = (0..pixels.len())
+            .filter(|index| pixels[*index] == 0xFFFFFF)
+            .map(|index| (index % 40, index / 40))
+            .collect();
+        let (xs, ys): (Vec<usize>, Vec<usize>) = lit.iter().copied().unzip();
+        // 16px では 5x7 のドットが 10x14 ピクセルになる
+        assert_eq!(xs.iter().min(), Some(&17));
+        assert_eq!(xs.iter().max(), Some(&22));
+        assert_eq!((ys.iter().min(), ys.iter().max()), (Some(&3), Some(&16)));
+    }
+
+    #[test]
+    fn framebuffer_shadows_sit_under_the_shape() {
+        let framebuffer = Framebuffer::new(4, 1);
+        framebuffer.execute(&DrawCommand::Shadow {
+            color: "#0000FF".into(),
+            blur: 0,
+            offset: Point { x: 1, y: 0 },
+        });
+        framebuffer.execute(&DrawCommand::FillRect {
+            rect: Rect::new_from_x_y(0, 0, 2, 1),
+            color: "#FFFFFF".into(),
+        });
+
+        assert_eq!(
+            framebuffer.pixels().as_slice(),
+            &[0xFFFFFF, 0xFFFFFF, 0x0000FF, 0]
+        );
+    }
+}
@@ -1,22 +1,44 @@
-use crate::{browser, sound};
+use crate::browser;
+use crate::draw::{
+    self, Bitmap, Color, Context, DrawBackend, DrawCommand, RenderModifiers, Surface, TextStyle,
+};
+use crate::golden::{self, GoldenRecorder};
+use crate::latency::{self, LatencyProbe};
+#[cfg(not(feature = "desktop"))]
+use crate::mixer::{Bus, DuckingRule, BUSES, DEFAULT_DUCKING};
+use crate::platform;
+#[cfg(not(feature = "desktop"))]
+use crate::sound;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use browser::LoopClosure;
 use futures::channel::mpsc::unbounded;
 use futures::channel::mpsc::UnboundedReceiver;
+#[cfg(not(feature = "desktop"))]
 use futures::channel::oneshot::channel;
 use serde::Deserialize;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
+#[cfg(not(feature = "desktop"))]
 use std::sync::Mutex;
+#[cfg(not(feature = "desktop"))]
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
+#[cfg(not(feature = "desktop"))]
+use web_sys::js_sys::ArrayBuffer;
+#[cfg(not(feature = "desktop"))]
 use web_sys::AudioBuffer;
+#[cfg(not(feature = "desktop"))]
+use web_sys::AudioBufferSourceNode;
+#[cfg(not(feature = "desktop"))]
 use web_sys::AudioContext;
-use web_sys::CanvasRenderingContext2d;
+#[cfg(not(feature = "desktop"))]
+use web_sys::GainNode;
 use web_sys::HtmlElement;
-use web_sys::HtmlImageElement;
+
+#[cfg(feature = "desktop")]
+pub use crate::desktop::audio::{Audio, Sound};
 
 #[derive(Deserialize, Clone)]
 pub struct SheetRect {
@@ -33,77 +55,592 @@ pub struct Cell {
     pub sprite_source_size: SheetRect,
 }
 
+impl SheetRect {
+    fn to_rect(&self) -> Rect {
+        Rect::new_from_x_y(self.x, self.y, self.w, self.h)
+    }
+}
+
+fn default_duration() -> u8 {
+    3
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AnimationMeta {
+    pub frames: Vec<String>,
+    #[serde(default = "default_duration")]
+    pub duration: u8,
+}
+
+// 複数のセルを横に並べて一つの物体として扱う (浮島など)
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompositeMeta {
+    pub cells: Vec<String>,
+    pub bounding_boxes: Vec<SheetRect>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SheetMeta {
+    pub animations: HashMap<String, AnimationMeta>,
+    pub composites: HashMap<String, CompositeMeta>,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Sheet {
     pub frames: HashMap<String, Cell>,
+    #[serde(default)]
+    pub meta: SheetMeta,
+}
+
+impl Sheet {
+    // 差し替え前に必要なフレームが全て揃っているか確認する
+    pub fn validate_frames<'a>(&self, mut names: impl Iterator<Item = &'a str>) -> Result<()> {
+        match names.find(|name| !self.frames.contains_key(*name)) {
+            Some(missing) => Err(anyhow!("Sheet is missing frame {}", missing)),
+            None => Ok(()),
+        }
+    }
+}
+
+// 名前から一度だけ引いておく、シート内のフレーム番号
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteHandle(u16);
+
+pub struct Animation {
+    handles: Vec<SpriteHandle>,
+    duration: u8,
+}
+
+impl Animation {
+    // 経過フレーム数から表示するセルを選ぶ
+    pub fn frame_at(&self, tick: u8) -> Option<SpriteHandle> {
+        self.handles
+            .get((tick / self.duration.max(1)) as usize)
+            .copied()
+    }
+}
+
+pub struct Composite {
+    handles: Vec<SpriteHandle>,
+    bounding_boxes: Vec<Rect>,
+}
+
+impl Composite {
+    pub fn handles(&self) -> &[SpriteHandle] {
+        &self.handles
+    }
+
+    pub fn bounding_boxes(&self) -> &[Rect] {
+        &self.bounding_boxes
+    }
 }
 
 pub struct SpriteSheet {
-    sheet: Sheet,
-    image: HtmlImageElement,
+    cells: Vec<Cell>,
+    animations: HashMap<String, Rc<Animation>>,
+    composites: HashMap<String, Rc<Composite>>,
+    image: Bitmap,
 }
 
 impl SpriteSheet {
-    pub fn new(sheet: Sheet, image: HtmlImageElement) -> Self {
-        SpriteSheet { sheet, image }
+    pub fn new(sheet: Sheet, image: Bitmap) -> Self {
+        let mut frames: Vec<(String, Cell)> = sheet.frames.into_iter().collect();
+        frames.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+        let handles: HashMap<String, SpriteHandle> = frames
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| (name.clone(), SpriteHandle(index as u16)))
+            .collect();
+        let cells = frames.into_iter().map(|(_, cell)| cell).collect();
+
+        // セルが欠けているものは登録しない (validate_* で検出できるように)
+        let resolve = |names: &[String]| -> Option<Vec<SpriteHandle>> {
+            names
+                .iter()
+                .map(|name| handles.get(name).copied())
+                .collect()
+        };
+        let animations = sheet
+            .meta
+            .animations
+            .iter()
+            .filter_map(|(name, animation)| {
+                resolve(&animation.frames).map(|frame_handles| {
+                    let animation = Animation {
+                        handles: frame_handles,
+                        duration: animation.duration,
+                    };
+                    (name.clone(), Rc::new(animation))
+                })
+            })
+            .collect();
+        let composites = sheet
+            .meta
+            .composites
+            .iter()
+            .filter_map(|(name, composite)| {
+                resolve(&composite.cells).map(|cell_handles| {
+                    let composite = Composite {
+                        handles: cell_handles,
+                        bounding_boxes: composite
+                            .bounding_boxes
+                            .iter()
+                            .map(SheetRect::to_rect)
+                            .collect(),
+                    };
+                    (name.clone(), Rc::new(composite))
+                })
+            })
+            .collect();
+
+        SpriteSheet {
+            cells,
+            animations,
+            composites,
+            image,
+        }
+    }
+
+    pub fn animation(&self, name: &str) -> Option<Rc<Animation>> {
+        self.animations.get(name).cloned()
+    }
+
+    pub fn composite(&self, name: &str) -> Option<Rc<Composite>> {
+        self.composites.get(name).cloned()
+    }
+
+    pub fn composite_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.composites.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn validate_animations<'a>(&self, mut names: impl Iterator<Item = &'a str>) -> Result<()> {
+        match names.find(|name| !self.animations.contains_key(*name)) {
+            Some(missing) => Err(anyhow!("Sheet is missing animation {}", missing)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn validate_composites<'a>(&self, mut names: impl Iterator<Item = &'a str>) -> Result<()> {
+        match names.find(|name| !self.composites.contains_key(*name)) {
+            Some(missing) => Err(anyhow!("Sheet is missing composite {}", missing)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn cell_at(&self, handle: SpriteHandle) -> &Cell {
+        &self.cells[handle.0 as usize]
     }
 
-    pub fn cell(&self, name: &str) -> Option<&Cell> {
-        self.sheet.frames.get(name)
+    pub fn draw_handle(&self, renderer: &Renderer, handle: SpriteHandle, destination: &Rect) {
+        self.draw_handle_ex(
+            renderer,
+            handle,
+            destination,
+            Scale::IDENTITY,
+            &destination.position,
+        );
     }
 
-    pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) {
-        renderer.draw_image(&self.image, source, destination);
+    pub fn draw_handle_ex(
+        &self,
+        renderer: &Renderer,
+        handle: SpriteHandle,
+        destination: &Rect,
+        scale: Scale,
+        anchor: &Point,
+    ) {
+        let frame = &self.cell_at(handle).frame;
+        renderer.draw_image_ex(
+            &self.image,
+            &Rect::new_from_x_y(frame.x, frame.y, frame.w, frame.h),
+            destination,
+            scale,
+            anchor,
+        );
     }
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
 }
 
+// ワールド座標と画面座標の変換。position は画面の左上に映るワールドの点で、
+// zoom が 1 より小さいと広い範囲を縮めて映す
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Point,
+    pub zoom: f64,
+}
+
+impl Camera {
+    pub fn new(position: Point) -> Self {
+        Camera {
+            position,
+            zoom: 1.0,
+        }
+    }
+
+    // 画面上の focus に映っているワールドの点を動かさずに拡大率を変える
+    pub fn zoom_at(&self, zoom: f64, focus: &Point) -> Self {
+        let world = self.to_world(focus);
+        Camera {
+            position: Point {
+                x: world.x - (f64::from(focus.x) / zoom).round() as i16,
+                y: world.y - (f64::from(focus.y) / zoom).round() as i16,
+            },
+            zoom,
+        }
+    }
+
+    pub fn to_screen(self, point: &Point) -> Point {
+        Point {
+            x: (f64::from(point.x - self.position.x) * self.zoom).round() as i16,
+            y: (f64::from(point.y - self.position.y) * self.zoom).round() as i16,
+        }
+    }
+
+    pub fn to_world(self, point: &Point) -> Point {
+        Point {
+            x: self.position.x + (f64::from(point.x) / self.zoom).round() as i16,
+            y: self.position.y + (f64::from(point.y) / self.zoom).round() as i16,
+        }
+    }
+
+    // width x height の画面に映るワールドの範囲
+    pub fn view(&self, width: i16, height: i16) -> Rect {
+        Rect::new(
+            self.position,
+            (f64::from(width) / self.zoom).round() as i16,
+            (f64::from(height) / self.zoom).round() as i16,
+        )
+    }
+}
+
+// 画像の拡大率。x と y を別々にすると縦横に伸び縮みする
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scale {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Scale {
+    pub const IDENTITY: Scale = Scale { x: 1.0, y: 1.0 };
+
+    pub fn new(x: f64, y: f64) -> Self {
+        Scale { x, y }
+    }
+
+    pub fn uniform(factor: f64) -> Self {
+        Scale::new(factor, factor)
+    }
+
+    // anchor を動かさずに rect を拡大縮小する。絵と当たり判定の両方をこれで合わせる
+    pub fn apply(self, rect: &Rect, anchor: &Point) -> Rect {
+        if self == Scale::IDENTITY {
+            return *rect;
+        }
+        let scale = |position: i16, anchor: i16, factor: f64| {
+            anchor + (f64::from(position - anchor) * factor).round() as i16
+        };
+        let x = scale(rect.x(), anchor.x, self.x);
+        let y = scale(rect.y(), anchor.y, self.y);
+        Rect::new_from_x_y(
+            x,
+            y,
+            scale(rect.right(), anchor.x, self.x) - x,
+            scale(rect.bottom(), anchor.y, self.y) - y,
+        )
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::IDENTITY
+    }
+}
+
+// 画面の揺れ。amplitude ピクセルから始めて、毎フレーム decay 倍ずつ収まっていく
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shake {
+    amplitude: f32,
+    decay: f32,
+    frame: u32,
+}
+
+// これより小さくなったら止める
+const SHAKE_MINIMUM: f32 = 0.5;
+
+impl Shake {
+    pub fn new(amplitude: f32, decay: f32) -> Self {
+        Shake {
+            amplitude,
+            decay,
+            frame: 0,
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.is_active() {
+            self.amplitude *= self.decay;
+            self.frame += 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.amplitude >= SHAKE_MINIMUM
+    }
+
+    // 乱数を使うとゴールデンテストの展開が変わるので、周期の違う sin と cos で揺らす
+    pub fn offset(&self) -> Point {
+        if !self.is_active() {
+            return Point::default();
+        }
+        let phase = self.frame as f32;
+        Point {
+            x: (self.amplitude * (phase * 2.1).sin()).round() as i16,
+            y: (self.amplitude * (phase * 1.7).cos()).round() as i16,
+        }
+    }
+
+    pub fn apply(&self, camera: Camera) -> Camera {
+        let offset = self.offset();
+        Camera {
+            position: Point {
+                x: camera.position.x + offset.x,
+                y: camera.position.y + offset.y,
+            },
+            ..camera
+        }
+    }
+}
+
 #[async_trait(?Send)]
 pub trait Game {
     async fn initialize(&self) -> Result<Box<dyn Game>>;
     fn update(&mut self, keystate: &KeyState);
     fn draw(&self, renderer: &Renderer);
+    // 1.0 より小さいとゲーム全体がゆっくり進む
+    fn time_scale(&self) -> f32 {
+        1.0
+    }
+    // Escape で止めてよい場面なら true。止まっている間は update を呼ばずに draw_paused を重ねる
+    fn can_pause(&self) -> bool {
+        false
+    }
+    fn draw_paused(&self, _renderer: &Renderer) {}
+    // 止まっている間に受け付ける操作
+    fn update_paused(&mut self, _keystate: &KeyState) {}
+    // 直前のフレームの更新と描画にかかった時間 (ms)
+    fn record_frame_time(&mut self, _frame_time: f32) {}
+    // Some なら描画をこの FPS までに抑える
+    fn frame_rate_cap(&self) -> Option<f64> {
+        None
+    }
 }
 
 const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
+// タブの切り替えで BGM を絞る、戻すのにかける秒数
+#[cfg(not(feature = "desktop"))]
+const BACKGROUND_FADE: f64 = 0.5;
+// ダッキングで絞った音量を戻すのにかける秒数
+#[cfg(not(feature = "desktop"))]
+const DUCK_RELEASE: f64 = 0.4;
+const PAUSE_KEY: &str = "Escape";
 pub struct GameLoop {
     last_frame: f64,
     accumulated_delta: f32,
+    paused: bool,
+    last_render: f64,
+    metrics: FrameMetrics,
+    scaler: ResolutionScaler,
+    latency: Option<LatencyProbe>,
+}
+
+#[derive(Default)]
+pub struct FrameMetrics {
+    average_frame_time: f32,
+}
+
+impl FrameMetrics {
+    fn record(&mut self, frame_time: f32) {
+        const SMOOTHING: f32 = 0.1;
+        self.average_frame_time += (frame_time - self.average_frame_time) * SMOOTHING;
+    }
+
+    pub fn average_frame_time(&self) -> f32 {
+        self.average_frame_time
+    }
+}
+
+const MINIMUM_RESOLUTION_SCALE: f64 = 0.5;
+const RESOLUTION_SCALE_STEP: f64 = 0.25;
+const FRAMES_BEFORE_DOWNSCALE: u32 = 60;
+const FRAMES_BEFORE_UPSCALE: u32 = 180;
+
+// 処理時間が予算を超え続けたら解像度を下げ、余裕が続いたら元に戻す
+struct ResolutionScaler {
+    scale: f64,
+    over_budget_frames: u32,
+    under_budget_frames: u32,
+}
+
+impl ResolutionScaler {
+    fn new() -> Self {
+        ResolutionScaler {
+            scale: 1.0,
+            over_budget_frames: 0,
+            under_budget_frames: 0,
+        }
+    }
+
+    fn update(&mut self, average_frame_time: f32) -> Option<f64> {
+        if average_frame_time > FRAME_SIZE {
+            self.over_budget_frames += 1;
+            self.under_budget_frames = 0;
+        } else if average_frame_time < FRAME_SIZE / 2.0 {
+            self.under_budget_frames += 1;
+            self.over_budget_frames = 0;
+        } else {
+            self.over_budget_frames = 0;
+            self.under_budget_frames = 0;
+        }
+
+        let scale = if self.over_budget_frames >= FRAMES_BEFORE_DOWNSCALE {
+            (self.scale - RESOLUTION_SCALE_STEP).max(MINIMUM_RESOLUTION_SCALE)
+        } else if self.under_budget_frames >= FRAMES_BEFORE_UPSCALE {
+            (self.scale + RESOLUTION_SCALE_STEP).min(1.0)
+        } else {
+            return None;
+        };
+
+        self.over_budget_frames = 0;
+        self.under_budget_frames = 0;
+        if scale == self.scale {
+            None
+        } else {
+            self.scale = scale;
+            Some(scale)
+        }
+    }
 }
 
 type SharedLoopClosure = Rc<RefCell<Option<LoopClosure>>>;
 
 impl GameLoop {
     pub async fn start(game: impl Game + 'static) -> Result<()> {
-        let mut keyevent_receiver = prepare_input()?;
+        if golden::requested() {
+            return golden::run().await;
+        }
+
+        let mut keyevent_receiver = platform::current().input()?;
+        let (visibility_sender, mut visibility_receiver) = unbounded();
+        browser::on_visibility_change(move |hidden| {
+            let _ = visibility_sender.unbounded_send(hidden);
+        })?;
         let mut game = game.initialize().await?;
         let mut game_loop = GameLoop {
             last_frame: browser::now()?,
             accumulated_delta: 0.0,
+            paused: false,
+            last_render: 0.0,
+            metrics: FrameMetrics::default(),
+            scaler: ResolutionScaler::new(),
+            latency: latency::requested().then(LatencyProbe::new),
         };
 
-        let renderer = Renderer {
-            context: browser::context()?,
-        };
+        let renderer = Renderer::new(browser::context()?)?;
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
         let g = f.clone();
         let mut keystate = KeyState::new();
+        let mut input_queue = VecDeque::new();
 
         *g.borrow_mut() = Some(browser::create_raf_closure(move |perf: f64| {
-            process_input(&mut keystate, &mut keyevent_receiver);
-            game_loop.accumulated_delta += (perf - game_loop.last_frame) as f32;
-            while game_loop.accumulated_delta > FRAME_SIZE {
+            queue_input(&mut input_queue, &mut keyevent_receiver);
+            // ゲームパッドに対応していないブラウザでは何もしない
+            if let Ok(buttons) = browser::pressed_gamepad_buttons() {
+                keystate.set_gamepad_buttons(&buttons);
+            }
+            // タブが隠れていた間の時間は進めず、止められる場面なら止めたまま戻る
+            let mut was_hidden = false;
+            while let Ok(Some(hidden)) = visibility_receiver.try_next() {
+                was_hidden |= hidden;
+            }
+            if was_hidden {
+                game_loop.last_frame = perf;
+                game_loop.paused |= game.can_pause();
+            }
+            if game_loop.paused {
+                apply_input(&mut keystate, &mut input_queue, perf);
+                game_loop.update_pause(keystate.is_just_pressed(PAUSE_KEY), game.can_pause());
+                if game_loop.paused {
+                    game.update_paused(&keystate);
+                }
+                keystate.clear_just_pressed();
+            }
+            let time_scale = game.time_scale();
+            if !game_loop.paused {
+                game_loop.accumulated_delta += (perf - game_loop.last_frame) as f32 * time_scale;
+            }
+            while !game_loop.paused && game_loop.accumulated_delta > FRAME_SIZE {
+                // この update が受け持つ時間の終わり。それまでに起きた入力だけを反映する
+                let tick_end =
+                    perf - ((game_loop.accumulated_delta - FRAME_SIZE) / time_scale) as f64;
+                apply_input(&mut keystate, &mut input_queue, tick_end);
+                game_loop.update_pause(keystate.is_just_pressed(PAUSE_KEY), game.can_pause());
+                if game_loop.paused {
+                    keystate.clear_just_pressed();
+                    break;
+                }
+                let pressed_at = keystate.just_pressed_at();
                 game.update(&keystate);
+                if let (Some(probe), Some(pressed_at)) = (&mut game_loop.latency, pressed_at) {
+                    probe.consumed(pressed_at, browser::now().unwrap_or(perf));
+                }
+                keystate.clear_just_pressed();
                 game_loop.accumulated_delta -= FRAME_SIZE;
             }
             game_loop.last_frame = perf;
+            // 上限より早く来たフレームは描かずに次を待つ。1ms は RAF の揺れの分
+            if game
+                .frame_rate_cap()
+                .is_some_and(|fps| perf - game_loop.last_render < 1000.0 / fps - 1.0)
+            {
+                if let Err(err) = browser::request_animation_frame(f.borrow().as_ref().unwrap()) {
+                    log!("Could not request the next frame {:#?}", err);
+                }
+                return;
+            }
+            game_loop.last_render = perf;
+            renderer.begin_frame();
             game.draw(&renderer);
+            if game_loop.paused {
+                game.draw_paused(&renderer);
+            }
+            if let Some(probe) = &game_loop.latency {
+                probe.draw(&renderer);
+            }
+            renderer.end_frame();
+            if let Some(probe) = &mut game_loop.latency {
+                probe.rendered(browser::now().unwrap_or(perf));
+            }
+
+            let frame_time = browser::now().unwrap_or(perf) - perf;
+            game_loop.metrics.record(frame_time as f32);
+            game.record_frame_time(frame_time as f32);
+            if let Some(scale) = game_loop
+                .scaler
+                .update(game_loop.metrics.average_frame_time())
+            {
+                renderer.set_resolution_scale(scale);
+            }
             browser::request_animation_frame(f.borrow().as_ref().unwrap());
         }));
 
@@ -115,9 +652,22 @@ impl GameLoop {
 
         Ok(())
     }
+
+    // 止めている間の時間は捨てて、再開したときに早送りしない
+    fn update_pause(&mut self, pause_pressed: bool, can_pause: bool) {
+        if !pause_pressed {
+            return;
+        }
+        if self.paused {
+            self.paused = false;
+            self.accumulated_delta = 0.0;
+        } else if can_pause {
+            self.paused = true;
+        }
+    }
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Rect {
     pub position: Point,
     pub width: i16,
@@ -144,6 +694,13 @@ impl Rect {
             && self.bottom() > rect.y()
     }
 
+    pub fn contains(&self, point: &Point) -> bool {
+        point.x >= self.x()
+            && point.x < self.right()
+            && point.y >= self.y()
+            && point.y < self.bottom()
+    }
+
     pub fn right(&self) -> i16 {
         self.x() + self.width
     }
@@ -163,56 +720,741 @@ impl Rect {
     pub fn set_x(&mut self, x: i16) {
         self.position.x = x;
     }
+
+    pub fn inset(&self, insets: &Insets) -> Rect {
+        Rect::new_from_x_y(
+            self.x() + insets.left,
+            self.y() + insets.top,
+            (self.width - insets.left - insets.right).max(0),
+            (self.height - insets.top - insets.bottom).max(0),
+        )
+    }
+}
+
+// 画像の透明な余白を当たり判定から除くための幅
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Insets {
+    pub left: i16,
+    pub top: i16,
+    pub right: i16,
+    pub bottom: i16,
+}
+
+impl Insets {
+    // 1 ピクセル 1 バイトのアルファ値から、不透明な部分を囲む余白を求める
+    pub fn from_alpha(alpha: &[u8], width: usize, height: usize) -> Insets {
+        let opaque = |x: usize, y: usize| alpha[y * width + x] > ALPHA_THRESHOLD;
+        let columns: Vec<usize> = (0..width)
+            .filter(|x| (0..height).any(|y| opaque(*x, y)))
+            .collect();
+        let rows: Vec<usize> = (0..height)
+            .filter(|y| (0..width).any(|x| opaque(x, *y)))
+            .collect();
+        match (columns.first(), columns.last(), rows.first(), rows.last()) {
+            (Some(left), Some(right), Some(top), Some(bottom)) => Insets {
+                left: *left as i16,
+                top: *top as i16,
+                right: (width - 1 - right) as i16,
+                bottom: (height - 1 - bottom) as i16,
+            },
+            _ => Insets::default(),
+        }
+    }
+}
+
+const ALPHA_THRESHOLD: u8 = 16;
+
+// 画像を cell_size 四方のマスに区切り、不透明なピクセルを含むマスを記録した粗いマスク
+pub struct AlphaMask {
+    cell_size: i16,
+    columns: i16,
+    rows: i16,
+    cells: Vec<bool>,
+}
+
+impl AlphaMask {
+    pub fn from_alpha(alpha: &[u8], width: usize, height: usize, cell_size: i16) -> Self {
+        let size = cell_size.max(1) as usize;
+        let columns = width.div_ceil(size);
+        let rows = height.div_ceil(size);
+        let cells = (0..rows * columns)
+            .map(|index| {
+                let (column, row) = (index % columns, index / columns);
+                (row * size..((row + 1) * size).min(height)).any(|y| {
+                    (column * size..((column + 1) * size).min(width))
+                        .any(|x| alpha[y * width + x] > ALPHA_THRESHOLD)
+                })
+            })
+            .collect();
+        AlphaMask {
+            cell_size: size as i16,
+            columns: columns as i16,
+            rows: rows as i16,
+            cells,
+        }
+    }
+
+    // origin に置いたマスクと rect が重なるか。rect にかかるマスだけを調べる
+    pub fn overlaps(&self, origin: &Point, rect: &Rect) -> bool {
+        let first_column = ((rect.x() - origin.x) / self.cell_size).max(0);
+        let last_column = ((rect.right() - 1 - origin.x) / self.cell_size).min(self.columns - 1);
+        let first_row = ((rect.y() - origin.y) / self.cell_size).max(0);
+        let last_row = ((rect.bottom() - 1 - origin.y) / self.cell_size).min(self.rows - 1);
+        (first_row..=last_row).any(|row| {
+            (first_column..=last_column)
+                .any(|column| self.cells[(row * self.columns + column) as usize])
+        })
+    }
+}
+
+// オフスクリーンのキャンバスに描いて、各ピクセルのアルファ値だけを取り出す
+#[cfg(not(feature = "desktop"))]
+pub fn image_alpha(image: &Bitmap) -> Result<Vec<u8>> {
+    let target = RenderTarget::new(image.width(), image.height())?;
+    target
+        .context
+        .draw_image_with_html_image_element(image, 0.0, 0.0)
+        .map_err(|err| anyhow!("Could not draw image {:#?}", err))?;
+    let data = target
+        .context
+        .get_image_data(0.0, 0.0, image.width().into(), image.height().into())
+        .map_err(|err| anyhow!("Could not read image data {:#?}", err))?
+        .data();
+    Ok(data.chunks(4).map(|pixel| pixel[3]).collect())
+}
+
+// デスクトップの画像はデコードしたときのアルファ値をそのまま持っている
+#[cfg(feature = "desktop")]
+pub fn image_alpha(image: &Bitmap) -> Result<Vec<u8>> {
+    Ok(image.alpha().to_vec())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Effect {
+    None,
+    NightTint,
+    Sepia,
+    DamageVignette,
+}
+
+pub struct RenderTarget {
+    canvas: Surface,
+    context: Context,
+}
+
+impl RenderTarget {
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        let canvas = browser::create_canvas(width, height)?;
+        let context = browser::context_of(&canvas)?;
+        Ok(RenderTarget { canvas, context })
+    }
+
+    pub fn resize(&self, width: u32, height: u32) {
+        draw::resize_surface(&self.canvas, width, height);
+    }
+
+    pub fn canvas(&self) -> &Surface {
+        &self.canvas
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+// 一度だけ描画してキャッシュし、毎フレームは転送だけ行うレイヤー
+pub struct CachedLayer {
+    target: RenderTarget,
+    dirty: std::cell::Cell<bool>,
+}
+
+impl CachedLayer {
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        Ok(CachedLayer {
+            target: RenderTarget::new(width, height)?,
+            dirty: std::cell::Cell::new(true),
+        })
+    }
+
+    pub fn invalidate(&self) {
+        self.dirty.set(true);
+    }
+
+    pub fn width(&self) -> u32 {
+        draw::surface_size(self.target.canvas()).0
+    }
+
+    pub fn height(&self) -> u32 {
+        draw::surface_size(self.target.canvas()).1
+    }
 }
 
 pub struct Renderer {
-    context: CanvasRenderingContext2d,
+    context: Context,
+    layer_target: RefCell<Option<Context>>,
+    world: RenderTarget,
+    resolution_scale: std::cell::Cell<f64>,
+    post_effect: std::cell::Cell<Effect>,
+    retro_filter: std::cell::Cell<bool>,
+    retro_buffer: RenderTarget,
+    recorder: RefCell<Option<GoldenRecorder>>,
+    // (描画先のレイヤー, 重なり順, 命令)。描画先が None ならワールドに描く
+    commands: RefCell<Vec<(Option<Context>, Layer, DrawCommand)>>,
+    layer: std::cell::Cell<Layer>,
+    hitboxes: std::cell::Cell<bool>,
+}
+
+// 後処理で画面全体に重ねる色
+const NIGHT_TINT_COLOR: &str = "rgb(70, 80, 160)";
+const SEPIA_COLOR: &str = "rgb(112, 66, 20)";
+const DAMAGE_VIGNETTE_COLOR: &str = "rgba(200, 0, 0, 0.6)";
+// ブラウン管の歪みは CRT_SLICE_HEIGHT ピクセルの横のスライスごとに近似する
+const CRT_SLICE_HEIGHT: f64 = 4.0;
+const CRT_BULGE: f64 = 0.04;
+const SCANLINE_SPACING: f64 = 3.0;
+const SCANLINE_COLOR: &str = "rgba(0, 0, 0, 0.25)";
+
+// ?hitboxes を付けて開くと最初から当たり判定の枠を出す
+const HITBOXES_PARAM: &str = "hitboxes";
+
+pub fn hitboxes_requested() -> bool {
+    browser::query_param(HITBOXES_PARAM).is_some()
+}
+
+// 重なり順。後ろのものほど手前に描く。on_layer の外で描いたものは UI として一番手前に出す
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Background,
+    Obstacles,
+    Player,
+    Foreground,
+    #[default]
+    Ui,
+}
+
+// オフスクリーンへの命令は出した順のまま、それを転送する命令より前に流す。
+// ワールドへの命令は重なり順に並べ、同じ重なり順の中では出した順を保つ
+fn sort_by_layer<T>(commands: &mut [(Option<T>, Layer, DrawCommand)]) {
+    commands.sort_by_key(|(target, layer, _)| target.is_none().then_some(*layer));
 }
 
 impl Renderer {
-    pub fn clear(&self, rect: &Rect) {
-        self.context.clear_rect(
-            rect.x().into(),
-            rect.y().into(),
-            rect.width.into(),
-            rect.height.into(),
+    pub fn new(context: Context) -> Result<Self> {
+        let (width, height) = draw::surface_of(&context)
+            .map(|canvas| draw::surface_size(&canvas))
+            .ok_or_else(|| anyhow!("No canvas for the renderer"))?;
+        Ok(Renderer {
+            context,
+            layer_target: RefCell::new(None),
+            world: RenderTarget::new(width, height)?,
+            resolution_scale: std::cell::Cell::new(1.0),
+            post_effect: std::cell::Cell::new(Effect::None),
+            retro_filter: std::cell::Cell::new(false),
+            retro_buffer: RenderTarget::new(width, height)?,
+            recorder: RefCell::new(None),
+            commands: RefCell::new(vec![]),
+            layer: std::cell::Cell::new(Layer::default()),
+            hitboxes: std::cell::Cell::new(false),
+        })
+    }
+
+    // ゴールデンテスト中だけ描画命令を記録する
+    pub fn start_recording(&self) {
+        self.recorder.replace(Some(GoldenRecorder::new()));
+    }
+
+    pub fn recorded_frames(&self) -> Vec<u64> {
+        self.recorder
+            .borrow()
+            .as_ref()
+            .map(|recorder| recorder.frames().to_vec())
+            .unwrap_or_default()
+    }
+
+    fn submit(&self, command: DrawCommand) {
+        let target = self.layer_target.borrow().clone();
+        self.commands
+            .borrow_mut()
+            .push((target, self.layer.get(), command));
+    }
+
+    // draw の中で出した命令を layer の重なり順で描く。変換は layer の中で閉じておく
+    pub fn on_layer(&self, layer: Layer, draw: impl FnOnce(&Renderer)) {
+        let previous = self.layer.replace(layer);
+        draw(self);
+        self.layer.set(previous);
+    }
+
+    // ためた命令をフレームの終わりにまとめてバックエンドへ流す
+    // ワールドとオフスクリーンへの命令を流し、画面の UI への命令は返す。
+    // 解像度を落としている間も、UI は拡大でぼやけないよう元の画面に後から描く
+    fn flush(&self) -> Vec<DrawCommand> {
+        let world = self.world_context();
+        let mut commands = self.commands.borrow_mut();
+        sort_by_layer(&mut commands);
+        let ui = commands
+            .iter()
+            .position(|(target, layer, _)| target.is_none() && *layer == Layer::Ui)
+            .unwrap_or(commands.len());
+        let ui = commands
+            .split_off(ui)
+            .into_iter()
+            .map(|(_, _, command)| command)
+            .collect();
+        commands.drain(..).for_each(|(target, _, command)| {
+            self.execute(target.as_ref().unwrap_or(&world), &command);
+        });
+        ui
+    }
+
+    fn execute(&self, context: &Context, command: &DrawCommand) {
+        if let Some(recorder) = self.recorder.borrow_mut().as_mut() {
+            recorder.record(&command.describe());
+        }
+        context.execute(command);
+    }
+
+    fn is_scaled(&self) -> bool {
+        self.resolution_scale.get() < 1.0
+    }
+
+    // 解像度を落としている間はワールドをオフスクリーンに描画する
+    fn world_context(&self) -> Context {
+        if self.is_scaled() {
+            self.world.context().clone()
+        } else {
+            self.context.clone()
+        }
+    }
+
+    pub fn draw_layer(
+        &self,
+        layer: &CachedLayer,
+        position: &Point,
+        redraw: impl FnOnce(&Renderer),
+    ) {
+        if layer.dirty.replace(false) {
+            let target = &layer.target;
+            self.layer_target.replace(Some(target.context().clone()));
+            let (width, height) = draw::surface_size(target.canvas());
+            self.submit(DrawCommand::Clear(Rect::new_from_x_y(
+                0,
+                0,
+                width as i16,
+                height as i16,
+            )));
+            redraw(self);
+            self.layer_target.replace(None);
+        }
+
+        self.submit(DrawCommand::Canvas {
+            canvas: layer.target.canvas().clone(),
+            position: *position,
+        });
+    }
+
+    // offset だけずらした座標系で draw を呼ぶ (カメラ用)
+    pub fn translate(&self, offset: &Point, draw: impl FnOnce(&Renderer)) {
+        self.submit(DrawCommand::Save);
+        self.submit(DrawCommand::Translate(*offset));
+        draw(self);
+        self.submit(DrawCommand::Restore);
+    }
+
+    // ワールド座標のまま描けるように camera の変換をかけて draw を呼ぶ
+    pub fn with_camera(&self, camera: &Camera, draw: impl FnOnce(&Renderer)) {
+        self.submit(DrawCommand::Save);
+        if camera.zoom != 1.0 {
+            self.submit(DrawCommand::Scale(camera.zoom));
+        }
+        self.submit(DrawCommand::Translate(Point {
+            x: -camera.position.x,
+            y: -camera.position.y,
+        }));
+        draw(self);
+        self.submit(DrawCommand::Restore);
+    }
+
+    // draw で描いたものの後ろに color の光をにじませる。キャンバスの影を使うので絵にも図形にも効く
+    pub fn with_glow(&self, color: impl Into<Color>, blur: i16, draw: impl FnOnce(&Renderer)) {
+        self.with_shadow(color, blur, Point { x: 0, y: 0 }, draw);
+    }
+
+    // 影を上下左右に width ずつずらして draw を描き重ね、形に沿った縁にする。
+    // 本体の上にも影がかかるので、この後で本体をもう一度描く
+    pub fn with_outline(&self, color: impl Into<Color>, width: i16, draw: impl Fn(&Renderer)) {
+        let color = color.into();
+        [(width, 0), (-width, 0), (0, width), (0, -width)]
+            .iter()
+            .for_each(|(x, y)| self.with_shadow(color.clone(), 0, Point { x: *x, y: *y }, &draw));
+    }
+
+    // modifiers の点滅をかけて draw を描く。白い光は同じ絵を加算で重ねて出す
+    pub fn with_modifiers(&self, modifiers: RenderModifiers, draw: impl Fn(&Renderer)) {
+        if modifiers == RenderModifiers::default() {
+            draw(self);
+            return;
+        }
+        let alpha = modifiers.alpha();
+        self.with_blend(alpha, "source-over", &draw);
+        if modifiers.flash() > 0.0 {
+            self.with_blend(alpha * modifiers.flash(), "lighter", &draw);
+        }
+    }
+
+    fn with_blend(&self, alpha: f64, operation: &'static str, draw: impl FnOnce(&Renderer)) {
+        self.submit(DrawCommand::Save);
+        self.submit(DrawCommand::Blend { alpha, operation });
+        draw(self);
+        self.submit(DrawCommand::Restore);
+    }
+
+    fn with_shadow(
+        &self,
+        color: impl Into<Color>,
+        blur: i16,
+        offset: Point,
+        draw: impl FnOnce(&Renderer),
+    ) {
+        self.submit(DrawCommand::Save);
+        self.submit(DrawCommand::Shadow {
+            color: color.into(),
+            blur,
+            offset,
+        });
+        draw(self);
+        self.submit(DrawCommand::Restore);
+    }
+
+    // factor 倍に拡大縮小した座標系で draw を呼ぶ (サムネイル用)
+    pub fn scale(&self, factor: f64, draw: impl FnOnce(&Renderer)) {
+        self.submit(DrawCommand::Save);
+        self.submit(DrawCommand::Scale(factor));
+        draw(self);
+        self.submit(DrawCommand::Restore);
+    }
+
+    pub fn set_resolution_scale(&self, scale: f64) {
+        if let Some(canvas) = draw::surface_of(&self.context) {
+            let (width, height) = draw::surface_size(&canvas);
+            self.world.resize(
+                (width as f64 * scale) as u32,
+                (height as f64 * scale) as u32,
+            );
+        }
+        self.resolution_scale.set(scale);
+    }
+
+    pub fn begin_frame(&self) {
+        if self.is_scaled() {
+            self.scale_world(self.resolution_scale.get());
+        }
+    }
+
+    pub fn end_frame(&self) {
+        let ui = self.flush();
+        if self.is_scaled() {
+            self.upscale_world();
+        }
+        ui.iter()
+            .for_each(|command| self.execute(&self.context, command));
+        self.apply_post_effect();
+        if let Some(recorder) = self.recorder.borrow_mut().as_mut() {
+            recorder.end_frame();
+        }
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn scale_world(&self, scale: f64) {
+        if let Err(err) = self
+            .world
+            .context()
+            .set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0)
+        {
+            log!("Could not scale the world target {:#?}", err);
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    fn scale_world(&self, scale: f64) {
+        self.world.context().reset(scale);
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn upscale_world(&self) {
+        if let Some(canvas) = self.context.canvas() {
+            if let Err(err) = self
+                .context
+                .draw_image_with_html_canvas_element_and_dw_and_dh(
+                    self.world.canvas(),
+                    0.0,
+                    0.0,
+                    canvas.width() as f64,
+                    canvas.height() as f64,
+                )
+            {
+                log!("Could not upscale the world target {:#?}", err);
+            }
+        }
+    }
+
+    #[cfg(feature = "desktop")]
+    fn upscale_world(&self) {
+        self.context.stretch(self.world.canvas());
+    }
+
+    pub fn set_post_effect(&self, effect: Effect) {
+        self.post_effect.set(effect);
+    }
+
+    pub fn set_retro_filter(&self, enabled: bool) {
+        self.retro_filter.set(enabled);
+    }
+
+    // 当たり判定の枠はすべて draw_bounding_box を通し、ここで出すかどうかを決める
+    pub fn set_hitboxes(&self, visible: bool) {
+        self.hitboxes.set(visible);
+    }
+
+    // フレーム全体の描画が終わった後に画面全体へ合成する
+    fn apply_post_effect(&self) {
+        let (width, height) = match draw::surface_of(&self.context) {
+            Some(canvas) => {
+                let (width, height) = draw::surface_size(&canvas);
+                (width as f64, height as f64)
+            }
+            None => return,
+        };
+
+        let mut result = self.apply_effect(self.post_effect.get(), width, height);
+        if self.retro_filter.get() {
+            result = result
+                .and_then(|_| self.apply_effect(Effect::Sepia, width, height))
+                .and_then(|_| self.apply_crt(width, height));
+        }
+
+        if let Err(err) = result {
+            log!("Could not apply post effect {:#?}", err);
+        }
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn apply_effect(&self, effect: Effect, width: f64, height: f64) -> Result<()> {
+        self.context.save();
+        let result = match effect {
+            Effect::None => Ok(()),
+            Effect::NightTint => {
+                self.fill_with_composite("multiply", NIGHT_TINT_COLOR, width, height)
+            }
+            Effect::Sepia => self.fill_with_composite("color", SEPIA_COLOR, width, height),
+            Effect::DamageVignette => self.fill_vignette(DAMAGE_VIGNETTE_COLOR, width, height),
+        };
+        self.context.restore();
+        result
+    }
+
+    // 合成の仕方はキャンバスと同じものを Framebuffer が CPU で行う
+    #[cfg(feature = "desktop")]
+    fn apply_effect(&self, effect: Effect, _width: f64, _height: f64) -> Result<()> {
+        match effect {
+            Effect::None => {}
+            Effect::NightTint => self.context.multiply(NIGHT_TINT_COLOR),
+            Effect::Sepia => self.context.colorize(SEPIA_COLOR),
+            Effect::DamageVignette => self.context.vignette(DAMAGE_VIGNETTE_COLOR),
+        }
+        Ok(())
+    }
+
+    // 横方向のスライスごとに幅を変えて描き直し、ブラウン管の歪みを近似する
+    #[cfg(not(feature = "desktop"))]
+    fn apply_crt(&self, width: f64, height: f64) -> Result<()> {
+        let buffer = self.retro_buffer.canvas();
+        let buffer_context = self.retro_buffer.context();
+        let screen = self
+            .context
+            .canvas()
+            .ok_or_else(|| anyhow!("No canvas for the renderer"))?;
+        buffer_context
+            .draw_image_with_html_canvas_element(&screen, 0.0, 0.0)
+            .map_err(|err| anyhow!("Could not copy screen to retro buffer {:#?}", err))?;
+
+        self.context.clear_rect(0.0, 0.0, width, height);
+        let center_y = height / 2.0;
+        let mut y = 0.0;
+        while y < height {
+            let distance = (y + CRT_SLICE_HEIGHT / 2.0 - center_y) / center_y;
+            let slice_width = width * (1.0 + CRT_BULGE * (1.0 - distance * distance));
+            self.context
+                .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    buffer,
+                    0.0,
+                    y,
+                    width,
+                    CRT_SLICE_HEIGHT,
+                    (width - slice_width) / 2.0,
+                    y,
+                    slice_width,
+                    CRT_SLICE_HEIGHT,
+                )
+                .map_err(|err| anyhow!("Could not draw retro slice {:#?}", err))?;
+            y += CRT_SLICE_HEIGHT;
+        }
+
+        self.context.save();
+        self.context.set_fill_style_str(SCANLINE_COLOR);
+        let mut y = 0.0;
+        while y < height {
+            self.context.fill_rect(0.0, y, width, 1.0);
+            y += SCANLINE_SPACING;
+        }
+        self.context.restore();
+        Ok(())
+    }
+
+    #[cfg(feature = "desktop")]
+    fn apply_crt(&self, width: f64, height: f64) -> Result<()> {
+        self.context.crt(
+            self.retro_buffer.canvas(),
+            CRT_SLICE_HEIGHT as usize,
+            CRT_BULGE,
         );
+        let mut y = 0.0;
+        while y < height {
+            self.context.execute(&DrawCommand::FillRect {
+                rect: Rect::new_from_x_y(0, y as i16, width as i16, 1),
+                color: SCANLINE_COLOR.into(),
+            });
+            y += SCANLINE_SPACING;
+        }
+        Ok(())
     }
 
-    pub fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
+    #[cfg(not(feature = "desktop"))]
+    fn fill_with_composite(
+        &self,
+        operation: &str,
+        color: &str,
+        width: f64,
+        height: f64,
+    ) -> Result<()> {
         self.context
-            .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-                &image,
-                frame.x().into(),
-                frame.y().into(),
-                frame.width.into(),
-                frame.height.into(),
-                destination.x().into(),
-                destination.y().into(),
-                destination.width.into(),
-                destination.height.into(),
+            .set_global_composite_operation(operation)
+            .map_err(|err| anyhow!("Could not set composite operation {:#?}", err))?;
+        self.context.set_fill_style_str(color);
+        self.context.fill_rect(0.0, 0.0, width, height);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn fill_vignette(&self, color: &str, width: f64, height: f64) -> Result<()> {
+        let (center_x, center_y) = (width / 2.0, height / 2.0);
+        let gradient = self
+            .context
+            .create_radial_gradient(
+                center_x,
+                center_y,
+                center_x.min(center_y) * 0.5,
+                center_x,
+                center_y,
+                center_x.hypot(center_y),
             )
-            .expect("Drawing is throwing exceptions! Uncoverable error.");
+            .map_err(|err| anyhow!("Could not create gradient {:#?}", err))?;
+        gradient
+            .add_color_stop(0.0, "rgba(0, 0, 0, 0)")
+            .and_then(|_| gradient.add_color_stop(1.0, color))
+            .map_err(|err| anyhow!("Could not add color stop {:#?}", err))?;
+        self.context.set_fill_style_canvas_gradient(&gradient);
+        self.context.fill_rect(0.0, 0.0, width, height);
+        Ok(())
     }
 
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
-        self.context
-            .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
-            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    pub fn clear(&self, rect: &Rect) {
+        self.submit(DrawCommand::Clear(*rect));
+    }
+
+    pub fn draw_image(&self, image: &Bitmap, frame: &Rect, destination: &Rect) {
+        self.submit(DrawCommand::Image {
+            image: image.clone(),
+            frame: *frame,
+            destination: *destination,
+        });
+    }
+
+    // destination を anchor を中心に scale 倍して描く
+    pub fn draw_image_ex(
+        &self,
+        image: &Bitmap,
+        frame: &Rect,
+        destination: &Rect,
+        scale: Scale,
+        anchor: &Point,
+    ) {
+        self.draw_image(image, frame, &scale.apply(destination, anchor));
+    }
+
+    pub fn draw_entire_image(&self, image: &Bitmap, position: &Point) {
+        self.submit(DrawCommand::EntireImage {
+            image: image.clone(),
+            position: *position,
+        });
+    }
+
+    pub fn fill_rect(&self, rect: &Rect, color: impl Into<Color>) {
+        self.submit(DrawCommand::FillRect {
+            rect: *rect,
+            color: color.into(),
+        });
+    }
+
+    pub fn fill_circle(&self, center: &Point, radius: i16, color: impl Into<Color>) {
+        self.submit(DrawCommand::FillCircle {
+            center: *center,
+            radius,
+            color: color.into(),
+        });
+    }
+
+    pub fn fill_ellipse(
+        &self,
+        center: &Point,
+        radius_x: i16,
+        radius_y: i16,
+        color: impl Into<Color>,
+    ) {
+        self.submit(DrawCommand::FillEllipse {
+            center: *center,
+            radius_x,
+            radius_y,
+            color: color.into(),
+        });
+    }
+
+    pub fn stroke_rect(&self, rect: &Rect, color: impl Into<Color>) {
+        self.submit(DrawCommand::StrokeRect {
+            rect: *rect,
+            color: color.into(),
+        });
+    }
+
+    // 色だけを渡せば既定の大きさと揃え方で描く
+    pub fn draw_text(&self, text: &str, position: &Point, style: impl Into<TextStyle>) {
+        self.submit(DrawCommand::Text {
+            text: text.into(),
+            position: *position,
+            style: style.into(),
+        });
     }
 
     pub fn draw_bounding_box(&self, bounding_box: &Rect) {
-        self.context.set_stroke_style_str("#FF0000");
-        self.context.stroke_rect(
-            bounding_box.x().into(),
-            bounding_box.y().into(),
-            bounding_box.width.into(),
-            bounding_box.height.into(),
-        );
+        if self.hitboxes.get() {
+            self.stroke_rect(bounding_box, "#FF0000");
+        }
     }
 }
 
-pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
+#[cfg(not(feature = "desktop"))]
+pub async fn load_image(source: &str) -> Result<Bitmap> {
     let image = browser::new_image()?;
 
     let (complete_tx, complete_rx) = channel::<Result<()>>();
@@ -239,85 +1481,246 @@ pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
     Ok(image)
 }
 
-enum KeyPress {
-    KeyUp(web_sys::KeyboardEvent),
-    KeyDown(web_sys::KeyboardEvent),
+// デスクトップでは PNG を読んでその場でデコードする
+#[cfg(feature = "desktop")]
+pub async fn load_image(source: &str) -> Result<Bitmap> {
+    let bytes = crate::platform::current().fetch_bytes(source).await?;
+    Bitmap::decode(source, &bytes)
 }
 
-fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
-    let (keydown_sender, keyevent_receiver) = unbounded();
-    let keydown_sender = Rc::new(RefCell::new(keydown_sender));
-    let keyup_sender = Rc::clone(&keydown_sender);
-
-    let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
-        keydown_sender
-            .borrow_mut()
-            .start_send(KeyPress::KeyDown(keycode));
-    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
-
-    let onkeyup = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
-        keyup_sender
-            .borrow_mut()
-            .start_send(KeyPress::KeyUp(keycode));
-    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+// キーは KeyboardEvent.code と同じ名前で送る
+pub enum KeyPress {
+    KeyUp(String),
+    KeyDown(String),
+    PointerDown { position: Point, touch: bool },
+    PointerMove(Point),
+    PointerUp,
+}
 
-    browser::canvas()
-        .unwrap()
-        .set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
-    browser::canvas()
-        .unwrap()
-        .set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
+// イベントが起きた時刻 (performance.now() と同じ基準) と一緒に送る
+pub type InputEvent = (f64, KeyPress);
 
-    onkeydown.forget();
-    onkeyup.forget();
+#[derive(Clone)]
+pub struct KeyState {
+    // 押されているキーと、押した時刻
+    pressed_keys: HashMap<String, f64>,
+    just_pressed_keys: HashSet<String>,
+    pointer: Option<Point>,
+    pointer_down: bool,
+    pointer_just_pressed: bool,
+    touch_start: Option<Point>,
+    gesture_keys: HashSet<String>,
+    gamepad_keys: HashSet<String>,
+    // 補助機能がその update だけ押したことにしたキー
+    injected_keys: HashSet<String>,
+    // リプレイが押し続けていることにしたキー。押した瞬間の扱いにはならない
+    held_keys: HashSet<String>,
+}
 
-    Ok(keyevent_receiver)
+// タッチのジェスチャーはキー入力に読み替える。タップでジャンプ、下へのスワイプでスライディング
+const TAP_KEY: &str = "Space";
+const SWIPE_DOWN_KEY: &str = "ArrowDown";
+const TAP_DISTANCE: i16 = 20;
+const SWIPE_DISTANCE: i16 = 40;
+
+// 標準配置のゲームパッドのボタン番号とキーの対応。A でジャンプ、B でスライディング
+const GAMEPAD_KEYS: [(u32, &str); 8] = [
+    (0, "Space"),
+    (1, "ArrowDown"),
+    (2, "KeyX"),
+    (9, "Enter"),
+    (12, "ArrowUp"),
+    (13, "ArrowDown"),
+    (14, "ArrowLeft"),
+    (15, "ArrowRight"),
+];
+
+fn gamepad_keys(buttons: &[u32]) -> HashSet<String> {
+    GAMEPAD_KEYS
+        .iter()
+        .filter(|(button, _)| buttons.contains(button))
+        .map(|(_, key)| key.to_string())
+        .collect()
 }
 
-pub struct KeyState {
-    pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
+fn gesture_key(start: &Point, end: &Point) -> Option<&'static str> {
+    let (dx, dy) = ((end.x - start.x).abs(), end.y - start.y);
+    if dx < TAP_DISTANCE && dy.abs() < TAP_DISTANCE {
+        Some(TAP_KEY)
+    } else if dy >= SWIPE_DISTANCE && dy > dx {
+        Some(SWIPE_DOWN_KEY)
+    } else {
+        None
+    }
 }
 
 impl KeyState {
     fn new() -> Self {
         KeyState {
             pressed_keys: HashMap::new(),
+            just_pressed_keys: HashSet::new(),
+            pointer: None,
+            pointer_down: false,
+            pointer_just_pressed: false,
+            touch_start: None,
+            gesture_keys: HashSet::new(),
+            gamepad_keys: HashSet::new(),
+            injected_keys: HashSet::new(),
+            held_keys: HashSet::new(),
         }
     }
 
+    // code も押されたことにした写しを返す。元の入力は変えない
+    pub fn with_injected(&self, code: &str) -> KeyState {
+        let mut keystate = self.clone();
+        keystate.injected_keys.insert(code.into());
+        keystate
+    }
+
+    // codes だけが押されている入力。リプレイで記録から組み立てるのに使う
+    pub fn injected<'a>(codes: impl Iterator<Item = &'a str>) -> KeyState {
+        KeyState {
+            injected_keys: codes.map(String::from).collect(),
+            ..KeyState::new()
+        }
+    }
+
+    // held は押し続けているだけのキー、just はその update で押したキー
+    pub fn replayed<'a>(
+        held: impl Iterator<Item = &'a str>,
+        just: impl Iterator<Item = &'a str>,
+    ) -> KeyState {
+        KeyState {
+            held_keys: held.map(String::from).collect(),
+            ..KeyState::injected(just)
+        }
+    }
+
+    pub fn pointer(&self) -> Option<Point> {
+        self.pointer
+    }
+
+    pub fn is_pointer_down(&self) -> bool {
+        self.pointer_down
+    }
+
+    pub fn is_pointer_just_pressed(&self) -> bool {
+        self.pointer_just_pressed
+    }
+
     pub fn is_pressed(&self, code: &str) -> bool {
         self.pressed_keys.contains_key(code)
+            || self.gesture_keys.contains(code)
+            || self.gamepad_keys.contains(code)
+            || self.injected_keys.contains(code)
+            || self.held_keys.contains(code)
+    }
+
+    // 押された瞬間の最初の update でのみ true を返す
+    pub fn is_just_pressed(&self, code: &str) -> bool {
+        self.just_pressed_keys.contains(code)
+            || self.gesture_keys.contains(code)
+            || self.injected_keys.contains(code)
     }
 
-    fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
-        self.pressed_keys.insert(code.into(), event);
+    // まだ update に使われていないキー入力のうち、いちばん古いものの時刻
+    fn just_pressed_at(&self) -> Option<f64> {
+        self.just_pressed_keys
+            .iter()
+            .filter_map(|code| self.pressed_keys.get(code))
+            .copied()
+            .reduce(f64::min)
+    }
+
+    fn set_pressed(&mut self, code: &str, at: f64) {
+        if !self.pressed_keys.contains_key(code) {
+            self.just_pressed_keys.insert(code.into());
+        }
+        self.pressed_keys.insert(code.into(), at);
     }
 
     fn set_released(&mut self, code: &str) {
         self.pressed_keys.remove(code.into());
     }
+
+    fn set_pointer_down(&mut self, position: Point, touch: bool) {
+        self.pointer = Some(position);
+        self.pointer_just_pressed = !self.pointer_down;
+        self.pointer_down = true;
+        if touch {
+            self.touch_start = Some(position);
+        }
+    }
+
+    // ゲームパッドのボタンはキーボードのキーと同じ名前で扱う
+    fn set_gamepad_buttons(&mut self, buttons: &[u32]) {
+        let keys = gamepad_keys(buttons);
+        let pressed: Vec<String> = keys.difference(&self.gamepad_keys).cloned().collect();
+        self.just_pressed_keys.extend(pressed);
+        self.gamepad_keys = keys;
+    }
+
+    // ジェスチャーのキーは指を離した次の update の 1 回だけ押されている
+    fn set_pointer_up(&mut self) {
+        self.pointer_down = false;
+        if let Some(start) = self.touch_start.take() {
+            let end = self.pointer.unwrap_or(start);
+            if let Some(key) = gesture_key(&start, &end) {
+                self.gesture_keys.insert(key.into());
+            }
+        }
+    }
+
+    fn clear_just_pressed(&mut self) {
+        self.just_pressed_keys.clear();
+        self.pointer_just_pressed = false;
+        self.gesture_keys.clear();
+    }
 }
 
-fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
-    loop {
-        match keyevent_receiver.try_next() {
-            Ok(None) => break,
-            Err(_err) => break,
-            Ok(Some(evt)) => match evt {
-                KeyPress::KeyUp(evt) => state.set_released(&evt.code()),
-                KeyPress::KeyDown(evt) => state.set_pressed(&evt.code(), evt),
-            },
+fn queue_input(
+    queue: &mut VecDeque<InputEvent>,
+    keyevent_receiver: &mut UnboundedReceiver<InputEvent>,
+) {
+    while let Ok(Some(event)) = keyevent_receiver.try_next() {
+        queue.push_back(event);
+    }
+}
+
+// tick_end までに起きた入力を順に反映する。
+// 押してすぐ離した場合は、離したことを次の update まで待たせて、少なくとも 1 回は押されているように見せる
+fn apply_input(state: &mut KeyState, queue: &mut VecDeque<InputEvent>, tick_end: f64) {
+    while let Some((at, press)) = queue.front() {
+        let released_too_soon = match press {
+            KeyPress::KeyUp(code) => state.is_just_pressed(code),
+            KeyPress::PointerUp => state.is_pointer_just_pressed(),
+            _ => false,
         };
+        if *at > tick_end || released_too_soon {
+            break;
+        }
+        if let Some((at, press)) = queue.pop_front() {
+            match press {
+                KeyPress::KeyUp(code) => state.set_released(&code),
+                KeyPress::KeyDown(code) => state.set_pressed(&code, at),
+                KeyPress::PointerDown { position, touch } => {
+                    state.set_pointer_down(position, touch)
+                }
+                KeyPress::PointerMove(position) => state.pointer = Some(position),
+                KeyPress::PointerUp => state.set_pointer_up(),
+            }
+        }
     }
 }
 
+#[derive(Clone)]
 pub struct Image {
-    element: HtmlImageElement,
+    element: Bitmap,
     bounding_box: Rect,
 }
 
 impl Image {
-    pub fn new(element: HtmlImageElement, position: Point) -> Self {
+    pub fn new(element: Bitmap, position: Point) -> Self {
         let bounding_box = Rect::new_from_x_y(
             position.x.into(),
             position.y.into(),
@@ -339,6 +1742,10 @@ impl Image {
         &self.bounding_box
     }
 
+    pub fn element(&self) -> &Bitmap {
+        &self.element
+    }
+
     pub fn move_horizontally(&mut self, distance: i16) {
         self.bounding_box.set_x(self.bounding_box.x() + distance);
     }
@@ -352,26 +1759,82 @@ impl Image {
     }
 }
 
+// 一つのバス。ダッキング用のノードから音量のノードへと直列に繋ぐ
+#[cfg(not(feature = "desktop"))]
+#[derive(Clone)]
+struct BusNodes {
+    input: GainNode,
+    volume: GainNode,
+}
+
+#[cfg(not(feature = "desktop"))]
 #[derive(Clone)]
 pub struct Audio {
     context: AudioContext,
+    // すべての音が通る全体音量。ミュートもここで行う
+    master: GainNode,
+    master_volume: Rc<std::cell::Cell<f32>>,
+    muted: Rc<std::cell::Cell<bool>>,
+    // タブが隠れたときに BGM と環境音だけを絞るためのノード
+    focus: GainNode,
+    // BUSES と同じ並び
+    buses: Vec<BusNodes>,
+    ducking: Rc<std::cell::Cell<DuckingRule>>,
+    music_in_background: Rc<std::cell::Cell<bool>>,
+    // 今流れている BGM。複製した Audio でも同じ曲を止められるように共有する
+    music_track: Rc<RefCell<Option<(AudioBufferSourceNode, GainNode)>>>,
+    // 今の BGM を流し始めたときの音の時計
+    music_started: Rc<std::cell::Cell<f64>>,
+    ambience_track: Rc<RefCell<Option<(AudioBufferSourceNode, GainNode)>>>,
 }
 
+#[cfg(not(feature = "desktop"))]
 #[derive(Clone)]
 pub struct Sound {
     buffer: AudioBuffer,
 }
 
+#[cfg(not(feature = "desktop"))]
 impl Audio {
     pub fn new() -> Result<Self> {
+        let context = sound::create_audio_context()?;
+        let master = sound::create_gain(&context)?;
+        let focus = sound::create_gain_to(&context, &master)?;
+        let buses = BUSES
+            .iter()
+            .map(|bus| {
+                let output = if bus.follows_focus() { &focus } else { &master };
+                let volume = sound::create_gain_to(&context, output)?;
+                let input = sound::create_gain_to(&context, &volume)?;
+                Ok(BusNodes { input, volume })
+            })
+            .collect::<Result<Vec<_>>>()?;
         Ok(Audio {
-            context: sound::create_audio_context()?,
+            master,
+            master_volume: Rc::new(std::cell::Cell::new(1.0)),
+            muted: Rc::new(std::cell::Cell::new(false)),
+            focus,
+            buses,
+            ducking: Rc::new(std::cell::Cell::new(DEFAULT_DUCKING)),
+            music_in_background: Rc::new(std::cell::Cell::new(false)),
+            music_track: Rc::new(RefCell::new(None)),
+            music_started: Rc::new(std::cell::Cell::new(0.0)),
+            ambience_track: Rc::new(RefCell::new(None)),
+            context,
         })
     }
 
+    fn bus(&self, bus: Bus) -> &GainNode {
+        &self.buses[bus.index()].input
+    }
+
     pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
         let array_buffer = browser::fetch_array_buffer(filename).await?;
-        let audio_buffer = sound::decode_audio_data(&self.context, &array_buffer).await?;
+        self.decode_sound(&array_buffer).await
+    }
+
+    pub async fn decode_sound(&self, array_buffer: &ArrayBuffer) -> Result<Sound> {
+        let audio_buffer = sound::decode_audio_data(&self.context, array_buffer).await?;
 
         Ok(Sound {
             buffer: audio_buffer,
@@ -379,11 +1842,141 @@ impl Audio {
     }
 
     pub fn play_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::NO)
+        self.play_sound_on(Bus::Sfx, sound)
+    }
+
+    pub fn play_sound_on(&self, bus: Bus, sound: &Sound) -> Result<()> {
+        sound::play_sound(
+            &self.context,
+            &sound.buffer,
+            self.bus(bus),
+            sound::LOOPING::NO,
+        )
+    }
+
+    // ジャンプなどの大きな効果音。ダッキングの決まりに従って音楽と環境音を絞る
+    pub fn play_loud_sound(&self, sound: &Sound) -> Result<()> {
+        self.play_sound(sound)?;
+        let rule = self.ducking.get();
+        BUSES
+            .iter()
+            .filter(|bus| bus.is_ducked())
+            .try_for_each(|bus| {
+                sound::duck(
+                    &self.context,
+                    self.bus(*bus),
+                    rule.gain(),
+                    rule.hold(),
+                    DUCK_RELEASE,
+                )
+            })
+    }
+
+    pub fn set_ducking(&self, rule: DuckingRule) {
+        self.ducking.set(rule);
+    }
+
+    // 環境音もループする。流れている音があれば fade 秒かけて重ねながら入れ替える
+    pub fn play_ambience(&self, sound: &Sound, fade: f64) -> Result<()> {
+        self.stop_ambience(fade)?;
+        let track = sound::play_music(&self.context, &sound.buffer, self.bus(Bus::Ambience), fade)?;
+        self.ambience_track.replace(Some(track));
+        Ok(())
+    }
+
+    pub fn stop_ambience(&self, fade: f64) -> Result<()> {
+        match self.ambience_track.take() {
+            Some((track_source, fader)) => {
+                sound::fade_out(&self.context, &track_source, &fader, fade)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // BGM はループして音楽の音量に従う。流れている曲があれば重ねながら入れ替える
+    pub fn play_music(&self, sound: &Sound, fade: f64) -> Result<()> {
+        self.stop_music(fade)?;
+        let track = sound::play_music(&self.context, &sound.buffer, self.bus(Bus::Music), fade)?;
+        self.music_track.replace(Some(track));
+        self.music_started.set(self.current_time());
+        Ok(())
+    }
+
+    // AudioContext の時計 (秒)。requestAnimationFrame の間隔や処理落ちに左右されない
+    pub fn current_time(&self) -> f64 {
+        self.context.current_time()
+    }
+
+    // 今の BGM を流し始めてからの秒数。流れていなければ None
+    pub fn music_time(&self) -> Option<f64> {
+        self.is_music_playing()
+            .then(|| self.current_time() - self.music_started.get())
+    }
+
+    pub fn stop_music(&self, fade: f64) -> Result<()> {
+        match self.music_track.take() {
+            Some((track_source, fader)) => {
+                sound::fade_out(&self.context, &track_source, &fader, fade)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_music_playing(&self) -> bool {
+        self.music_track.borrow().is_some()
+    }
+
+    // false ならタブが隠れている間は BGM を絞る
+    pub fn set_music_in_background(&self, keep_playing: bool) {
+        self.music_in_background.set(keep_playing);
+    }
+
+    // 切り替えはぶつ切りにせず、少しずつ絞ったり戻したりする
+    pub fn watch_visibility(&self) -> Result<()> {
+        let audio = self.clone();
+        browser::on_visibility_change(move |hidden| {
+            let volume = if hidden && !audio.music_in_background.get() {
+                0.0
+            } else {
+                1.0
+            };
+            if let Err(err) = sound::fade_to(&audio.context, &audio.focus, volume, BACKGROUND_FADE)
+            {
+                log!("Could not fade music for visibility {:#?}", err);
+            }
+        })
     }
 
-    pub fn play_looping_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::YES)
+    pub fn play_tone(&self, frequency: f32, duration: f64) -> Result<()> {
+        self.play_tone_on(Bus::Sfx, frequency, duration)
+    }
+
+    pub fn play_tone_on(&self, bus: Bus, frequency: f32, duration: f64) -> Result<()> {
+        sound::play_tone(&self.context, self.bus(bus), frequency, duration)
+    }
+
+    pub fn set_bus_volume(&self, bus: Bus, volume: f32) {
+        self.buses[bus.index()].volume.gain().set_value(volume);
+    }
+
+    pub fn set_master_volume(&self, volume: f32) {
+        self.master_volume.set(volume);
+        self.apply_master();
+    }
+
+    // ミュートを解除したら元の全体音量に戻す
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.set(muted);
+        self.apply_master();
+    }
+
+    fn apply_master(&self) {
+        let volume = if self.muted.get() {
+            0.0
+        } else {
+            self.master_volume.get()
+        };
+        self.master.gain().set_value(volume);
     }
 }
 
@@ -401,6 +1994,199 @@ pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn alpha_mask_only_hits_opaque_cells() {
+        #[rustfmt::skip]
+        let alpha = [
+            255, 255, 0, 0,
+            255, 255, 0, 0,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+        ];
+        let mask = AlphaMask::from_alpha(&alpha, 4, 4, 2);
+        let origin = Point { x: 10, y: 10 };
+
+        assert!(mask.overlaps(&origin, &Rect::new_from_x_y(11, 11, 2, 2)));
+        assert!(!mask.overlaps(&origin, &Rect::new_from_x_y(12, 12, 4, 4)));
+    }
+
+    #[test]
+    fn shake_decays_until_it_stops() {
+        let mut shake = Shake::new(12.0, 0.8);
+        assert!(shake.is_active());
+        assert_eq!(shake.offset().y, 12);
+
+        (0..20).for_each(|_| shake.update());
+        assert!(!shake.is_active());
+        assert_eq!(shake.offset().x, 0);
+        assert_eq!(shake.offset().y, 0);
+        assert_eq!(Shake::default().offset().x, 0);
+    }
+
+    #[test]
+    fn camera_zoom_keeps_the_focus_in_place() {
+        let camera = Camera::new(Point { x: 0, y: -100 });
+        let focus = Point { x: 0, y: 600 };
+        let zoomed = camera.zoom_at(0.5, &focus);
+
+        assert_eq!(zoomed.to_world(&focus).y, camera.to_world(&focus).y);
+        assert_eq!(zoomed.to_screen(&Point { x: 200, y: 300 }).x, 100);
+        assert_eq!(zoomed.view(600, 600).height, 1200);
+        assert_eq!(zoomed.position.y, -700);
+    }
+
+    #[test]
+    fn scale_keeps_the_anchor_in_place() {
+        let rect = Rect::new_from_x_y(100, 200, 40, 60);
+        let feet = Point { x: 120, y: 260 };
+        let giant = Scale::uniform(2.0).apply(&rect, &feet);
+        let squashed = Scale::new(1.5, 0.5).apply(&rect, &feet);
+
+        assert_eq!(
+            (giant.x(), giant.y(), giant.width, giant.height),
+            (80, 140, 80, 120)
+        );
+        assert_eq!(giant.bottom(), feet.y);
+        assert_eq!((squashed.width, squashed.height), (60, 30));
+        assert_eq!(squashed.bottom(), feet.y);
+        assert_eq!(Scale::IDENTITY.apply(&rect, &feet).x(), rect.x());
+    }
+
+    #[test]
+    fn commands_are_sorted_by_layer() {
+        let mut commands = vec![
+            (
+                None,
+                Layer::Ui,
+                DrawCommand::Translate(Point { x: 1, y: 0 }),
+            ),
+            (None, Layer::Player, DrawCommand::Save),
+            (
+                Some(()),
+                Layer::Ui,
+                DrawCommand::Translate(Point { x: 2, y: 0 }),
+            ),
+            (None, Layer::Background, DrawCommand::Restore),
+            (
+                None,
+                Layer::Player,
+                DrawCommand::Translate(Point { x: 3, y: 0 }),
+            ),
+            (Some(()), Layer::Background, DrawCommand::Save),
+        ];
+        sort_by_layer(&mut commands);
+
+        let order: Vec<(bool, String)> = commands
+            .iter()
+            .map(|(target, _, command)| (target.is_some(), command.describe()))
+            .collect();
+        let expected: Vec<(bool, String)> = vec![
+            (
+                true,
+                DrawCommand::Translate(Point { x: 2, y: 0 }).describe(),
+            ),
+            (true, DrawCommand::Save.describe()),
+            (false, DrawCommand::Restore.describe()),
+            (false, DrawCommand::Save.describe()),
+            (
+                false,
+                DrawCommand::Translate(Point { x: 3, y: 0 }).describe(),
+            ),
+            (
+                false,
+                DrawCommand::Translate(Point { x: 1, y: 0 }).describe(),
+            ),
+        ];
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn input_is_applied_to_the_tick_it_happened_in() {
+        let mut state = KeyState::new();
+        let mut queue: VecDeque<InputEvent> = VecDeque::from(vec![
+            (
+                5.0,
+                KeyPress::PointerDown {
+                    position: Point { x: 1, y: 2 },
+                    touch: false,
+                },
+            ),
+            (6.0, KeyPress::PointerUp),
+            (
+                20.0,
+                KeyPress::PointerDown {
+                    position: Point { x: 3, y: 4 },
+                    touch: false,
+                },
+            ),
+        ]);
+
+        apply_input(&mut state, &mut queue, 16.0);
+        assert!(state.is_pointer_just_pressed());
+        assert!(state.is_pointer_down());
+        assert_eq!(queue.len(), 2);
+
+        state.clear_just_pressed();
+        apply_input(&mut state, &mut queue, 33.0);
+        assert!(state.is_pointer_just_pressed());
+        assert_eq!(state.pointer().map(|point| point.x), Some(3));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn gamepad_buttons_press_the_same_keys_as_the_keyboard() {
+        let mut state = KeyState::new();
+        state.set_gamepad_buttons(&[0, 15]);
+        assert!(state.is_just_pressed("Space"));
+        assert!(state.is_pressed("ArrowRight"));
+
+        state.clear_just_pressed();
+        state.set_gamepad_buttons(&[0]);
+        assert!(state.is_pressed("Space"));
+        assert!(!state.is_just_pressed("Space"));
+        assert!(!state.is_pressed("ArrowRight"));
+    }
+
+    #[test]
+    fn touch_gestures_press_jump_and_slide_keys() {
+        let mut state = KeyState::new();
+        state.set_pointer_down(Point { x: 100, y: 100 }, true);
+        state.set_pointer_up();
+        assert!(state.is_just_pressed(TAP_KEY));
+
+        state.clear_just_pressed();
+        state.set_pointer_down(Point { x: 100, y: 100 }, true);
+        state.pointer = Some(Point { x: 110, y: 160 });
+        state.set_pointer_up();
+        assert!(state.is_pressed(SWIPE_DOWN_KEY));
+        assert!(!state.is_pressed(TAP_KEY));
+
+        state.clear_just_pressed();
+        state.set_pointer_down(Point { x: 100, y: 100 }, false);
+        state.set_pointer_up();
+        assert!(!state.is_pressed(TAP_KEY));
+    }
+
+    #[test]
+    fn insets_skip_transparent_padding() {
+        #[rustfmt::skip]
+        let alpha = [
+            0, 0, 0, 0,
+            0, 255, 0, 0,
+            0, 255, 255, 0,
+        ];
+        assert_eq!(
+            Insets::from_alpha(&alpha, 4, 3),
+            Insets {
+                left: 1,
+                top: 1,
+                right: 1,
+                bottom: 0
+            }
+        );
+    }
+
     #[test]
     fn two_rects_that_intersect_on_the_left() {
         let rect1 = Rect {
@@ -417,4 +2203,76 @@ mod tests {
 
         assert_eq!(rect2.intersects(&rect1), true);
     }
+
+    #[test]
+    fn pausing_drops_the_time_spent_paused() {
+        let mut game_loop = GameLoop {
+            last_frame: 0.0,
+            accumulated_delta: FRAME_SIZE * 3.0,
+            paused: false,
+            last_render: 0.0,
+            metrics: FrameMetrics::default(),
+            scaler: ResolutionScaler::new(),
+            latency: None,
+        };
+
+        game_loop.update_pause(true, false);
+        assert!(!game_loop.paused);
+        game_loop.update_pause(true, true);
+        assert!(game_loop.paused);
+        game_loop.update_pause(false, true);
+        assert!(game_loop.paused);
+        game_loop.update_pause(true, false);
+        assert!(!game_loop.paused);
+        assert_eq!(game_loop.accumulated_delta, 0.0);
+    }
+
+    #[test]
+    fn animation_advances_every_duration_frames() {
+        let animation = Animation {
+            handles: vec![SpriteHandle(4), SpriteHandle(7)],
+            duration: 3,
+        };
+
+        assert_eq!(animation.frame_at(2), Some(SpriteHandle(4)));
+        assert_eq!(animation.frame_at(3), Some(SpriteHandle(7)));
+        assert_eq!(animation.frame_at(6), None);
+    }
+
+    #[test]
+    fn resolution_scale_drops_under_load_and_recovers() {
+        let mut scaler = ResolutionScaler::new();
+
+        let downscaled = (0..FRAMES_BEFORE_DOWNSCALE)
+            .filter_map(|_| scaler.update(FRAME_SIZE * 2.0))
+            .last();
+        assert_eq!(downscaled, Some(1.0 - RESOLUTION_SCALE_STEP));
+
+        let upscaled = (0..FRAMES_BEFORE_UPSCALE)
+            .filter_map(|_| scaler.update(FRAME_SIZE / 4.0))
+            .last();
+        assert_eq!(upscaled, Some(1.0));
+    }
+
+    // 解像度を落としても UI は元の画面のピクセルに描く
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn ui_is_drawn_at_full_resolution_while_the_world_is_scaled() {
+        use crate::draw::software::Framebuffer;
+
+        let screen = Rc::new(Framebuffer::new(8, 8));
+        let renderer = Renderer::new(screen.clone()).unwrap();
+        renderer.set_resolution_scale(0.5);
+        renderer.begin_frame();
+        renderer.on_layer(Layer::Background, |renderer| {
+            renderer.fill_rect(&Rect::new_from_x_y(0, 0, 8, 8), "#000000");
+        });
+        renderer.fill_rect(&Rect::new_from_x_y(3, 3, 1, 1), "#FFFFFF");
+        renderer.end_frame();
+
+        let pixels = screen.pixels();
+        assert_eq!(pixels[3 * 8 + 3], 0xFFFFFF);
+        assert_eq!(pixels[2 * 8 + 2], 0x000000);
+        assert_eq!(pixels[3 * 8 + 4], 0x000000);
+    }
 }
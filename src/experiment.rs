@@ -0,0 +1,94 @@
+use crate::config::GameConfig;
+use crate::profile::player_id;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Experiment {
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Variant {
+    pub name: String,
+    pub gravity: Option<i16>,
+    pub jump_speed: Option<i16>,
+    pub running_speed: Option<i16>,
+    pub obstacle_buffer: Option<i16>,
+}
+
+impl Variant {
+    fn apply(&self, config: &mut GameConfig) {
+        config.gravity = self.gravity.unwrap_or(config.gravity);
+        config.jump_speed = self.jump_speed.unwrap_or(config.jump_speed);
+        config.running_speed = self.running_speed.unwrap_or(config.running_speed);
+        config.obstacle_buffer = self.obstacle_buffer.unwrap_or(config.obstacle_buffer);
+    }
+}
+
+#[derive(Clone)]
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: String,
+}
+
+impl Experiment {
+    // 同じプレイヤーには常に同じバリアントを割り当てる
+    fn variant_for(&self, player_id: &str) -> Option<&Variant> {
+        if self.variants.is_empty() {
+            return None;
+        }
+        let index = fnv1a(&format!("{}:{}", self.name, player_id)) as usize % self.variants.len();
+        self.variants.get(index)
+    }
+}
+
+fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+pub fn assign(config: &mut GameConfig) -> Option<Assignment> {
+    let experiment = config.experiment.clone()?;
+    let variant = experiment.variant_for(&player_id())?;
+    variant.apply(config);
+    Some(Assignment {
+        experiment: experiment.name.clone(),
+        variant: variant.name.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_assignment_is_stable_per_player() {
+        let experiment = Experiment {
+            name: "jump_tuning".into(),
+            variants: vec![
+                Variant {
+                    name: "control".into(),
+                    ..Variant::default()
+                },
+                Variant {
+                    name: "floaty".into(),
+                    jump_speed: Some(-22),
+                    ..Variant::default()
+                },
+            ],
+        };
+
+        let first = experiment.variant_for("player").map(|v| v.name.clone());
+        let second = experiment.variant_for("player").map(|v| v.name.clone());
+        assert_eq!(first, second);
+
+        let assigned: std::collections::HashSet<String> = (0..32)
+            .filter_map(|i| experiment.variant_for(&format!("player{}", i)))
+            .map(|variant| variant.name.clone())
+            .collect();
+        assert_eq!(assigned.len(), 2);
+    }
+}
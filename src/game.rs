@@ -4,192 +4,3261 @@ mod test_browser;
 #[cfg(test)]
 use test_browser as browser;
 
+use crate::achievement;
+use crate::ambience::Ambience;
+use crate::analytics::Analytics;
+use crate::arena::{Arena, ArenaStats, OverflowPolicy};
+use crate::assets::{AssetManager, PendingSound};
+use crate::assist;
+use crate::beat_map::BeatMap;
 #[cfg(not(test))]
 use crate::browser;
+use crate::cloud_save::{self, CloudSave};
+use crate::collision::BroadPhase;
+use crate::config::{self, GameConfig, Physics};
+use crate::connectivity;
+use crate::difficulty::{Difficulty, DIFFICULTIES};
+use crate::draw::{Bitmap, RenderModifiers, TextAlign, TextStyle};
 use crate::engine;
 use crate::engine::Audio;
 use crate::engine::KeyState;
 use crate::engine::Sound;
-use crate::engine::SpriteSheet;
-use crate::engine::{Cell, Game, Image, Point, Rect, Renderer, Sheet};
-use crate::segment::{platform_and_stone, stone_and_platform, Disturbee, Obstacle};
+use crate::engine::{Animation, SpriteHandle, SpriteSheet};
+use crate::engine::{
+    CachedLayer, Camera, Cell, Effect, Game, Image, Layer, Point, Rect, Renderer, Scale, Shake,
+    Sheet,
+};
+use crate::experiment;
+use crate::history::{self, RunRecord};
+use crate::initials::{InitialsEndState, InitialsEntry};
+use crate::leaderboard::{self, HighScore, LeaderboardTable};
+use crate::level::{self, Level};
+use crate::mixer::{Bus, BUSES};
+use crate::mutator::MutatorRoulette;
+use crate::notifications;
+use crate::parallax::Scenery;
+use crate::particle::Particles;
+use crate::platform;
+use crate::pool::PoolStats;
+use crate::power_up::{self, PowerUpKind, PowerUps, POWER_UP_KINDS, SPEED_BOOST};
+use crate::profile::{self, Profile, MAX_PROFILES};
+use crate::quality::{QualityDetector, LOW_TIER_FRAME_RATE, QUALITIES};
+use crate::ramp::DifficultyRamp;
+use crate::random;
+use crate::registry::{self, ObstacleRegistry, SpawnContext};
+use crate::replay::{InputRecorder, Replay};
+use crate::run_log::{RunEventKind, RunLog, NEAR_MISS_CLEARANCE};
+use crate::score::Score;
+use crate::script::{Scheduler, Script, ScriptCommand, ScriptView};
+use crate::seasonal::{MonthDay, SeasonalCalendar, SeasonalEvent, SkinAssets};
+use crate::segment::{
+    enemy_animation_names, platform_and_stone, required_obstacle_composites, rhythm_segment,
+    rolling_ground, stone_and_platform, stone_with_coin_arc, vertical_climb, BarrierTemplate,
+    CoinTrail, Disturbee, FinishLine, Obstacle, ObstaclePool, PowerUp, COIN_BONUS, LOW_PLATFORM,
+    STONE_ON_GROUND,
+};
+use crate::segment_picker::{SegmentEntry, SegmentPicker};
+use crate::settings::{
+    self, Settings, MAXIMUM_GAME_SPEED, MINIMUM_GAME_SPEED, MUTE_KEY, OPTIONS_KEY, RETRO_FILTER_KEY,
+};
+use crate::speedrun::SpeedrunTimer;
+use crate::stamina::Stamina;
+use crate::tweak::{TweakEndState, TweakPanel, TWEAK_PANEL_KEY};
+use crate::tween::{lerp, Easing, Tween};
+use crate::ui::{
+    Button, Choice, Label, MenuAction, MenuItem, MenuList, Panel, Slider, Toggle, Widget,
+    WidgetEvent,
+};
+use crate::world_map::{WorldMap, WorldMapEndState};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use futures::channel::mpsc::UnboundedReceiver;
-use rand::{thread_rng, Rng};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use rand::rngs::StdRng;
+use rand::Rng;
 use std::collections::btree_map::Keys;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
-use web_sys::HtmlImageElement;
 
+const CANVAS_WIDTH: i16 = 600;
+// BGM の入れ替えにかける秒数
+const MUSIC_FADE: f64 = 1.5;
+const BACKGROUND_MUSIC: &str = "background_song.mp3";
+const BEAT_MAP: &str = "beatmap.json";
 const CANVAS_HEIGHT: i16 = 600;
-const TIMELINE_MINIMUM: i16 = 1000;
-const OBSTACLE_BUFFER: i16 = 20;
+// 起伏のない場所の地面の高さ
+const GROUND_LEVEL: i16 = CANVAS_HEIGHT;
+const DAY_LENGTH: u32 = 60 * 60;
+const OBSTACLE_CAPACITY: usize = 32;
+// 少年がこの高さより上に行ったらカメラを上げる
+const CAMERA_ANCHOR: i16 = 200;
+const CAMERA_MAX_RISE: i16 = 480;
+const CAMERA_EASING: i16 = 8;
+// 高く跳んだら地面から JUMP_ZOOM_START を超えた分だけ引いて、頂点まで映す
+const JUMP_ZOOM_START: i16 = 200;
+const JUMP_ZOOM_RANGE: i16 = 200;
+const JUMP_ZOOM_DEPTH: f64 = 0.15;
+const ZOOM_EASING: f64 = 8.0;
+const JUMP_PREVIEW_DOTS: u16 = 12;
+const JUMP_PREVIEW_RADIUS: i16 = 3;
+const JUMP_PREVIEW_COLOR: &str = "rgba(255, 255, 255, 0.35)";
+// 倒れたら少年に寄る
+const DEATH_ZOOM: f64 = 1.6;
+// 倒れたときの揺れ。0.5 秒ほどで収まる
+const KNOCKOUT_SHAKE_AMPLITUDE: f32 = 12.0;
+const KNOCKOUT_SHAKE_DECAY: f32 = 0.88;
+// 引くときは左下を動かさない。地面と少年の位置があまりずれない
+const ZOOM_FOCUS: Point = Point {
+    x: 0,
+    y: CANVAS_HEIGHT,
+};
+const HITBOXES_KEY: &str = "F1";
+const DEBUG_OVERLAY_KEY: &str = "F3";
+const FREE_CAMERA_KEY: &str = "F4";
+const FREE_CAMERA_SPEED: i16 = 8;
+const FREE_CAMERA_KEYS: [(&str, i16, i16); 4] = [
+    ("KeyW", 0, -1),
+    ("KeyA", -1, 0),
+    ("KeyS", 0, 1),
+    ("KeyD", 1, 0),
+];
+const FRAME_DATA_KEY: &str = "F7";
+const FREE_CAMERA_ZOOM_KEYS: [(&str, f64); 2] = [("KeyE", 1.1), ("KeyQ", 1.0 / 1.1)];
+const FREE_CAMERA_CENTER: Point = Point {
+    x: CANVAS_WIDTH / 2,
+    y: CANVAS_HEIGHT / 2,
+};
+
+pub struct Walk {
+    obstacle_sheet: Rc<SpriteSheet>,
+    boy: RedHatBoy,
+    backgrounds: [Image; 2],
+    background_layer: CachedLayer,
+    scenery: Scenery,
+    obstacles: Arena<Box<dyn Obstacle>>,
+    // 画面から出た石と足場を取っておき、次のセグメントで使い回す
+    pool: ObstaclePool,
+    broad_phase: BroadPhase,
+    registry: ObstacleRegistry,
+    stone: BarrierTemplate,
+    // 敵は障害物のシートとは別の、自分のシートでアニメーションする
+    enemy_sheet: Rc<SpriteSheet>,
+    timeline: i16,
+    elapsed_frames: u32,
+    camera_y: i16,
+    zoom: f64,
+    // 拡大縮小しても画面上で動かない点
+    zoom_focus: Point,
+    shake: Shake,
+    free_camera: Option<Camera>,
+    seasonal_event: Option<SeasonalEvent>,
+    // 設定から読んだ値 (base_config) に難易度を当てたものが config
+    base_config: GameConfig,
+    difficulty: Difficulty,
+    config: GameConfig,
+    stamina: Option<Stamina>,
+    score: Score,
+    assets: AssetManager,
+    background_music: Option<PendingSound>,
+    music: Option<Sound>,
+    analytics: Analytics,
+    bookmark: Option<Bookmark>,
+    // ギャラリーから選んだセグメント。Some の間はこれだけが続けて出てくる
+    practice_segment: Option<usize>,
+    speedrun: Option<SpeedrunTimer>,
+    roulette: Option<MutatorRoulette>,
+    jump_preview: bool,
+    run_log: RunLog,
+    // 今跨いでいる地面の障害物の上を、いちばん低く越えたときの隙間
+    hazard_clearance: Option<i16>,
+    // 走り出すときに共有の乱数へ与えたシード。同じシードと入力でランを再現できる
+    seed: u64,
+    recorder: InputRecorder,
+    // Some の間は記録した入力で走る
+    replay: Option<Replay>,
+    // タイトルの「Daily」から始めたラン。シードはその日の日付で決まる
+    daily: bool,
+    // 少年を倒したもの
+    death_cause: Option<&'static str>,
+    power_ups: PowerUps,
+    // 走った距離に応じて速さと障害物の間隔を変える
+    ramp: DifficultyRamp,
+    // 最近出たセグメントを覚えて、次を選ぶ
+    segments: SegmentPicker,
+    // 走り出すときから効かせるパワーアップ。記録にも残す
+    loadout: Option<PowerUpKind>,
+    // このランで拾ったコインの数。終わったらプロフィールの財布に入れる
+    coins: u32,
+    // リズム区間の譜面。BGM と組になっている
+    beat_map: BeatMap,
+    ambience: Ambience,
+    // セグメントから始めた、時間をかけて障害物を出していく流れ
+    scripts: Scheduler,
+    // レースの途中。両方の端末で同じになるよう、拍は音の時計ではなく進んだティックで数える
+    lockstep: bool,
+    // レベルモードで遊べるコース。level はそのうち今走っている番号
+    levels: Vec<Level>,
+    level: Option<usize>,
+    // ゴールの線を置いたら、その先のセグメントは作らない
+    finish_placed: bool,
+    // ゴールの線を越えたときの経過フレーム
+    finished_at: Option<u32>,
+    // ゴールのあと、止まるまで足を緩めていく
+    braking: Option<Tween>,
+    confetti: Particles,
+    // 勝利の流れが終わった。結果の画面に移る
+    level_complete: bool,
+}
+
+// セグメントにパワーアップの箱を一つ置く確率と、セグメントの始まりからの位置
+const POWER_UP_CHANCE: f64 = 0.25;
+const POWER_UP_OFFSET: i16 = 200;
+const POWER_UP_HEIGHT: i16 = 420;
+const SHIELD_COLOR: &str = "rgba(95, 184, 255, 0.35)";
+const BOY_OUTLINE_WIDTH: i16 = 2;
+
+// ギャラリーに並べる名前と出やすさ。番号は Walk::build_segment と揃える
+const SEGMENTS: [SegmentEntry; 10] = [
+    SegmentEntry {
+        name: "Stone and platform",
+        weight: 10,
+        tier: 0,
+    },
+    SegmentEntry {
+        name: "Platform and stone",
+        weight: 10,
+        tier: 0,
+    },
+    SegmentEntry {
+        name: "Floating platform",
+        weight: 8,
+        tier: 0,
+    },
+    SegmentEntry {
+        name: "Rolling ground",
+        weight: 6,
+        tier: 0,
+    },
+    SegmentEntry {
+        name: "Vertical climb",
+        weight: 5,
+        tier: 1,
+    },
+    SegmentEntry {
+        name: "Coin arc",
+        weight: 6,
+        tier: 0,
+    },
+    SegmentEntry {
+        name: "Projectile",
+        weight: 6,
+        tier: 1,
+    },
+    SegmentEntry {
+        name: "Patrolling dog",
+        weight: 5,
+        tier: 2,
+    },
+    SegmentEntry {
+        name: "Rhythm steps",
+        weight: 3,
+        tier: 1,
+    },
+    SegmentEntry {
+        name: "Volley",
+        weight: 3,
+        tier: 2,
+    },
+];
+const VOLLEY_SEGMENT: usize = 9;
+// 台本で出す障害物は、予告が見えるように画面の右端からこれだけ外に置く
+const SCRIPT_SPAWN_MARGIN: i16 = 300;
+const VOLLEY_HEIGHT: i16 = 560;
+// セグメントができてから石のあたりに着くまでの距離 (m)
+const VOLLEY_LEAD: u32 = 18;
+const VOLLEY_GAP: u32 = 50;
+
+// 石を越えたところへ火の玉を投げ、着地する頃に矢で追い打ちをかける。
+// 速さが変わっても石との間が崩れないよう、最初は走った距離で待つ
+async fn volley(script: Script) {
+    let start = script.view().distance;
+    script
+        .wait_until(move |view| view.distance >= start + VOLLEY_LEAD)
+        .await;
+    script.spawn("fireball", VOLLEY_HEIGHT);
+    script.wait(VOLLEY_GAP).await;
+    script.spawn("arrow", VOLLEY_HEIGHT);
+}
+
+// ゴールの旗は、最後のセグメントの続きからこれだけ右に立てる
+const FINISH_OFFSET: i16 = 300;
+const BRAKING_FRAMES: u32 = 45;
+const CONFETTI_COUNT: usize = 60;
+// 画面の下の両端から打ち上げる
+const CONFETTI_CANNONS: [Point; 2] = [
+    Point {
+        x: 100,
+        y: CANVAS_HEIGHT,
+    },
+    Point {
+        x: 500,
+        y: CANVAS_HEIGHT,
+    },
+];
+const RESULTS_DELAY: u32 = 90;
+const LEVEL_HUD_POSITION: Point = Point { x: 300, y: 30 };
+
+// ゴールを越えたら足を緩め、止まって喜ぶところで紙吹雪を撒き、少し見せてから結果に移る。
+// 跳んでいる最中に止まったときは、着地して走りの姿に戻るのを待つ
+async fn victory(script: Script) {
+    script.request(ScriptCommand::Decelerate);
+    script
+        .wait_until(|view| view.speed == 0 && view.running)
+        .await;
+    script.request(ScriptCommand::Celebrate);
+    script.request(ScriptCommand::Confetti);
+    script.wait(RESULTS_DELAY).await;
+    script.request(ScriptCommand::ShowResults);
+}
+
+// 練習モードのしおり。走りの結果を左右する状態だけを写し、背景の飾りなどは戻さない
+struct Bookmark {
+    boy: BoySnapshot,
+    backgrounds: [Image; 2],
+    obstacles: Vec<Box<dyn Obstacle>>,
+    timeline: i16,
+    elapsed_frames: u32,
+    camera_y: i16,
+    stamina: Option<Stamina>,
+    score: Score,
+    power_ups: PowerUps,
+    segments: SegmentPicker,
+    rng: StdRng,
+}
+
+impl Walk {
+    fn velocity(&self) -> i16 {
+        -self.boy.walking_speed()
+    }
+
+    fn generate_next_segment(&mut self) {
+        if let Some(length) = self.current_level().map(|level| level.length) {
+            if self.finish_placed {
+                return;
+            }
+            if self.score.distance() >= length {
+                self.place_finish_line();
+                return;
+            }
+        }
+        let mut rng = random::rng();
+        let distance = self.score.distance();
+        let next_segment = match self.practice_segment {
+            Some(index) => index,
+            None => self.segments.pick(&SEGMENTS, distance, &mut rng),
+        };
+
+        let offset = self.timeline + self.ramp.obstacle_buffer(self.config.obstacle_buffer);
+        let mut next_obstacles = self.build_segment(next_segment, offset, &mut rng);
+        if next_segment == VOLLEY_SEGMENT {
+            self.scripts.start(volley);
+        }
+        if self.practice_segment.is_none() && rng.gen_bool(POWER_UP_CHANCE) {
+            let kind = POWER_UP_KINDS[rng.gen_range(0..POWER_UP_KINDS.len())];
+            next_obstacles.push(Box::new(PowerUp::new(
+                kind,
+                Point {
+                    x: offset + POWER_UP_OFFSET,
+                    y: POWER_UP_HEIGHT,
+                },
+            )));
+        }
+
+        self.timeline = rightmost(&next_obstacles);
+        self.obstacles.extend(next_obstacles);
+    }
+
+    // レベルの終わり。ここから先はコースを作らない
+    fn place_finish_line(&mut self) {
+        let offset = self.timeline + self.ramp.obstacle_buffer(self.config.obstacle_buffer);
+        let finish: Box<dyn Obstacle> = Box::new(FinishLine::new(offset + FINISH_OFFSET));
+        self.timeline = finish.right();
+        self.obstacles.extend(vec![finish]);
+        self.finish_placed = true;
+    }
+
+    fn current_level(&self) -> Option<&Level> {
+        self.levels.get(self.level?)
+    }
+
+    // index は SEGMENTS の番号。offset から右に並べる
+    fn build_segment(&self, index: usize, offset: i16, rng: &mut StdRng) -> Vec<Box<dyn Obstacle>> {
+        match index {
+            0 => stone_and_platform(&self.pool, &self.stone, self.obstacle_sheet.clone(), offset),
+            1 => platform_and_stone(&self.pool, &self.stone, self.obstacle_sheet.clone(), offset),
+            2 => {
+                const INITIAL_PLATFORM_OFFSET: i16 = 150;
+                let kinds = self.obstacle_sheet.composite_names();
+                let kind = kinds[rng.gen_range(0..kinds.len())];
+                self.spawn(
+                    kind,
+                    Point {
+                        x: offset + INITIAL_PLATFORM_OFFSET,
+                        y: LOW_PLATFORM,
+                    },
+                )
+            }
+            3 => rolling_ground(offset),
+            4 => vertical_climb(&self.pool, self.obstacle_sheet.clone(), offset),
+            5 => stone_with_coin_arc(&self.pool, &self.stone, &self.config.physics(), offset),
+            6 => {
+                const PROJECTILE_OFFSET: i16 = 300;
+                const PROJECTILE_HEIGHT: i16 = 560;
+                let kind = if rng.gen_bool(0.5) {
+                    "fireball"
+                } else {
+                    "arrow"
+                };
+                self.spawn(
+                    kind,
+                    Point {
+                        x: offset + PROJECTILE_OFFSET,
+                        y: PROJECTILE_HEIGHT,
+                    },
+                )
+            }
+            7 => {
+                const DOG_OFFSET: i16 = 300;
+                self.spawn(
+                    "dog",
+                    Point {
+                        x: offset + DOG_OFFSET,
+                        y: GROUND_LEVEL,
+                    },
+                )
+            }
+            8 => rhythm_segment(
+                &self.beat_map,
+                &self.ramped_physics(),
+                offset,
+                self.beat_clock(),
+            ),
+            // 飛び道具は走りながら台本 (volley) で出す
+            VOLLEY_SEGMENT => {
+                const VOLLEY_STONE_OFFSET: i16 = 150;
+                self.spawn(
+                    "stone",
+                    Point {
+                        x: offset + VOLLEY_STONE_OFFSET,
+                        y: STONE_ON_GROUND,
+                    },
+                )
+            }
+            _ => vec![],
+        }
+    }
+
+    // 台本を 1 ティック進め、頼まれた障害物を画面の右の外に置く
+    fn run_scripts(&mut self) {
+        let view = ScriptView {
+            distance: self.score.distance(),
+            boy_x: self.boy.bounding_box().x(),
+            speed: self.boy.walking_speed(),
+            running: self.boy.is_running(),
+        };
+        for command in self.scripts.tick(view) {
+            match command {
+                ScriptCommand::Spawn { name, height } => {
+                    let position = Point {
+                        x: CANVAS_WIDTH + SCRIPT_SPAWN_MARGIN,
+                        y: height,
+                    };
+                    let obstacles = self.spawn(name, position);
+                    self.obstacles.extend(obstacles);
+                }
+                ScriptCommand::Decelerate => {
+                    self.braking = Some(Tween::new(
+                        f32::from(self.boy.walking_speed()),
+                        0.0,
+                        BRAKING_FRAMES,
+                        Easing::EaseOut,
+                    ));
+                }
+                ScriptCommand::Celebrate => self.boy.celebrate(),
+                ScriptCommand::Confetti => CONFETTI_CANNONS
+                    .iter()
+                    .for_each(|cannon| self.confetti.confetti(*cannon, CONFETTI_COUNT)),
+                ScriptCommand::ShowResults => self.level_complete = true,
+            }
+        }
+    }
+
+    fn recycle_obstacles(&mut self) {
+        self.scripts.clear();
+        let pool = &self.pool;
+        self.obstacles
+            .drain()
+            .for_each(|obstacle| pool.recycle(obstacle));
+    }
+
+    // 拍を数える時計 (秒)。BGM が流れていればその頭から、なければ音の時計そのもの
+    fn beat_clock(&self) -> f64 {
+        if self.lockstep {
+            return f64::from(self.elapsed_frames) / f64::from(FRAMES_PER_SECOND);
+        }
+        let audio = self.boy.audio();
+        audio.music_time().unwrap_or_else(|| audio.current_time())
+    }
+
+    // 一つのセグメントだけを繰り返すランにする
+    fn practice_segment(&mut self, index: usize) {
+        self.practice_segment = Some(index);
+        self.recycle_obstacles();
+        self.timeline = 0;
+        self.generate_next_segment();
+    }
+
+    // リプレイなら記録したシードを、デイリーならその日のシードを、レベルならレベルのシードを、
+    // URL でシードが指定されていればそれを、どれでもなければ新しいシードを共有の乱数へ与える。
+    // 変化のルーレットはそのあとの乱数から作り直す
+    fn seed_run(&mut self) {
+        self.seed = self
+            .replay
+            .as_ref()
+            .map(Replay::seed)
+            .or_else(|| self.daily.then(|| random::daily_seed(browser::timestamp())))
+            .or_else(|| self.current_level().map(|level| level.seed))
+            .or_else(random::requested_seed)
+            .unwrap_or_else(|| random::rng().gen());
+        random::seed(self.seed);
+        if self.roulette.is_some() {
+            self.roulette = Some(MutatorRoulette::new(random::rng()));
+        }
+    }
+
+    // 倒れたランを履歴に残す。練習中のランは再現できないので入力を付けない
+    fn run_record(&self) -> RunRecord {
+        RunRecord {
+            date: browser::timestamp(),
+            distance: self.score.distance(),
+            score: self.score.total(),
+            seed: self.seed,
+            cause: self.death_cause.unwrap_or("Unknown").into(),
+            difficulty: self.difficulty,
+            inputs: (!self.is_practice()).then(|| self.recorder.changes().to_vec()),
+            loadout: self.loadout,
+        }
+    }
+
+    fn is_replay(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    // 練習中のランは最高記録に数えない
+    fn is_practice(&self) -> bool {
+        self.config.practice || self.practice_segment.is_some()
+    }
+
+    // デバッグ用のフリーカメラが有効ならそちらを優先する
+    fn camera(&self) -> Camera {
+        self.free_camera.unwrap_or_else(|| {
+            let camera = Camera::new(Point {
+                x: 0,
+                y: self.camera_y,
+            })
+            .zoom_at(self.zoom, &self.zoom_focus);
+            self.shake.apply(camera)
+        })
+    }
+
+    fn to_world(&self, point: &Point) -> Point {
+        self.camera().to_world(point)
+    }
+
+    fn inspect(&self, point: &Point) -> Vec<String> {
+        let point = self.to_world(point);
+        let boy = self
+            .boy
+            .bounding_box()
+            .contains(&point)
+            .then(|| self.boy.inspect());
+        boy.into_iter()
+            .chain(
+                self.obstacles
+                    .iter()
+                    .filter_map(|obstacle| obstacle.inspect(&point)),
+            )
+            .collect()
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.on_layer(Layer::Background, |renderer| {
+            self.draw_backgrounds(renderer);
+            self.scenery.draw(renderer);
+        });
+        let camera = self.camera();
+        renderer.on_layer(Layer::Player, |renderer| {
+            renderer.with_camera(&camera, |renderer| {
+                // シールドは泡で見せるので、縁取りはそれ以外の効いているものの色にする
+                if let Some(kind) = POWER_UP_KINDS
+                    .iter()
+                    .find(|kind| **kind != PowerUpKind::Shield && self.power_ups.is_active(**kind))
+                {
+                    renderer.with_outline(kind.color(), BOY_OUTLINE_WIDTH, |renderer| {
+                        self.boy.draw_sprite(renderer)
+                    });
+                }
+                self.boy.draw(renderer);
+                if self.power_ups.is_active(PowerUpKind::Shield) {
+                    self.draw_shield(renderer);
+                }
+            });
+        });
+        self.obstacles.iter().for_each(|obstacle| {
+            renderer.on_layer(obstacle.layer(), |renderer| {
+                renderer.with_camera(&camera, |renderer| obstacle.draw(renderer));
+            });
+        });
+        renderer.on_layer(Layer::Foreground, |renderer| {
+            self.draw_telegraphs(renderer);
+            if self.jump_preview {
+                renderer.with_camera(&camera, |renderer| self.draw_jump_preview(renderer));
+            }
+            self.confetti.draw(renderer);
+        });
+        if let Some(level) = self.current_level() {
+            renderer.draw_text(
+                &format!(
+                    "{}  {} / {} m",
+                    level.name,
+                    self.score.distance().min(level.length),
+                    level.length
+                ),
+                &LEVEL_HUD_POSITION,
+                TextStyle::new(TITLE_TEXT_COLOR).align(TextAlign::Center),
+            );
+        }
+        if let Some(stamina) = &self.stamina {
+            stamina.draw(renderer);
+        }
+        self.score.draw(renderer);
+        self.power_ups.draw(renderer);
+        if let Some(speedrun) = &self.speedrun {
+            speedrun.draw(renderer);
+        }
+        if let Some(roulette) = &self.roulette {
+            roulette.draw(renderer);
+        }
+
+        if self.is_night() {
+            renderer.set_post_effect(Effect::NightTint);
+        } else {
+            renderer.set_post_effect(Effect::None);
+        }
+    }
+
+    // 2枚の背景はレイヤーに並べて一度だけ描き、左端の位置に転送する
+    fn draw_backgrounds(&self, renderer: &Renderer) {
+        let origin = self
+            .backgrounds
+            .iter()
+            .map(|background| background.bounding_box().position)
+            .min_by_key(|position| position.x)
+            .unwrap_or_default();
+
+        renderer.draw_layer(&self.background_layer, &origin, |renderer| {
+            let mut x = 0;
+            self.backgrounds.iter().for_each(|background| {
+                renderer.draw_entire_image(background.element(), &Point { x, y: 0 });
+                renderer.draw_bounding_box(&Rect::new(
+                    Point { x, y: 0 },
+                    background.bounding_box().width,
+                    background.bounding_box().height,
+                ));
+                x += background.bounding_box().width;
+            });
+        });
+    }
+
+    // 走っていたところからジャンプやスライディングに移ったら記録する
+    fn log_move(&mut self, was_running: bool) {
+        let kind = if !was_running {
+            return;
+        } else if self.boy.is_jumping() {
+            RunEventKind::Jump
+        } else if self.boy.is_sliding() {
+            RunEventKind::Slide
+        } else {
+            return;
+        };
+        self.run_log.push(kind, self.score.distance());
+    }
+
+    // 地面の障害物を越え終わったとき、すれすれだったら記録する
+    fn watch_near_misses(&mut self) {
+        let boy = self.boy.bounding_box();
+        let clearance = self
+            .obstacles
+            .iter()
+            .filter_map(|obstacle| obstacle.ground_hazard())
+            .filter(|hazard| hazard.x() < boy.right() && hazard.right() > boy.x())
+            .map(|hazard| hazard.y() - boy.bottom())
+            .min();
+        match (clearance, self.hazard_clearance) {
+            (Some(clearance), closest) => {
+                self.hazard_clearance =
+                    Some(closest.map_or(clearance, |closest| closest.min(clearance)));
+            }
+            (None, Some(closest)) => {
+                if (0..NEAR_MISS_CLEARANCE).contains(&closest) && !self.boy.is_down() {
+                    self.run_log
+                        .push(RunEventKind::NearMiss, self.score.distance());
+                }
+                self.hazard_clearance = None;
+            }
+            (None, None) => {}
+        }
+    }
+
+    fn jump_key(&self) -> &'static str {
+        if self.config.inverted_controls {
+            "ArrowDown"
+        } else {
+            "Space"
+        }
+    }
+
+    fn slide_key(&self) -> &'static str {
+        if self.config.inverted_controls {
+            "Space"
+        } else {
+            "ArrowDown"
+        }
+    }
+
+    // 自動ジャンプの補助が今押すべきキー
+    fn auto_jump_key(&self) -> Option<&'static str> {
+        let should_jump = self.boy.is_running()
+            && assist::should_jump(
+                &self.boy.bounding_box(),
+                &self.boy.physics(),
+                self.boy.walking_speed(),
+                self.obstacles
+                    .iter()
+                    .filter_map(|obstacle| obstacle.ground_hazard()),
+            );
+        should_jump.then(|| self.jump_key())
+    }
+
+    // 今跳んだら体の中心がたどる放物線。障害物は走る速さで流れてくるので、その分だけ横に伸ばす
+    fn draw_jump_preview(&self, renderer: &Renderer) {
+        if !self.boy.is_running() {
+            return;
+        }
+        let bounding_box = self.boy.bounding_box();
+        let arc = CoinTrail::Arc {
+            from: Point {
+                x: bounding_box.x() + bounding_box.width / 2,
+                y: bounding_box.y() + bounding_box.height / 2,
+            },
+            count: JUMP_PREVIEW_DOTS,
+        };
+        let physics = Physics {
+            running_speed: self.boy.walking_speed(),
+            ..self.boy.physics()
+        };
+        arc.positions(&physics).iter().for_each(|position| {
+            renderer.fill_circle(position, JUMP_PREVIEW_RADIUS, JUMP_PREVIEW_COLOR);
+        });
+    }
+
+    // 画面に入ってくる前の速い障害物を、右端の「!」で高さとともに知らせる
+    fn draw_telegraphs(&self, renderer: &Renderer) {
+        let camera = self.camera();
+        let view = camera.view(CANVAS_WIDTH, CANVAS_HEIGHT);
+        self.obstacles
+            .iter()
+            .filter_map(|obstacle| obstacle.telegraph())
+            .for_each(|(target, telegraph)| {
+                if let Some(marker) = telegraph.marker(&target, &view) {
+                    let marker = camera.to_screen(&Point {
+                        x: view.x() + marker.x,
+                        y: view.y() + marker.y,
+                    });
+                    renderer.draw_text("!", &marker, telegraph.color);
+                }
+            });
+    }
+
+    fn is_night(&self) -> bool {
+        self.config.marathon || (self.elapsed_frames / DAY_LENGTH) % 2 == 1
+    }
+
+    pub fn knocked_out(&self) -> bool {
+        self.boy.knocked_out()
+    }
+
+    pub fn distance(&self) -> u32 {
+        self.score.distance()
+    }
+
+    // レースのスタート。待たずに走り出し、拍は音の時計ではなく進んだティックで数える
+    pub fn start_race(&mut self) {
+        self.lockstep = true;
+        self.boy.run_right();
+    }
+
+    // レースで両方の端末の展開がそろっているかを確かめる値。走りの結果に効く状態だけを入れる
+    pub fn hash_state(&self, hasher: &mut impl Hasher) {
+        let boy = self.boy.bounding_box();
+        (boy.x(), boy.y(), boy.width, boy.height).hash(hasher);
+        (self.timeline, self.elapsed_frames, self.coins).hash(hasher);
+        (
+            self.score.total(),
+            self.score.traveled(),
+            self.knocked_out(),
+        )
+            .hash(hasher);
+        self.obstacles
+            .iter()
+            .for_each(|obstacle| (obstacle.left(), obstacle.right()).hash(hasher));
+    }
+
+    // 相手の少年の頭の上の、画面での位置。相手の走った分だけ自分のコースの上でずらす
+    pub fn rival_anchor(&self, rival: &Walk) -> Point {
+        let bounding_box = rival.boy.bounding_box();
+        self.camera().to_screen(&Point {
+            x: bounding_box.x() + bounding_box.width / 2 + self.rival_offset(rival),
+            y: bounding_box.y(),
+        })
+    }
+
+    // 相手の少年を自分のコースに重ね、姿の色で縁取って描く
+    pub fn draw_rival(&self, renderer: &Renderer, rival: &Walk, color: &'static str) {
+        let camera = self.camera();
+        let offset = Point {
+            x: self.rival_offset(rival),
+            y: 0,
+        };
+        renderer.on_layer(Layer::Player, |renderer| {
+            renderer.with_camera(&camera, |renderer| {
+                renderer.translate(&offset, |renderer| {
+                    renderer.with_outline(color, BOY_OUTLINE_WIDTH, |renderer| {
+                        rival.boy.draw_sprite(renderer)
+                    });
+                    rival.boy.draw(renderer);
+                });
+            });
+        });
+    }
+
+    fn rival_offset(&self, rival: &Walk) -> i16 {
+        let ahead = i64::from(rival.score.traveled()) - i64::from(self.score.traveled());
+        ahead.clamp(i64::from(i16::MIN), i64::from(i16::MAX)) as i16
+    }
+
+    fn set_bookmark(&mut self) {
+        self.bookmark = Some(Bookmark {
+            boy: self.boy.snapshot(),
+            backgrounds: self.backgrounds.clone(),
+            obstacles: self
+                .obstacles
+                .iter()
+                .map(|obstacle| obstacle.snapshot())
+                .collect(),
+            timeline: self.timeline,
+            elapsed_frames: self.elapsed_frames,
+            camera_y: self.camera_y,
+            stamina: self.stamina.clone(),
+            score: self.score.clone(),
+            power_ups: self.power_ups.clone(),
+            segments: self.segments.clone(),
+            rng: random::snapshot(),
+        });
+    }
+
+    // しおりがなければ何もせず false を返す
+    fn return_to_bookmark(&mut self) -> bool {
+        let Some(bookmark) = &self.bookmark else {
+            return false;
+        };
+        self.boy.restore(&bookmark.boy);
+        self.backgrounds = bookmark.backgrounds.clone();
+        self.scripts.clear();
+        let pool = &self.pool;
+        self.obstacles
+            .drain()
+            .for_each(|obstacle| pool.recycle(obstacle));
+        self.obstacles.extend(
+            bookmark
+                .obstacles
+                .iter()
+                .map(|obstacle| obstacle.snapshot()),
+        );
+        self.timeline = bookmark.timeline;
+        self.elapsed_frames = bookmark.elapsed_frames;
+        self.camera_y = bookmark.camera_y;
+        self.stamina = bookmark.stamina.clone();
+        self.score = bookmark.score.clone();
+        self.power_ups = bookmark.power_ups.clone();
+        self.segments = bookmark.segments.clone();
+        random::restore(bookmark.rng.clone());
+        true
+    }
+
+    // 走っている途中でデコードが終わったら、その場で流し始める
+    fn poll_music(&mut self, walking: bool) {
+        let Some(pending) = &mut self.background_music else {
+            return;
+        };
+        match pending.take() {
+            Some(Ok(music)) => {
+                self.music = Some(music);
+                self.background_music = None;
+                if walking {
+                    self.start_music();
+                }
+            }
+            Some(Err(err)) => {
+                log!("Could not load background music {:#?}", err);
+                self.background_music = None;
+            }
+            None => {}
+        }
+    }
+
+    fn start_music(&self) {
+        if let Some(music) = &self.music {
+            if let Err(err) = self.boy.audio().play_music(music, MUSIC_FADE) {
+                log!("Could not play background music {:#?}", err);
+            }
+        }
+    }
+
+    fn stop_music(&self) {
+        if let Err(err) = self.boy.audio().stop_music(MUSIC_FADE) {
+            log!("Could not stop background music {:#?}", err);
+        }
+    }
+
+    fn draw_music_progress(&self, renderer: &Renderer) {
+        if let Some(pending) = &self.background_music {
+            renderer.draw_text(
+                &format!("Loading music {}%", (pending.progress() * 100.0) as u32),
+                &Point { x: 20, y: 20 },
+                TITLE_TEXT_COLOR,
+            );
+        }
+    }
+
+    // 拾ったコインはその場で点にして音を鳴らす。季節のイベント中は倍率がかかる
+    fn collect_pickups(&mut self) -> u32 {
+        let pickups = self.boy.take_pickups();
+        if pickups == 0 {
+            return 0;
+        }
+        let multiplier = self
+            .seasonal_event
+            .as_ref()
+            .map_or(1, |event| event.collectible_multiplier);
+        self.score.add_bonus(pickups * COIN_BONUS * multiplier);
+        if let Err(err) = self
+            .boy
+            .audio()
+            .play_tone(PICKUP_FREQUENCY, PICKUP_DURATION)
+        {
+            log!("Could not play pickup sound {:#?}", err);
+        }
+        pickups
+    }
+
+    // 拾ったパワーアップを効かせ始める。効いているものは残り時間を減らす
+    fn update_power_ups(&mut self) {
+        self.power_ups.update();
+        for kind in self.boy.take_power_ups() {
+            self.power_ups.activate(kind);
+            if let Err(err) = self
+                .boy
+                .audio()
+                .play_tone(POWER_UP_FREQUENCY, POWER_UP_DURATION)
+            {
+                log!("Could not play power-up sound {:#?}", err);
+            }
+        }
+        if self.power_ups.is_active(PowerUpKind::Magnet) {
+            let boy_box = self.boy.bounding_box();
+            let center = Point {
+                x: boy_box.x() + boy_box.width / 2,
+                y: boy_box.y() + boy_box.height / 2,
+            };
+            self.obstacles
+                .iter_mut()
+                .for_each(|obstacle| obstacle.attract(&center));
+        }
+        self.boy.shield = self.power_ups.is_active(PowerUpKind::Shield);
+    }
+
+    // 距離で上がった分を含めた、今の本来の走る速さ
+    fn ramped_physics(&self) -> Physics {
+        let mut physics = self.config.physics();
+        physics.running_speed = self.ramp.running_speed(physics.running_speed);
+        physics
+    }
+
+    // スタミナが尽きている間は走る速さを落としていき、0 で倒れる。加速中は上乗せする
+    fn update_stamina(&mut self, pickups: u32) {
+        // ゴールのあとは距離にも体力にもよらず、止まるまで緩める
+        if let Some(braking) = &mut self.braking {
+            braking.update();
+            let running_speed = braking.value().round() as i16;
+            self.boy.set_physics(Physics {
+                running_speed,
+                ..self.config.physics()
+            });
+            return;
+        }
+        self.ramp.update(self.score.distance());
+        let full_speed = self.ramped_physics().running_speed;
+        let speed = match &mut self.stamina {
+            Some(stamina) => {
+                stamina.update(pickups);
+                stamina.running_speed(full_speed)
+            }
+            None => full_speed,
+        };
+        let boost = if self.power_ups.is_active(PowerUpKind::SpeedBoost) {
+            SPEED_BOOST
+        } else {
+            0
+        };
+        if speed == 0 {
+            self.boy.collapse();
+            self.death_cause = Some("Exhaustion");
+        } else if speed + boost != self.boy.physics().running_speed {
+            self.boy.set_physics(Physics {
+                running_speed: speed + boost,
+                ..self.config.physics()
+            });
+        }
+    }
+
+    // シールドは少年を包む泡として描く
+    fn draw_shield(&self, renderer: &Renderer) {
+        const SHIELD_MARGIN: i16 = 12;
+        let boy_box = self.boy.bounding_box();
+        renderer.fill_ellipse(
+            &Point {
+                x: boy_box.x() + boy_box.width / 2,
+                y: boy_box.y() + boy_box.height / 2,
+            },
+            boy_box.width / 2 + SHIELD_MARGIN,
+            boy_box.height / 2 + SHIELD_MARGIN,
+            SHIELD_COLOR,
+        );
+    }
+
+    // 縦に登る区間では少年を追って上下にスクロールする
+    fn follow_camera(&mut self) {
+        let target = (self.boy.pos_y() - CAMERA_ANCHOR).clamp(-CAMERA_MAX_RISE, 0);
+        self.camera_y = ease_toward(self.camera_y, target);
+
+        self.shake.update();
+        if self.boy.take_impact() {
+            self.shake = Shake::new(KNOCKOUT_SHAKE_AMPLITUDE, KNOCKOUT_SHAKE_DECAY);
+        }
+
+        let (zoom, focus) = if self.boy.is_down() {
+            let bounding_box = self.boy.bounding_box();
+            let boy = Point {
+                x: bounding_box.x() + bounding_box.width / 2,
+                y: bounding_box.y() + bounding_box.height / 2 - self.camera_y,
+            };
+            (DEATH_ZOOM, boy)
+        } else {
+            let height =
+                (self.boy.height_above_ground() - JUMP_ZOOM_START).clamp(0, JUMP_ZOOM_RANGE);
+            (
+                1.0 - JUMP_ZOOM_DEPTH * f64::from(height) / f64::from(JUMP_ZOOM_RANGE),
+                ZOOM_FOCUS,
+            )
+        };
+        self.zoom_focus = Point {
+            x: ease_toward(self.zoom_focus.x, focus.x),
+            y: ease_toward(self.zoom_focus.y, focus.y),
+        };
+        self.zoom += (zoom - self.zoom) / ZOOM_EASING;
+        if (zoom - self.zoom).abs() < 0.001 {
+            self.zoom = zoom;
+        }
+    }
+
+    fn set_background(&mut self, background: Bitmap) -> Result<()> {
+        let background_width = background.width() as i16;
+        if background.width() * 2 == self.background_layer.width()
+            && background.height() == self.background_layer.height()
+        {
+            self.background_layer.invalidate();
+        } else {
+            self.background_layer = CachedLayer::new(background.width() * 2, background.height())?;
+        }
+        self.backgrounds = [
+            Image::new(background.clone(), Point { x: 0, y: 0 }),
+            Image::new(
+                background,
+                Point {
+                    x: background_width,
+                    y: 0,
+                },
+            ),
+        ];
+        Ok(())
+    }
+
+    async fn apply_seasonal_event(&mut self, event: SeasonalEvent) -> Result<()> {
+        log!("Seasonal event {} is active", event.name);
+        if let Some(background) = &event.background {
+            let background = self.assets.image(background).await?;
+            self.set_background(background)?;
+        }
+        if let Some(skin) = &event.skin {
+            let (sheet, image) = load_sheet(&mut self.assets, skin).await?;
+            self.boy.set_sheet(sheet, image)?;
+        }
+        if let Some(obstacle_skin) = &event.obstacle_skin {
+            let (sheet, image) = load_sheet(&mut self.assets, obstacle_skin).await?;
+            self.set_obstacle_sheet(Rc::new(SpriteSheet::new(sheet, image)))?;
+        }
+        self.seasonal_event = Some(event);
+        Ok(())
+    }
+
+    fn start_speedrun(&mut self, best: BTreeMap<u32, f64>) {
+        match browser::now() {
+            Ok(now) => {
+                self.speedrun = Some(SpeedrunTimer::new(now, &self.config.split_distances, best))
+            }
+            Err(err) => {
+                log!("Could not start the speedrun timer {:#?}", err);
+            }
+        }
+    }
+
+    fn update_speedrun(&mut self) {
+        let distance = self.score.distance();
+        if let (Some(speedrun), Ok(now)) = (&mut self.speedrun, browser::now()) {
+            speedrun.update(now, distance);
+        }
+    }
+
+    fn update_mutators(&mut self) {
+        let distance = self.score.distance();
+        if let Some(roulette) = &mut self.roulette {
+            if roulette.update(distance, &mut self.config) {
+                self.boy.set_physics(self.config.physics());
+                self.boy.set_scale(Scale::uniform(self.config.boy_scale));
+            }
+        }
+    }
+
+    fn set_config(&mut self, config: GameConfig) {
+        self.base_config = config;
+        self.apply_difficulty();
+    }
+
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty;
+        self.apply_difficulty();
+    }
+
+    fn apply_difficulty(&mut self) {
+        let config = self.difficulty.apply(&self.base_config);
+        self.boy.set_physics(config.physics());
+        self.boy.forgiveness = config.forgiveness_ticks;
+        self.boy.knockouts = config.knockouts;
+        self.boy.set_scale(Scale::uniform(config.boy_scale));
+        self.stamina = config.marathon.then(Stamina::new);
+        self.roulette = config.mutators.then(|| MutatorRoulette::new(random::rng()));
+        self.ramp = DifficultyRamp::new(&config);
+        self.ramp.update(self.score.distance());
+        self.config = config;
+    }
+
+    fn spawn(&self, name: &str, position: Point) -> Vec<Box<dyn Obstacle>> {
+        let context = SpawnContext {
+            pool: &self.pool,
+            stone: &self.stone,
+            sheet: &self.obstacle_sheet,
+            enemy_sheet: &self.enemy_sheet,
+            physics: self.config.physics(),
+        };
+        self.registry
+            .spawn(name, &context, position)
+            .unwrap_or_else(|err| {
+                log!("Could not spawn obstacle {:#?}", err);
+                vec![]
+            })
+    }
+
+    // 走行中の障害物も含めて、ランをリセットせずにシートを差し替える
+    fn set_obstacle_sheet(&mut self, sheet: Rc<SpriteSheet>) -> Result<()> {
+        sheet.validate_composites(required_obstacle_composites())?;
+        self.obstacles
+            .iter_mut()
+            .for_each(|obstacle| obstacle.set_sheet(sheet.clone()));
+        self.obstacle_sheet = sheet;
+        Ok(())
+    }
+
+    // 走っている間の 1 ティック。倒れたかどうかは呼んだ側が knocked_out で見る
+    pub fn step(&mut self, keystate: &KeyState) {
+        self.recorder.record(keystate);
+        let was_running = self.boy.is_running();
+        // 跳んだ update のうちに二段目まで出ないよう、先に二段目を見る
+        if keystate.is_just_pressed(self.jump_key()) {
+            self.boy.double_jump();
+        }
+        if keystate.is_pressed(self.jump_key()) {
+            self.boy.jump();
+        }
+        // 押した瞬間はスライディング、押し続けていればその後しゃがみ続ける
+        if keystate.is_just_pressed(self.slide_key()) {
+            self.boy.slide();
+        } else {
+            self.boy.crouch(keystate.is_pressed(self.slide_key()));
+        }
+        self.log_move(was_running);
+        if keystate.is_just_pressed(ATTACK_KEY) {
+            self.boy.attack();
+        }
+
+        self.boy.update();
+        self.elapsed_frames += 1;
+
+        let velocity = self.velocity();
+
+        let [first_background, second_background] = &mut self.backgrounds;
+        first_background.move_horizontally(velocity);
+        second_background.move_horizontally(velocity);
+
+        if first_background.right() < 0 {
+            first_background.set_x(second_background.right());
+        }
+
+        if second_background.right() < 0 {
+            second_background.set_x(first_background.right());
+        }
+        self.scenery.update(velocity);
+
+        self.score.travel(velocity);
+        self.ambience
+            .update(self.boy.audio(), self.score.distance());
+        self.run_scripts();
+        let score = &mut self.score;
+        let pool = &self.pool;
+        self.obstacles.retain_with(
+            |obstacle| {
+                let on_screen = obstacle.right() > 0;
+                if !on_screen {
+                    score.add_bonus(obstacle.bonus());
+                }
+                on_screen
+            },
+            |obstacle| pool.recycle(obstacle),
+        );
+
+        let boy_box = self.boy.bounding_box();
+        let boy_x = boy_box.x() + boy_box.width / 2;
+        let ground = self
+            .obstacles
+            .iter()
+            .filter_map(|obstacle| obstacle.ground_at(boy_x))
+            .min()
+            .unwrap_or(GROUND_LEVEL);
+        self.boy.set_ground(ground);
+
+        let clock = self.beat_clock();
+        self.obstacles.iter_mut().for_each(|obstacle| {
+            obstacle.move_horizontally(velocity);
+            obstacle.sync(clock);
+        });
+
+        // 体か攻撃の届く範囲と横に重なるものだけを確かめる
+        let body = self.boy.bounding_box();
+        let reach = self.boy.hitbox().map_or(body, |hitbox| {
+            let left = body.x().min(hitbox.x());
+            let right = body.right().max(hitbox.right());
+            Rect::new_from_x_y(left, body.y(), right - left, body.height)
+        });
+        let obstacles = &self.obstacles;
+        let boy_ref = &mut self.boy;
+        let death_cause = &mut self.death_cause;
+        let broad_phase = &mut self.broad_phase;
+        broad_phase.rebuild(
+            obstacles
+                .iter()
+                .map(|obstacle| (obstacle.left(), obstacle.right())),
+        );
+        broad_phase.query(&reach).iter().for_each(|index| {
+            let Some(obstacle) = obstacles.get(*index) else {
+                return;
+            };
+            let was_down = boy_ref.is_down();
+            obstacle.check_intersection(boy_ref);
+            if !was_down && boy_ref.is_down() {
+                *death_cause = Some(obstacle.cause());
+            }
+        });
+
+        if self.boy.take_finish() && self.finished_at.is_none() {
+            self.finished_at = Some(self.elapsed_frames);
+            self.scripts.start(victory);
+        }
+        self.confetti.update();
+
+        if self.power_ups.is_active(PowerUpKind::Shield) && !self.boy.shield {
+            self.power_ups.consume(PowerUpKind::Shield);
+        }
+
+        self.watch_near_misses();
+        self.update_power_ups();
+        let pickups = self.collect_pickups();
+        self.coins += pickups;
+        self.update_stamina(pickups);
+        self.update_speedrun();
+        self.update_mutators();
+        self.follow_camera();
+
+        if self.timeline < self.config.timeline_minimum {
+            self.generate_next_segment();
+        } else {
+            self.timeline += velocity;
+        }
+    }
+
+    // 少年とコースの絵と音を読み込み、タイトルに出す前の Walk を作る。レースでは一人に一つずつ作る
+    pub async fn load(config: GameConfig, analytics: Analytics, audio: Audio) -> Result<Self> {
+        let sheet: Option<Sheet> = platform::fetch_json("rhb.json").await?;
+        let mut assets = AssetManager::new(config.texture_budget_mb as usize * 1024 * 1024);
+        let image = Some(assets.essential_image("rhb.png").await?);
+        let background = assets.essential_image("BG.png").await?;
+        let stone = registry::stone_template(assets.essential_image("Stone.png").await?);
+
+        let sound = audio.load_sound("SFX_Jump_23.mp3").await?;
+        // BGM はデコードを待たずに始め、読み終わってから流す
+        let background_music = assets.sound_in_background(&audio, BACKGROUND_MUSIC);
+        let ambience = Ambience::load(&assets, &audio);
+
+        let rhb_sheet = SpriteSheet::new(
+            sheet.clone().ok_or_else(|| anyhow!("No Sheet Present"))?,
+            image.clone().ok_or_else(|| anyhow!("No Imgage Present"))?,
+        );
+        rhb_sheet.validate_animations(animation_names())?;
+        let rhb = RedHatBoy::new(
+            rhb_sheet,
+            audio,
+            sound,
+            config.physics(),
+            config.forgiveness_ticks,
+        );
+
+        let sheet: Option<Sheet> = platform::fetch_json("tiles.json").await?;
+
+        let sprite_sheet = Rc::new(SpriteSheet::new(
+            sheet.expect("Could not load tiles.json"),
+            assets.essential_image("tiles.png").await?,
+        ));
+        sprite_sheet.validate_composites(required_obstacle_composites())?;
+
+        let sheet: Sheet = platform::fetch_json("enemy.json").await?;
+        let enemy_sheet = Rc::new(SpriteSheet::new(
+            sheet,
+            assets.essential_image("enemy.png").await?,
+        ));
+        enemy_sheet.validate_animations(enemy_animation_names())?;
+
+        let pool = ObstaclePool::default();
+        let starting_obstacles = stone_and_platform(&pool, &stone, sprite_sheet.clone(), 0);
+        let timeline = rightmost(&starting_obstacles);
+        let mut obstacles = Arena::with_capacity(OBSTACLE_CAPACITY, OverflowPolicy::DropOldest);
+        obstacles.extend(starting_obstacles);
+
+        let background_width = background.width() as i16;
+        let background_layer = CachedLayer::new(background.width() * 2, background.height())?;
+
+        let beat_map = load_beat_map(&config).await;
+        let levels = level::load().await;
+        let ramp = DifficultyRamp::new(&config);
+        let mut walk = Walk {
+            boy: rhb,
+            backgrounds: [
+                Image::new(background.clone(), Point { x: 0, y: 0 }),
+                Image::new(
+                    background,
+                    Point {
+                        x: background_width,
+                        y: 0,
+                    },
+                ),
+            ],
+            background_layer,
+            scenery: Scenery::new(),
+            obstacles,
+            pool,
+            broad_phase: BroadPhase::default(),
+            registry: ObstacleRegistry::with_builtins(sprite_sheet.composite_names().into_iter()),
+            obstacle_sheet: sprite_sheet,
+            stone: stone,
+            enemy_sheet,
+            timeline: timeline,
+            elapsed_frames: 0,
+            camera_y: 0,
+            zoom: 1.0,
+            zoom_focus: ZOOM_FOCUS,
+            shake: Shake::default(),
+            free_camera: None,
+            seasonal_event: None,
+            base_config: config.clone(),
+            difficulty: Difficulty::default(),
+            config,
+            stamina: None,
+            score: Score::new(),
+            assets,
+            background_music: Some(background_music),
+            music: None,
+            analytics,
+            bookmark: None,
+            practice_segment: None,
+            speedrun: None,
+            roulette: None,
+            jump_preview: false,
+            run_log: RunLog::default(),
+            hazard_clearance: None,
+            seed: 0,
+            recorder: InputRecorder::default(),
+            replay: None,
+            daily: false,
+            death_cause: None,
+            power_ups: PowerUps::default(),
+            ramp,
+            segments: SegmentPicker::default(),
+            loadout: None,
+            coins: 0,
+            beat_map,
+            ambience,
+            scripts: Scheduler::default(),
+            lockstep: false,
+            levels,
+            level: None,
+            finish_placed: false,
+            finished_at: None,
+            braking: None,
+            confetti: Particles::default(),
+            level_complete: false,
+        };
+
+        walk.apply_difficulty();
+        Ok(walk)
+    }
+
+    pub fn reset(mut walk: Self) -> Self {
+        // 効いたままの変化を戻してから次のランに持ち越す
+        if let Some(roulette) = &mut walk.roulette {
+            roulette.revert(&mut walk.config);
+        }
+        // 確保済みの領域をそのまま使い回す
+        let mut obstacles = walk.obstacles;
+        let pool = walk.pool;
+        obstacles
+            .drain()
+            .for_each(|obstacle| pool.recycle(obstacle));
+        let starting_obstacles =
+            stone_and_platform(&pool, &walk.stone, walk.obstacle_sheet.clone(), 0);
+        let timeline = rightmost(&starting_obstacles);
+        obstacles.extend(starting_obstacles);
+
+        // マラソンで落ちた速さを元に戻す
+        let mut boy = RedHatBoy::reset(walk.boy);
+        boy.set_physics(walk.config.physics());
+
+        let ramp = DifficultyRamp::new(&walk.config);
+        let mut reset = Walk {
+            boy,
+            backgrounds: walk.backgrounds,
+            background_layer: walk.background_layer,
+            scenery: walk.scenery,
+            obstacles,
+            pool,
+            broad_phase: walk.broad_phase,
+            registry: walk.registry,
+            obstacle_sheet: walk.obstacle_sheet,
+            stone: walk.stone,
+            enemy_sheet: walk.enemy_sheet,
+            timeline,
+            elapsed_frames: 0,
+            camera_y: 0,
+            zoom: 1.0,
+            zoom_focus: ZOOM_FOCUS,
+            shake: Shake::default(),
+            free_camera: walk.free_camera,
+            seasonal_event: walk.seasonal_event,
+            base_config: walk.base_config,
+            difficulty: walk.difficulty,
+            stamina: walk.config.marathon.then(Stamina::new),
+            score: Score::new(),
+            roulette: walk
+                .config
+                .mutators
+                .then(|| MutatorRoulette::new(random::rng())),
+            config: walk.config,
+            assets: walk.assets,
+            background_music: walk.background_music,
+            music: walk.music,
+            analytics: walk.analytics,
+            bookmark: None,
+            practice_segment: walk.practice_segment,
+            speedrun: None,
+            jump_preview: walk.jump_preview,
+            run_log: RunLog::default(),
+            hazard_clearance: None,
+            seed: 0,
+            recorder: InputRecorder::default(),
+            replay: None,
+            daily: false,
+            death_cause: None,
+            power_ups: PowerUps::default(),
+            ramp,
+            segments: SegmentPicker::default(),
+            loadout: None,
+            coins: 0,
+            beat_map: walk.beat_map,
+            ambience: walk.ambience,
+            scripts: Scheduler::default(),
+            lockstep: walk.lockstep,
+            levels: walk.levels,
+            level: walk.level,
+            finish_placed: false,
+            finished_at: None,
+            braking: None,
+            confetti: Particles::default(),
+            level_complete: false,
+        };
+        if let Some(index) = reset.practice_segment {
+            reset.practice_segment(index);
+        }
+        reset
+    }
+}
+
+const PROFILE_KEYS: [&str; MAX_PROFILES] = ["Digit1", "Digit2", "Digit3"];
+const LOADOUT_KEY: &str = "KeyL";
+
+pub struct WalkTheDog {
+    machine: Option<WalkTheDogStateMachine>,
+    profile_slot: usize,
+    profile: Profile,
+    cloud_save: Option<CloudSave>,
+    options: Option<OptionsMenu>,
+    gallery: Option<SegmentGallery>,
+    history: Option<HistoryScene>,
+    level_select: Option<LevelSelect>,
+    world_map: Option<WorldMap>,
+    initials: Option<InitialsEntry>,
+    leaderboard: Option<LeaderboardTable>,
+    tweaks: Option<TweakPanel>,
+    frame_data: Option<FrameDataViewer>,
+    debug_overlay: bool,
+    hitboxes: bool,
+    inspector: Vec<String>,
+    quality: QualityDetector,
+}
+
+impl WalkTheDog {
+    pub fn new() -> Self {
+        WalkTheDog {
+            machine: None,
+            profile_slot: 0,
+            profile: Profile::default(),
+            cloud_save: None,
+            options: None,
+            gallery: None,
+            history: None,
+            level_select: None,
+            world_map: None,
+            initials: None,
+            leaderboard: None,
+            tweaks: None,
+            frame_data: None,
+            debug_overlay: false,
+            hitboxes: engine::hitboxes_requested(),
+            inspector: vec![],
+            quality: QualityDetector::new(),
+        }
+    }
+
+    // プロフィールの切り替えはタイトル画面と走り出す前の画面でのみ受け付ける
+    fn select_profile(&mut self, keystate: &KeyState) {
+        if let Some(slot) = PROFILE_KEYS
+            .iter()
+            .position(|key| keystate.is_just_pressed(key))
+        {
+            if slot != self.profile_slot {
+                self.profile_slot = slot;
+                self.profile = profile::load(slot);
+                profile::set_active_slot(slot);
+                self.show_profile();
+                self.apply_settings();
+            }
+        }
+    }
+
+    // 走り出す前の画面で、持って出るパワーアップを選ぶ。プロフィールを替えて払えなくなったら外す
+    fn select_loadout(&self, keystate: &KeyState, machine: &mut WalkTheDogStateMachine) {
+        if !machine.shows_loadout() {
+            return;
+        }
+        let walk = machine.walk_mut();
+        let available = |kind| self.profile.can_start_with(kind);
+        walk.loadout = walk.loadout.filter(|kind| available(*kind));
+        if keystate.is_just_pressed(LOADOUT_KEY) {
+            walk.loadout = power_up::next_loadout(walk.loadout, available);
+        }
+    }
+
+    fn show_profile(&self) {
+        let text = format!("{} (1-{} to switch)", self.profile.name, MAX_PROFILES);
+        if let Err(err) = browser::set_text_by_id("profile", &text) {
+            log!("Could not show profile {:#?}", err);
+        }
+    }
+
+    fn apply_settings(&mut self) {
+        let low_quality = self.low_quality();
+        if let Some(machine) = &mut self.machine {
+            let audio = machine.walk().boy.audio();
+            audio.set_master_volume(self.profile.settings.master_volume);
+            audio.set_muted(self.profile.settings.muted);
+            let settings = &self.profile.settings;
+            BUSES
+                .iter()
+                .for_each(|bus| audio.set_bus_volume(*bus, settings.bus_volume(*bus)));
+            audio.set_ducking(self.profile.settings.ducking());
+            audio.set_music_in_background(self.profile.settings.music_in_background);
+            machine.walk_mut().scenery.set_detail(!low_quality);
+            machine.walk_mut().jump_preview = self.profile.settings.jump_preview;
+        }
+    }
+
+    fn is_practice(&self) -> bool {
+        self.machine
+            .as_ref()
+            .is_some_and(|machine| machine.is_walking() && machine.walk().config.practice)
+    }
+
+    fn low_quality(&self) -> bool {
+        self.profile
+            .settings
+            .quality
+            .is_low(self.quality.detected())
+    }
+
+    // オプション画面を開いている間はゲームを進めない。保存は閉じたときにまとめて行う
+    fn update_options(&mut self, keystate: &KeyState) -> bool {
+        let options = match &mut self.options {
+            Some(options) => options,
+            None => return false,
+        };
+
+        match options.update(keystate) {
+            OptionsEndState::Continue => {}
+            OptionsEndState::Changed(settings) => {
+                self.profile.settings = settings;
+                self.apply_settings();
+                self.refresh_options();
+            }
+            OptionsEndState::Close => {
+                self.options = None;
+                self.save_profile();
+            }
+        }
+        true
+    }
+
+    fn refresh_options(&mut self) {
+        if let Some(options) = &mut self.options {
+            options.refresh(self.profile.settings);
+        }
+    }
+
+    fn update_gallery(&mut self, keystate: &KeyState) -> bool {
+        let gallery = match &mut self.gallery {
+            Some(gallery) => gallery,
+            None => return false,
+        };
+
+        match gallery.update(keystate) {
+            GalleryEndState::Continue => {}
+            GalleryEndState::Practice(index) => {
+                self.gallery = None;
+                self.machine = self
+                    .machine
+                    .take()
+                    .map(|machine| machine.practice_segment(index));
+            }
+            GalleryEndState::Close => self.gallery = None,
+        }
+        true
+    }
+
+    fn update_history(&mut self, keystate: &KeyState) -> bool {
+        let history = match &mut self.history {
+            Some(history) => history,
+            None => return false,
+        };
+
+        match history.update(keystate) {
+            HistoryEndState::Continue => {}
+            HistoryEndState::Replay(record) => {
+                self.history = None;
+                self.machine = self.machine.take().map(|machine| machine.replay(&record));
+            }
+            HistoryEndState::Close => self.history = None,
+        }
+        true
+    }
+
+    fn update_level_select(&mut self, keystate: &KeyState) -> bool {
+        let level_select = match &mut self.level_select {
+            Some(level_select) => level_select,
+            None => return false,
+        };
+
+        match level_select.update(keystate) {
+            LevelSelectEndState::Continue => {}
+            LevelSelectEndState::Play(index) => {
+                self.level_select = None;
+                self.machine = self.machine.take().map(|machine| machine.play_level(index));
+            }
+            LevelSelectEndState::Map => {
+                self.level_select = None;
+                if let Some(machine) = &self.machine {
+                    self.world_map = Some(WorldMap::new(&machine.walk().levels, &self.profile));
+                }
+            }
+            LevelSelectEndState::Close => self.level_select = None,
+        }
+        true
+    }
+
+    fn update_world_map(&mut self, keystate: &KeyState) -> bool {
+        let world_map = match &mut self.world_map {
+            Some(world_map) => world_map,
+            None => return false,
+        };
+
+        match world_map.update(keystate) {
+            WorldMapEndState::Continue => {}
+            WorldMapEndState::Play(index) => {
+                self.world_map = None;
+                self.machine = self.machine.take().map(|machine| machine.play_level(index));
+            }
+            WorldMapEndState::Close => self.world_map = None,
+        }
+        true
+    }
+
+    // 入力が終わったら表に入れ、入った順位を光らせて見せる
+    fn update_initials(&mut self, keystate: &KeyState) -> bool {
+        let Some(entry) = &mut self.initials else {
+            return false;
+        };
+        if let InitialsEndState::Confirm(high_score) = entry.update(keystate) {
+            self.initials = None;
+            self.profile.initials = high_score.initials.clone();
+            leaderboard::insert(&mut self.profile.high_scores, high_score.clone());
+            self.save_profile();
+            self.leaderboard = Some(
+                LeaderboardTable::new(self.profile.high_scores.clone(), self.stats_summary())
+                    .highlight(high_score),
+            );
+        }
+        true
+    }
+
+    fn update_leaderboard(&mut self, keystate: &KeyState) -> bool {
+        let Some(leaderboard) = &mut self.leaderboard else {
+            return false;
+        };
+        if !leaderboard.update(keystate) {
+            self.leaderboard = None;
+        }
+        true
+    }
+
+    fn stats_summary(&self) -> String {
+        let stats = &self.profile.stats;
+        let difficulty = self
+            .machine
+            .as_ref()
+            .map(|machine| machine.walk().difficulty)
+            .unwrap_or_default();
+        let best = stats
+            .best_by_difficulty
+            .get(&difficulty)
+            .copied()
+            .unwrap_or_default();
+        format!(
+            "Runs {} / Best {}s ({})",
+            stats.runs,
+            best / FRAMES_PER_SECOND,
+            difficulty.label()
+        )
+    }
+
+    fn draw_best(&self, renderer: &Renderer) {
+        renderer.draw_text(
+            &format!(
+                "Best: {} ({} m)",
+                self.profile.high_score, self.profile.stats.best_distance
+            ),
+            &Point { x: 20, y: 580 },
+            TITLE_TEXT_COLOR,
+        );
+    }
+
+    fn draw_loadout(&self, renderer: &Renderer, loadout: Option<PowerUpKind>) {
+        let choice = match loadout {
+            Some(kind) if self.profile.has_unlocked(kind) => format!("{} (unlocked)", kind.label()),
+            Some(kind) => format!("{} ({} coins)", kind.label(), kind.price()),
+            None => "Nothing".to_string(),
+        };
+        renderer.draw_text(
+            &format!("Start with: {} - L to change", choice),
+            &Point { x: 20, y: 560 },
+            TITLE_TEXT_COLOR,
+        );
+        renderer.draw_text(
+            &format!("Coins: {}", self.profile.coins),
+            &Point { x: 20, y: 580 },
+            TITLE_TEXT_COLOR,
+        );
+    }
+
+    fn select_title_choice(&mut self, choice: TitleChoice) {
+        match choice {
+            TitleChoice::Options => {
+                self.options = Some(OptionsMenu::new(self.profile.settings));
+            }
+            TitleChoice::Gallery => {
+                let Some(machine) = &self.machine else {
+                    return;
+                };
+                match SegmentGallery::new(machine.walk()) {
+                    Ok(gallery) => self.gallery = Some(gallery),
+                    Err(err) => {
+                        log!("Could not open the segment gallery {:#?}", err);
+                    }
+                }
+            }
+            TitleChoice::History => self.history = Some(HistoryScene::new(history::load())),
+            TitleChoice::Levels => {
+                let Some(machine) = &self.machine else {
+                    return;
+                };
+                if machine.walk().levels.is_empty() {
+                    notifications::push("No levels found");
+                } else {
+                    self.level_select =
+                        Some(LevelSelect::new(&machine.walk().levels, &self.profile));
+                }
+            }
+            TitleChoice::Stats => {
+                self.leaderboard = Some(LeaderboardTable::new(
+                    self.profile.high_scores.clone(),
+                    self.stats_summary(),
+                ));
+            }
+        }
+    }
+
+    // F3 のデバッグ表示中だけ、カメラの切り離しとクリックでの状態表示を受け付ける
+    fn update_debug_tools(&mut self, keystate: &KeyState) {
+        let walk = match &mut self.machine {
+            Some(machine) => machine.walk_mut(),
+            None => return,
+        };
+
+        if keystate.is_just_pressed(FREE_CAMERA_KEY) {
+            walk.free_camera = match walk.free_camera {
+                Some(_) => None,
+                None => Some(walk.camera()),
+            };
+        }
+        if let Some(camera) = &mut walk.free_camera {
+            FREE_CAMERA_KEYS.iter().for_each(|(key, x, y)| {
+                if keystate.is_pressed(key) {
+                    camera.position.x += x * FREE_CAMERA_SPEED;
+                    camera.position.y += y * FREE_CAMERA_SPEED;
+                }
+            });
+            FREE_CAMERA_ZOOM_KEYS.iter().for_each(|(key, factor)| {
+                if keystate.is_just_pressed(key) {
+                    *camera = camera.zoom_at(camera.zoom * factor, &FREE_CAMERA_CENTER);
+                }
+            });
+        }
+
+        if let (Some(point), true) = (keystate.pointer(), keystate.is_pointer_just_pressed()) {
+            self.inspector = walk.inspect(&point);
+            self.inspector.iter().for_each(|line| {
+                log!("{}", line);
+            });
+        }
+    }
+
+    // フレームデータを見ている間はゲームを止め、キーを動作の切り替えに使う
+    fn update_frame_data(&mut self, keystate: &KeyState) -> bool {
+        let Some(viewer) = &mut self.frame_data else {
+            return false;
+        };
+        if !viewer.update(keystate) {
+            self.frame_data = None;
+        }
+        true
+    }
+
+    // 調整パネルを開いている間もゲームを止め、矢印キーをスライダーに使う
+    fn update_tweaks(&mut self, keystate: &KeyState) -> bool {
+        let (tweaks, machine) = match (&mut self.tweaks, &mut self.machine) {
+            (Some(tweaks), Some(machine)) => (tweaks, machine),
+            _ => return false,
+        };
+
+        match tweaks.update(keystate) {
+            TweakEndState::Continue => {}
+            TweakEndState::Changed(config) => machine.walk_mut().set_config(*config),
+            TweakEndState::Export(json) => {
+                log!("{}", json);
+                notifications::push("Tweaks exported to the console");
+            }
+            TweakEndState::Close => self.tweaks = None,
+        }
+        true
+    }
+
+    fn save_profile(&mut self) {
+        profile::save(self.profile_slot, &mut self.profile);
+        if let Some(cloud_save) = &self.cloud_save {
+            cloud_save.upload_in_background(self.profile_slot, self.profile.clone());
+        }
+    }
+}
+enum WalkTheDogStateMachine {
+    Title(WalkTheDogState<Title>),
+    Ready(WalkTheDogState<Ready>),
+    Walking(WalkTheDogState<Walking>),
+    GameOver(WalkTheDogState<GameOver>),
+    Victory(WalkTheDogState<Victory>),
+}
+
+struct WalkTheDogState<T> {
+    _state: T,
+    walk: Walk,
+}
+
+const TITLE_LOGO: &str = "WALK THE DOG";
+const TITLE_PLAY: usize = 0;
+const TITLE_DIFFICULTY: usize = 1;
+const TITLE_DAILY: usize = 2;
+const TITLE_OPTIONS: usize = 3;
+const TITLE_STATS: usize = 4;
+const TITLE_GALLERY: usize = 5;
+const TITLE_HISTORY: usize = 6;
+const TITLE_LEVELS: usize = 7;
+const TITLE_TEXT_COLOR: &str = "#FFFFFF";
+// 練習モードで一時停止しているときだけ使う
+const BOOKMARK_KEY: &str = "KeyB";
+const RETRY_KEY: &str = "KeyR";
+const TITLE_SHADE_COLOR: &str = "rgba(0, 0, 0, 0.4)";
+const FRAMES_PER_SECOND: u32 = 60;
+const TITLE_MENU_SLIDE_FRAMES: u32 = 20;
+const TITLE_LOGO_DROP_FRAMES: f32 = 30.0;
+const TITLE_LOGO_STAGGER: f32 = 3.0;
+
+// タイトル画面のメニューのうち、ゲーム全体 (WalkTheDog) 側で処理するもの
+#[derive(Clone, Copy)]
+enum TitleChoice {
+    Options,
+    Stats,
+    Gallery,
+    History,
+    Levels,
+}
+
+// 起動直後の画面。Press Start のあとにメニューを出す
+struct Title {
+    frame: u32,
+    menu: Option<Panel>,
+    menu_slide: Tween,
+    choice: Option<TitleChoice>,
+}
+
+impl Title {
+    fn new() -> Self {
+        Title {
+            frame: 0,
+            menu: None,
+            menu_slide: Tween::new(
+                CANVAS_HEIGHT as f32,
+                0.0,
+                TITLE_MENU_SLIDE_FRAMES,
+                Easing::EaseOut,
+            ),
+            choice: None,
+        }
+    }
+
+    fn menu(difficulty: Difficulty) -> Panel {
+        let mut panel = Panel::new(Point { x: 200, y: 220 }, 200);
+        panel.add(Box::new(Button::new("Play")));
+        panel.add(Box::new(Choice::new(
+            "Mode",
+            DIFFICULTIES
+                .iter()
+                .map(|difficulty| difficulty.label().to_string())
+                .collect(),
+            difficulty.index(),
+        )));
+        panel.add(Box::new(Button::new("Daily")));
+        panel.add(Box::new(Button::new("Options")));
+        panel.add(Box::new(Button::new("Stats")));
+        panel.add(Box::new(Button::new("Segments")));
+        panel.add(Box::new(Button::new("History")));
+        panel.add(Box::new(Button::new("Levels")));
+        panel
+    }
+
+    fn start_pressed(keystate: &KeyState) -> bool {
+        keystate.is_just_pressed("Enter")
+            || keystate.is_just_pressed("Space")
+            || keystate.is_pointer_just_pressed()
+    }
+
+    // 文字ごとに少しずつ遅れて上から落ちてくる
+    fn draw_logo(&self, renderer: &Renderer) {
+        TITLE_LOGO.chars().enumerate().for_each(|(index, letter)| {
+            let phase = (self.frame as f32 + index as f32 * 6.0) / 12.0;
+            let drop =
+                (self.frame as f32 - index as f32 * TITLE_LOGO_STAGGER) / TITLE_LOGO_DROP_FRAMES;
+            let y = lerp(-20.0, 160.0, Easing::EaseInOut.apply(drop));
+            let position = Point {
+                x: 190 + index as i16 * 18,
+                y: y as i16 + (phase.sin() * 8.0) as i16,
+            };
+            renderer.draw_text(&letter.to_string(), &position, TITLE_TEXT_COLOR);
+        });
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        renderer.fill_rect(
+            &Rect::new_from_x_y(0, 0, 600, CANVAS_HEIGHT),
+            TITLE_SHADE_COLOR,
+        );
+        self.draw_logo(renderer);
+        match &self.menu {
+            Some(menu) => renderer.translate(
+                &Point {
+                    x: 0,
+                    y: self.menu_slide.value() as i16,
+                },
+                |renderer| menu.draw(renderer),
+            ),
+            None => {
+                if self.frame % FRAMES_PER_SECOND < FRAMES_PER_SECOND * 2 / 3 {
+                    renderer.draw_text(
+                        "Press Enter to start",
+                        &Point { x: 210, y: 300 },
+                        TITLE_TEXT_COLOR,
+                    );
+                }
+            }
+        }
+    }
+}
+
+const DISMISSED_ANNOUNCEMENT_KEY: &str = "walk_the_dog.dismissed_announcement";
+
+struct Ready {
+    announcement: Option<Announcement>,
+}
+
+impl Ready {
+    fn new(config: &GameConfig) -> Self {
+        Ready {
+            announcement: Announcement::show(config),
+        }
+    }
+
+    fn close_announcement(&mut self) {
+        if self.announcement.take().is_some() {
+            if let Err(err) = browser::hide_ui() {
+                log!("Could not hide announcement {:#?}", err);
+            }
+        }
+    }
+}
+
+// タイトル画面に出すお知らせ。一度閉じたメッセージは次回以降表示しない
+struct Announcement {
+    message: String,
+    dismiss_event: UnboundedReceiver<()>,
+    menu: MenuList,
+}
+
+impl Announcement {
+    fn show(config: &GameConfig) -> Option<Self> {
+        let message = config.message_of_the_day.clone()?;
+        let dismissed = browser::storage::get_item(DISMISSED_ANNOUNCEMENT_KEY)
+            .ok()
+            .flatten();
+        if dismissed.as_deref() == Some(message.as_str()) {
+            return None;
+        }
+
+        let link = config
+            .message_of_the_day_link
+            .as_ref()
+            .map(|url| format!("<a href='{}' target='_blank'>More</a>", escape_html(url)))
+            .unwrap_or_default();
+        let menu = MenuList::new(vec![MenuItem::new("dismiss_announcement", "OK")]);
+        let html = format!(
+            "<div id='announcement'><span>{}</span>{}{}</div>",
+            escape_html(&message),
+            link,
+            menu.html()
+        );
+
+        browser::draw_ui(&html)
+            .and_then(|_unit| browser::find_html_element_by_id("dismiss_announcement"))
+            .map(|element| Announcement {
+                message,
+                dismiss_event: engine::add_click_handler(element),
+                menu,
+            })
+            .map_err(|err| {
+                log!("Could not show announcement {:#?}", err);
+            })
+            .ok()
+    }
+
+    // OK ボタンのクリックに加え、Enter / Escape でも閉じられる
+    fn dismiss_pressed(&mut self, keystate: &KeyState) -> bool {
+        self.menu.update(keystate).is_some()
+            || matches!(self.dismiss_event.try_next(), Ok(Some(())))
+    }
+
+    fn remember_dismissed(&self) {
+        if let Err(err) = browser::storage::set_item(DISMISSED_ANNOUNCEMENT_KEY, &self.message) {
+            log!("Could not store dismissed announcement {:#?}", err);
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&#39;")
+        .replace('"', "&quot;")
+}
+
+const VOLUME_STEP: f32 = 0.1;
+// ダッキングの深さ (dB) と長さ (ms) の範囲
+const MAXIMUM_DUCK_DEPTH: f32 = -24.0;
+const DUCK_DEPTH_STEP: f32 = 3.0;
+const MAXIMUM_DUCK_LENGTH: f32 = 1000.0;
+const DUCK_LENGTH_STEP: f32 = 50.0;
+const GAME_SPEED_STEP: f32 = 0.25;
+
+// 設定画面に上から並べる項目。パネルの番号はこの並びから決まる
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OptionsEntry {
+    Title,
+    RetroFilter,
+    MasterVolume,
+    Mute,
+    Audio,
+    GameSpeed,
+    Quality,
+    MusicInBackground,
+    SpeedrunTimer,
+    Accessibility,
+    Close,
+}
+
+const OPTIONS_ENTRIES: [OptionsEntry; 11] = [
+    OptionsEntry::Title,
+    OptionsEntry::RetroFilter,
+    OptionsEntry::MasterVolume,
+    OptionsEntry::Mute,
+    OptionsEntry::Audio,
+    OptionsEntry::GameSpeed,
+    OptionsEntry::Quality,
+    OptionsEntry::MusicInBackground,
+    OptionsEntry::SpeedrunTimer,
+    OptionsEntry::Accessibility,
+    OptionsEntry::Close,
+];
+
+impl OptionsEntry {
+    fn widget(self, settings: &Settings) -> Box<dyn Widget> {
+        match self {
+            OptionsEntry::Title => Box::new(Label::new("Options")),
+            OptionsEntry::RetroFilter => {
+                Box::new(Toggle::new("Retro filter", settings.retro_filter))
+            }
+            OptionsEntry::MasterVolume => Box::new(Slider::new(
+                "Volume",
+                settings.master_volume,
+                0.0,
+                1.0,
+                VOLUME_STEP,
+            )),
+            OptionsEntry::Mute => Box::new(Toggle::new("Mute", settings.muted)),
+            OptionsEntry::Audio => Box::new(Button::new("Audio")),
+            OptionsEntry::GameSpeed => Box::new(Slider::new(
+                "Speed",
+                settings.game_speed,
+                MINIMUM_GAME_SPEED,
+                MAXIMUM_GAME_SPEED,
+                GAME_SPEED_STEP,
+            )),
+            OptionsEntry::Quality => Box::new(Choice::new(
+                "Quality",
+                QUALITIES
+                    .iter()
+                    .map(|quality| quality.label().to_string())
+                    .collect(),
+                settings.quality.index(),
+            )),
+            OptionsEntry::MusicInBackground => Box::new(Toggle::new(
+                "Music in background",
+                settings.music_in_background,
+            )),
+            OptionsEntry::SpeedrunTimer => {
+                Box::new(Toggle::new("Speedrun timer", settings.speedrun_timer))
+            }
+            OptionsEntry::Accessibility => Box::new(Button::new("Accessibility")),
+            OptionsEntry::Close => Box::new(Button::new("Close")),
+        }
+    }
+}
+
+// 「Audio」で開くバスごとの音量とダッキングの設定
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AudioEntry {
+    Title,
+    Volume(Bus),
+    DuckDepth,
+    DuckLength,
+    Back,
+}
+
+const AUDIO_ENTRIES: [AudioEntry; 8] = [
+    AudioEntry::Title,
+    AudioEntry::Volume(Bus::Music),
+    AudioEntry::Volume(Bus::Sfx),
+    AudioEntry::Volume(Bus::Ambience),
+    AudioEntry::Volume(Bus::Ui),
+    AudioEntry::DuckDepth,
+    AudioEntry::DuckLength,
+    AudioEntry::Back,
+];
+
+impl AudioEntry {
+    fn widget(self, settings: &Settings) -> Box<dyn Widget> {
+        match self {
+            AudioEntry::Title => Box::new(Label::new("Audio")),
+            AudioEntry::Volume(bus) => {
+                let label = match bus {
+                    Bus::Music => "Music",
+                    Bus::Sfx => "Sound",
+                    Bus::Ambience => "Ambience",
+                    Bus::Ui => "Interface",
+                };
+                Box::new(Slider::new(
+                    label,
+                    settings.bus_volume(bus),
+                    0.0,
+                    1.0,
+                    VOLUME_STEP,
+                ))
+            }
+            // 右へ動かすほど深く絞る
+            AudioEntry::DuckDepth => Box::new(Slider::new(
+                "Ducking",
+                -settings.duck_depth_db,
+                0.0,
+                -MAXIMUM_DUCK_DEPTH,
+                DUCK_DEPTH_STEP,
+            )),
+            AudioEntry::DuckLength => Box::new(Slider::new(
+                "Ducking length",
+                settings.duck_ms,
+                0.0,
+                MAXIMUM_DUCK_LENGTH,
+                DUCK_LENGTH_STEP,
+            )),
+            AudioEntry::Back => Box::new(Button::new("Back")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AccessibilityEntry {
+    Title,
+    JumpPreview,
+    AutoJump,
+    Back,
+}
+
+const ACCESSIBILITY_ENTRIES: [AccessibilityEntry; 4] = [
+    AccessibilityEntry::Title,
+    AccessibilityEntry::JumpPreview,
+    AccessibilityEntry::AutoJump,
+    AccessibilityEntry::Back,
+];
+
+impl AccessibilityEntry {
+    fn widget(self, settings: &Settings) -> Box<dyn Widget> {
+        match self {
+            AccessibilityEntry::Title => Box::new(Label::new("Accessibility")),
+            AccessibilityEntry::JumpPreview => {
+                Box::new(Toggle::new("Jump preview", settings.jump_preview))
+            }
+            AccessibilityEntry::AutoJump => Box::new(Toggle::new("Auto jump", settings.auto_jump)),
+            AccessibilityEntry::Back => Box::new(Button::new("Back")),
+        }
+    }
+}
+
+fn entries_panel<E: Copy>(entries: &[E], widget: impl Fn(E) -> Box<dyn Widget>) -> Panel {
+    let mut panel = Panel::new(Point { x: 150, y: 120 }, 300);
+    entries.iter().for_each(|entry| {
+        panel.add(widget(*entry));
+    });
+    panel
+}
+
+struct OptionsMenu {
+    panel: Panel,
+    // Some の間は補助機能の設定を開いている
+    accessibility: Option<Panel>,
+    // Some の間はバスごとの音量とダッキングの設定を開いている
+    audio: Option<Panel>,
+    settings: Settings,
+}
+
+enum OptionsEndState {
+    Continue,
+    Changed(Settings),
+    Close,
+}
+
+impl OptionsMenu {
+    fn new(settings: Settings) -> Self {
+        OptionsMenu {
+            panel: OptionsMenu::main_panel(&settings),
+            accessibility: None,
+            audio: None,
+            settings,
+        }
+    }
+
+    fn main_panel(settings: &Settings) -> Panel {
+        entries_panel(&OPTIONS_ENTRIES, |entry| entry.widget(settings))
+    }
+
+    fn audio_panel(settings: &Settings) -> Panel {
+        entries_panel(&AUDIO_ENTRIES, |entry| entry.widget(settings))
+    }
+
+    fn accessibility_panel(settings: &Settings) -> Panel {
+        entries_panel(&ACCESSIBILITY_ENTRIES, |entry| entry.widget(settings))
+    }
+
+    // 設定が変わったら (キー操作などこの画面の外からでも)、今の値で描き直す
+    fn refresh(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.panel.refresh(OptionsMenu::main_panel(&settings));
+        if let Some(audio) = &mut self.audio {
+            audio.refresh(OptionsMenu::audio_panel(&settings));
+        }
+        if let Some(accessibility) = &mut self.accessibility {
+            accessibility.refresh(OptionsMenu::accessibility_panel(&settings));
+        }
+    }
+
+    fn update_audio(&mut self, keystate: &KeyState) -> Option<OptionsEndState> {
+        let audio = self.audio.as_mut()?;
+        if keystate.is_just_pressed("Escape") {
+            self.audio = None;
+            return Some(OptionsEndState::Continue);
+        }
+
+        let settings = &mut self.settings;
+        Some(match audio.update(keystate) {
+            Some((index, event)) => match (AUDIO_ENTRIES[index], event) {
+                (AudioEntry::Volume(bus), WidgetEvent::Changed(value)) => {
+                    *settings.bus_volume_mut(bus) = value;
+                    OptionsEndState::Changed(*settings)
+                }
+                (AudioEntry::DuckDepth, WidgetEvent::Changed(value)) => {
+                    settings.duck_depth_db = -value;
+                    OptionsEndState::Changed(*settings)
+                }
+                (AudioEntry::DuckLength, WidgetEvent::Changed(value)) => {
+                    settings.duck_ms = value;
+                    OptionsEndState::Changed(*settings)
+                }
+                (AudioEntry::Back, WidgetEvent::Pressed) => {
+                    self.audio = None;
+                    OptionsEndState::Continue
+                }
+                _ => OptionsEndState::Continue,
+            },
+            None => OptionsEndState::Continue,
+        })
+    }
+
+    fn update_accessibility(&mut self, keystate: &KeyState) -> Option<OptionsEndState> {
+        let accessibility = self.accessibility.as_mut()?;
+        if keystate.is_just_pressed("Escape") {
+            self.accessibility = None;
+            return Some(OptionsEndState::Continue);
+        }
+
+        Some(match accessibility.update(keystate) {
+            Some((index, event)) => match (ACCESSIBILITY_ENTRIES[index], event) {
+                (AccessibilityEntry::JumpPreview, WidgetEvent::Toggled(value)) => {
+                    self.settings.jump_preview = value;
+                    OptionsEndState::Changed(self.settings)
+                }
+                (AccessibilityEntry::AutoJump, WidgetEvent::Toggled(value)) => {
+                    self.settings.auto_jump = value;
+                    OptionsEndState::Changed(self.settings)
+                }
+                (AccessibilityEntry::Back, WidgetEvent::Pressed) => {
+                    self.accessibility = None;
+                    OptionsEndState::Continue
+                }
+                _ => OptionsEndState::Continue,
+            },
+            None => OptionsEndState::Continue,
+        })
+    }
+
+    fn update(&mut self, keystate: &KeyState) -> OptionsEndState {
+        if let Some(state) = self.update_accessibility(keystate) {
+            return state;
+        }
+        if let Some(state) = self.update_audio(keystate) {
+            return state;
+        }
+        if keystate.is_just_pressed("Escape") || keystate.is_just_pressed(OPTIONS_KEY) {
+            return OptionsEndState::Close;
+        }
+
+        let Some((index, event)) = self.panel.update(keystate) else {
+            return OptionsEndState::Continue;
+        };
+        let settings = &mut self.settings;
+        match (OPTIONS_ENTRIES[index], event) {
+            (OptionsEntry::RetroFilter, WidgetEvent::Toggled(value)) => {
+                settings.retro_filter = value
+            }
+            (OptionsEntry::MasterVolume, WidgetEvent::Changed(value)) => {
+                settings.master_volume = value
+            }
+            (OptionsEntry::Mute, WidgetEvent::Toggled(value)) => settings.muted = value,
+            (OptionsEntry::GameSpeed, WidgetEvent::Changed(value)) => settings.game_speed = value,
+            (OptionsEntry::Quality, WidgetEvent::Selected(index)) => {
+                settings.quality = QUALITIES[index]
+            }
+            (OptionsEntry::MusicInBackground, WidgetEvent::Toggled(value)) => {
+                settings.music_in_background = value
+            }
+            (OptionsEntry::SpeedrunTimer, WidgetEvent::Toggled(value)) => {
+                settings.speedrun_timer = value
+            }
+            (OptionsEntry::Audio, WidgetEvent::Pressed) => {
+                self.audio = Some(OptionsMenu::audio_panel(settings));
+                return OptionsEndState::Continue;
+            }
+            (OptionsEntry::Accessibility, WidgetEvent::Pressed) => {
+                self.accessibility = Some(OptionsMenu::accessibility_panel(settings));
+                return OptionsEndState::Continue;
+            }
+            (OptionsEntry::Close, WidgetEvent::Pressed) => return OptionsEndState::Close,
+            _ => return OptionsEndState::Continue,
+        }
+        OptionsEndState::Changed(*settings)
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        match (&self.accessibility, &self.audio) {
+            (Some(panel), _) | (None, Some(panel)) => panel.draw(renderer),
+            (None, None) => self.panel.draw(renderer),
+        }
+    }
+}
+
+const GALLERY_SEGMENT: usize = 1;
+const GALLERY_PRACTICE: usize = 2;
+const GALLERY_CLOSE: usize = 3;
+// サムネイルはワールドを 1/4 に縮めて、セグメント 2 画面分ほどを映す
+const THUMBNAIL_SCALE: f64 = 0.25;
+const THUMBNAIL_WIDTH: u32 = 300;
+const THUMBNAIL_HEIGHT: u32 = 150;
+const THUMBNAIL_POSITION: Point = Point { x: 150, y: 50 };
+const THUMBNAIL_SKY_COLOR: &str = "#87CEEB";
+const THUMBNAIL_FRAME_COLOR: &str = "#FFFFFF";
+
+// セグメントを一覧して、選んだものだけを繰り返し練習できる画面
+struct SegmentGallery {
+    panel: Panel,
+    selected: usize,
+    // サムネイルは開いたときに一度だけオフスクリーンのキャンバスへ描く
+    thumbnails: Vec<(CachedLayer, Vec<Box<dyn Obstacle>>)>,
+}
+
+enum GalleryEndState {
+    Continue,
+    Practice(usize),
+    Close,
+}
+
+impl SegmentGallery {
+    fn new(walk: &Walk) -> Result<Self> {
+        let mut panel = Panel::new(Point { x: 150, y: 220 }, 300);
+        panel.add(Box::new(Label::new("Segments")));
+        panel.add(Box::new(Choice::new(
+            "Segment",
+            SEGMENTS
+                .iter()
+                .map(|segment| segment.name.to_string())
+                .collect(),
+            0,
+        )));
+        panel.add(Box::new(Button::new("Practice this")));
+        panel.add(Box::new(Button::new("Close")));
+
+        let mut rng = random::rng();
+        let thumbnails = (0..SEGMENTS.len())
+            .map(|index| {
+                Ok((
+                    CachedLayer::new(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT)?,
+                    walk.build_segment(index, 0, &mut rng),
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(SegmentGallery {
+            panel,
+            selected: 0,
+            thumbnails,
+        })
+    }
+
+    fn update(&mut self, keystate: &KeyState) -> GalleryEndState {
+        if keystate.is_just_pressed("Escape") {
+            return GalleryEndState::Close;
+        }
+
+        match self.panel.update(keystate) {
+            Some((GALLERY_SEGMENT, WidgetEvent::Selected(index))) => {
+                self.selected = index;
+                GalleryEndState::Continue
+            }
+            Some((GALLERY_PRACTICE, WidgetEvent::Pressed)) => {
+                GalleryEndState::Practice(self.selected)
+            }
+            Some((GALLERY_CLOSE, WidgetEvent::Pressed)) => GalleryEndState::Close,
+            _ => GalleryEndState::Continue,
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if let Some((layer, obstacles)) = self.thumbnails.get(self.selected) {
+            renderer.draw_layer(layer, &THUMBNAIL_POSITION, |renderer| {
+                renderer.fill_rect(
+                    &Rect::new_from_x_y(0, 0, THUMBNAIL_WIDTH as i16, THUMBNAIL_HEIGHT as i16),
+                    THUMBNAIL_SKY_COLOR,
+                );
+                renderer.scale(THUMBNAIL_SCALE, |renderer| {
+                    obstacles
+                        .iter()
+                        .for_each(|obstacle| obstacle.draw(renderer));
+                });
+            });
+            renderer.stroke_rect(
+                &Rect::new(
+                    THUMBNAIL_POSITION,
+                    THUMBNAIL_WIDTH as i16,
+                    THUMBNAIL_HEIGHT as i16,
+                ),
+                THUMBNAIL_FRAME_COLOR,
+            );
+        }
+        self.panel.draw(renderer);
+    }
+}
+
+const HISTORY_RUN: usize = 1;
+const HISTORY_REPLAY: usize = 2;
+const HISTORY_CLOSE: usize = 3;
+const HISTORY_DETAIL_POSITION: Point = Point { x: 150, y: 150 };
+const HISTORY_LINE_HEIGHT: i16 = 24;
+
+// 最近のランを一覧して、入力の記録があるものはリプレイできる画面
+struct HistoryScene {
+    panel: Panel,
+    runs: Vec<RunRecord>,
+    selected: usize,
+}
+
+enum HistoryEndState {
+    Continue,
+    Replay(RunRecord),
+    Close,
+}
+
+impl HistoryScene {
+    fn new(runs: Vec<RunRecord>) -> Self {
+        let labels = if runs.is_empty() {
+            vec!["No runs yet".to_string()]
+        } else {
+            runs.iter().map(RunRecord::label).collect()
+        };
+        let mut panel = Panel::new(Point { x: 150, y: 220 }, 300);
+        panel.add(Box::new(Label::new("History")));
+        panel.add(Box::new(Choice::new("Run", labels, 0)));
+        panel.add(Box::new(Button::new("Replay")));
+        panel.add(Box::new(Button::new("Close")));
+        HistoryScene {
+            panel,
+            runs,
+            selected: 0,
+        }
+    }
+
+    fn update(&mut self, keystate: &KeyState) -> HistoryEndState {
+        if keystate.is_just_pressed("Escape") {
+            return HistoryEndState::Close;
+        }
+
+        match self.panel.update(keystate) {
+            Some((HISTORY_RUN, WidgetEvent::Selected(index))) => {
+                self.selected = index;
+                HistoryEndState::Continue
+            }
+            Some((HISTORY_REPLAY, WidgetEvent::Pressed)) => {
+                match self
+                    .runs
+                    .get(self.selected)
+                    .filter(|run| run.inputs.is_some())
+                {
+                    Some(run) => HistoryEndState::Replay(run.clone()),
+                    None => {
+                        notifications::push("This run has no recording");
+                        HistoryEndState::Continue
+                    }
+                }
+            }
+            Some((HISTORY_CLOSE, WidgetEvent::Pressed)) => HistoryEndState::Close,
+            _ => HistoryEndState::Continue,
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if let Some(run) = self.runs.get(self.selected) {
+            let lines = [
+                format!("Score {} ({})", run.score, run.difficulty.label()),
+                format!("Knocked out by {}", run.cause),
+                format!(
+                    "Seed {:016x}{}",
+                    run.seed,
+                    if run.inputs.is_some() {
+                        ""
+                    } else {
+                        " (no recording)"
+                    }
+                ),
+            ];
+            lines.iter().enumerate().for_each(|(index, line)| {
+                renderer.draw_text(
+                    line,
+                    &Point {
+                        x: HISTORY_DETAIL_POSITION.x,
+                        y: HISTORY_DETAIL_POSITION.y + index as i16 * HISTORY_LINE_HEIGHT,
+                    },
+                    TITLE_TEXT_COLOR,
+                );
+            });
+        }
+        self.panel.draw(renderer);
+    }
+}
 
-struct Walk {
-    obstacle_sheet: Rc<SpriteSheet>,
-    boy: RedHatBoy,
-    backgrounds: [Image; 2],
-    obstacles: Vec<Box<dyn Obstacle>>,
-    stone: HtmlImageElement,
-    timeline: i16,
+const LEVEL_SELECT_LEVEL: usize = 1;
+const LEVEL_SELECT_PLAY: usize = 2;
+const LEVEL_SELECT_MAP: usize = 3;
+const LEVEL_SELECT_CLOSE: usize = 4;
+
+// レベルを選んで走り出す画面
+struct LevelSelect {
+    panel: Panel,
+    // レベルごとに、距離と取った星を並べた一行
+    details: Vec<String>,
+    selected: usize,
 }
 
-impl Walk {
-    fn velocity(&self) -> i16 {
-        -self.boy.walking_speed()
+enum LevelSelectEndState {
+    Continue,
+    Play(usize),
+    Map,
+    Close,
+}
+
+impl LevelSelect {
+    fn new(levels: &[Level], profile: &Profile) -> Self {
+        let mut panel = Panel::new(Point { x: 150, y: 220 }, 300);
+        panel.add(Box::new(Label::new("Levels")));
+        panel.add(Box::new(Choice::new(
+            "Level",
+            levels.iter().map(|level| level.name.clone()).collect(),
+            0,
+        )));
+        panel.add(Box::new(Button::new("Play")));
+        panel.add(Box::new(Button::new("Map")));
+        panel.add(Box::new(Button::new("Close")));
+        LevelSelect {
+            panel,
+            details: levels
+                .iter()
+                .enumerate()
+                .map(|(index, level)| {
+                    let medal = profile
+                        .medal(&level.name)
+                        .map_or("not cleared".into(), level::stars_text);
+                    format!("Level {} - {} m  {}", index + 1, level.length, medal)
+                })
+                .collect(),
+            selected: 0,
+        }
     }
 
-    fn generate_next_segment(&mut self) {
-        let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..2);
-
-        let mut next_obstacles = match next_segment {
-            0 => stone_and_platform(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            1 => platform_and_stone(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            _ => vec![],
-        };
+    fn update(&mut self, keystate: &KeyState) -> LevelSelectEndState {
+        if keystate.is_just_pressed("Escape") {
+            return LevelSelectEndState::Close;
+        }
 
-        self.timeline = rightmost(&next_obstacles);
-        self.obstacles.append(&mut next_obstacles);
+        match self.panel.update(keystate) {
+            Some((LEVEL_SELECT_LEVEL, WidgetEvent::Selected(index))) => {
+                self.selected = index;
+                LevelSelectEndState::Continue
+            }
+            Some((LEVEL_SELECT_PLAY, WidgetEvent::Pressed)) => {
+                LevelSelectEndState::Play(self.selected)
+            }
+            Some((LEVEL_SELECT_MAP, WidgetEvent::Pressed)) => LevelSelectEndState::Map,
+            Some((LEVEL_SELECT_CLOSE, WidgetEvent::Pressed)) => LevelSelectEndState::Close,
+            _ => LevelSelectEndState::Continue,
+        }
     }
 
     fn draw(&self, renderer: &Renderer) {
-        self.backgrounds.iter().for_each(|background| {
-            background.draw(renderer);
-        });
-        self.boy.draw(renderer);
-        self.obstacles.iter().for_each(|obstacle| {
-            obstacle.draw(renderer);
-        });
+        if let Some(detail) = self.details.get(self.selected) {
+            renderer.draw_text(detail, &HISTORY_DETAIL_POSITION, TITLE_TEXT_COLOR);
+        }
+        self.panel.draw(renderer);
     }
+}
 
-    fn knocked_out(&self) -> bool {
-        self.boy.knocked_out()
+struct Walking;
+struct GameOver {
+    new_game_event: UnboundedReceiver<()>,
+    title_event: UnboundedReceiver<()>,
+    menu: MenuList,
+    summary: RunSummary,
+}
+
+const COUNT_UP_FRAMES: u32 = 90;
+const DOUBLE_JUMP_FREQUENCY: f32 = 990.0;
+const DOUBLE_JUMP_DURATION: f64 = 0.12;
+const PICKUP_FREQUENCY: f32 = 1320.0;
+const PICKUP_DURATION: f64 = 0.08;
+const POWER_UP_FREQUENCY: f32 = 660.0;
+const POWER_UP_DURATION: f64 = 0.2;
+const TICK_FREQUENCY: f32 = 880.0;
+const TICK_DURATION: f64 = 0.03;
+const THUNK_FREQUENCY: f32 = 90.0;
+const THUNK_DURATION: f64 = 0.25;
+const NEW_BEST_COLOR: &str = "#FFD700";
+// ジャンプ、スライディング、きわどかった所、倒れた所を並べた帯
+const RUN_STRIP: Rect = Rect::new_from_x_y(100, 260, 400, 30);
+
+#[derive(Debug, PartialEq)]
+enum SummaryCue {
+    Tick,
+    Thunk,
+}
+
+// ゲームオーバー時のスコア表示。だんだん速く数え上げ、最後に低い音で止める
+struct RunSummary {
+    score: u32,
+    difficulty: Difficulty,
+    count_up: Tween,
+    shown: u32,
+    frame: u32,
+    new_best: bool,
+    run_log: RunLog,
+    distance: u32,
+}
+
+impl RunSummary {
+    fn new(elapsed_frames: u32, difficulty: Difficulty) -> Self {
+        let score = elapsed_frames / FRAMES_PER_SECOND;
+        RunSummary {
+            score,
+            difficulty,
+            count_up: Tween::new(0.0, score as f32, COUNT_UP_FRAMES, Easing::EaseIn),
+            shown: 0,
+            frame: 0,
+            new_best: false,
+            run_log: RunLog::default(),
+            distance: 0,
+        }
     }
 
-    fn reset(walk: Self) -> Self {
-        let starting_obstacles =
-            stone_and_platform(walk.stone.clone(), walk.obstacle_sheet.clone(), 0);
-        let timeline = rightmost(&starting_obstacles);
+    // 帯に出すランの記録
+    fn with_run(self, run_log: RunLog, distance: u32) -> Self {
+        RunSummary {
+            run_log,
+            distance,
+            ..self
+        }
+    }
 
-        Walk {
-            boy: RedHatBoy::reset(walk.boy),
-            backgrounds: walk.backgrounds,
-            obstacles: starting_obstacles,
-            obstacle_sheet: walk.obstacle_sheet,
-            stone: walk.stone,
-            timeline,
+    fn mark_new_best(&mut self) {
+        self.new_best = true;
+    }
+
+    fn update(&mut self) -> Option<SummaryCue> {
+        self.frame = self.frame.wrapping_add(1);
+        if self.count_up.finished() {
+            return None;
+        }
+
+        self.count_up.update();
+        if self.count_up.finished() {
+            self.shown = self.score;
+            Some(SummaryCue::Thunk)
+        } else {
+            let value = self.count_up.value() as u32;
+            let ticked = value > self.shown;
+            self.shown = value;
+            ticked.then_some(SummaryCue::Tick)
         }
     }
+
+    fn draw(&self, renderer: &Renderer) {
+        renderer.draw_text(
+            &format!("Time {}s ({})", self.shown, self.difficulty.label()),
+            &Point { x: 250, y: 200 },
+            TITLE_TEXT_COLOR,
+        );
+        let flash = self.frame % FRAMES_PER_SECOND < FRAMES_PER_SECOND / 2;
+        if self.new_best && self.count_up.finished() && flash {
+            renderer.draw_text("NEW BEST!", &Point { x: 250, y: 230 }, NEW_BEST_COLOR);
+        }
+        self.run_log.draw_strip(renderer, &RUN_STRIP, self.distance);
+    }
 }
 
-pub struct WalkTheDog {
-    machine: Option<WalkTheDogStateMachine>,
+enum GameOverChoice {
+    NewGame,
+    Title,
 }
 
-impl WalkTheDog {
-    pub fn new() -> Self {
-        WalkTheDog { machine: None }
+impl GameOver {
+    // Escape でもタイトルへ戻る
+    fn choice(&mut self, keystate: &KeyState) -> Option<GameOverChoice> {
+        match self.menu.update(keystate) {
+            Some(MenuAction::Select(0)) => return Some(GameOverChoice::NewGame),
+            Some(MenuAction::Select(_)) | Some(MenuAction::Cancel) => {
+                return Some(GameOverChoice::Title)
+            }
+            None => {}
+        }
+        if matches!(self.new_game_event.try_next(), Ok(Some(()))) {
+            Some(GameOverChoice::NewGame)
+        } else if matches!(self.title_event.try_next(), Ok(Some(()))) {
+            Some(GameOverChoice::Title)
+        } else {
+            None
+        }
     }
 }
-enum WalkTheDogStateMachine {
-    Ready(WalkTheDogState<Ready>),
-    Walking(WalkTheDogState<Walking>),
-    GameOver(WalkTheDogState<GameOver>),
+
+const RESULT_LABELS: [&str; 3] = ["Time", "Coins", "Score"];
+const RESULT_COUNT_FRAMES: u32 = 40;
+const RESULTS_POSITION: Point = Point { x: 220, y: 150 };
+const RESULTS_LINE_HEIGHT: i16 = 28;
+
+// レベルを終えたときの結果。時間、コイン、点の順に一行ずつ数え上げ、行ごとに低い音で止める
+// 数え終わったら取った星を出す
+struct LevelResults {
+    name: String,
+    values: [u32; 3],
+    stars: u8,
+    line: usize,
+    count_up: Tween,
+    shown: u32,
 }
 
-struct WalkTheDogState<T> {
-    _state: T,
-    walk: Walk,
+impl LevelResults {
+    fn new(name: &str, frames: u32, coins: u32, score: u32, stars: u8) -> Self {
+        let values = [frames / FRAMES_PER_SECOND, coins, score];
+        LevelResults {
+            name: name.into(),
+            values,
+            stars,
+            line: 0,
+            count_up: LevelResults::count_up(values[0]),
+            shown: 0,
+        }
+    }
+
+    fn count_up(value: u32) -> Tween {
+        Tween::new(0.0, value as f32, RESULT_COUNT_FRAMES, Easing::EaseOut)
+    }
+
+    fn finished(&self) -> bool {
+        self.line >= self.values.len()
+    }
+
+    fn update(&mut self) -> Option<SummaryCue> {
+        self.values.get(self.line)?;
+        self.count_up.update();
+        if self.count_up.finished() {
+            self.line += 1;
+            self.shown = 0;
+            if let Some(next) = self.values.get(self.line) {
+                self.count_up = LevelResults::count_up(*next);
+            }
+            Some(SummaryCue::Thunk)
+        } else {
+            let value = self.count_up.value() as u32;
+            let ticked = value > self.shown;
+            self.shown = value;
+            ticked.then_some(SummaryCue::Tick)
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        renderer.draw_text(
+            &format!("{} clear!", self.name),
+            &RESULTS_POSITION,
+            NEW_BEST_COLOR,
+        );
+        RESULT_LABELS
+            .iter()
+            .zip(self.values)
+            .enumerate()
+            .take(self.line + 1)
+            .for_each(|(index, (label, value))| {
+                let shown = if index < self.line { value } else { self.shown };
+                let unit = if index == 0 { "s" } else { "" };
+                renderer.draw_text(
+                    &format!("{} {}{}", label, shown, unit),
+                    &Point {
+                        x: RESULTS_POSITION.x,
+                        y: RESULTS_POSITION.y + (index as i16 + 1) * RESULTS_LINE_HEIGHT,
+                    },
+                    TITLE_TEXT_COLOR,
+                );
+            });
+        if self.finished() {
+            renderer.draw_text(
+                &format!("Stars {}", level::stars_text(self.stars)),
+                &Point {
+                    x: RESULTS_POSITION.x,
+                    y: RESULTS_POSITION.y + (RESULT_LABELS.len() as i16 + 1) * RESULTS_LINE_HEIGHT,
+                },
+                NEW_BEST_COLOR,
+            );
+        }
+    }
 }
 
-struct Ready;
-struct Walking;
-struct GameOver {
-    new_game_event: UnboundedReceiver<()>,
+#[derive(Clone, Copy)]
+enum VictoryChoice {
+    NextLevel,
+    Retry,
+    Title,
 }
 
-impl GameOver {
-    fn new_game_pressed(&mut self) -> bool {
-        matches!(self.new_game_event.try_next(), Ok(Some(())))
+// ゴールのあとの画面。結果を数え終わってからメニューを出す
+struct Victory {
+    results: LevelResults,
+    menu: Panel,
+    // menu のボタンと同じ並び
+    choices: Vec<VictoryChoice>,
+}
+
+impl Victory {
+    fn new(results: LevelResults, has_next: bool) -> Self {
+        let mut menu = Panel::new(Point { x: 200, y: 300 }, 200);
+        let mut choices = vec![];
+        if has_next {
+            menu.add(Box::new(Button::new("Next level")));
+            choices.push(VictoryChoice::NextLevel);
+        }
+        menu.add(Box::new(Button::new("Retry")));
+        choices.push(VictoryChoice::Retry);
+        menu.add(Box::new(Button::new("Title")));
+        choices.push(VictoryChoice::Title);
+        Victory {
+            results,
+            menu,
+            choices,
+        }
+    }
+
+    // Escape でもタイトルへ戻る
+    fn choice(&mut self, keystate: &KeyState) -> Option<VictoryChoice> {
+        if keystate.is_just_pressed("Escape") {
+            return Some(VictoryChoice::Title);
+        }
+        match self.menu.update(keystate)? {
+            (index, WidgetEvent::Pressed) => self.choices.get(index).copied(),
+            _ => None,
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        self.results.draw(renderer);
+        if self.results.finished() {
+            self.menu.draw(renderer);
+        }
+    }
+}
+
+fn play_summary_cue(audio: &Audio, cue: Option<SummaryCue>) {
+    let played = match cue {
+        Some(SummaryCue::Tick) => audio.play_tone_on(Bus::Ui, TICK_FREQUENCY, TICK_DURATION),
+        Some(SummaryCue::Thunk) => audio.play_tone_on(Bus::Ui, THUNK_FREQUENCY, THUNK_DURATION),
+        None => Ok(()),
+    };
+    if let Err(err) = played {
+        log!("Could not play summary sound {:#?}", err);
     }
 }
 
 enum Event {
     Run,
     Slide,
+    Crouch,
+    Stand,
     Update,
     Jump,
+    DoubleJump,
     KnockOut,
+    Stumble,
     Land(i16),
+    Ground(i16),
+    Tune(Physics),
+    Celebrate,
 }
 
+// ぶつかる直前の状態。猶予のうちにジャンプが来たらここまで巻き戻す
+#[derive(Clone)]
+struct Grace {
+    before: RedHatBoyStateMachine,
+    ticks_left: u8,
+}
+
+// 巻き戻してから石を飛び越えるまでの間は、同じ石に当たっても倒れない
+const RESCUE_TICKS: u8 = 12;
+// よろけたあと、同じ障害物を抜けるまではぶつからない
+const STUMBLE_RECOVERY_TICKS: u8 = 60;
+const ATTACK_KEY: &str = "KeyX";
+// 振り始めから ATTACK_ACTIVE_TICKS の間だけ前方に当たり判定が出る
+const ATTACK_TICKS: u8 = 24;
+const ATTACK_ACTIVE_TICKS: u8 = 10;
+const ATTACK_REACH: i16 = 40;
+const ATTACK_COLOR: &str = "rgba(255, 255, 255, 0.5)";
+
 pub struct RedHatBoy {
     state_machine: RedHatBoyStateMachine,
-    sprite_sheet: Sheet,
-    image: HtmlImageElement,
+    audio: Audio,
+    jump_sound: Sound,
+    sprite_sheet: SpriteSheet,
+    animations: Vec<Option<Rc<Animation>>>,
+    forgiveness: u8,
+    knockouts: bool,
+    grace: Option<Grace>,
+    rescue_ticks: u8,
+    pickups: u32,
+    // 拾ったまま Walk がまだ受け取っていないパワーアップ
+    power_ups: Vec<PowerUpKind>,
+    // Walk がシールドの効いている間だけ立てる。倒れるのを一回防ぐと下ろす
+    shield: bool,
+    attack_ticks: u8,
+    // 倒れ始めたフレームだけ true。Walk が拾って画面を揺らす
+    impact: bool,
+    // ゴールの線を越えたフレームだけ true。Walk が拾って勝利の流れを始める
+    crossed_finish: bool,
+    // 変化で大きさが変わったとき、絵と当たり判定を足元を中心に同じだけ伸ばす
+    scale: Scale,
 }
 
 impl RedHatBoy {
-    fn new(sheet: Sheet, image: HtmlImageElement, audio: Audio, jump_sound: Sound) -> Self {
+    fn new(
+        sprite_sheet: SpriteSheet,
+        audio: Audio,
+        jump_sound: Sound,
+        physics: Physics,
+        forgiveness: u8,
+    ) -> Self {
         RedHatBoy {
-            state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, jump_sound)),
-            sprite_sheet: sheet,
-            image: image,
+            state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new(physics)),
+            audio,
+            jump_sound,
+            animations: resolve_animations(&sprite_sheet),
+            sprite_sheet,
+            forgiveness,
+            knockouts: true,
+            grace: None,
+            rescue_ticks: 0,
+            pickups: 0,
+            power_ups: vec![],
+            shield: false,
+            attack_ticks: 0,
+            impact: false,
+            crossed_finish: false,
+            scale: Scale::IDENTITY,
         }
     }
 
-    fn frame_name(&self) -> String {
-        format!(
-            "{} ({}).png",
-            self.state_machine.frame_name(),
-            (self.state_machine.context().frame / 3) + 1
-        )
+    fn set_sheet(&mut self, sheet: Sheet, image: Bitmap) -> Result<()> {
+        let frame_names = required_frame_names();
+        sheet.validate_frames(frame_names.iter().map(|name| name.as_str()))?;
+        let sprite_sheet = SpriteSheet::new(sheet, image);
+        sprite_sheet.validate_animations(animation_names())?;
+        self.animations = resolve_animations(&sprite_sheet);
+        self.sprite_sheet = sprite_sheet;
+        Ok(())
+    }
+
+    // 毎フレームの描画では名前を組み立てずに、解決済みのハンドルを引く
+    fn current_handle(&self) -> Option<SpriteHandle> {
+        self.animations
+            .get(self.state_machine.animation())?
+            .as_ref()?
+            .frame_at(self.state_machine.context().frame)
     }
 
     fn current_sprite(&self) -> Option<&Cell> {
-        self.sprite_sheet.frames.get(&self.frame_name())
+        self.current_handle()
+            .map(|handle| self.sprite_sheet.cell_at(handle))
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        let sprite = self.current_sprite().expect("Cell not found");
+    fn draw_sprite(&self, renderer: &Renderer) {
+        let handle = self.current_handle().expect("Cell not found");
+        let destination = self.destination_for(self.sprite_sheet.cell_at(handle));
+
+        renderer.with_modifiers(self.state_machine.context().modifiers, |renderer| {
+            self.sprite_sheet.draw_handle_ex(
+                renderer,
+                handle,
+                &destination,
+                self.scale,
+                &self.feet(),
+            );
+        });
+    }
 
-        renderer.draw_image(
-            &self.image,
-            &Rect::new_from_x_y(
-                sprite.frame.x.into(),
-                sprite.frame.y.into(),
-                sprite.frame.w.into(),
-                sprite.frame.h.into(),
-            ),
-            &self.destination_box(),
-        );
+    fn draw(&self, renderer: &Renderer) {
+        self.draw_sprite(renderer);
 
         renderer.draw_bounding_box(&self.bounding_box());
+        if let Some(hitbox) = self.hitbox() {
+            renderer.fill_rect(&hitbox, ATTACK_COLOR);
+        }
     }
 
     fn update(&mut self) {
-        self.state_machine = self.state_machine.clone().update();
+        self.state_machine.update();
+        self.rescue_ticks = self.rescue_ticks.saturating_sub(1);
+        self.attack_ticks = self.attack_ticks.saturating_sub(1);
+        if let Some(grace) = &mut self.grace {
+            grace.ticks_left = grace.ticks_left.saturating_sub(1);
+            if grace.ticks_left == 0 {
+                self.grace = None;
+            }
+        }
     }
 
     fn run_right(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Run);
+        self.state_machine.transition(Event::Run);
     }
 
     fn slide(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Slide);
+        self.state_machine.transition(Event::Slide);
+    }
+
+    // 押している間だけしゃがみ続ける。離したら立ち上がる
+    fn crouch(&mut self, held: bool) {
+        let event = if held { Event::Crouch } else { Event::Stand };
+        self.state_machine.transition(event);
+    }
+
+    // 走っているときだけ振れる。振り終わるまでは次を受け付けない
+    fn attack(&mut self) {
+        let running = matches!(self.state_machine, RedHatBoyStateMachine::Running(_));
+        if running && self.attack_ticks == 0 {
+            self.attack_ticks = ATTACK_TICKS;
+        }
     }
 
     fn jump(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Jump);
+        if let Some(grace) = self.grace.take() {
+            self.state_machine = grace.before;
+            self.state_machine
+                .modify(RenderModifiers::blink(RESCUE_TICKS));
+            self.rescue_ticks = RESCUE_TICKS;
+        }
+        let running = self.is_running();
+        self.state_machine.transition(Event::Jump);
+        if running && self.is_jumping() {
+            if let Err(err) = self.audio.play_loud_sound(&self.jump_sound) {
+                log!("Error playing jump sound {:#?}", err);
+            }
+        }
+    }
+
+    // 跳んでいる最中に押し直したときだけ。押しっぱなしでは出ない
+    fn double_jump(&mut self) {
+        let jumping = matches!(self.state_machine, RedHatBoyStateMachine::Jumping(_));
+        self.state_machine.transition(Event::DoubleJump);
+        if jumping && matches!(self.state_machine, RedHatBoyStateMachine::DoubleJumping(_)) {
+            if let Err(err) = self
+                .audio
+                .play_tone(DOUBLE_JUMP_FREQUENCY, DOUBLE_JUMP_DURATION)
+            {
+                log!("Error playing double jump sound {:#?}", err);
+            }
+        }
     }
 
     fn log_context(&self) {
@@ -201,8 +3270,10 @@ impl RedHatBoy {
     }
 
     fn destination_box(&self) -> Rect {
-        let sprite = self.current_sprite().expect("Cell not found!");
+        self.destination_for(self.current_sprite().expect("Cell not found!"))
+    }
 
+    fn destination_for(&self, sprite: &Cell) -> Rect {
         Rect::new_from_x_y(
             (self.state_machine.context().position.x + sprite.sprite_source_size.x as i16).into(),
             (self.state_machine.context().position.y + sprite.sprite_source_size.y as i16).into(),
@@ -211,21 +3282,123 @@ impl RedHatBoy {
         )
     }
 
+    fn audio(&self) -> &Audio {
+        &self.audio
+    }
+
+    fn inspect(&self) -> String {
+        let context = self.state_machine.context();
+        format!(
+            "Boy {} frame {} at {:?} velocity {:?} box {:?}",
+            self.state_machine.frame_name(),
+            context.frame,
+            context.position,
+            context.velocity,
+            self.bounding_box()
+        )
+    }
+
     fn walking_speed(&self) -> i16 {
         self.state_machine.context().velocity.x
     }
 
+    fn physics(&self) -> Physics {
+        self.state_machine.context().physics
+    }
+
+    fn height_above_ground(&self) -> i16 {
+        self.state_machine.context().ground - self.bounding_box().bottom()
+    }
+
+    fn take_pickups(&mut self) -> u32 {
+        std::mem::take(&mut self.pickups)
+    }
+
+    fn take_power_ups(&mut self) -> Vec<PowerUpKind> {
+        std::mem::take(&mut self.power_ups)
+    }
+
+    // 猶予も Kid モードも関係なく倒れる
+    fn collapse(&mut self) {
+        self.grace = None;
+        self.fall();
+    }
+
+    fn fall(&mut self) {
+        let falling = matches!(self.state_machine, RedHatBoyStateMachine::Falling(_));
+        self.state_machine.transition(Event::KnockOut);
+        self.impact |= !falling && matches!(self.state_machine, RedHatBoyStateMachine::Falling(_));
+    }
+
+    fn take_impact(&mut self) -> bool {
+        std::mem::take(&mut self.impact)
+    }
+
+    fn take_finish(&mut self) -> bool {
+        std::mem::take(&mut self.crossed_finish)
+    }
+
+    // 走っていて地面にいるときだけ、その場で待機の姿に戻る
+    fn celebrate(&mut self) {
+        self.state_machine.transition(Event::Celebrate);
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(self.state_machine, RedHatBoyStateMachine::Running(_))
+    }
+
+    fn is_jumping(&self) -> bool {
+        matches!(
+            self.state_machine,
+            RedHatBoyStateMachine::Jumping(_) | RedHatBoyStateMachine::DoubleJumping(_)
+        )
+    }
+
+    fn is_sliding(&self) -> bool {
+        matches!(self.state_machine, RedHatBoyStateMachine::Sliding(_))
+    }
+
+    fn is_crouching(&self) -> bool {
+        matches!(self.state_machine, RedHatBoyStateMachine::Crouching(_))
+    }
+
+    // 倒れている途中も含める
+    fn is_down(&self) -> bool {
+        matches!(
+            self.state_machine,
+            RedHatBoyStateMachine::Falling(_) | RedHatBoyStateMachine::KnockedOut(_)
+        )
+    }
+
+    fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    // 拡大縮小の中心。大きさが変わっても立っている高さは変わらない
+    fn feet(&self) -> Point {
+        let destination_box = self.destination_box();
+        Point {
+            x: destination_box.x() + destination_box.width / 2,
+            y: destination_box.bottom(),
+        }
+    }
+
     fn knocked_out(&self) -> bool {
         self.state_machine.knocked_out()
     }
 
     fn reset(boy: Self) -> Self {
-        RedHatBoy::new(
-            boy.sprite_sheet,
-            boy.image,
-            boy.state_machine.context().audio.clone(),
-            boy.state_machine.context().jump_sound.clone(),
-        )
+        let knockouts = boy.knockouts;
+        RedHatBoy {
+            knockouts,
+            ..RedHatBoy::new(
+                boy.sprite_sheet,
+                boy.audio,
+                boy.jump_sound,
+                boy.state_machine.context().physics,
+                boy.forgiveness,
+            )
+        }
     }
 }
 
@@ -234,12 +3407,22 @@ impl Disturbee for RedHatBoy {
         const X_OFFSET: i16 = 18;
         const Y_OFFSET: i16 = 14;
         const WIDTH_OFFSET: i16 = 28;
+        // しゃがんでいる間は頭の上をさらに空け、低い障害物の下をくぐれるようにする
+        const CROUCHING_Y_OFFSET: i16 = 20;
         let destination_box = self.destination_box();
-        Rect::new_from_x_y(
-            destination_box.x() + X_OFFSET,
-            destination_box.y() + Y_OFFSET,
-            destination_box.width - WIDTH_OFFSET,
-            destination_box.height - Y_OFFSET,
+        let y_offset = if self.is_crouching() {
+            Y_OFFSET + CROUCHING_Y_OFFSET
+        } else {
+            Y_OFFSET
+        };
+        self.scale.apply(
+            &Rect::new_from_x_y(
+                destination_box.x() + X_OFFSET,
+                destination_box.y() + y_offset,
+                destination_box.width - WIDTH_OFFSET,
+                destination_box.height - y_offset,
+            ),
+            &self.feet(),
         )
     }
 
@@ -252,14 +3435,132 @@ impl Disturbee for RedHatBoy {
     }
 
     fn land_on(&mut self, ground_height: i16) {
-        self.state_machine = self
-            .state_machine
-            .clone()
-            .transition(Event::Land(ground_height));
+        self.state_machine.transition(Event::Land(ground_height));
     }
 
     fn knock_out(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::KnockOut);
+        if self.rescue_ticks > 0 {
+            return;
+        }
+        let hit = RenderModifiers::hit(STUMBLE_RECOVERY_TICKS);
+        if self.shield {
+            self.shield = false;
+            self.rescue_ticks = STUMBLE_RECOVERY_TICKS;
+            self.state_machine.modify(hit);
+            return;
+        }
+        if !self.knockouts {
+            self.state_machine.transition(Event::Stumble);
+            self.state_machine.modify(hit);
+            self.rescue_ticks = STUMBLE_RECOVERY_TICKS;
+            return;
+        }
+        let can_jump = matches!(self.state_machine, RedHatBoyStateMachine::Running(_));
+        if can_jump && self.forgiveness > 0 && self.grace.is_none() {
+            self.grace = Some(Grace {
+                before: self.state_machine.clone(),
+                ticks_left: self.forgiveness,
+            });
+        }
+        self.fall();
+        self.state_machine.modify(RenderModifiers::hit(0));
+    }
+
+    fn collect(&mut self) {
+        self.pickups += 1;
+    }
+
+    fn power_up(&mut self, kind: PowerUpKind) {
+        self.power_ups.push(kind);
+    }
+
+    fn cross_finish(&mut self) {
+        self.crossed_finish = true;
+    }
+
+    fn hitbox(&self) -> Option<Rect> {
+        if self.attack_ticks + ATTACK_ACTIVE_TICKS <= ATTACK_TICKS || self.knocked_out() {
+            return None;
+        }
+        let body = self.bounding_box();
+        Some(Rect::new_from_x_y(
+            body.right(),
+            body.y(),
+            ATTACK_REACH,
+            body.height,
+        ))
+    }
+}
+
+// しおり用に写しておく RedHatBoy の状態
+struct BoySnapshot {
+    state_machine: RedHatBoyStateMachine,
+    grace: Option<Grace>,
+    rescue_ticks: u8,
+    pickups: u32,
+    attack_ticks: u8,
+}
+
+impl RedHatBoy {
+    fn snapshot(&self) -> BoySnapshot {
+        BoySnapshot {
+            state_machine: self.state_machine.clone(),
+            grace: self.grace.clone(),
+            rescue_ticks: self.rescue_ticks,
+            pickups: self.pickups,
+            attack_ticks: self.attack_ticks,
+        }
+    }
+
+    // 待機状態から inputs を送って始めた動きを、別の状態に移るか 1 周するまで記録する。
+    // 今の状態は最後に元へ戻す
+    fn record_move(&mut self, inputs: &[FrameInput]) -> Vec<FrameData> {
+        let snapshot = self.snapshot();
+        self.state_machine = RedHatBoyState::new(self.physics()).into();
+        inputs
+            .iter()
+            .for_each(|input| self.state_machine.transition(input.event()));
+
+        let state = std::mem::discriminant(&self.state_machine);
+        let mut frames = vec![];
+        while std::mem::discriminant(&self.state_machine) == state
+            && frames.len() < FRAME_DATA_MAX_TICKS
+            && (frames.is_empty() || self.state_machine.context().frame != 0)
+        {
+            frames.push(FrameData {
+                state: self.state_machine.frame_name().to_string(),
+                frame: self.state_machine.context().frame,
+                handle: self.current_handle(),
+                destination: self.destination_box(),
+                bounding_box: self.bounding_box(),
+                cancels: FRAME_INPUTS
+                    .iter()
+                    .copied()
+                    .filter(|input| self.state_machine.accepts(*input))
+                    .collect(),
+            });
+            self.state_machine.update();
+        }
+        self.restore(&snapshot);
+        frames
+    }
+
+    fn restore(&mut self, snapshot: &BoySnapshot) {
+        self.state_machine = snapshot.state_machine.clone();
+        self.grace = snapshot.grace.clone();
+        self.rescue_ticks = snapshot.rescue_ticks;
+        self.pickups = snapshot.pickups;
+        self.attack_ticks = snapshot.attack_ticks;
+    }
+
+    fn set_physics(&mut self, physics: Physics) {
+        self.state_machine.transition(Event::Tune(physics));
+    }
+
+    fn set_ground(&mut self, ground: i16) {
+        if self.state_machine.context().ground != ground {
+            self.state_machine.transition(Event::Ground(ground));
+        }
     }
 }
 
@@ -268,62 +3569,186 @@ enum RedHatBoyStateMachine {
     Idle(RedHatBoyState<Idle>),
     Running(RedHatBoyState<Running>),
     Sliding(RedHatBoyState<Sliding>),
+    Crouching(RedHatBoyState<Crouching>),
     Jumping(RedHatBoyState<Jumping>),
+    DoubleJumping(RedHatBoyState<DoubleJumping>),
     Falling(RedHatBoyState<Falling>),
     KnockedOut(RedHatBoyState<KnockedOut>),
+    Stumbling(RedHatBoyState<Stumbling>),
 }
 
 impl RedHatBoyStateMachine {
-    fn transition(self, event: Event) -> Self {
-        match (self.clone(), event) {
+    // 状態をその場で進める。取り出している間だけ同じ context の待機状態を置くので、複製はしない
+    fn transition(&mut self, event: Event) {
+        let placeholder = RedHatBoyState::placeholder(*self.context()).into();
+        *self = std::mem::replace(self, placeholder).next(event);
+    }
+
+    fn next(self, event: Event) -> Self {
+        match (self, event) {
             (RedHatBoyStateMachine::Idle(state), Event::Run) => state.run().into(),
             (RedHatBoyStateMachine::Running(state), Event::Slide) => state.slide().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Crouch) => state.crouch().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Celebrate) => state.celebrate().into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::Stand) => state.stand().into(),
             (RedHatBoyStateMachine::Running(state), Event::Jump) => state.jump().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::DoubleJump) => {
+                state.double_jump().into()
+            }
             (RedHatBoyStateMachine::Idle(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Running(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Sliding(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Jumping(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Falling(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::KnockedOut(state), Event::Update) => state.update().into(),
             (RedHatBoyStateMachine::Running(state), Event::KnockOut) => state.knock_out().into(),
             (RedHatBoyStateMachine::Jumping(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::KnockOut) => {
+                state.knock_out().into()
+            }
             (RedHatBoyStateMachine::Sliding(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Stumble) => state.stumble().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Stumble) => state.stumble().into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Stumble) => state.stumble().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Stumble) => state.stumble().into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::Stumble) => state.stumble().into(),
+            (RedHatBoyStateMachine::Stumbling(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Stumbling(state), Event::Land(y)) => state.land_on(y).into(),
+            (RedHatBoyStateMachine::Stumbling(state), Event::Ground(y)) => {
+                state.set_ground(y).into()
+            }
+            (RedHatBoyStateMachine::Stumbling(state), Event::Tune(p)) => {
+                state.set_physics(p).into()
+            }
             (RedHatBoyStateMachine::Jumping(state), Event::Land(y)) => state.land_on(y).into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Land(y)) => {
+                state.land_on(y).into()
+            }
             (RedHatBoyStateMachine::Running(state), Event::Land(y)) => state.land_on(y).into(),
             (RedHatBoyStateMachine::Sliding(state), Event::Land(y)) => state.land_on(y).into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::Land(y)) => state.land_on(y).into(),
             (RedHatBoyStateMachine::KnockedOut(state), Event::Land(y)) => state.land_on(y).into(),
-            _ => self,
+            (RedHatBoyStateMachine::Idle(state), Event::Ground(y)) => state.set_ground(y).into(),
+            (RedHatBoyStateMachine::Running(state), Event::Ground(y)) => state.set_ground(y).into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Ground(y)) => state.set_ground(y).into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::Ground(y)) => {
+                state.set_ground(y).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::Ground(y)) => state.set_ground(y).into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Ground(y)) => {
+                state.set_ground(y).into()
+            }
+            (RedHatBoyStateMachine::Falling(state), Event::Ground(y)) => state.set_ground(y).into(),
+            (RedHatBoyStateMachine::KnockedOut(state), Event::Ground(y)) => {
+                state.set_ground(y).into()
+            }
+            (RedHatBoyStateMachine::Idle(state), Event::Tune(p)) => state.set_physics(p).into(),
+            (RedHatBoyStateMachine::Running(state), Event::Tune(p)) => state.set_physics(p).into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Tune(p)) => state.set_physics(p).into(),
+            (RedHatBoyStateMachine::Crouching(state), Event::Tune(p)) => {
+                state.set_physics(p).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::Tune(p)) => state.set_physics(p).into(),
+            (RedHatBoyStateMachine::DoubleJumping(state), Event::Tune(p)) => {
+                state.set_physics(p).into()
+            }
+            (RedHatBoyStateMachine::Falling(state), Event::Tune(p)) => state.set_physics(p).into(),
+            (RedHatBoyStateMachine::KnockedOut(state), Event::Tune(p)) => {
+                state.set_physics(p).into()
+            }
+            (machine, _) => machine,
         }
     }
 
-    pub fn frame_name(&self) -> &str {
+    // ANIMATIONS の番号。描画では名前を比べずにこれで引く
+    pub fn animation(&self) -> usize {
         match self {
-            RedHatBoyStateMachine::Idle(state) => state.frame_name(),
-            RedHatBoyStateMachine::Running(state) => state.frame_name(),
-            RedHatBoyStateMachine::Sliding(state) => state.frame_name(),
-            RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
-            RedHatBoyStateMachine::Falling(state) => state.frame_name(),
-            RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
+            RedHatBoyStateMachine::Idle(state) => state.animation(),
+            RedHatBoyStateMachine::Running(state) => state.animation(),
+            RedHatBoyStateMachine::Sliding(state) => state.animation(),
+            RedHatBoyStateMachine::Crouching(state) => state.animation(),
+            RedHatBoyStateMachine::Jumping(state) => state.animation(),
+            RedHatBoyStateMachine::DoubleJumping(state) => state.animation(),
+            RedHatBoyStateMachine::Falling(state) => state.animation(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.animation(),
+            RedHatBoyStateMachine::Stumbling(state) => state.animation(),
         }
     }
 
+    pub fn frame_name(&self) -> &'static str {
+        ANIMATIONS[self.animation()].0
+    }
+
     pub fn context(&self) -> &RedHatBoyContext {
         match self {
             RedHatBoyStateMachine::Idle(state) => &state.context(),
             RedHatBoyStateMachine::Running(state) => &state.context(),
             RedHatBoyStateMachine::Sliding(state) => &state.context(),
+            RedHatBoyStateMachine::Crouching(state) => state.context(),
             RedHatBoyStateMachine::Jumping(state) => &state.context(),
+            RedHatBoyStateMachine::DoubleJumping(state) => state.context(),
             RedHatBoyStateMachine::Falling(state) => &state.context(),
             RedHatBoyStateMachine::KnockedOut(state) => &state.context(),
+            RedHatBoyStateMachine::Stumbling(state) => state.context(),
+        }
+    }
+
+    fn context_mut(&mut self) -> &mut RedHatBoyContext {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.context_mut(),
+            RedHatBoyStateMachine::Running(state) => state.context_mut(),
+            RedHatBoyStateMachine::Sliding(state) => state.context_mut(),
+            RedHatBoyStateMachine::Crouching(state) => state.context_mut(),
+            RedHatBoyStateMachine::Jumping(state) => state.context_mut(),
+            RedHatBoyStateMachine::DoubleJumping(state) => state.context_mut(),
+            RedHatBoyStateMachine::Falling(state) => state.context_mut(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.context_mut(),
+            RedHatBoyStateMachine::Stumbling(state) => state.context_mut(),
         }
     }
 
-    pub fn update(self) -> Self {
-        self.transition(Event::Update)
+    pub fn update(&mut self) {
+        self.transition(Event::Update);
+    }
+
+    fn knocked_out(&self) -> bool {
+        matches!(self, RedHatBoyStateMachine::KnockedOut(_))
+    }
+
+    // 描き方の変化を今の状態の context に付ける。状態は変えない
+    fn modify(&mut self, modifiers: RenderModifiers) {
+        self.context_mut().modifiers = modifiers;
     }
 
-    fn knocked_out(&self) -> bool {
-        matches!(self, RedHatBoyStateMachine::KnockedOut(_))
+    // input で別の状態へ移れるか。transition の表と揃える
+    fn accepts(&self, input: FrameInput) -> bool {
+        matches!(
+            (self, input),
+            (RedHatBoyStateMachine::Idle(_), FrameInput::Run)
+                | (
+                    RedHatBoyStateMachine::Running(_),
+                    FrameInput::Slide
+                        | FrameInput::Crouch
+                        | FrameInput::Jump
+                        | FrameInput::KnockOut
+                        | FrameInput::Stumble
+                )
+                | (
+                    RedHatBoyStateMachine::Crouching(_),
+                    FrameInput::Stand | FrameInput::KnockOut | FrameInput::Stumble
+                )
+                | (
+                    RedHatBoyStateMachine::Jumping(_),
+                    FrameInput::DoubleJump | FrameInput::KnockOut | FrameInput::Stumble
+                )
+                | (
+                    RedHatBoyStateMachine::Sliding(_) | RedHatBoyStateMachine::DoubleJumping(_),
+                    FrameInput::KnockOut | FrameInput::Stumble
+                )
+        )
     }
 }
 
@@ -345,12 +3770,35 @@ impl From<RedHatBoyState<Sliding>> for RedHatBoyStateMachine {
     }
 }
 
+impl From<RedHatBoyState<Crouching>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Crouching>) -> Self {
+        RedHatBoyStateMachine::Crouching(state)
+    }
+}
+
 impl From<RedHatBoyState<Jumping>> for RedHatBoyStateMachine {
     fn from(state: RedHatBoyState<Jumping>) -> Self {
         RedHatBoyStateMachine::Jumping(state)
     }
 }
 
+impl From<RedHatBoyState<DoubleJumping>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<DoubleJumping>) -> Self {
+        RedHatBoyStateMachine::DoubleJumping(state)
+    }
+}
+
+impl From<DoubleJumpingEndState> for RedHatBoyStateMachine {
+    fn from(end_state: DoubleJumpingEndState) -> Self {
+        match end_state {
+            DoubleJumpingEndState::Complete(running_state) => running_state.into(),
+            DoubleJumpingEndState::DoubleJumping(double_jumping_state) => {
+                double_jumping_state.into()
+            }
+        }
+    }
+}
+
 impl From<RedHatBoyState<Falling>> for RedHatBoyStateMachine {
     fn from(state: RedHatBoyState<Falling>) -> Self {
         RedHatBoyStateMachine::Falling(state)
@@ -381,6 +3829,21 @@ impl From<RedHatBoyState<KnockedOut>> for RedHatBoyStateMachine {
     }
 }
 
+impl From<RedHatBoyState<Stumbling>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Stumbling>) -> Self {
+        RedHatBoyStateMachine::Stumbling(state)
+    }
+}
+
+impl From<StumblingEndState> for RedHatBoyStateMachine {
+    fn from(end_state: StumblingEndState) -> Self {
+        match end_state {
+            StumblingEndState::Complete(running_state) => running_state.into(),
+            StumblingEndState::Stumbling(stumbling_state) => stumbling_state.into(),
+        }
+    }
+}
+
 impl From<FallingEndState> for RedHatBoyStateMachine {
     fn from(end_state: FallingEndState) -> Self {
         match end_state {
@@ -391,12 +3854,11 @@ impl From<FallingEndState> for RedHatBoyStateMachine {
 }
 
 mod red_hat_boy_states {
-    use crate::engine::Audio;
+    use crate::config::Physics;
+    use crate::draw::RenderModifiers;
     use crate::engine::Point;
-    use crate::engine::Sound;
 
     use super::RedHatBoyStateMachine;
-    const FLOOR: i16 = 479;
     const STARTING_POINT: i16 = -20;
     const IDLE_FRAME_NAME: &str = "Idle";
     const RUN_FRAME_NAME: &str = "Run";
@@ -405,15 +3867,50 @@ mod red_hat_boy_states {
     const IDLE_FRAMES: u8 = 29;
     const RUNNING_FRAMES: u8 = 23;
     const SLIDING_FRAMES: u8 = 14;
+    // しゃがみの絵はないので、スライディングの一番低い姿勢で止める
+    const CROUCHING_FRAME: u8 = SLIDING_FRAMES - 1;
     const JUMPING_FRAMES: u8 = 35;
+    // 二段目はジャンプの絵の後半、体を丸めたところだけを繰り返す
+    const DOUBLE_JUMPING_FIRST_FRAME: u8 = 12;
+    // 二段目の跳ぶ強さは一段目の 2/3
+    const DOUBLE_JUMP_STRENGTH: (i16, i16) = (2, 3);
     const FALLING_FRAMES: u8 = 29;
     const FALLING_FRAME_NAME: &str = "Dead";
-    const RUNNING_SPEED: i16 = 4;
-    const JUMP_SPEED: i16 = -25;
-    const GRAVITY: i16 = 1;
-    use super::CANVAS_HEIGHT;
-    const PLAYER_HEIGHT: i16 = CANVAS_HEIGHT - FLOOR;
-    const FALLING_TERMINAL_SPEED: i16 = 20;
+    const STUMBLING_FRAME_NAME: &str = "Hurt";
+    const STUMBLING_FRAMES: u8 = 21;
+    use super::GROUND_LEVEL;
+    const PLAYER_HEIGHT: i16 = 121;
+
+    // ANIMATIONS での並び
+    const IDLE_ANIMATION: usize = 0;
+    const RUN_ANIMATION: usize = 1;
+    const SLIDING_ANIMATION: usize = 2;
+    const JUMPING_ANIMATION: usize = 3;
+    const FALLING_ANIMATION: usize = 4;
+    const STUMBLING_ANIMATION: usize = 5;
+
+    pub const ANIMATIONS: [(&str, u8); 6] = [
+        (IDLE_FRAME_NAME, IDLE_FRAMES),
+        (RUN_FRAME_NAME, RUNNING_FRAMES),
+        (SLIDING_FRAME_NAME, SLIDING_FRAMES),
+        (JUMPING_FRAME_NAME, JUMPING_FRAMES),
+        (FALLING_FRAME_NAME, FALLING_FRAMES),
+        (STUMBLING_FRAME_NAME, STUMBLING_FRAMES),
+    ];
+
+    pub fn animation_names() -> impl Iterator<Item = &'static str> {
+        ANIMATIONS.iter().map(|(name, _frames)| *name)
+    }
+
+    // 各状態のアニメーションで参照される全フレーム名
+    pub fn required_frame_names() -> Vec<String> {
+        ANIMATIONS
+            .iter()
+            .flat_map(|(name, frames)| {
+                (1..=(frames / 3) + 1).map(move |index| format!("{} ({}).png", name, index))
+            })
+            .collect()
+    }
 
     #[derive(Clone)]
     pub struct RedHatBoyState<S> {
@@ -425,15 +3922,47 @@ mod red_hat_boy_states {
         pub fn context(&self) -> &RedHatBoyContext {
             &self.context
         }
+
+        pub fn set_ground(mut self, ground: i16) -> Self {
+            self.context.ground = ground;
+            self
+        }
+
+        pub fn context_mut(&mut self) -> &mut RedHatBoyContext {
+            &mut self.context
+        }
+
+        // 走っている最中なら新しい速さで走り続ける
+        pub fn set_physics(mut self, physics: Physics) -> Self {
+            if self.context.velocity.x == self.context.physics.running_speed {
+                self.context.velocity.x = physics.running_speed;
+            }
+            self.context.physics = physics;
+            self
+        }
+
+        // よろけている間は半分の速さで進む
+        pub fn stumble(self) -> RedHatBoyState<Stumbling> {
+            let slow = self.context.physics.running_speed / 2;
+            let mut context = self.context.reset_frame();
+            context.velocity.x = slow;
+            RedHatBoyState {
+                context,
+                _state: Stumbling,
+            }
+        }
     }
 
-    #[derive(Clone)]
+    // 音は RedHatBoy が持つので、context は丸ごと写しても安い
+    #[derive(Clone, Copy)]
     pub struct RedHatBoyContext {
         pub frame: u8,
         pub position: Point,
         pub velocity: Point,
-        pub audio: Audio,
-        pub jump_sound: Sound,
+        pub ground: i16,
+        pub physics: Physics,
+        // ぶつかった後の光や点滅。update のたびに弱まる
+        pub modifiers: RenderModifiers,
     }
 
     #[derive(Copy, Clone)]
@@ -445,32 +3974,50 @@ mod red_hat_boy_states {
     #[derive(Copy, Clone)]
     pub struct Sliding;
 
+    #[derive(Copy, Clone)]
+    pub struct Crouching;
+
     #[derive(Copy, Clone)]
     pub struct Jumping;
 
+    #[derive(Copy, Clone)]
+    pub struct DoubleJumping;
+
     #[derive(Copy, Clone)]
     pub struct Falling;
 
     #[derive(Copy, Clone)]
     pub struct KnockedOut;
 
+    #[derive(Copy, Clone)]
+    pub struct Stumbling;
+
     impl RedHatBoyState<Idle> {
-        pub fn new(audio: Audio, jump_sound: Sound) -> Self {
+        pub fn new(physics: Physics) -> Self {
             RedHatBoyState {
                 context: RedHatBoyContext {
                     frame: 0,
                     position: Point {
                         x: STARTING_POINT,
-                        y: FLOOR,
+                        y: GROUND_LEVEL - PLAYER_HEIGHT,
                     },
                     velocity: Point { x: 0, y: 0 },
-                    audio,
-                    jump_sound,
+                    ground: GROUND_LEVEL,
+                    physics,
+                    modifiers: RenderModifiers::default(),
                 },
                 _state: Idle {},
             }
         }
 
+        // 状態を入れ替える間だけ置いておく。context はそのまま写す
+        pub fn placeholder(context: RedHatBoyContext) -> Self {
+            RedHatBoyState {
+                context,
+                _state: Idle,
+            }
+        }
+
         pub fn run(self) -> RedHatBoyState<Running> {
             RedHatBoyState {
                 context: self.context.reset_frame().run_right(),
@@ -478,8 +4025,8 @@ mod red_hat_boy_states {
             }
         }
 
-        pub fn frame_name(&self) -> &str {
-            IDLE_FRAME_NAME
+        pub fn animation(&self) -> usize {
+            IDLE_ANIMATION
         }
 
         pub fn update(mut self) -> Self {
@@ -489,8 +4036,8 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Running> {
-        pub fn frame_name(&self) -> &str {
-            RUN_FRAME_NAME
+        pub fn animation(&self) -> usize {
+            RUN_ANIMATION
         }
 
         pub fn update(mut self) -> Self {
@@ -505,13 +4052,17 @@ mod red_hat_boy_states {
             }
         }
 
+        pub fn crouch(self) -> RedHatBoyState<Crouching> {
+            RedHatBoyState {
+                context: self.context.fix_frame(CROUCHING_FRAME),
+                _state: Crouching,
+            }
+        }
+
         pub fn jump(self) -> RedHatBoyState<Jumping> {
+            let jump_speed = self.context.physics.jump_speed;
             RedHatBoyState {
-                context: self
-                    .context
-                    .set_vertical_velocity(JUMP_SPEED)
-                    .reset_frame()
-                    .play_jump_sound(),
+                context: self.context.set_vertical_velocity(jump_speed).reset_frame(),
                 _state: Jumping {},
             }
         }
@@ -529,6 +4080,14 @@ mod red_hat_boy_states {
                 _state: Running,
             }
         }
+
+        // ゴールで止まったところで、待機の姿になって喜ぶ
+        pub fn celebrate(self) -> RedHatBoyState<Idle> {
+            RedHatBoyState {
+                context: self.context.reset_frame().stop(),
+                _state: Idle,
+            }
+        }
     }
 
     pub enum SlidingEndState {
@@ -537,8 +4096,8 @@ mod red_hat_boy_states {
     }
 
     impl RedHatBoyState<Sliding> {
-        pub fn frame_name(&self) -> &str {
-            SLIDING_FRAME_NAME
+        pub fn animation(&self) -> usize {
+            SLIDING_ANIMATION
         }
 
         pub fn update(mut self) -> SlidingEndState {
@@ -573,20 +4132,57 @@ mod red_hat_boy_states {
         }
     }
 
+    impl RedHatBoyState<Crouching> {
+        pub fn animation(&self) -> usize {
+            SLIDING_ANIMATION
+        }
+
+        // 走る速さはそのままで、姿勢だけ低く保つ
+        pub fn update(mut self) -> Self {
+            self.context = self
+                .context
+                .update(SLIDING_FRAMES)
+                .fix_frame(CROUCHING_FRAME);
+            self
+        }
+
+        pub fn stand(self) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.reset_frame(),
+                _state: Running,
+            }
+        }
+
+        pub fn knock_out(self) -> RedHatBoyState<Falling> {
+            RedHatBoyState {
+                context: self.context.reset_frame().stop(),
+                _state: Falling {},
+            }
+        }
+
+        pub fn land_on(self, position: i16) -> Self {
+            RedHatBoyState {
+                context: self.context.set_on(position),
+                _state: Crouching,
+            }
+        }
+    }
+
     pub enum JumpingEndState {
         Complete(RedHatBoyState<Running>),
         Jumping(RedHatBoyState<Jumping>),
     }
 
     impl RedHatBoyState<Jumping> {
-        pub fn frame_name(&self) -> &str {
-            JUMPING_FRAME_NAME
+        pub fn animation(&self) -> usize {
+            JUMPING_ANIMATION
         }
 
         pub fn update(mut self) -> JumpingEndState {
             self.context = self.context.update(JUMPING_FRAMES);
-            if self.context.position.y >= FLOOR {
-                JumpingEndState::Complete(self.land_on(CANVAS_HEIGHT))
+            if self.context.position.y >= self.context.floor() {
+                let ground = self.context.ground;
+                JumpingEndState::Complete(self.land_on(ground))
             } else {
                 JumpingEndState::Jumping(self)
             }
@@ -607,14 +4203,67 @@ mod red_hat_boy_states {
         }
     }
 
+    impl RedHatBoyState<Jumping> {
+        // 空中でもう一度だけ、一段目より弱く跳べる
+        pub fn double_jump(self) -> RedHatBoyState<DoubleJumping> {
+            let (numerator, denominator) = DOUBLE_JUMP_STRENGTH;
+            let jump_speed = self.context.physics.jump_speed * numerator / denominator;
+            RedHatBoyState {
+                context: self
+                    .context
+                    .set_vertical_velocity(jump_speed)
+                    .fix_frame(DOUBLE_JUMPING_FIRST_FRAME),
+                _state: DoubleJumping,
+            }
+        }
+    }
+
+    pub enum DoubleJumpingEndState {
+        Complete(RedHatBoyState<Running>),
+        DoubleJumping(RedHatBoyState<DoubleJumping>),
+    }
+
+    impl RedHatBoyState<DoubleJumping> {
+        pub fn animation(&self) -> usize {
+            JUMPING_ANIMATION
+        }
+
+        pub fn update(mut self) -> DoubleJumpingEndState {
+            self.context = self.context.update(JUMPING_FRAMES);
+            if self.context.frame < DOUBLE_JUMPING_FIRST_FRAME {
+                self.context = self.context.fix_frame(DOUBLE_JUMPING_FIRST_FRAME);
+            }
+            if self.context.position.y >= self.context.floor() {
+                let ground = self.context.ground;
+                DoubleJumpingEndState::Complete(self.land_on(ground))
+            } else {
+                DoubleJumpingEndState::DoubleJumping(self)
+            }
+        }
+
+        pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.reset_frame().set_on(position),
+                _state: Running,
+            }
+        }
+
+        pub fn knock_out(self) -> RedHatBoyState<Falling> {
+            RedHatBoyState {
+                context: self.context.reset_frame().stop(),
+                _state: Falling {},
+            }
+        }
+    }
+
     pub enum FallingEndState {
         KnockedOut(RedHatBoyState<KnockedOut>),
         Falling(RedHatBoyState<Falling>),
     }
 
     impl RedHatBoyState<Falling> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
+        pub fn animation(&self) -> usize {
+            FALLING_ANIMATION
         }
 
         fn down(self) -> RedHatBoyState<KnockedOut> {
@@ -634,9 +4283,47 @@ mod red_hat_boy_states {
         }
     }
 
+    pub enum StumblingEndState {
+        Complete(RedHatBoyState<Running>),
+        Stumbling(RedHatBoyState<Stumbling>),
+    }
+
+    impl RedHatBoyState<Stumbling> {
+        pub fn animation(&self) -> usize {
+            STUMBLING_ANIMATION
+        }
+
+        // 空中でよろけた場合は、着地してから走りに戻る
+        pub fn update(mut self) -> StumblingEndState {
+            self.context = self.context.update(STUMBLING_FRAMES);
+            if self.context.frame >= STUMBLING_FRAMES
+                && self.context.position.y >= self.context.floor()
+            {
+                StumblingEndState::Complete(self.recover())
+            } else {
+                StumblingEndState::Stumbling(self)
+            }
+        }
+
+        fn recover(self) -> RedHatBoyState<Running> {
+            let ground = self.context.ground;
+            let mut context = self.context.reset_frame().set_on(ground);
+            context.velocity.x = context.physics.running_speed;
+            RedHatBoyState {
+                context,
+                _state: Running,
+            }
+        }
+
+        pub fn land_on(mut self, position: i16) -> Self {
+            self.context = self.context.set_on(position);
+            self
+        }
+    }
+
     impl RedHatBoyState<KnockedOut> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
+        pub fn animation(&self) -> usize {
+            FALLING_ANIMATION
         }
 
         pub fn update(mut self) -> Self {
@@ -656,9 +4343,10 @@ mod red_hat_boy_states {
 
     impl RedHatBoyContext {
         fn update(mut self, frame_count: u8) -> Self {
-            self.velocity.y += GRAVITY;
-            if self.velocity.y >= FALLING_TERMINAL_SPEED {
-                self.velocity.y = FALLING_TERMINAL_SPEED;
+            self.modifiers = self.modifiers.update();
+            self.velocity.y += self.physics.gravity;
+            if self.velocity.y >= self.physics.terminal_speed {
+                self.velocity.y = self.physics.terminal_speed;
             }
 
             if self.frame < frame_count {
@@ -667,19 +4355,24 @@ mod red_hat_boy_states {
                 self.frame = 0;
             }
             self.position.y += self.velocity.y;
-            if self.position.y > FLOOR {
-                self.position.y = FLOOR;
+            // 地面の起伏に合わせて足元の高さを追従させる
+            if self.position.y > self.floor() {
+                self.position.y = self.floor();
             }
             self
         }
 
+        pub fn floor(&self) -> i16 {
+            self.ground - PLAYER_HEIGHT
+        }
+
         fn reset_frame(mut self) -> Self {
             self.frame = 0;
             self
         }
 
         fn run_right(mut self) -> Self {
-            self.velocity.x += RUNNING_SPEED;
+            self.velocity.x += self.physics.running_speed;
             self
         }
 
@@ -706,13 +4399,6 @@ mod red_hat_boy_states {
             self.frame = frame;
             self
         }
-
-        fn play_jump_sound(self) -> Self {
-            if let Err(err) = self.audio.play_sound(&self.jump_sound) {
-                log!("Error playing jump sound {:#?}", err);
-            }
-            self
-        }
     }
 }
 
@@ -721,67 +4407,255 @@ impl Game for WalkTheDog {
     async fn initialize(&self) -> Result<Box<dyn Game>> {
         match self.machine {
             None => {
-                let json = browser::fetch_json("rhb.json").await?;
-                let sheet: Option<Sheet> = serde_wasm_bindgen::from_value(json)
-                    .expect("Could not convert rhb.json into a Sheet structure.");
-                let image = Some(engine::load_image("rhb.png").await?);
-                let background = engine::load_image("BG.png").await?;
-                let stone = engine::load_image("Stone.png").await?;
-
+                let mut config = config::load_game_config().await;
+                let assignment = experiment::assign(&mut config);
+                let analytics = Analytics::new(config.analytics_url.clone(), assignment);
+                let cloud_save = CloudSave::new(config.cloud_save_url.clone());
                 let audio = Audio::new()?;
-                let sound = audio.load_sound("SFX_Jump_23.mp3").await?;
-                let background_music = audio.load_sound("background_song.mp3").await?;
-                // audio.play_looping_sound(&background_music)?; // BGMの再生処理
-
-                let rhb = RedHatBoy::new(
-                    sheet.clone().ok_or_else(|| anyhow!("No Sheet Present"))?,
-                    image.clone().ok_or_else(|| anyhow!("No Imgage Present"))?,
-                    audio,
-                    sound,
-                );
-
-                let json = browser::fetch_json("tiles.json").await?;
-                let sheet: Option<Sheet> = serde_wasm_bindgen::from_value(json)
-                    .expect("Could not convert tiles.json into a Sheet structure.");
-
-                let sprite_sheet = Rc::new(SpriteSheet::new(
-                    sheet.expect("Could not load tiles.json"),
-                    engine::load_image("tiles.png").await?,
-                ));
-
-                let starting_obstacles = stone_and_platform(stone.clone(), sprite_sheet.clone(), 0);
-                let timeline = rightmost(&starting_obstacles);
-
-                let background_width = background.width() as i16;
-
-                let machine = WalkTheDogStateMachine::new(Walk {
-                    boy: rhb,
-                    backgrounds: [
-                        Image::new(background.clone(), Point { x: 0, y: 0 }),
-                        Image::new(
-                            background,
-                            Point {
-                                x: background_width,
-                                y: 0,
-                            },
-                        ),
-                    ],
-                    obstacles: starting_obstacles,
-                    obstacle_sheet: sprite_sheet,
-                    stone: stone,
-                    timeline: timeline,
-                });
-                Ok(Box::new(WalkTheDog {
+                connectivity::watch();
+                if let Err(err) = audio.watch_visibility() {
+                    log!("Could not watch page visibility {:#?}", err);
+                }
+                let mut walk = Walk::load(config, analytics, audio).await?;
+
+                if walk.config.seasonal_events {
+                    if let Some(event) = load_seasonal_event().await {
+                        if let Err(err) = walk.apply_seasonal_event(event).await {
+                            log!("Could not apply seasonal event {:#?}", err);
+                        }
+                    }
+                }
+
+                let machine = WalkTheDogStateMachine::new(walk);
+                let profile_slot = profile::active_slot();
+                let profile = match &cloud_save {
+                    Some(cloud_save) => {
+                        cloud_save
+                            .sync(profile_slot, profile::load(profile_slot))
+                            .await
+                    }
+                    None => profile::load(profile_slot),
+                };
+                let mut game = WalkTheDog {
                     machine: Some(machine),
-                }))
+                    profile_slot,
+                    profile,
+                    cloud_save,
+                    options: None,
+                    gallery: None,
+                    history: None,
+                    level_select: None,
+                    world_map: None,
+                    initials: None,
+                    leaderboard: None,
+                    tweaks: None,
+                    frame_data: None,
+                    debug_overlay: false,
+                    hitboxes: self.hitboxes,
+                    inspector: vec![],
+                    quality: QualityDetector::new(),
+                };
+                game.show_profile();
+                game.apply_settings();
+                Ok(Box::new(game))
             }
             Some(_) => Err(anyhow!("Error: Game is already initialized!")),
         }
     }
 
     fn update(&mut self, keystate: &KeyState) {
-        if let Some(machine) = self.machine.take() {
-            self.machine.replace(machine.update(keystate));
+        if keystate.is_just_pressed(RETRO_FILTER_KEY) {
+            self.profile.settings.toggle_retro_filter();
+            self.refresh_options();
+            self.save_profile();
+        }
+        // 名前入力中は文字のキーをそちらに任せる
+        if self.initials.is_none() && keystate.is_just_pressed(MUTE_KEY) {
+            self.profile.settings.toggle_mute();
+            self.apply_settings();
+            self.refresh_options();
+            self.save_profile();
+        }
+        if let Some(muted) = settings::take_mute_request() {
+            self.profile.settings.muted = muted;
+            self.apply_settings();
+            self.refresh_options();
+            self.save_profile();
+        }
+        // 起動時に失敗した同期をやり直したら、サーバーの方が新しかった
+        if let Some((slot, profile)) = cloud_save::take_restored() {
+            if slot == self.profile_slot {
+                self.profile = profile;
+                self.show_profile();
+                self.apply_settings();
+                self.refresh_options();
+            }
+        }
+
+        notifications::update();
+        if keystate.is_just_pressed(HITBOXES_KEY) {
+            self.hitboxes = !self.hitboxes;
+            // 背景の枠はレイヤーに描いてあるので描き直させる
+            if let Some(machine) = &mut self.machine {
+                machine.walk_mut().background_layer.invalidate();
+            }
+        }
+        if keystate.is_just_pressed(DEBUG_OVERLAY_KEY) {
+            self.debug_overlay = !self.debug_overlay;
+            if !self.debug_overlay {
+                self.inspector.clear();
+                if let Some(machine) = &mut self.machine {
+                    machine.walk_mut().free_camera = None;
+                }
+            }
+        }
+        if self.debug_overlay {
+            self.update_debug_tools(keystate);
+        }
+        if self.update_options(keystate)
+            || self.update_gallery(keystate)
+            || self.update_history(keystate)
+            || self.update_level_select(keystate)
+            || self.update_world_map(keystate)
+            || self.update_initials(keystate)
+            || self.update_leaderboard(keystate)
+            || self.update_tweaks(keystate)
+            || self.update_frame_data(keystate)
+        {
+            return;
+        }
+        if self.debug_overlay && keystate.is_just_pressed(FRAME_DATA_KEY) {
+            if let Some(machine) = &mut self.machine {
+                self.frame_data = Some(FrameDataViewer::new(&mut machine.walk_mut().boy));
+                return;
+            }
+        }
+        if self.debug_overlay && keystate.is_just_pressed(TWEAK_PANEL_KEY) {
+            if let Some(machine) = &self.machine {
+                self.tweaks = Some(TweakPanel::new(machine.walk().base_config.clone()));
+                return;
+            }
+        }
+
+        if let Some(mut machine) = self.machine.take() {
+            if machine.is_menu() {
+                if keystate.is_just_pressed(OPTIONS_KEY) {
+                    self.options = Some(OptionsMenu::new(self.profile.settings));
+                }
+                self.select_profile(keystate);
+                self.select_loadout(keystate, &mut machine);
+            }
+
+            let was_walking = machine.is_walking();
+            // リプレイ中はプレイヤーの入力の代わりに記録を流す
+            let replayed = machine
+                .walk_mut()
+                .replay
+                .as_mut()
+                .filter(|_| was_walking)
+                .map(Replay::next_keystate);
+            let assisted = (was_walking && self.profile.settings.auto_jump && replayed.is_none())
+                .then(|| machine.walk().auto_jump_key())
+                .flatten()
+                .map(|key| keystate.with_injected(key));
+            let mut machine =
+                machine.update(replayed.as_ref().or(assisted.as_ref()).unwrap_or(keystate));
+            let walking = machine.is_walking();
+            machine.walk_mut().poll_music(walking);
+            if !was_walking && walking && self.profile.settings.speedrun_timer {
+                let walk = machine.walk_mut();
+                walk.start_speedrun(self.profile.best_splits(walk.difficulty));
+            }
+            // 買えたときだけ効果を付ける。リプレイは記録に残ったものを持って出るだけで、コインは払わない
+            if let (false, true, Some(kind)) = (was_walking, walking, machine.walk().loadout) {
+                let replay = machine.walk().is_replay();
+                if replay || self.profile.buy_loadout(kind) {
+                    machine.walk_mut().power_ups.activate(kind);
+                    if !replay {
+                        self.save_profile();
+                    }
+                } else {
+                    machine.walk_mut().loadout = None;
+                }
+            }
+            if let Some(choice) = machine.take_title_choice() {
+                self.select_title_choice(choice);
+            }
+            // レベルで拾ったコインも財布に入れ、星はレベルごとに多いほうを残す
+            if let (true, WalkTheDogStateMachine::Victory(state)) =
+                (was_walking && !machine.walk().is_replay(), &machine)
+            {
+                self.profile.coins += state.walk.coins;
+                if let Some(level) = state.walk.current_level() {
+                    if self
+                        .profile
+                        .record_medal(&level.name, state._state.results.stars)
+                    {
+                        notifications::push("New medal!");
+                    }
+                }
+                achievement::award(&mut self.profile, state.walk.levels.len())
+                    .iter()
+                    .for_each(|unlocked| {
+                        state.walk.analytics.track(
+                            &format!("achievement_{}", unlocked.id()),
+                            state.walk.elapsed_frames,
+                        );
+                        notifications::push(&format!("Achievement: {}", unlocked.title()));
+                    });
+                self.save_profile();
+            }
+            // リプレイは記録にも履歴にも数えない
+            if let (true, WalkTheDogStateMachine::GameOver(state)) =
+                (was_walking && !machine.walk().is_replay(), &mut machine)
+            {
+                if !state.walk.is_practice()
+                    && self
+                        .profile
+                        .record_run(state.walk.elapsed_frames, state.walk.difficulty)
+                {
+                    state._state.summary.mark_new_best();
+                    notifications::push("New best run!");
+                }
+                if !state.walk.is_practice() {
+                    self.profile.coins += state.walk.coins;
+                }
+                if !state.walk.is_practice()
+                    && self
+                        .profile
+                        .record_score(state.walk.score.total(), state.walk.score.distance())
+                {
+                    notifications::push("New high score!");
+                }
+                let high_score = HighScore {
+                    initials: String::new(),
+                    score: state.walk.score.total(),
+                    distance: state.walk.score.distance(),
+                    date: browser::timestamp(),
+                    difficulty: state.walk.difficulty,
+                    loadout: state.walk.loadout,
+                };
+                if !state.walk.is_practice()
+                    && leaderboard::rank(
+                        &self.profile.high_scores,
+                        high_score.difficulty,
+                        high_score.score,
+                    )
+                    .is_some()
+                {
+                    self.initials = Some(InitialsEntry::new(&self.profile.initials, high_score));
+                }
+                if let Some(speedrun) = &state.walk.speedrun {
+                    speedrun.finish();
+                    if !state.walk.is_practice() {
+                        self.profile
+                            .record_splits(state.walk.difficulty, speedrun.best_splits());
+                    }
+                }
+                history::push(state.walk.run_record());
+                self.save_profile();
+            }
+            self.machine.replace(machine);
             // let mut velocity = Point { x: 0, y: 0 };
             // if keystate.is_pressed("ArrowDown") {
             //     velocity.y += 3;
@@ -829,50 +4703,280 @@ impl Game for WalkTheDog {
             //     obstacle.check_intersection(boy_ref);
             // });
 
-            // if walk.timeline < TIMELINE_MINIMUM {
-            //     walk.generate_next_segment();
-            // } else {
-            //     walk.timeline += velocity;
-            // }
+            // if walk.timeline < TIMELINE_MINIMUM {
+            //     walk.generate_next_segment();
+            // } else {
+            //     walk.timeline += velocity;
+            // }
+        }
+
+        assert!(self.machine.is_some());
+    }
+
+    fn time_scale(&self) -> f32 {
+        self.profile.settings.game_speed
+    }
+
+    fn record_frame_time(&mut self, frame_time: f32) {
+        if let Some(low) = self.quality.record(frame_time) {
+            log!(
+                "Detected quality tier: {}",
+                if low { "low" } else { "high" }
+            );
+            self.apply_settings();
+        }
+    }
+
+    fn frame_rate_cap(&self) -> Option<f64> {
+        self.low_quality().then_some(LOW_TIER_FRAME_RATE)
+    }
+
+    // メニューやパネルを開いている間は Escape をそちらに任せる
+    fn can_pause(&self) -> bool {
+        self.options.is_none()
+            && self.tweaks.is_none()
+            && self.frame_data.is_none()
+            && self
+                .machine
+                .as_ref()
+                .is_some_and(|machine| machine.is_walking())
+    }
+
+    fn draw_paused(&self, renderer: &Renderer) {
+        renderer.fill_rect(
+            &Rect::new_from_x_y(0, 0, CANVAS_WIDTH, CANVAS_HEIGHT),
+            TITLE_SHADE_COLOR,
+        );
+        renderer.draw_text(
+            "PAUSED",
+            &Point { x: 300, y: 280 },
+            TextStyle::new(TITLE_TEXT_COLOR)
+                .size(32)
+                .align(TextAlign::Center),
+        );
+        renderer.draw_text(
+            "Press Escape to resume",
+            &Point { x: 300, y: 320 },
+            TextStyle::new(TITLE_TEXT_COLOR).align(TextAlign::Center),
+        );
+        if self.is_practice() {
+            renderer.draw_text(
+                "B: bookmark here  R: back to bookmark",
+                &Point { x: 300, y: 350 },
+                TextStyle::new(TITLE_TEXT_COLOR).align(TextAlign::Center),
+            );
+        }
+    }
+
+    fn update_paused(&mut self, keystate: &KeyState) {
+        if !self.is_practice() {
+            return;
+        }
+        let Some(machine) = &mut self.machine else {
+            return;
+        };
+        if keystate.is_just_pressed(BOOKMARK_KEY) {
+            machine.walk_mut().set_bookmark();
+            notifications::push("Bookmark set");
+        }
+        if keystate.is_just_pressed(RETRY_KEY) && !machine.walk_mut().return_to_bookmark() {
+            notifications::push("No bookmark yet");
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        renderer.on_layer(Layer::Background, |renderer| {
+            renderer.clear(&Rect::new_from_x_y(0, 0, 600, CANVAS_HEIGHT));
+        });
+        renderer.set_retro_filter(self.profile.settings.retro_filter);
+        renderer.set_hitboxes(self.hitboxes);
+
+        if let Some(machine) = &self.machine {
+            machine.draw(renderer);
+            if machine.shows_best() {
+                self.draw_best(renderer);
+            }
+            if machine.shows_loadout() {
+                self.draw_loadout(renderer, machine.walk().loadout);
+            }
+            if let Some(options) = &self.options {
+                options.draw(renderer);
+            }
+            if let Some(gallery) = &self.gallery {
+                gallery.draw(renderer);
+            }
+            if let Some(history) = &self.history {
+                history.draw(renderer);
+            }
+            if let Some(level_select) = &self.level_select {
+                level_select.draw(renderer);
+            }
+            if let Some(world_map) = &self.world_map {
+                let boy = &machine.walk().boy;
+                world_map.draw(renderer, |renderer, feet| {
+                    let offset = Point {
+                        x: feet.x - boy.feet().x,
+                        y: feet.y - boy.feet().y,
+                    };
+                    renderer.translate(&offset, |renderer| boy.draw_sprite(renderer));
+                });
+            }
+            if let Some(initials) = &self.initials {
+                initials.draw(renderer);
+            }
+            if let Some(leaderboard) = &self.leaderboard {
+                leaderboard.draw(renderer);
+            }
+            if let Some(tweaks) = &self.tweaks {
+                tweaks.draw(renderer);
+            }
+            if let Some(viewer) = &self.frame_data {
+                viewer.draw(renderer, &machine.walk().boy);
+            }
+            connectivity::draw(renderer);
+            notifications::draw(renderer);
+            if self.debug_overlay {
+                draw_debug_overlay(renderer, machine.walk());
+                draw_inspector(renderer, &self.inspector);
+            }
+            // walk.backgrounds.iter().for_each(|background| {
+            //     background.draw(renderer);
+            // });
+            // walk.boy.draw(renderer);
+            // walk.obstacles.iter().for_each(|obstacle| {
+            //     obstacle.draw(renderer);
+            // });
+        }
+    }
+} // impl Game for WalkTheDog
+
+impl WalkTheDogStateMachine {
+    fn new(walk: Walk) -> Self {
+        WalkTheDogStateMachine::Title(WalkTheDogState::<Title>::new(walk))
+    }
+
+    fn walk_mut(&mut self) -> &mut Walk {
+        match self {
+            WalkTheDogStateMachine::Title(state) => &mut state.walk,
+            WalkTheDogStateMachine::Ready(state) => &mut state.walk,
+            WalkTheDogStateMachine::Walking(state) => &mut state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &mut state.walk,
+            WalkTheDogStateMachine::Victory(state) => &mut state.walk,
+        }
+    }
+
+    fn walk(&self) -> &Walk {
+        match self {
+            WalkTheDogStateMachine::Title(state) => &state.walk,
+            WalkTheDogStateMachine::Ready(state) => &state.walk,
+            WalkTheDogStateMachine::Walking(state) => &state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &state.walk,
+            WalkTheDogStateMachine::Victory(state) => &state.walk,
+        }
+    }
+
+    // タイトルと走り出す前の画面ではプロフィールやオプションを操作できる
+    fn is_menu(&self) -> bool {
+        matches!(
+            self,
+            WalkTheDogStateMachine::Title(_) | WalkTheDogStateMachine::Ready(_)
+        )
+    }
+
+    fn shows_best(&self) -> bool {
+        matches!(
+            self,
+            WalkTheDogStateMachine::Title(_) | WalkTheDogStateMachine::GameOver(_)
+        )
+    }
+
+    // 練習とリプレイでは選ばせない
+    fn shows_loadout(&self) -> bool {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => {
+                !state.walk.is_practice() && !state.walk.is_replay()
+            }
+            _ => false,
         }
+    }
 
-        assert!(self.machine.is_some());
+    fn take_title_choice(&mut self) -> Option<TitleChoice> {
+        match self {
+            WalkTheDogStateMachine::Title(state) => state._state.choice.take(),
+            _ => None,
+        }
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        renderer.clear(&Rect::new_from_x_y(0, 0, 600, CANVAS_HEIGHT));
+    fn is_walking(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::Walking(_))
+    }
 
-        if let Some(machine) = &self.machine {
-            machine.draw(renderer);
-            // walk.backgrounds.iter().for_each(|background| {
-            //     background.draw(renderer);
-            // });
-            // walk.boy.draw(renderer);
-            // walk.obstacles.iter().for_each(|obstacle| {
-            //     obstacle.draw(renderer);
-            // });
+    // ギャラリーはタイトル画面からだけ開くので、それ以外では何もしない
+    fn practice_segment(self, index: usize) -> Self {
+        match self {
+            WalkTheDogStateMachine::Title(mut state) => {
+                state.walk.practice_segment(index);
+                WalkTheDogState::<Ready>::new(state.walk).into()
+            }
+            machine => machine,
         }
     }
-} // impl Game for WalkTheDog
 
-impl WalkTheDogStateMachine {
-    fn new(walk: Walk) -> Self {
-        WalkTheDogStateMachine::Ready(WalkTheDogState::new(walk))
+    // 履歴もタイトル画面からだけ開く。記録のないランは流せない
+    fn replay(self, record: &RunRecord) -> Self {
+        match (self, &record.inputs) {
+            (WalkTheDogStateMachine::Title(mut state), Some(inputs)) => {
+                state.walk.practice_segment = None;
+                state.walk.set_difficulty(record.difficulty);
+                let mut walk = Walk::reset(state.walk);
+                walk.replay = Some(Replay::new(record.seed, inputs.clone()));
+                walk.loadout = record.loadout;
+                WalkTheDogState::<Ready>::new(walk).into()
+            }
+            (machine, _) => machine,
+        }
+    }
+
+    // レベル選びもタイトル画面からだけ開く
+    fn play_level(self, index: usize) -> Self {
+        match self {
+            WalkTheDogStateMachine::Title(mut state) => {
+                state.walk.practice_segment = None;
+                state.walk.level = Some(index);
+                WalkTheDogState::<Ready>::new(Walk::reset(state.walk)).into()
+            }
+            machine => machine,
+        }
     }
 
     fn update(self, keystate: &KeyState) -> Self {
         match self {
+            WalkTheDogStateMachine::Title(state) => state.update(keystate).into(),
             WalkTheDogStateMachine::Ready(state) => state.update(keystate).into(),
             WalkTheDogStateMachine::Walking(state) => state.update(keystate).into(),
-            WalkTheDogStateMachine::GameOver(state) => state.update().into(),
+            WalkTheDogStateMachine::GameOver(state) => state.update(keystate).into(),
+            WalkTheDogStateMachine::Victory(state) => state.update(keystate).into(),
         }
     }
 
     fn draw(&self, renderer: &Renderer) {
         match self {
+            WalkTheDogStateMachine::Title(state) => {
+                state.draw(renderer);
+                state._state.draw(renderer);
+                state.walk.draw_music_progress(renderer);
+            }
             WalkTheDogStateMachine::Ready(state) => state.draw(renderer),
             WalkTheDogStateMachine::Walking(state) => state.draw(renderer),
-            WalkTheDogStateMachine::GameOver(state) => state.draw(renderer),
+            WalkTheDogStateMachine::GameOver(state) => {
+                state.draw(renderer);
+                state._state.summary.draw(renderer);
+                renderer.set_post_effect(Effect::DamageVignette);
+            }
+            WalkTheDogStateMachine::Victory(state) => {
+                state.draw(renderer);
+                state._state.draw(renderer);
+            }
         }
     }
 }
@@ -883,17 +4987,112 @@ impl<T> WalkTheDogState<T> {
     }
 }
 
+impl WalkTheDogState<Title> {
+    fn new(walk: Walk) -> WalkTheDogState<Title> {
+        WalkTheDogState {
+            _state: Title::new(),
+            walk,
+        }
+    }
+
+    fn update(mut self, keystate: &KeyState) -> TitleEndState {
+        self._state.frame = self._state.frame.wrapping_add(1);
+        self.walk.boy.update();
+
+        let menu = match &mut self._state.menu {
+            // 下から滑り込んでくる間は操作を受け付けない
+            Some(_) if !self._state.menu_slide.finished() => {
+                self._state.menu_slide.update();
+                return TitleEndState::Continue(self);
+            }
+            Some(menu) => menu,
+            None => {
+                if Title::start_pressed(keystate) {
+                    self._state.menu = Some(Title::menu(self.walk.difficulty));
+                }
+                return TitleEndState::Continue(self);
+            }
+        };
+
+        match menu.update(keystate) {
+            Some((TITLE_PLAY, WidgetEvent::Pressed)) => {
+                TitleEndState::Complete(WalkTheDogState::<Ready>::new(self.walk))
+            }
+            Some((TITLE_DIFFICULTY, WidgetEvent::Selected(index))) => {
+                if let Some(difficulty) = DIFFICULTIES.get(index) {
+                    self.walk.set_difficulty(*difficulty);
+                }
+                TitleEndState::Continue(self)
+            }
+            Some((TITLE_DAILY, WidgetEvent::Pressed)) => {
+                notifications::push("Daily run");
+                self.walk.practice_segment = None;
+                self.walk.daily = true;
+                TitleEndState::Complete(WalkTheDogState::<Ready>::new(self.walk))
+            }
+            Some((TITLE_OPTIONS, WidgetEvent::Pressed)) => {
+                self._state.choice = Some(TitleChoice::Options);
+                TitleEndState::Continue(self)
+            }
+            Some((TITLE_STATS, WidgetEvent::Pressed)) => {
+                self._state.choice = Some(TitleChoice::Stats);
+                TitleEndState::Continue(self)
+            }
+            Some((TITLE_GALLERY, WidgetEvent::Pressed)) => {
+                self._state.choice = Some(TitleChoice::Gallery);
+                TitleEndState::Continue(self)
+            }
+            Some((TITLE_HISTORY, WidgetEvent::Pressed)) => {
+                self._state.choice = Some(TitleChoice::History);
+                TitleEndState::Continue(self)
+            }
+            Some((TITLE_LEVELS, WidgetEvent::Pressed)) => {
+                self._state.choice = Some(TitleChoice::Levels);
+                TitleEndState::Continue(self)
+            }
+            _ => TitleEndState::Continue(self),
+        }
+    }
+}
+
+enum TitleEndState {
+    Complete(WalkTheDogState<Ready>),
+    Continue(WalkTheDogState<Title>),
+}
+
+impl From<TitleEndState> for WalkTheDogStateMachine {
+    fn from(state: TitleEndState) -> Self {
+        match state {
+            TitleEndState::Complete(ready) => ready.into(),
+            TitleEndState::Continue(title) => title.into(),
+        }
+    }
+}
+
+impl From<WalkTheDogState<Title>> for WalkTheDogStateMachine {
+    fn from(state: WalkTheDogState<Title>) -> Self {
+        WalkTheDogStateMachine::Title(state)
+    }
+}
+
 impl WalkTheDogState<Ready> {
     fn new(walk: Walk) -> WalkTheDogState<Ready> {
         WalkTheDogState {
-            _state: Ready,
+            _state: Ready::new(&walk.config),
             walk,
         }
     }
 
     fn update(mut self, keystate: &KeyState) -> ReadyEndState {
+        if let Some(announcement) = &mut self._state.announcement {
+            if announcement.dismiss_pressed(keystate) {
+                announcement.remember_dismissed();
+                self._state.close_announcement();
+            }
+        }
+
         self.walk.boy.update();
-        if keystate.is_pressed("ArrowRight") {
+        if keystate.is_pressed("ArrowRight") || keystate.is_just_pressed("Space") {
             ReadyEndState::Complete(self.start_running())
         } else {
             ReadyEndState::Continue(self)
@@ -901,7 +5100,15 @@ impl WalkTheDogState<Ready> {
     }
 
     fn start_running(mut self) -> WalkTheDogState<Walking> {
+        self._state.close_announcement();
+        self.walk
+            .analytics
+            .track("run_start", self.walk.elapsed_frames);
         self.walk.boy.run_right();
+        self.walk.seed_run();
+        if !self.walk.boy.audio().is_music_playing() {
+            self.walk.start_music();
+        }
         WalkTheDogState {
             _state: Walking,
             walk: self.walk,
@@ -925,55 +5132,73 @@ impl From<ReadyEndState> for WalkTheDogStateMachine {
 
 impl WalkTheDogState<Walking> {
     fn update(mut self, keystate: &KeyState) -> WalkingEndState {
-        if keystate.is_pressed("Space") {
-            self.walk.boy.jump();
-        }
-
-        self.walk.boy.update();
-
-        let velocity = self.walk.velocity();
-
-        let [first_background, second_background] = &mut self.walk.backgrounds;
-        first_background.move_horizontally(velocity);
-        second_background.move_horizontally(velocity);
-
-        if first_background.right() < 0 {
-            first_background.set_x(second_background.right());
-        }
-
-        if second_background.right() < 0 {
-            second_background.set_x(first_background.right());
-        }
-
-        self.walk.obstacles.retain(|obstacle| obstacle.right() > 0);
-
-        let boy_ref = &mut self.walk.boy;
-        self.walk.obstacles.iter_mut().for_each(|obstacle| {
-            obstacle.move_horizontally(velocity);
-            obstacle.check_intersection(boy_ref);
-        });
+        self.walk.step(keystate);
 
-        if self.walk.timeline < TIMELINE_MINIMUM {
-            self.walk.generate_next_segment();
-        } else {
-            self.walk.timeline += velocity;
+        if self.walk.level_complete {
+            return WalkingEndState::Victory(self.win());
         }
-
         if self.walk.knocked_out() {
+            if self.walk.config.practice && self.walk.return_to_bookmark() {
+                return WalkingEndState::Continue(self);
+            }
             WalkingEndState::Complete(self.end_game())
         } else {
             WalkingEndState::Continue(self)
         }
     }
 
-    fn end_game(self) -> WalkTheDogState<GameOver> {
-        let receiver = browser::draw_ui("<button id='new_game'>New Game</button>")
-            .and_then(|_unit| browser::find_html_element_by_id("new_game"))
-            .map(|element| engine::add_click_handler(element))
-            .unwrap();
+    fn win(self) -> WalkTheDogState<Victory> {
+        let walk = self.walk;
+        walk.analytics.track("level_complete", walk.elapsed_frames);
+        let frames = walk.finished_at.unwrap_or(walk.elapsed_frames);
+        let results = LevelResults::new(
+            walk.current_level().map_or("", |level| &level.name),
+            frames,
+            walk.coins,
+            walk.score.total(),
+            walk.current_level().map_or(1, |level| {
+                level.stars(walk.coins, frames / FRAMES_PER_SECOND)
+            }),
+        );
+        let has_next = walk
+            .level
+            .is_some_and(|level| level + 1 < walk.levels.len());
+        WalkTheDogState {
+            _state: Victory::new(results, has_next),
+            walk,
+        }
+    }
+
+    fn end_game(mut self) -> WalkTheDogState<GameOver> {
+        let distance = self.walk.score.distance();
+        self.walk.run_log.push(RunEventKind::Death, distance);
+        self.walk.stop_music();
+        self.walk.ambience.stop(self.walk.boy.audio());
+        self.walk
+            .analytics
+            .track("game_over", self.walk.elapsed_frames);
+        let menu = MenuList::new(vec![
+            MenuItem::new("new_game", "New Game"),
+            MenuItem::new("back_to_title", "Title"),
+        ]);
+        if let Err(err) = browser::draw_ui(&menu.html()) {
+            log!("Could not show game over menu {:#?}", err);
+        }
+        // ボタンが出せなければクリックは届かないので、メニューのキー操作だけで選ぶ
+        let click_receiver = |id: &str| {
+            browser::find_html_element_by_id(id)
+                .map(engine::add_click_handler)
+                .unwrap_or_else(|_| unbounded().1)
+        };
+        let receiver = click_receiver("new_game");
+        let title_receiver = click_receiver("back_to_title");
         WalkTheDogState {
             _state: GameOver {
                 new_game_event: receiver,
+                title_event: title_receiver,
+                menu,
+                summary: RunSummary::new(self.walk.elapsed_frames, self.walk.difficulty)
+                    .with_run(self.walk.run_log.clone(), distance),
             },
             walk: self.walk,
         }
@@ -982,6 +5207,7 @@ impl WalkTheDogState<Walking> {
 
 enum WalkingEndState {
     Complete(WalkTheDogState<GameOver>),
+    Victory(WalkTheDogState<Victory>),
     Continue(WalkTheDogState<Walking>),
 }
 
@@ -989,31 +5215,43 @@ impl From<WalkingEndState> for WalkTheDogStateMachine {
     fn from(state: WalkingEndState) -> Self {
         match state {
             WalkingEndState::Complete(game_over) => game_over.into(),
+            WalkingEndState::Victory(victory) => victory.into(),
             WalkingEndState::Continue(walking) => walking.into(),
         }
     }
 }
 
 impl WalkTheDogState<GameOver> {
-    fn update(mut self) -> GameOverEndState {
-        if self._state.new_game_pressed() {
-            GameOverEndState::Complete(self.new_game())
-        } else {
-            GameOverEndState::Continue(self)
+    fn update(mut self, keystate: &KeyState) -> GameOverEndState {
+        self.walk.follow_camera();
+        play_summary_cue(self.walk.boy.audio(), self._state.summary.update());
+
+        match self._state.choice(keystate) {
+            Some(GameOverChoice::NewGame) => GameOverEndState::Complete(self.new_game()),
+            Some(GameOverChoice::Title) => GameOverEndState::Title(self.back_to_title()),
+            None => GameOverEndState::Continue(self),
         }
     }
 
     fn new_game(self) -> WalkTheDogState<Ready> {
         browser::hide_ui();
-        WalkTheDogState {
-            _state: Ready,
-            walk: Walk::reset(self.walk),
+        WalkTheDogState::<Ready>::new(Walk::reset(self.walk))
+    }
+
+    fn back_to_title(self) -> WalkTheDogState<Title> {
+        if let Err(err) = browser::hide_ui() {
+            log!("Could not hide the game over menu {:#?}", err);
         }
+        let mut walk = self.walk;
+        walk.practice_segment = None;
+        walk.level = None;
+        WalkTheDogState::<Title>::new(Walk::reset(walk))
     }
 }
 
 enum GameOverEndState {
     Complete(WalkTheDogState<Ready>),
+    Title(WalkTheDogState<Title>),
     Continue(WalkTheDogState<GameOver>),
 }
 
@@ -1021,11 +5259,63 @@ impl From<GameOverEndState> for WalkTheDogStateMachine {
     fn from(state: GameOverEndState) -> Self {
         match state {
             GameOverEndState::Complete(ready) => ready.into(),
+            GameOverEndState::Title(title) => title.into(),
             GameOverEndState::Continue(game_over) => game_over.into(),
         }
     }
 }
 
+impl WalkTheDogState<Victory> {
+    // 止まった少年は待機の姿のまま、紙吹雪は結果を出している間も降らせる
+    fn update(mut self, keystate: &KeyState) -> VictoryEndState {
+        self.walk.boy.update();
+        self.walk.confetti.update();
+        play_summary_cue(self.walk.boy.audio(), self._state.results.update());
+        if !self._state.results.finished() {
+            return VictoryEndState::Continue(self);
+        }
+
+        match self._state.choice(keystate) {
+            Some(VictoryChoice::NextLevel) => {
+                let mut walk = self.walk;
+                walk.level = walk.level.map(|level| level + 1);
+                VictoryEndState::Complete(WalkTheDogState::<Ready>::new(Walk::reset(walk)))
+            }
+            Some(VictoryChoice::Retry) => {
+                VictoryEndState::Complete(WalkTheDogState::<Ready>::new(Walk::reset(self.walk)))
+            }
+            Some(VictoryChoice::Title) => {
+                let mut walk = self.walk;
+                walk.level = None;
+                VictoryEndState::Title(WalkTheDogState::<Title>::new(Walk::reset(walk)))
+            }
+            None => VictoryEndState::Continue(self),
+        }
+    }
+}
+
+enum VictoryEndState {
+    Complete(WalkTheDogState<Ready>),
+    Title(WalkTheDogState<Title>),
+    Continue(WalkTheDogState<Victory>),
+}
+
+impl From<VictoryEndState> for WalkTheDogStateMachine {
+    fn from(state: VictoryEndState) -> Self {
+        match state {
+            VictoryEndState::Complete(ready) => ready.into(),
+            VictoryEndState::Title(title) => title.into(),
+            VictoryEndState::Continue(victory) => victory.into(),
+        }
+    }
+}
+
+impl From<WalkTheDogState<Victory>> for WalkTheDogStateMachine {
+    fn from(state: WalkTheDogState<Victory>) -> Self {
+        WalkTheDogStateMachine::Victory(state)
+    }
+}
+
 impl From<WalkTheDogState<Ready>> for WalkTheDogStateMachine {
     fn from(state: WalkTheDogState<Ready>) -> Self {
         WalkTheDogStateMachine::Ready(state)
@@ -1044,6 +5334,379 @@ impl From<WalkTheDogState<GameOver>> for WalkTheDogStateMachine {
     }
 }
 
+async fn load_sheet(manager: &mut AssetManager, assets: &SkinAssets) -> Result<(Sheet, Bitmap)> {
+    let sheet: Sheet = platform::fetch_json(&assets.sheet).await?;
+    let image = manager.image(&assets.image).await?;
+    Ok((sheet, image))
+}
+
+// 譜面が読めないか BGM と合わなければ、BGM のテンポから作った譜面を使う
+async fn load_beat_map(config: &GameConfig) -> BeatMap {
+    let beat_map = platform::fetch_json::<BeatMap>(BEAT_MAP)
+        .await
+        .and_then(|beat_map| {
+            beat_map
+                .validate(BACKGROUND_MUSIC, &config.physics())
+                .map(|_| beat_map)
+        });
+    beat_map.unwrap_or_else(|err| {
+        log!("Could not load beat map {:#?}", err);
+        BeatMap::from_bpm(BACKGROUND_MUSIC, config.music_bpm)
+    })
+}
+
+// イベント定義が読めなくてもゲームは通常通り開始する
+async fn load_seasonal_event() -> Option<SeasonalEvent> {
+    let calendar: SeasonalCalendar =
+        platform::fetch_json("events.json")
+            .await
+            .unwrap_or_else(|err| {
+                log!("Could not load events.json {:#?}", err);
+                SeasonalCalendar::default()
+            });
+    let (month, day) = browser::month_and_day();
+    calendar.active(MonthDay { month, day }).cloned()
+}
+
+// フレームデータ表示で、各 tick に受け付けるかを調べる入力
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameInput {
+    Run,
+    Slide,
+    Crouch,
+    Stand,
+    Jump,
+    DoubleJump,
+    KnockOut,
+    Stumble,
+}
+
+const FRAME_INPUTS: [FrameInput; 8] = [
+    FrameInput::Run,
+    FrameInput::Slide,
+    FrameInput::Crouch,
+    FrameInput::Stand,
+    FrameInput::Jump,
+    FrameInput::DoubleJump,
+    FrameInput::KnockOut,
+    FrameInput::Stumble,
+];
+
+impl FrameInput {
+    fn label(&self) -> &'static str {
+        match self {
+            FrameInput::Run => "Run",
+            FrameInput::Slide => "Slide",
+            FrameInput::Crouch => "Crouch",
+            FrameInput::Stand => "Stand",
+            FrameInput::Jump => "Jump",
+            FrameInput::DoubleJump => "Double jump",
+            FrameInput::KnockOut => "Knock out",
+            FrameInput::Stumble => "Stumble",
+        }
+    }
+
+    fn event(&self) -> Event {
+        match self {
+            FrameInput::Run => Event::Run,
+            FrameInput::Slide => Event::Slide,
+            FrameInput::Crouch => Event::Crouch,
+            FrameInput::Stand => Event::Stand,
+            FrameInput::Jump => Event::Jump,
+            FrameInput::DoubleJump => Event::DoubleJump,
+            FrameInput::KnockOut => Event::KnockOut,
+            FrameInput::Stumble => Event::Stumble,
+        }
+    }
+}
+
+// 表示する動きと、待機状態からそこへ入るまでの入力
+const FRAME_DATA_MOVES: [(&str, &[FrameInput]); 8] = [
+    ("Idle", &[]),
+    ("Run", &[FrameInput::Run]),
+    ("Slide", &[FrameInput::Run, FrameInput::Slide]),
+    ("Crouch", &[FrameInput::Run, FrameInput::Crouch]),
+    ("Jump", &[FrameInput::Run, FrameInput::Jump]),
+    (
+        "Double jump",
+        &[FrameInput::Run, FrameInput::Jump, FrameInput::DoubleJump],
+    ),
+    ("Knock out", &[FrameInput::Run, FrameInput::KnockOut]),
+    ("Stumble", &[FrameInput::Run, FrameInput::Stumble]),
+];
+const FRAME_DATA_MAX_TICKS: usize = 120;
+// 少年の立ち位置を画面の中ほどへずらして描く
+const FRAME_DATA_OFFSET: Point = Point { x: 250, y: -120 };
+const FRAME_DATA_TIMELINE: Rect = Rect {
+    position: Point { x: 120, y: 470 },
+    width: 460,
+    height: 14,
+};
+const FRAME_DATA_SHADE_COLOR: &str = "rgba(0, 0, 0, 0.85)";
+const FRAME_DATA_BOX_COLOR: &str = "#FF4040";
+const FRAME_DATA_SPRITE_COLOR: &str = "#4080FF";
+const FRAME_DATA_WINDOW_COLOR: &str = "#40C040";
+const FRAME_DATA_CURSOR_COLOR: &str = "#FFFFFF";
+
+// 1 tick 分の記録
+struct FrameData {
+    state: String,
+    frame: u8,
+    handle: Option<SpriteHandle>,
+    destination: Rect,
+    bounding_box: Rect,
+    // この tick に受け付けて、別の状態へ移る入力
+    cancels: Vec<FrameInput>,
+}
+
+// input を受け付ける tick の区間 (start..end) を並べる
+fn cancel_windows(frames: &[FrameData], input: FrameInput) -> Vec<std::ops::Range<usize>> {
+    let mut windows: Vec<std::ops::Range<usize>> = vec![];
+    frames
+        .iter()
+        .enumerate()
+        .filter(|(_, frame)| frame.cancels.contains(&input))
+        .for_each(|(tick, _)| match windows.last_mut() {
+            Some(window) if window.end == tick => window.end = tick + 1,
+            _ => windows.push(tick..tick + 1),
+        });
+    windows
+}
+
+// 格闘ゲームのフレームデータ表のように、動きを 1 tick ずつ再生して
+// 当たり判定と、どの入力で動きを切り替えられるかを見る
+struct FrameDataViewer {
+    moves: Vec<(&'static str, Vec<FrameData>)>,
+    selected: usize,
+    tick: usize,
+    playing: bool,
+}
+
+impl FrameDataViewer {
+    fn new(boy: &mut RedHatBoy) -> Self {
+        FrameDataViewer {
+            moves: FRAME_DATA_MOVES
+                .iter()
+                .map(|(name, inputs)| (*name, boy.record_move(inputs)))
+                .collect(),
+            selected: 0,
+            tick: 0,
+            playing: true,
+        }
+    }
+
+    fn frames(&self) -> &[FrameData] {
+        &self.moves[self.selected].1
+    }
+
+    // 閉じるときは false を返す
+    fn update(&mut self, keystate: &KeyState) -> bool {
+        if keystate.is_just_pressed("Escape") || keystate.is_just_pressed(FRAME_DATA_KEY) {
+            return false;
+        }
+
+        let count = self.moves.len();
+        if keystate.is_just_pressed("ArrowDown") {
+            self.selected = (self.selected + 1) % count;
+            self.tick = 0;
+        }
+        if keystate.is_just_pressed("ArrowUp") {
+            self.selected = (self.selected + count - 1) % count;
+            self.tick = 0;
+        }
+        if keystate.is_just_pressed("Space") {
+            self.playing = !self.playing;
+        }
+
+        let len = self.frames().len().max(1);
+        if keystate.is_just_pressed("ArrowRight") {
+            self.playing = false;
+            self.tick = (self.tick + 1) % len;
+        }
+        if keystate.is_just_pressed("ArrowLeft") {
+            self.playing = false;
+            self.tick = (self.tick + len - 1) % len;
+        }
+        if self.playing {
+            self.tick = (self.tick + 1) % len;
+        }
+        true
+    }
+
+    fn draw(&self, renderer: &Renderer, boy: &RedHatBoy) {
+        renderer.fill_rect(
+            &Rect::new_from_x_y(0, 0, CANVAS_WIDTH, CANVAS_HEIGHT),
+            FRAME_DATA_SHADE_COLOR,
+        );
+        let (name, frames) = &self.moves[self.selected];
+        renderer.draw_text(
+            "Up/Down: move  Left/Right: step  Space: play  F7: close",
+            &Point { x: 10, y: 20 },
+            TITLE_TEXT_COLOR,
+        );
+        let Some(frame) = frames.get(self.tick) else {
+            return;
+        };
+
+        renderer.translate(&FRAME_DATA_OFFSET, |renderer| {
+            if let Some(handle) = frame.handle {
+                boy.sprite_sheet
+                    .draw_handle(renderer, handle, &frame.destination);
+            }
+            renderer.stroke_rect(&frame.destination, FRAME_DATA_SPRITE_COLOR);
+            renderer.stroke_rect(&frame.bounding_box, FRAME_DATA_BOX_COLOR);
+        });
+
+        renderer.draw_text(
+            &format!(
+                "{}: {} frame {}  tick {}/{}",
+                name,
+                frame.state,
+                frame.frame,
+                self.tick + 1,
+                frames.len()
+            ),
+            &Point { x: 10, y: 420 },
+            TITLE_TEXT_COLOR,
+        );
+        let cancels: Vec<&str> = frame.cancels.iter().map(FrameInput::label).collect();
+        renderer.draw_text(
+            &format!(
+                "Cancels: {}",
+                if cancels.is_empty() {
+                    "none".to_string()
+                } else {
+                    cancels.join(", ")
+                }
+            ),
+            &Point { x: 10, y: 445 },
+            TITLE_TEXT_COLOR,
+        );
+
+        // 受け付ける入力ごとに 1 行ずつ、切り替えられる区間を塗る
+        let cell = (FRAME_DATA_TIMELINE.width / frames.len().max(1) as i16).max(1);
+        FRAME_INPUTS
+            .iter()
+            .filter(|input| frames.iter().any(|frame| frame.cancels.contains(input)))
+            .enumerate()
+            .for_each(|(row, input)| {
+                let y = FRAME_DATA_TIMELINE.y() + row as i16 * (FRAME_DATA_TIMELINE.height + 6);
+                renderer.draw_text(
+                    input.label(),
+                    &Point {
+                        x: 10,
+                        y: y + FRAME_DATA_TIMELINE.height / 2,
+                    },
+                    TITLE_TEXT_COLOR,
+                );
+                cancel_windows(frames, *input).iter().for_each(|window| {
+                    renderer.fill_rect(
+                        &Rect::new_from_x_y(
+                            FRAME_DATA_TIMELINE.x() + window.start as i16 * cell,
+                            y,
+                            window.len() as i16 * cell,
+                            FRAME_DATA_TIMELINE.height,
+                        ),
+                        FRAME_DATA_WINDOW_COLOR,
+                    );
+                });
+                renderer.stroke_rect(
+                    &Rect::new_from_x_y(
+                        FRAME_DATA_TIMELINE.x() + self.tick as i16 * cell,
+                        y,
+                        cell,
+                        FRAME_DATA_TIMELINE.height,
+                    ),
+                    FRAME_DATA_CURSOR_COLOR,
+                );
+            });
+    }
+}
+
+fn draw_debug_overlay(renderer: &Renderer, walk: &Walk) {
+    let lines = [
+        ("obstacles", walk.obstacles.stats()),
+        ("toasts", notifications::stats()),
+    ];
+    lines.iter().enumerate().for_each(|(index, (name, stats))| {
+        renderer.draw_text(
+            &format_stats(name, stats),
+            &Point {
+                x: 10,
+                y: 20 + index as i16 * 20,
+            },
+            "#FFFFFF",
+        );
+    });
+    let (barriers, platforms) = walk.pool.stats();
+    [("stones", barriers), ("platforms", platforms)]
+        .iter()
+        .enumerate()
+        .for_each(|(index, (name, stats))| {
+            renderer.draw_text(
+                &format_pool_stats(name, stats),
+                &Point {
+                    x: 10,
+                    y: 20 + (lines.len() + index) as i16 * 20,
+                },
+                "#FFFFFF",
+            );
+        });
+}
+
+fn draw_inspector(renderer: &Renderer, lines: &[String]) {
+    const LINE_HEIGHT: i16 = 20;
+    if lines.is_empty() {
+        return;
+    }
+    let top = CANVAS_HEIGHT - LINE_HEIGHT * (lines.len() as i16 + 1);
+    renderer.fill_rect(
+        &Rect::new_from_x_y(0, top, 600, CANVAS_HEIGHT - top),
+        "rgba(0, 0, 0, 0.6)",
+    );
+    lines.iter().enumerate().for_each(|(index, line)| {
+        renderer.draw_text(
+            line,
+            &Point {
+                x: 10,
+                y: top + LINE_HEIGHT * (index as i16 + 1),
+            },
+            "#FFFFFF",
+        );
+    });
+}
+
+fn format_stats(name: &str, stats: &ArenaStats) -> String {
+    format!(
+        "{} {}/{} peak {} dropped {}",
+        name, stats.len, stats.capacity, stats.peak, stats.dropped
+    )
+}
+
+fn format_pool_stats(name: &str, stats: &PoolStats) -> String {
+    format!(
+        "{} pool {} free, {} allocated, {} reused",
+        name, stats.free, stats.allocated, stats.reused
+    )
+}
+
+// ANIMATIONS と同じ並びで、シートのアニメーション定義を引いておく
+fn resolve_animations(sprite_sheet: &SpriteSheet) -> Vec<Option<Rc<Animation>>> {
+    animation_names()
+        .map(|name| sprite_sheet.animation(name))
+        .collect()
+}
+
+// 毎フレーム残りの 1/CAMERA_EASING ずつ target に近づける
+fn ease_toward(current: i16, target: i16) -> i16 {
+    let step = (target - current) / CAMERA_EASING;
+    if step == 0 {
+        target
+    } else {
+        current + step
+    }
+}
+
 fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
     obstacle_list
         .iter()
@@ -1051,3 +5714,165 @@ fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
         .max_by(|x, y| x.cmp(&y))
         .unwrap_or(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_sheet_has_every_red_hat_boy_frame() {
+        let sheet: Sheet = serde_json::from_str(include_str!("../static/rhb.json")).unwrap();
+        let frame_names = required_frame_names();
+
+        assert!(sheet
+            .validate_frames(frame_names.iter().map(|name| name.as_str()))
+            .is_ok());
+    }
+
+    #[test]
+    fn run_summary_counts_up_and_lands_once() {
+        let mut summary = RunSummary::new(FRAMES_PER_SECOND * 30, Difficulty::Normal);
+        let cues: Vec<SummaryCue> = (0..COUNT_UP_FRAMES * 2)
+            .filter_map(|_| summary.update())
+            .collect();
+
+        assert_eq!(summary.shown, 30);
+        assert_eq!(cues.last(), Some(&SummaryCue::Thunk));
+        assert_eq!(
+            cues.iter().filter(|cue| **cue == SummaryCue::Thunk).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn level_results_count_each_line_up_in_turn() {
+        let mut results = LevelResults::new("Park Loop", FRAMES_PER_SECOND * 42, 7, 1200, 2);
+        let cues: Vec<SummaryCue> = (0..RESULT_COUNT_FRAMES * 4)
+            .filter_map(|_| results.update())
+            .collect();
+
+        assert!(results.finished());
+        assert_eq!(results.values, [42, 7, 1200]);
+        assert_eq!(
+            cues.iter().filter(|cue| **cue == SummaryCue::Thunk).count(),
+            3
+        );
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn crossing_the_finish_line_brakes_celebrates_and_completes_the_level() {
+        let mut walk = crate::race::tests::load_walk();
+        walk.levels = vec![Level {
+            name: "Test".into(),
+            length: 0,
+            seed: 1,
+            stars: Default::default(),
+        }];
+        walk.level = Some(0);
+        let mut walk = Walk::reset(walk);
+        walk.boy.knockouts = false;
+        walk.boy.run_right();
+
+        let keystate = KeyState::injected(std::iter::empty());
+        for _ in 0..FRAMES_PER_SECOND * 60 {
+            if walk.level_complete {
+                break;
+            }
+            walk.step(&keystate);
+        }
+
+        assert!(walk.level_complete);
+        assert!(walk.finished_at.is_some());
+        assert!(matches!(
+            walk.boy.state_machine,
+            RedHatBoyStateMachine::Idle(_)
+        ));
+    }
+
+    #[test]
+    fn cancel_windows_merge_consecutive_ticks() {
+        let frames: Vec<FrameData> = vec![
+            vec![FrameInput::Jump],
+            vec![FrameInput::Jump, FrameInput::Slide],
+            vec![],
+            vec![FrameInput::Jump],
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(tick, cancels)| FrameData {
+            state: "Run".into(),
+            frame: tick as u8,
+            handle: None,
+            destination: Rect::default(),
+            bounding_box: Rect::default(),
+            cancels,
+        })
+        .collect();
+
+        assert_eq!(cancel_windows(&frames, FrameInput::Jump), vec![0..2, 3..4]);
+        assert_eq!(cancel_windows(&frames, FrameInput::Slide), vec![1..2]);
+        assert!(cancel_windows(&frames, FrameInput::Stumble).is_empty());
+    }
+
+    #[test]
+    fn bundled_sheet_defines_every_red_hat_boy_animation() {
+        let sheet: Sheet = serde_json::from_str(include_str!("../static/rhb.json")).unwrap();
+
+        assert!(animation_names().all(|name| sheet.meta.animations.contains_key(name)));
+    }
+
+    #[test]
+    fn bundled_enemy_sheet_defines_its_walk_cycle() {
+        let sheet: Sheet = serde_json::from_str(include_str!("../static/enemy.json")).unwrap();
+
+        assert!(
+            enemy_animation_names().all(|name| sheet.meta.animations.get(name).is_some_and(
+                |animation| sheet
+                    .validate_frames(animation.frames.iter().map(String::as_str))
+                    .is_ok()
+            ))
+        );
+    }
+
+    #[test]
+    fn bundled_beat_map_is_paired_with_the_music_and_reachable() {
+        let beat_map: BeatMap =
+            serde_json::from_str(include_str!("../static/beatmap.json")).unwrap();
+
+        assert!(beat_map
+            .validate(BACKGROUND_MUSIC, &GameConfig::default().physics())
+            .is_ok());
+    }
+
+    #[test]
+    fn state_machine_transitions_in_place() {
+        let physics = GameConfig::default().physics();
+        let mut machine: RedHatBoyStateMachine = RedHatBoyState::new(physics).into();
+        machine.transition(Event::Jump);
+        assert!(matches!(machine, RedHatBoyStateMachine::Idle(_)));
+
+        machine.transition(Event::Run);
+        machine.transition(Event::Jump);
+        assert!(matches!(machine, RedHatBoyStateMachine::Jumping(_)));
+        assert_eq!(machine.context().velocity.y, physics.jump_speed);
+
+        machine.modify(RenderModifiers::hit(0));
+        machine.update();
+        assert!(matches!(machine, RedHatBoyStateMachine::Jumping(_)));
+        assert_eq!(machine.context().frame, 1);
+        assert!(machine.context().modifiers != RenderModifiers::default());
+    }
+
+    #[test]
+    fn animation_index_matches_the_state_frame_name() {
+        let physics = GameConfig::default().physics();
+        let mut machine: RedHatBoyStateMachine = RedHatBoyState::new(physics).into();
+        assert_eq!(machine.frame_name(), "Idle");
+
+        machine.transition(Event::Run);
+        machine.transition(Event::Slide);
+        assert_eq!(ANIMATIONS[machine.animation()].0, "Slide");
+        assert_eq!(machine.frame_name(), "Slide");
+    }
+}
@@ -1,5 +1,4 @@
 use anyhow::{anyhow, Result};
-use wasm_bindgen::JsValue;
 use web_sys::HtmlElement;
 
 pub fn draw_ui(html: &str) -> Result<()> {
@@ -14,6 +13,30 @@ pub fn find_html_element_by_id(id: &str) -> Result<HtmlElement> {
     Err(anyhow!("Not implemented yet!"))
 }
 
-pub async fn fetch_json(json_path: &str) -> Result<JsValue> {
-    Err(anyhow!("Not implemented yet!"))
+pub fn set_text_by_id(_id: &str, _text: &str) -> Result<()> {
+    Ok(())
+}
+
+pub fn now() -> Result<f64> {
+    Ok(0.0)
+}
+
+pub fn timestamp() -> f64 {
+    0.0
+}
+
+pub fn month_and_day() -> (u8, u8) {
+    (1, 1)
+}
+
+pub mod storage {
+    use anyhow::Result;
+
+    pub fn get_item(_key: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    pub fn set_item(_key: &str, _value: &str) -> Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,223 @@
+use crate::analytics::Analytics;
+use crate::config::GameConfig;
+use crate::engine::{Audio, KeyState, Renderer};
+use crate::game::Walk;
+use crate::{browser, platform, random};
+use anyhow::{anyhow, Result};
+use std::hash::Hasher;
+
+pub const GOLDEN_SEED: u64 = 0x5EED;
+const GOLDEN_PARAM: &str = "golden";
+// static/ に置いた期待値。描画を変えたらテストの失敗に出るハッシュで書き直す
+const GOLDEN_FILE: &str = "golden.json";
+// ソフトウェア描画はデバッグビルドだと遅いので、テストで回すのは 2 秒分だけ
+pub const GOLDEN_FRAMES: u32 = 120;
+// (キー, 押し始めのフレーム, 離すフレーム)。ジャンプ、スライド、二段ジャンプの順に操作する
+const SCRIPT: [(&str, u32, u32); 4] = [
+    ("Space", 20, 30),
+    ("ArrowDown", 50, 75),
+    ("Space", 90, 96),
+    ("Space", 100, 106),
+];
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// ビルドやプラットフォームで値が変わらない FNV-1a。
+// std の DefaultHasher は Rust のバージョンで変わりうるので、端末どうしで比べる値にはこちらを使う
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(FNV_OFFSET)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+        });
+    }
+
+    // 整数はバイト順と幅をそろえて書く。usize は wasm32 と 64 ビットで幅が違う
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+}
+
+// 描画命令を文字列にしてフレームごとにハッシュする
+pub struct GoldenRecorder {
+    frames: Vec<u64>,
+    current: Fnv1a,
+}
+
+impl GoldenRecorder {
+    pub fn new() -> Self {
+        GoldenRecorder {
+            frames: vec![],
+            current: Fnv1a::default(),
+        }
+    }
+
+    pub fn record(&mut self, command: &str) {
+        self.current.write(command.as_bytes());
+        self.current.write(b"\n");
+    }
+
+    pub fn end_frame(&mut self) {
+        let frame = std::mem::take(&mut self.current);
+        self.frames.push(frame.finish());
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames
+    }
+}
+
+pub fn compare(expected: &[u64], recorded: &[u64]) -> Result<()> {
+    if let Some(frame) = expected
+        .iter()
+        .zip(recorded)
+        .position(|(expected, recorded)| expected != recorded)
+    {
+        return Err(anyhow!("Frame {} differs from the golden hash", frame));
+    }
+    if expected.len() != recorded.len() {
+        return Err(anyhow!(
+            "Recorded {} frames but the golden file has {}",
+            recorded.len(),
+            expected.len()
+        ));
+    }
+    Ok(())
+}
+
+// JavaScript の数値では 64 ビットを表せないので 16 進の文字列で保存する
+pub fn to_json(frames: &[u64]) -> String {
+    let hashes: Vec<String> = frames.iter().map(|hash| format!("{:016x}", hash)).collect();
+    serde_json::to_string(&hashes).unwrap_or_default()
+}
+
+pub fn from_json(json: &str) -> Result<Vec<u64>> {
+    let hashes: Vec<String> = serde_json::from_str(json)?;
+    hashes
+        .iter()
+        .map(|hash| {
+            u64::from_str_radix(hash, 16).map_err(|err| anyhow!("Bad golden hash {} {}", hash, err))
+        })
+        .collect()
+}
+
+// ?golden を付けて開くと、ブラウザの描画でも同じ台本を走らせて期待値と比べる
+pub fn requested() -> bool {
+    browser::query_param(GOLDEN_PARAM).is_some()
+}
+
+fn scripted_keys(frame: u32) -> KeyState {
+    let held = SCRIPT
+        .iter()
+        .filter(|(_, from, to)| (*from..*to).contains(&frame))
+        .map(|(code, _, _)| *code);
+    let just = SCRIPT
+        .iter()
+        .filter(|(_, from, _)| *from == frame)
+        .map(|(code, _, _)| *code);
+    KeyState::replayed(held, just)
+}
+
+// コースは読み込むときにも乱数で作るので、読み込む前にシードを固定する。音は鳴らさない
+pub async fn load_walk() -> Result<Walk> {
+    random::seed(GOLDEN_SEED);
+    let audio = Audio::new()?;
+    audio.set_muted(true);
+    Walk::load(GameConfig::default(), Analytics::new(None, None), audio).await
+}
+
+// 経過時間を使わず、台本の入力で 1 フレームに 1 回だけ進めて描画命令のハッシュを集める
+pub fn record_walk(walk: &mut Walk, renderer: &Renderer) -> Vec<u64> {
+    renderer.start_recording();
+    // 拍を音の時計ではなくフレームで数える、レースと同じ進め方にする
+    walk.start_race();
+    for frame in 0..GOLDEN_FRAMES {
+        walk.step(&scripted_keys(frame));
+        renderer.begin_frame();
+        walk.draw(renderer);
+        renderer.end_frame();
+    }
+    renderer.recorded_frames()
+}
+
+pub async fn run() -> Result<()> {
+    let mut walk = load_walk().await?;
+    let renderer = Renderer::new(browser::context()?)?;
+    let recorded = record_walk(&mut walk, &renderer);
+    log!("{}", to_json(&recorded));
+    let expected = from_json(&platform::current().fetch_text(GOLDEN_FILE).await?)?;
+    compare(&expected, &recorded)?;
+    log!("Golden frames match ({} frames)", recorded.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_hashes_catch_changed_frames() {
+        let mut recorder = GoldenRecorder::new();
+        recorder.record("fill_rect 0 0 10 10 #FFFFFF");
+        recorder.end_frame();
+        recorder.record("fill_rect 0 0 10 10 #FFFFFF");
+        recorder.end_frame();
+        let golden = from_json(&to_json(recorder.frames())).unwrap();
+
+        let mut changed = GoldenRecorder::new();
+        changed.record("fill_rect 0 0 10 10 #FFFFFF");
+        changed.end_frame();
+        changed.record("fill_rect 0 0 12 10 #FFFFFF");
+        changed.end_frame();
+
+        assert!(compare(&golden, recorder.frames()).is_ok());
+        assert!(compare(&golden, changed.frames()).is_err());
+        assert_eq!(golden[0], golden[1]);
+    }
+
+    #[test]
+    fn the_script_presses_each_key_once() {
+        assert!(scripted_keys(20).is_just_pressed("Space"));
+        assert!(!scripted_keys(21).is_just_pressed("Space"));
+        assert!(scripted_keys(21).is_pressed("Space"));
+        assert!(scripted_keys(60).is_pressed("ArrowDown"));
+        assert!(!scripted_keys(75).is_pressed("ArrowDown"));
+    }
+
+    // 描画はソフトウェアの描画先で行う。画像をデコードできるデスクトップ版でだけ動く
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn scripted_walk_matches_the_golden_hashes() {
+        let mut walk = futures::executor::block_on(load_walk()).unwrap();
+        let renderer = Renderer::new(browser::context().unwrap()).unwrap();
+        let recorded = record_walk(&mut walk, &renderer);
+
+        let expected = from_json(include_str!("../static/golden.json")).unwrap();
+        if let Err(err) = compare(&expected, &recorded) {
+            panic!("{}\nrecorded: {}", err, to_json(&recorded));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::browser::storage;
+use crate::difficulty::Difficulty;
+use crate::power_up::PowerUpKind;
+use crate::replay::InputChange;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_RUNS: usize = 50;
+const HISTORY_KEY: &str = "walk_the_dog.history";
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+// 一回分のランの記録。inputs があればリプレイできる
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RunRecord {
+    pub date: f64,
+    pub distance: u32,
+    pub score: u32,
+    pub seed: u64,
+    pub cause: String,
+    pub difficulty: Difficulty,
+    #[serde(default)]
+    pub inputs: Option<Vec<InputChange>>,
+    // リプレイでも同じものを持って走り出す
+    #[serde(default)]
+    pub loadout: Option<PowerUpKind>,
+}
+
+impl RunRecord {
+    // 一覧に出す一行
+    pub fn label(&self) -> String {
+        format!("{} {} m", format_date(self.date), self.distance)
+    }
+}
+
+// 新しいものから順に並んでいる
+pub fn load() -> Vec<RunRecord> {
+    storage::get_item(HISTORY_KEY)
+        .ok()
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn push(record: RunRecord) {
+    let mut runs = load();
+    add(&mut runs, record);
+    let result = serde_json::to_string(&runs)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| storage::set_item(HISTORY_KEY, &json));
+    if let Err(err) = result {
+        log!("Could not save run history {:#?}", err);
+    }
+}
+
+fn add(runs: &mut Vec<RunRecord>, record: RunRecord) {
+    runs.insert(0, record);
+    runs.truncate(MAX_RUNS);
+}
+
+// timestamp (ms) を UTC の月/日にする
+pub fn format_date(timestamp: f64) -> String {
+    let days = (timestamp / MS_PER_DAY).floor() as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    format!("{:02}/{:02}", month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_newest_runs() {
+        let record = |distance| RunRecord {
+            date: 1_792_195_200_000.0,
+            distance,
+            score: distance,
+            seed: 1,
+            cause: "Barrier".into(),
+            difficulty: Difficulty::default(),
+            inputs: None,
+            loadout: None,
+        };
+        let mut runs = vec![];
+        (0..MAX_RUNS as u32 + 5).for_each(|distance| add(&mut runs, record(distance)));
+
+        assert_eq!(runs.len(), MAX_RUNS);
+        assert_eq!(runs[0].distance, MAX_RUNS as u32 + 4);
+        assert_eq!(runs[0].label(), format!("10/17 {} m", MAX_RUNS + 4));
+        assert_eq!(format_date(0.0), "01/01");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{KeyState, Point, Rect, Renderer};
+use crate::leaderboard::HighScore;
+
+const LETTERS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const INITIALS_LENGTH: usize = 3;
+
+const SHADE: Rect = Rect::new_from_x_y(0, 0, 600, 600);
+const SHADE_COLOR: &str = "rgba(0, 0, 0, 0.7)";
+const TEXT_COLOR: &str = "#FFFFFF";
+const CURSOR_COLOR: &str = "#FFD23F";
+const SLOT_COLOR: &str = "#3A6EA5";
+const SLOT_WIDTH: i16 = 60;
+const SLOT_HEIGHT: i16 = 80;
+const SLOT_SPACING: i16 = 20;
+const SLOT_TOP: i16 = 240;
+const LETTER_SIZE: u16 = 48;
+const OK_BUTTON: Rect = Rect::new_from_x_y(250, 370, 100, 40);
+
+pub enum InitialsEndState {
+    Continue,
+    Confirm(HighScore),
+}
+
+// 自己ベストが表に入ったときの名前入力。上下で文字を、左右で桁を選ぶ。
+// ゲームパッドの十字キーは矢印キーに、タップは桁の上半分で次の文字、下半分で前の文字になる
+pub struct InitialsEntry {
+    letters: [usize; INITIALS_LENGTH],
+    cursor: usize,
+    // 名前のほかは埋めてある記録
+    high_score: HighScore,
+}
+
+impl InitialsEntry {
+    // 前回入れた名前を最初から選んでおく
+    pub fn new(previous: &str, high_score: HighScore) -> Self {
+        let mut letters = [0; INITIALS_LENGTH];
+        previous
+            .bytes()
+            .take(INITIALS_LENGTH)
+            .enumerate()
+            .for_each(|(index, letter)| {
+                letters[index] = LETTERS
+                    .iter()
+                    .position(|candidate| *candidate == letter.to_ascii_uppercase())
+                    .unwrap_or(0);
+            });
+        InitialsEntry {
+            letters,
+            cursor: 0,
+            high_score,
+        }
+    }
+
+    fn initials(&self) -> String {
+        self.letters
+            .iter()
+            .map(|letter| LETTERS[*letter] as char)
+            .collect()
+    }
+
+    fn confirm(&self) -> InitialsEndState {
+        InitialsEndState::Confirm(HighScore {
+            initials: self.initials(),
+            ..self.high_score.clone()
+        })
+    }
+
+    fn step(&mut self, delta: usize) {
+        let letter = &mut self.letters[self.cursor];
+        *letter = (*letter + delta) % LETTERS.len();
+    }
+
+    // 最後の桁でさらに進めたら決定
+    fn advance(&mut self) -> InitialsEndState {
+        if self.cursor + 1 < INITIALS_LENGTH {
+            self.cursor += 1;
+            InitialsEndState::Continue
+        } else {
+            self.confirm()
+        }
+    }
+
+    fn slot(index: usize) -> Rect {
+        let width =
+            SLOT_WIDTH * INITIALS_LENGTH as i16 + SLOT_SPACING * (INITIALS_LENGTH as i16 - 1);
+        Rect::new_from_x_y(
+            (SHADE.width - width) / 2 + index as i16 * (SLOT_WIDTH + SLOT_SPACING),
+            SLOT_TOP,
+            SLOT_WIDTH,
+            SLOT_HEIGHT,
+        )
+    }
+
+    pub fn update(&mut self, keystate: &KeyState) -> InitialsEndState {
+        // タップは Space としても届くので、先に位置で処理する
+        if let (Some(point), true) = (keystate.pointer(), keystate.is_pointer_just_pressed()) {
+            if OK_BUTTON.contains(&point) {
+                return self.confirm();
+            }
+            if let Some(index) =
+                (0..INITIALS_LENGTH).find(|index| Self::slot(*index).contains(&point))
+            {
+                self.cursor = index;
+                let slot = Self::slot(index);
+                if point.y < slot.y() + slot.height / 2 {
+                    self.step(1);
+                } else {
+                    self.step(LETTERS.len() - 1);
+                }
+            }
+            return InitialsEndState::Continue;
+        }
+
+        if let Some(letter) = LETTERS
+            .iter()
+            .position(|letter| keystate.is_just_pressed(&format!("Key{}", *letter as char)))
+        {
+            self.letters[self.cursor] = letter;
+            return self.advance();
+        }
+        if keystate.is_just_pressed("ArrowUp") {
+            self.step(1);
+        }
+        if keystate.is_just_pressed("ArrowDown") {
+            self.step(LETTERS.len() - 1);
+        }
+        if keystate.is_just_pressed("ArrowLeft") || keystate.is_just_pressed("Backspace") {
+            self.cursor = self.cursor.saturating_sub(1);
+        }
+        if keystate.is_just_pressed("ArrowRight") {
+            self.cursor = (self.cursor + 1).min(INITIALS_LENGTH - 1);
+        }
+        if keystate.is_just_pressed("Enter") || keystate.is_just_pressed("Space") {
+            return self.advance();
+        }
+        InitialsEndState::Continue
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.fill_rect(&SHADE, SHADE_COLOR);
+        let center = SHADE.width / 2;
+        renderer.draw_text(
+            "NEW HIGH SCORE!",
+            &Point { x: center, y: 160 },
+            TextStyle::new(CURSOR_COLOR).align(TextAlign::Center),
+        );
+        renderer.draw_text(
+            &format!(
+                "{}  ({} m)",
+                self.high_score.score, self.high_score.distance
+            ),
+            &Point { x: center, y: 200 },
+            TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+        );
+
+        self.letters.iter().enumerate().for_each(|(index, letter)| {
+            let slot = Self::slot(index);
+            renderer.fill_rect(&slot, SLOT_COLOR);
+            let color = if index == self.cursor {
+                renderer.stroke_rect(&slot, CURSOR_COLOR);
+                CURSOR_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            renderer.draw_text(
+                &(LETTERS[*letter] as char).to_string(),
+                &Point {
+                    x: slot.x() + slot.width / 2,
+                    y: slot.y() + slot.height / 2 + LETTER_SIZE as i16 / 3,
+                },
+                TextStyle::new(color)
+                    .size(LETTER_SIZE)
+                    .align(TextAlign::Center),
+            );
+        });
+
+        renderer.fill_rect(&OK_BUTTON, SLOT_COLOR);
+        renderer.draw_text(
+            "OK",
+            &Point {
+                x: OK_BUTTON.x() + OK_BUTTON.width / 2,
+                y: OK_BUTTON.y() + 28,
+            },
+            TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difficulty::Difficulty;
+
+    #[test]
+    fn letters_wrap_and_the_last_slot_confirms() {
+        let mut entry = InitialsEntry::new(
+            "zy",
+            HighScore {
+                initials: String::new(),
+                score: 120,
+                distance: 80,
+                date: 0.0,
+                difficulty: Difficulty::Hard,
+                loadout: None,
+            },
+        );
+        assert_eq!(entry.initials(), "ZYA");
+
+        entry.step(1);
+        assert_eq!(entry.initials(), "AYA");
+        entry.step(LETTERS.len() - 1);
+        assert_eq!(entry.initials(), "ZYA");
+
+        assert!(matches!(entry.advance(), InitialsEndState::Continue));
+        assert!(matches!(entry.advance(), InitialsEndState::Continue));
+        match entry.advance() {
+            InitialsEndState::Confirm(high_score) => {
+                assert_eq!(high_score.initials, "ZYA");
+                assert_eq!(high_score.score, 120);
+                assert_eq!(high_score.difficulty, Difficulty::Hard);
+            }
+            InitialsEndState::Continue => panic!("The last slot should confirm"),
+        }
+    }
+}
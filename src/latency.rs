@@ -0,0 +1,102 @@
+use crate::browser;
+use crate::engine::{Point, Rect, Renderer};
+
+// ?latency を付けて開くと、キー入力から update / 描画までの遅れを右上に出す
+const LATENCY_PARAM: &str = "latency";
+const PANEL: Rect = Rect::new_from_x_y(360, 10, 230, 60);
+const PANEL_COLOR: &str = "rgba(0, 0, 0, 0.6)";
+const TEXT_COLOR: &str = "#FFFFFF";
+
+pub fn requested() -> bool {
+    browser::query_param(LATENCY_PARAM).is_some()
+}
+
+#[derive(Default)]
+struct LatencyStats {
+    average: f64,
+    worst: f64,
+    samples: u32,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency: f64) {
+        const SMOOTHING: f64 = 0.1;
+        if self.samples == 0 {
+            self.average = latency;
+        } else {
+            self.average += (latency - self.average) * SMOOTHING;
+        }
+        self.worst = self.worst.max(latency);
+        self.samples += 1;
+    }
+
+    fn describe(&self, label: &str) -> String {
+        format!("{} {:.1}ms (max {:.1})", label, self.average, self.worst)
+    }
+}
+
+// 時刻はすべて performance.now() と同じ基準のミリ秒
+#[derive(Default)]
+pub struct LatencyProbe {
+    to_update: LatencyStats,
+    to_render: LatencyStats,
+    awaiting_render: Vec<f64>,
+}
+
+impl LatencyProbe {
+    pub fn new() -> Self {
+        LatencyProbe::default()
+    }
+
+    // pressed_at のキー入力を update が使った
+    pub fn consumed(&mut self, pressed_at: f64, now: f64) {
+        self.to_update.record(now - pressed_at);
+        self.awaiting_render.push(pressed_at);
+    }
+
+    // 使われた入力の結果が描画された
+    pub fn rendered(&mut self, now: f64) {
+        for pressed_at in self.awaiting_render.drain(..) {
+            self.to_render.record(now - pressed_at);
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.fill_rect(&PANEL, PANEL_COLOR);
+        renderer.draw_text(
+            &self.to_update.describe("update"),
+            &Point {
+                x: PANEL.x() + 10,
+                y: PANEL.y() + 18,
+            },
+            TEXT_COLOR,
+        );
+        renderer.draw_text(
+            &self.to_render.describe("render"),
+            &Point {
+                x: PANEL.x() + 10,
+                y: PANEL.y() + 42,
+            },
+            TEXT_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_probe_measures_update_and_render_separately() {
+        let mut probe = LatencyProbe::new();
+        probe.consumed(100.0, 104.0);
+        probe.rendered(110.0);
+        probe.rendered(130.0);
+
+        assert_eq!(probe.to_update.describe("update"), "update 4.0ms (max 4.0)");
+        assert_eq!(
+            probe.to_render.describe("render"),
+            "render 10.0ms (max 10.0)"
+        );
+    }
+}
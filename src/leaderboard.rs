@@ -0,0 +1,341 @@
+use crate::cloud_save;
+use crate::connectivity;
+use crate::difficulty::{Difficulty, DIFFICULTIES};
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{KeyState, Point, Rect, Renderer};
+use crate::history::format_date;
+use crate::power_up::PowerUpKind;
+use crate::ui::{Button, Choice, Panel, WidgetEvent};
+use serde::{Deserialize, Serialize};
+
+// 難易度ごとに残す件数
+pub const LEADERBOARD_SIZE: usize = 10;
+
+const TABLE: Rect = Rect::new_from_x_y(40, 30, 520, 340);
+const TABLE_COLOR: &str = "rgba(0, 0, 0, 0.7)";
+const TEXT_COLOR: &str = "#FFFFFF";
+const HEADER_COLOR: &str = "#9FC5E8";
+const HIGHLIGHT_COLOR: &str = "#FFD23F";
+const ROW_HEIGHT: i16 = 22;
+const PADDING: i16 = 20;
+// 順位、名前、スコア、距離、日付、難易度の列の左端
+const COLUMNS: [i16; 6] = [0, 44, 110, 190, 280, 360];
+
+const LEADERBOARD_TAB: usize = 0;
+const LEADERBOARD_SORT: usize = 1;
+const LEADERBOARD_CLOSE: usize = 2;
+const RETRY_KEY: &str = "KeyR";
+const OFFLINE_COLOR: &str = "#FF9F43";
+
+// この端末で出したスコアの記録。名前はアーケード風の 3 文字
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HighScore {
+    pub initials: String,
+    pub score: u32,
+    pub distance: u32,
+    #[serde(default)]
+    pub date: f64,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    // 最初から持って出たパワーアップ。補助ありの記録として表で見分ける
+    #[serde(default)]
+    pub loadout: Option<PowerUpKind>,
+}
+
+// 表に入るなら、同じ難易度の中で入る順位 (0 始まり) を返す。同点なら先に出した記録を上にする
+pub fn rank(scores: &[HighScore], difficulty: Difficulty, score: u32) -> Option<usize> {
+    let rank = scores
+        .iter()
+        .filter(|entry| entry.difficulty == difficulty)
+        .take_while(|entry| entry.score >= score)
+        .count();
+    (score > 0 && rank < LEADERBOARD_SIZE).then_some(rank)
+}
+
+// scores はスコアの高い順に並べておく
+pub fn insert(scores: &mut Vec<HighScore>, entry: HighScore) -> Option<usize> {
+    let rank = rank(scores, entry.difficulty, entry.score)?;
+    let position = scores
+        .iter()
+        .take_while(|other| other.score >= entry.score)
+        .count();
+    let difficulty = entry.difficulty;
+    scores.insert(position, entry);
+    let mut kept = 0;
+    scores.retain(|other| {
+        if other.difficulty != difficulty {
+            return true;
+        }
+        kept += 1;
+        kept <= LEADERBOARD_SIZE
+    });
+    Some(rank)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Score,
+    Distance,
+    Date,
+}
+
+const SORT_KEYS: [SortKey; 3] = [SortKey::Score, SortKey::Distance, SortKey::Date];
+
+impl SortKey {
+    fn label(&self) -> &'static str {
+        match self {
+            SortKey::Score => "Score",
+            SortKey::Distance => "Distance",
+            SortKey::Date => "Newest",
+        }
+    }
+}
+
+// tab が None なら全ての難易度から上位を出す
+fn rows(scores: &[HighScore], tab: Option<Difficulty>, sort: SortKey) -> Vec<(usize, &HighScore)> {
+    let mut rows: Vec<(usize, &HighScore)> = scores
+        .iter()
+        .filter(|entry| tab.is_none_or(|difficulty| entry.difficulty == difficulty))
+        .take(LEADERBOARD_SIZE)
+        .enumerate()
+        .collect();
+    match sort {
+        SortKey::Score => {}
+        SortKey::Distance => rows.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.distance)),
+        SortKey::Date => rows.sort_by(|(_, left), (_, right)| right.date.total_cmp(&left.date)),
+    }
+    rows
+}
+
+// ローカルの記録を並べた画面。難易度ごとのタブと並べ替えを切り替えられる
+pub struct LeaderboardTable {
+    scores: Vec<HighScore>,
+    summary: String,
+    panel: Panel,
+    tab: Option<Difficulty>,
+    sort: SortKey,
+    // 今入ったばかりの記録
+    highlight: Option<HighScore>,
+}
+
+impl LeaderboardTable {
+    pub fn new(scores: Vec<HighScore>, summary: String) -> Self {
+        LeaderboardTable {
+            scores,
+            summary,
+            panel: LeaderboardTable::panel(0),
+            tab: None,
+            sort: SortKey::Score,
+            highlight: None,
+        }
+    }
+
+    fn panel(tab: usize) -> Panel {
+        let mut panel = Panel::new(Point { x: 150, y: 390 }, 300);
+        panel.add(Box::new(Choice::new(
+            "Mode",
+            std::iter::once("All")
+                .chain(DIFFICULTIES.iter().map(Difficulty::label))
+                .map(String::from)
+                .collect(),
+            tab,
+        )));
+        panel.add(Box::new(Choice::new(
+            "Sort",
+            SORT_KEYS
+                .iter()
+                .map(|key| key.label().to_string())
+                .collect(),
+            0,
+        )));
+        panel.add(Box::new(Button::new("Close")));
+        panel
+    }
+
+    // 入ったばかりの記録の難易度のタブを開いて光らせる
+    pub fn highlight(self, entry: HighScore) -> Self {
+        LeaderboardTable {
+            panel: LeaderboardTable::panel(entry.difficulty.index() + 1),
+            tab: Some(entry.difficulty),
+            highlight: Some(entry),
+            ..self
+        }
+    }
+
+    // 閉じるときは false を返す
+    pub fn update(&mut self, keystate: &KeyState) -> bool {
+        if keystate.is_just_pressed("Escape") {
+            return false;
+        }
+        if keystate.is_just_pressed(RETRY_KEY) && connectivity::is_degraded(cloud_save::SERVICE) {
+            connectivity::retry();
+        }
+        match self.panel.update(keystate) {
+            Some((LEADERBOARD_TAB, WidgetEvent::Selected(index))) => {
+                self.tab = index
+                    .checked_sub(1)
+                    .and_then(|index| DIFFICULTIES.get(index))
+                    .copied();
+            }
+            Some((LEADERBOARD_SORT, WidgetEvent::Selected(index))) => {
+                self.sort = SORT_KEYS.get(index).copied().unwrap_or(SortKey::Score);
+            }
+            Some((LEADERBOARD_CLOSE, WidgetEvent::Pressed)) => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn draw_row(renderer: &Renderer, y: i16, cells: [&str; 6], color: &'static str) {
+        COLUMNS.iter().zip(cells).for_each(|(column, cell)| {
+            renderer.draw_text(
+                cell,
+                &Point {
+                    x: TABLE.x() + PADDING + column,
+                    y,
+                },
+                color,
+            );
+        });
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.fill_rect(&TABLE, TABLE_COLOR);
+        let center = TABLE.x() + TABLE.width / 2;
+        renderer.draw_text(
+            "HIGH SCORES",
+            &Point {
+                x: center,
+                y: TABLE.y() + PADDING + 8,
+            },
+            TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+        );
+        renderer.draw_text(
+            &self.summary,
+            &Point {
+                x: center,
+                y: TABLE.y() + PADDING + 8 + ROW_HEIGHT,
+            },
+            TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+        );
+
+        // 記録はこの端末にあるので、オフラインでも表はそのまま出す
+        if connectivity::is_degraded(cloud_save::SERVICE) {
+            renderer.draw_text(
+                "Offline - saved on this device (R: retry)",
+                &Point {
+                    x: center,
+                    y: TABLE.y() + PADDING + 8 + ROW_HEIGHT * 2,
+                },
+                TextStyle::new(OFFLINE_COLOR).align(TextAlign::Center),
+            );
+        }
+
+        let top = TABLE.y() + PADDING + 8 + ROW_HEIGHT * 3;
+        LeaderboardTable::draw_row(
+            renderer,
+            top,
+            ["#", "Name", "Score", "Dist", "Date", "Mode"],
+            HEADER_COLOR,
+        );
+        let rows = rows(&self.scores, self.tab, self.sort);
+        if rows.is_empty() {
+            renderer.draw_text(
+                "No scores yet",
+                &Point {
+                    x: center,
+                    y: top + ROW_HEIGHT * 2,
+                },
+                TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+            );
+        }
+        rows.iter().enumerate().for_each(|(index, (rank, entry))| {
+            let mode = match entry.loadout {
+                Some(kind) => format!("{}+{}", entry.difficulty.label(), kind.label()),
+                None => entry.difficulty.label().to_string(),
+            };
+            let color = if self.highlight.as_ref() == Some(*entry) {
+                HIGHLIGHT_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            LeaderboardTable::draw_row(
+                renderer,
+                top + (index as i16 + 1) * ROW_HEIGHT,
+                [
+                    &(rank + 1).to_string(),
+                    &entry.initials,
+                    &entry.score.to_string(),
+                    &format!("{} m", entry.distance),
+                    &format_date(entry.date),
+                    &mode,
+                ],
+                color,
+            );
+        });
+        self.panel.draw(renderer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(initials: &str, score: u32, difficulty: Difficulty) -> HighScore {
+        HighScore {
+            initials: initials.into(),
+            score,
+            distance: 200 - score,
+            date: score as f64,
+            difficulty,
+            loadout: None,
+        }
+    }
+
+    #[test]
+    fn scores_enter_the_table_in_order() {
+        let mut scores = vec![];
+        assert_eq!(
+            insert(&mut scores, entry("AAA", 50, Difficulty::Normal)),
+            Some(0)
+        );
+        assert_eq!(
+            insert(&mut scores, entry("BBB", 80, Difficulty::Normal)),
+            Some(0)
+        );
+        assert_eq!(
+            insert(&mut scores, entry("CCC", 50, Difficulty::Normal)),
+            Some(2)
+        );
+        assert_eq!(rank(&scores, Difficulty::Normal, 0), None);
+
+        (0..LEADERBOARD_SIZE as u32).for_each(|score| {
+            insert(&mut scores, entry("DDD", 100 + score, Difficulty::Normal));
+        });
+        assert_eq!(scores.len(), LEADERBOARD_SIZE);
+        assert_eq!(scores[0].score, 109);
+        assert_eq!(rank(&scores, Difficulty::Normal, 100), None);
+        // 難易度が違えば別の表に入る
+        assert_eq!(rank(&scores, Difficulty::Hard, 1), Some(0));
+    }
+
+    #[test]
+    fn tabs_filter_by_difficulty_and_keep_the_score_rank() {
+        let mut scores = vec![];
+        insert(&mut scores, entry("AAA", 90, Difficulty::Hard));
+        insert(&mut scores, entry("BBB", 70, Difficulty::Normal));
+        insert(&mut scores, entry("CCC", 60, Difficulty::Hard));
+
+        let hard = rows(&scores, Some(Difficulty::Hard), SortKey::Score);
+        assert_eq!(
+            hard.iter()
+                .map(|(rank, entry)| (*rank, entry.initials.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "AAA"), (1, "CCC")]
+        );
+
+        let by_distance = rows(&scores, None, SortKey::Distance);
+        assert_eq!(by_distance[0].1.initials, "CCC");
+        assert_eq!(by_distance[0].0, 2);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::platform;
+use serde::Deserialize;
+
+const LEVELS: &str = "levels.json";
+pub const MAX_STARS: u8 = 3;
+
+// ゴールすれば 1 つ。コインを coins 枚以上拾えば 1 つ、seconds 秒以内に着けばもう 1 つ
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StarThresholds {
+    pub coins: u32,
+    pub seconds: u32,
+}
+
+// レベルモードの 1 コース。ゴールまでの距離と、毎回同じ並びにするためのシードを決めておく
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Level {
+    pub name: String,
+    // ゴールの線を置き始める距離 (m)
+    pub length: u32,
+    pub seed: u64,
+    #[serde(default)]
+    pub stars: StarThresholds,
+}
+
+impl Level {
+    pub fn stars(&self, coins: u32, seconds: u32) -> u8 {
+        1 + u8::from(coins >= self.stars.coins) + u8::from(seconds <= self.stars.seconds)
+    }
+}
+
+// 画面の文字は ASCII だけなので、取った星を * で、まだの星を - で並べる
+pub fn stars_text(stars: u8) -> String {
+    (0..MAX_STARS)
+        .map(|index| if index < stars { '*' } else { '-' })
+        .collect()
+}
+
+// 読めなければレベルは並ばないが、ふつうのランはそのまま遊べる
+pub async fn load() -> Vec<Level> {
+    platform::fetch_json(LEVELS).await.unwrap_or_else(|err| {
+        log!("Could not load {} {:#?}", LEVELS, err);
+        vec![]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_levels_get_longer() {
+        let levels: Vec<Level> =
+            serde_json::from_str(include_str!("../static/levels.json")).unwrap();
+
+        assert!(!levels.is_empty());
+        assert!(levels
+            .windows(2)
+            .all(|pair| pair[0].length < pair[1].length));
+        assert!(levels.iter().all(|level| level.stars.seconds > 0));
+    }
+
+    #[test]
+    fn stars_add_up_from_the_clear_coins_and_time() {
+        let level = Level {
+            name: "Park Loop".into(),
+            length: 250,
+            seed: 202,
+            stars: StarThresholds {
+                coins: 10,
+                seconds: 40,
+            },
+        };
+
+        assert_eq!(level.stars(3, 60), 1);
+        assert_eq!(level.stars(10, 60), 2);
+        assert_eq!(level.stars(3, 40), 2);
+        assert_eq!(level.stars(12, 35), MAX_STARS);
+        assert_eq!(stars_text(2), "**-");
+    }
+}
@@ -1,13 +1,69 @@
 use engine::GameLoop;
 use game::WalkTheDog;
+use lobby::Lobby;
+use spectator::Spectator;
 use wasm_bindgen::prelude::*;
 
 #[macro_use]
 mod browser;
+mod achievement;
+mod ambience;
+mod analytics;
+mod arena;
+mod assets;
+mod assist;
+mod beat_map;
+mod cloud_save;
+mod collision;
+mod config;
+mod connectivity;
+#[cfg(feature = "desktop")]
+mod desktop;
+mod difficulty;
+mod draw;
 mod engine;
+mod experiment;
 mod game;
+mod golden;
+mod history;
+mod initials;
+mod latency;
+mod leaderboard;
+mod level;
+mod lobby;
+mod lockstep;
+mod mixer;
+mod mutator;
+mod notifications;
+mod parallax;
+mod particle;
+mod platform;
+mod pool;
+mod power_up;
+mod profile;
+mod quality;
+mod quick_chat;
+mod race;
+mod ramp;
+mod random;
+mod registry;
+mod replay;
+mod run_log;
+mod score;
+mod script;
+mod seasonal;
 mod segment;
+mod segment_picker;
+mod settings;
+#[cfg(not(feature = "desktop"))]
 mod sound;
+mod spectator;
+mod speedrun;
+mod stamina;
+mod tweak;
+mod tween;
+mod ui;
+mod world_map;
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -23,12 +79,44 @@ pub fn main_js() -> Result<(), JsValue> {
     console_error_panic_hook::set_once();
 
     browser::spawn_local(async move {
-        let game = WalkTheDog::new();
+        let started = if let Some(options) = spectator::requested() {
+            GameLoop::start(Spectator::new(options)).await
+        } else if let Some(code) = lobby::requested() {
+            GameLoop::start(Lobby::new(code)).await
+        } else {
+            GameLoop::start(WalkTheDog::new()).await
+        };
+        started.expect("Could not start game loop");
+    });
+
+    Ok(())
+}
 
-        GameLoop::start(game)
+// デスクトップ版の入口。ブラウザと同じゲームループを、ウィンドウの描画に合わせて回す
+#[cfg(feature = "desktop")]
+pub fn main_desktop() -> anyhow::Result<()> {
+    browser::spawn_local(async move {
+        GameLoop::start(WalkTheDog::new())
             .await
             .expect("Could not start game loop");
     });
+    desktop::run()
+}
 
-    Ok(())
+// ページ側のミュートボタンから音を消したり戻したりする
+#[wasm_bindgen]
+pub fn set_muted(muted: bool) {
+    settings::request_mute(muted);
+}
+
+// 最後に終わったランの区切りを JSON で書き出す
+#[wasm_bindgen]
+pub fn export_splits() -> String {
+    speedrun::export()
+}
+
+// ページ側からクラウドセーブ用のトークンを設定する
+#[wasm_bindgen]
+pub fn set_cloud_save_token(token: &str) {
+    cloud_save::set_token(token);
 }
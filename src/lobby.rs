@@ -0,0 +1,677 @@
+use crate::analytics::Analytics;
+use crate::browser::{self, socket::Socket};
+use crate::config::{self, GameConfig};
+use crate::connectivity;
+use crate::engine::{Audio, Game, KeyState, Point, Rect, Renderer};
+use crate::game::Walk;
+use crate::lockstep::LockstepMessage;
+use crate::notifications;
+use crate::profile;
+use crate::race::Race;
+use crate::random;
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// ?lobby で部屋を作り、?lobby=ABCD でその部屋に入る
+const LOBBY_PARAM: &str = "lobby";
+const CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CODE_LENGTH: usize = 4;
+// ロックステップは二人で走る
+const RACE_PLAYERS: usize = 2;
+// 全員の時計で同じ時刻に始まるよう、開始時刻そのものを送る
+const COUNTDOWN_MILLISECONDS: f64 = 3000.0;
+const SERVICE: &str = "Lobby";
+const RETRY_KEY: &str = "KeyR";
+const SKIN_KEY: &str = "KeyC";
+// 切れたことを知らせずにいなくなる相手もいるので、ときどき Present を送り直して生きていると伝え合う。
+// 後から来た観戦者も、これで部屋にいるプレイヤーを知る
+const HEARTBEAT_MILLISECONDS: f64 = 2000.0;
+const SILENT_MILLISECONDS: f64 = 8000.0;
+
+const SCREEN: Rect = Rect::new_from_x_y(0, 0, 600, 600);
+const BACKGROUND_COLOR: &str = "rgb(70, 80, 160)";
+const TEXT_COLOR: &str = "#FFFFFF";
+const READY_COLOR: &str = "#FFD700";
+const ROW_HEIGHT: i16 = 40;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LobbyMessage {
+    Join {
+        player: String,
+        name: String,
+        skin: String,
+    },
+    Leave {
+        player: String,
+    },
+    Ready {
+        player: String,
+        ready: bool,
+    },
+    // Join への返事。後から入ったプレイヤーに、先にいたプレイヤーの様子を伝える。これには返事をしない
+    Present {
+        player: String,
+        name: String,
+        skin: String,
+        ready: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Countdown {
+        starts_at: f64,
+    },
+    // レース中のロックステップのメッセージ。観戦者がどちらのランナーのものか分かるよう、送り主を付ける
+    Race {
+        player: String,
+        message: LockstepMessage,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbyPlayer {
+    pub id: String,
+    pub name: String,
+    pub skin: String,
+    pub ready: bool,
+}
+
+impl LobbyMessage {
+    pub fn player(&self) -> Option<&str> {
+        match self {
+            LobbyMessage::Join { player, .. }
+            | LobbyMessage::Leave { player }
+            | LobbyMessage::Ready { player, .. }
+            | LobbyMessage::Present { player, .. }
+            | LobbyMessage::Race { player, .. } => Some(player),
+            LobbyMessage::Countdown { .. } => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LobbyState {
+    players: Vec<LobbyPlayer>,
+    starts_at: Option<f64>,
+    // プレイヤーごとに最後にメッセージが届いた時刻
+    heard: BTreeMap<String, f64>,
+}
+
+impl LobbyState {
+    pub fn apply(&mut self, message: LobbyMessage) {
+        match message {
+            LobbyMessage::Join { player, name, skin } => {
+                let ready = self.find(&player).is_some_and(|joined| joined.ready);
+                self.upsert(LobbyPlayer {
+                    id: player,
+                    name,
+                    skin,
+                    ready,
+                });
+            }
+            LobbyMessage::Present {
+                player,
+                name,
+                skin,
+                ready,
+            } => self.upsert(LobbyPlayer {
+                id: player,
+                name,
+                skin,
+                ready,
+            }),
+            LobbyMessage::Leave { player } => {
+                self.players.retain(|joined| joined.id != player);
+                self.heard.remove(&player);
+                self.starts_at = None;
+            }
+            LobbyMessage::Ready { player, ready } => {
+                if let Some(joined) = self.players.iter_mut().find(|joined| joined.id == player) {
+                    joined.ready = ready;
+                }
+                if !ready {
+                    self.starts_at = None;
+                }
+            }
+            LobbyMessage::Countdown { starts_at } => self.starts_at = Some(starts_at),
+            LobbyMessage::Race { .. } => {}
+        }
+    }
+
+    pub fn heard_from(&mut self, player: &str, now: f64) {
+        self.heard.insert(player.to_string(), now);
+    }
+
+    // しばらく何も届いていない相手は、Leave を送らずにいなくなったものとして外す
+    pub fn drop_silent(&mut self, local: &str, now: f64) -> Vec<String> {
+        let silent: Vec<String> = self
+            .players
+            .iter()
+            .filter(|player| player.id != local)
+            .filter(|player| {
+                self.heard
+                    .get(&player.id)
+                    .is_none_or(|heard| now - heard > SILENT_MILLISECONDS)
+            })
+            .map(|player| player.id.clone())
+            .collect();
+        silent.iter().for_each(|player| {
+            self.apply(LobbyMessage::Leave {
+                player: player.clone(),
+            })
+        });
+        silent
+    }
+
+    pub fn players(&self) -> &[LobbyPlayer] {
+        &self.players
+    }
+
+    pub fn starts_at(&self) -> Option<f64> {
+        self.starts_at
+    }
+
+    fn find(&self, player: &str) -> Option<&LobbyPlayer> {
+        self.players.iter().find(|joined| joined.id == player)
+    }
+
+    // 届いた順は端末ごとに違うので、id の順に並べておく
+    fn upsert(&mut self, player: LobbyPlayer) {
+        match self
+            .players
+            .iter_mut()
+            .find(|joined| joined.id == player.id)
+        {
+            Some(joined) => *joined = player,
+            None => {
+                self.players.push(player);
+                self.players
+                    .sort_by(|first, second| first.id.cmp(&second.id));
+            }
+        }
+    }
+
+    pub fn all_ready(&self) -> bool {
+        self.players.len() == RACE_PLAYERS && self.players.iter().all(|player| player.ready)
+    }
+
+    // id の順で先頭のプレイヤーが部屋の主になり、カウントダウンを始める
+    pub fn is_host(&self, player: &str) -> bool {
+        self.players.first().is_some_and(|host| host.id == player)
+    }
+
+    // 走り出したら準備を解く。走り終えて全員がもう一度準備するまで、次のカウントダウンは始めない
+    pub fn start_race(&mut self) {
+        self.starts_at = None;
+        self.players
+            .iter_mut()
+            .for_each(|player| player.ready = false);
+    }
+
+    pub fn seconds_left(&self, now: f64) -> Option<u32> {
+        self.starts_at
+            .map(|starts_at| ((starts_at - now).max(0.0) / 1000.0).ceil() as u32)
+    }
+}
+
+// 部屋のソケットの URL。観戦者も同じ部屋につなぐ
+pub fn room_url(lobby_url: &str, code: &str) -> String {
+    format!("{}/{}", lobby_url, code)
+}
+
+// コースのシードは、どちらの端末でも同じになる部屋のコードと開始時刻から決める
+pub fn race_seed(code: &str, starts_at: f64) -> u64 {
+    code.bytes().fold(starts_at.to_bits(), |seed, byte| {
+        seed.rotate_left(8) ^ u64::from(byte)
+    })
+}
+
+pub fn requested() -> Option<String> {
+    let code = browser::query_param(LOBBY_PARAM)?;
+    if code.is_empty() {
+        Some(new_code())
+    } else {
+        Some(code.to_uppercase())
+    }
+}
+
+fn new_code() -> String {
+    let mut rng = random::rng();
+    (0..CODE_LENGTH)
+        .map(|_| CODE_LETTERS[rng.gen_range(0..CODE_LETTERS.len())] as char)
+        .collect()
+}
+
+pub struct Lobby {
+    code: String,
+    player: String,
+    url: Option<String>,
+    socket: Option<Socket>,
+    // つながらないか切れたまま。R でつなぎ直す
+    offline: bool,
+    joined: bool,
+    ready: bool,
+    state: LobbyState,
+    race: Option<Race>,
+    // レースで走らせる自分と相手の Walk。走り終えたら戻して次のレースで使い回す
+    walks: Option<(Walk, Walk)>,
+    // 相手の時計が少し進んでいると、こちらのカウントダウン中にレースの入力が届く
+    early: Vec<LockstepMessage>,
+    last_heartbeat: f64,
+}
+
+impl Lobby {
+    pub fn new(code: String) -> Self {
+        Lobby {
+            code,
+            player: profile::player_id(),
+            url: None,
+            socket: None,
+            offline: false,
+            joined: false,
+            ready: false,
+            state: LobbyState::default(),
+            race: None,
+            walks: None,
+            early: vec![],
+            last_heartbeat: 0.0,
+        }
+    }
+
+    // 送ったメッセージは自分にも反映する。サーバーは自分以外にだけ中継する
+    fn send(&mut self, message: LobbyMessage) {
+        let json = match serde_json::to_string(&message) {
+            Ok(json) => json,
+            Err(err) => {
+                log!("Could not encode lobby message {:#?}", err);
+                return;
+            }
+        };
+        if let Some(socket) = &self.socket {
+            if let Err(err) = socket.send(&json) {
+                log!("Could not send lobby message {:#?}", err);
+            }
+        }
+        self.state.apply(message);
+    }
+
+    // 自分の名前と姿は、いま選んでいるプロフィールのもの
+    fn join_message(&self) -> LobbyMessage {
+        let profile = profile::load(profile::active_slot());
+        LobbyMessage::Join {
+            player: self.player.clone(),
+            name: profile.name,
+            skin: profile.skin,
+        }
+    }
+
+    fn leave_message(&self) -> String {
+        serde_json::to_string(&LobbyMessage::Leave {
+            player: self.player.clone(),
+        })
+        .unwrap_or_default()
+    }
+
+    fn present_message(&self) -> LobbyMessage {
+        let profile = profile::load(profile::active_slot());
+        LobbyMessage::Present {
+            player: self.player.clone(),
+            name: profile.name,
+            skin: profile.skin,
+            ready: self.ready,
+        }
+    }
+
+    fn receive(&mut self, text: &str) {
+        let message = match serde_json::from_str::<LobbyMessage>(text) {
+            Ok(message) => message,
+            Err(err) => {
+                log!("Bad lobby message {} {:#?}", text, err);
+                return;
+            }
+        };
+        if let Some(player) = message.player() {
+            self.state.heard_from(player, browser::timestamp());
+        }
+        match message {
+            LobbyMessage::Race { message, .. } => match &mut self.race {
+                Some(race) => race.receive(message),
+                None if self.state.starts_at.is_some() => self.early.push(message),
+                None => {}
+            },
+            message => {
+                // 後から入ったプレイヤーは、返事がないと先にいたプレイヤーを知らないまま自分を主だと思う
+                let answer = matches!(message, LobbyMessage::Join { .. }) && self.joined;
+                self.state.apply(message);
+                if answer {
+                    self.send(self.present_message());
+                }
+            }
+        }
+    }
+
+    // ランナーの順は、どちらの端末でも同じになるプレイヤーの id の順
+    fn start_race(&mut self) {
+        let Some(starts_at) = self.state.starts_at else {
+            return;
+        };
+        let seed = race_seed(&self.code, starts_at);
+        let players = &self.state.players;
+        let Some(local) = players.iter().position(|player| player.id == self.player) else {
+            return;
+        };
+        let skins = [players[0].skin.as_str(), players[1].skin.as_str()];
+        let Some((mine, rival)) = self.walks.take() else {
+            log!("Lobby: the race walks are still running");
+            return;
+        };
+        let mut race = Race::new(seed, local, mine, rival, skins);
+        self.early
+            .drain(..)
+            .for_each(|message| race.receive(message));
+        self.race = Some(race);
+        self.ready = false;
+        self.state.start_race();
+    }
+
+    // 走っている間はキーをレースに渡し、終わったら Space でロビーに戻る
+    fn update_race(&mut self, keystate: &KeyState) {
+        let Some(race) = &mut self.race else {
+            return;
+        };
+        let outgoing = race.update(keystate);
+        let over = race.is_over();
+        outgoing.into_iter().for_each(|message| {
+            self.send(LobbyMessage::Race {
+                player: self.player.clone(),
+                message,
+            })
+        });
+        if self.state.players.len() < RACE_PLAYERS {
+            notifications::push("The other runner left the race");
+            self.end_race();
+        } else if over && keystate.is_just_pressed("Space") {
+            self.end_race();
+        }
+    }
+
+    // 走らせた Walk はスタートの状態に戻して取っておく
+    fn end_race(&mut self) {
+        if let Some(race) = self.race.take() {
+            let (mine, rival) = race.into_walks();
+            self.walks = Some((Walk::reset(mine), Walk::reset(rival)));
+        }
+    }
+
+    // つなぎ直すときは部屋の様子も一から受け取り直す
+    fn connect(&mut self) {
+        let Some(url) = self.url.clone() else {
+            return;
+        };
+        if let Some(socket) = self.socket.take() {
+            socket.close(&self.leave_message());
+        }
+        self.joined = false;
+        self.ready = false;
+        self.state = LobbyState::default();
+        self.end_race();
+        self.early.clear();
+        self.socket = match Socket::connect(&url) {
+            Ok(socket) => {
+                if let Err(err) = socket.close_on_unload(self.leave_message()) {
+                    log!("Could not watch the page unload {:#?}", err);
+                }
+                Some(socket)
+            }
+            Err(err) => {
+                log!("Could not connect to the lobby {:#?}", err);
+                None
+            }
+        };
+        self.offline = false;
+    }
+
+    fn watch_connection(&mut self) {
+        let offline = self.socket.as_ref().is_none_or(Socket::is_closed);
+        if offline && !self.offline {
+            connectivity::record_failure(SERVICE, None);
+        } else if !offline && self.offline {
+            connectivity::record_success(SERVICE);
+        }
+        self.offline = offline;
+    }
+
+    // 自分が生きていることを伝え、黙ったままの相手を部屋から外す
+    fn keep_alive(&mut self) {
+        let now = browser::timestamp();
+        if now - self.last_heartbeat >= HEARTBEAT_MILLISECONDS {
+            self.last_heartbeat = now;
+            self.send(self.present_message());
+        }
+        for player in self.state.drop_silent(&self.player, now) {
+            log!("Lobby: {} went silent", player);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Game for Lobby {
+    async fn initialize(&self) -> Result<Box<dyn Game>> {
+        let config = config::load_game_config().await;
+        // 部屋のサーバーがなければ、つながずにその旨だけ出す
+        let Some(url) = config.lobby_url else {
+            log!("lobbyUrl is not configured");
+            return Ok(Box::new(Lobby::new(self.code.clone())));
+        };
+        connectivity::watch();
+        // リモート設定は端末ごとに違うことがあるので、レースは両方で同じになる既定の値で走る。
+        // 相手の少年の音は鳴らさない
+        let load =
+            |audio: Audio| Walk::load(GameConfig::default(), Analytics::new(None, None), audio);
+        let mine = load(Audio::new()?).await?;
+        let rival_audio = Audio::new()?;
+        rival_audio.set_muted(true);
+        let rival = load(rival_audio).await?;
+        let mut lobby = Lobby {
+            url: Some(room_url(&url, &self.code)),
+            walks: Some((mine, rival)),
+            ..Lobby::new(self.code.clone())
+        };
+        lobby.connect();
+        Ok(Box::new(lobby))
+    }
+
+    fn update(&mut self, keystate: &KeyState) {
+        notifications::update();
+        if self.url.is_none() {
+            return;
+        }
+        self.watch_connection();
+        if self.offline {
+            if keystate.is_just_pressed(RETRY_KEY) {
+                self.connect();
+            }
+            return;
+        }
+        let mut received = vec![];
+        if let Some(socket) = &mut self.socket {
+            if !self.joined && socket.is_open() {
+                self.joined = true;
+                self.send(self.join_message());
+            }
+        }
+        if let Some(socket) = &mut self.socket {
+            while let Some(text) = socket.try_receive() {
+                received.push(text);
+            }
+        }
+        for text in received {
+            self.receive(&text);
+        }
+        if self.joined {
+            self.keep_alive();
+        }
+        if self.race.is_some() {
+            self.update_race(keystate);
+            return;
+        }
+        if self.state.seconds_left(browser::timestamp()) == Some(0) {
+            self.start_race();
+            return;
+        }
+
+        // 姿は準備する前に選ぶ。選び直したら Join を送り直して部屋の全員に伝える
+        if self.joined && !self.ready && keystate.is_just_pressed(SKIN_KEY) {
+            let slot = profile::active_slot();
+            let mut profile = profile::load(slot);
+            profile.next_skin();
+            profile::save(slot, &mut profile);
+            self.send(self.join_message());
+        }
+        if self.joined && keystate.is_just_pressed("Space") {
+            self.ready = !self.ready;
+            self.send(LobbyMessage::Ready {
+                player: self.player.clone(),
+                ready: self.ready,
+            });
+        }
+        if self.state.all_ready()
+            && self.state.starts_at.is_none()
+            && self.state.is_host(&self.player)
+        {
+            self.send(LobbyMessage::Countdown {
+                starts_at: browser::timestamp() + COUNTDOWN_MILLISECONDS,
+            });
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if let Some(race) = &self.race {
+            race.draw(renderer);
+            notifications::draw(renderer);
+            return;
+        }
+        renderer.fill_rect(&SCREEN, BACKGROUND_COLOR);
+        renderer.draw_text(
+            &format!("ROOM {}", self.code),
+            &Point { x: 40, y: 60 },
+            TEXT_COLOR,
+        );
+        let status = match self.state.seconds_left(browser::timestamp()) {
+            _ if self.url.is_none() => "Online play is not configured".to_string(),
+            _ if self.offline => "Offline - press R to retry".to_string(),
+            Some(0) => "GO!".to_string(),
+            Some(seconds) => format!("Starting in {}", seconds),
+            None if self.joined => "Space when ready, C to change skin".to_string(),
+            None => "Connecting...".to_string(),
+        };
+        renderer.draw_text(&status, &Point { x: 40, y: 100 }, TEXT_COLOR);
+
+        for (index, player) in self.state.players.iter().enumerate() {
+            let y = 160 + index as i16 * ROW_HEIGHT;
+            let (mark, color) = if player.ready {
+                ("READY", READY_COLOR)
+            } else {
+                ("...", TEXT_COLOR)
+            };
+            renderer.draw_text(
+                &format!("{} ({})", player.name, player.skin),
+                &Point { x: 40, y },
+                TEXT_COLOR,
+            );
+            renderer.draw_text(mark, &Point { x: 440, y }, color);
+        }
+        notifications::draw(renderer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lobby_counts_down_once_everyone_is_ready() {
+        let mut state = LobbyState::default();
+        let message: LobbyMessage =
+            serde_json::from_str(r#"{"type":"join","player":"a","name":"Host","skin":"rhb"}"#)
+                .unwrap();
+        state.apply(message);
+        state.apply(LobbyMessage::Join {
+            player: "b".into(),
+            name: "Guest".into(),
+            skin: "rhb".into(),
+        });
+        state.apply(LobbyMessage::Ready {
+            player: "a".into(),
+            ready: true,
+        });
+        assert!(!state.all_ready());
+
+        state.apply(LobbyMessage::Ready {
+            player: "b".into(),
+            ready: true,
+        });
+        assert!(state.all_ready());
+        assert!(state.is_host("a"));
+
+        // 主より先に並ぶ id のプレイヤーが後から入っても、順は id で決まる
+        let mut late = LobbyState::default();
+        late.apply(LobbyMessage::Join {
+            player: "b".into(),
+            name: "Guest".into(),
+            skin: "blue".into(),
+        });
+        late.apply(LobbyMessage::Present {
+            player: "a".into(),
+            name: "Host".into(),
+            skin: "rhb".into(),
+            ready: true,
+        });
+        assert!(late.is_host("a"));
+        assert!(late.find("a").unwrap().ready);
+
+        state.apply(LobbyMessage::Countdown { starts_at: 5000.0 });
+        assert_eq!(state.seconds_left(2500.0), Some(3));
+        assert_eq!(state.seconds_left(6000.0), Some(0));
+
+        state.start_race();
+        assert_eq!(state.seconds_left(6000.0), None);
+        assert!(!state.all_ready());
+
+        state.apply(LobbyMessage::Countdown { starts_at: 9000.0 });
+        state.apply(LobbyMessage::Leave { player: "b".into() });
+        assert_eq!(state.seconds_left(2500.0), None);
+    }
+
+    #[test]
+    fn silent_players_leave_the_room() {
+        let mut state = LobbyState::default();
+        for player in ["a", "b"] {
+            state.apply(LobbyMessage::Join {
+                player: player.into(),
+                name: player.into(),
+                skin: "rhb".into(),
+            });
+            state.heard_from(player, 1000.0);
+        }
+        state.apply(LobbyMessage::Countdown { starts_at: 5000.0 });
+
+        assert!(state
+            .drop_silent("a", 1000.0 + SILENT_MILLISECONDS)
+            .is_empty());
+        state.heard_from("b", 4000.0);
+        // 自分はいくら黙っていても外さない
+        assert!(state
+            .drop_silent("a", 4000.0 + SILENT_MILLISECONDS)
+            .is_empty());
+
+        assert_eq!(
+            state.drop_silent("a", 4001.0 + SILENT_MILLISECONDS),
+            vec!["b".to_string()]
+        );
+        assert!(state.find("b").is_none() && state.find("a").is_some());
+        assert_eq!(state.seconds_left(0.0), None);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+// 入力を送ってから実際に使うまでのティック数。通信の遅れをこの分だけ吸収する
+pub const INPUT_DELAY: u32 = 3;
+// 何ティックごとに状態のハッシュを送り合うか
+pub const HASH_INTERVAL: u32 = 30;
+
+// 1 ティック分のプレイヤーの操作。シミュレーションに効くものだけを持つ。
+// jump と slide は押し続けているか、attack はそのティックに押したか
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TickInput {
+    pub jump: bool,
+    pub slide: bool,
+    pub attack: bool,
+}
+
+// 相手とやり取りするメッセージ。シードさえ合っていれば入力だけで同じ結果になる
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LockstepMessage {
+    Input { tick: u32, input: TickInput },
+    Hash { tick: u32, hash: u64 },
+    // シミュレーションには影響しない。相手の画面に吹き出しを出すだけ
+    Emote { tick: u32, emote: u8 },
+}
+
+pub struct Lockstep {
+    tick: u32,
+    local: BTreeMap<u32, TickInput>,
+    remote: BTreeMap<u32, TickInput>,
+    local_hashes: BTreeMap<u32, u64>,
+    remote_hashes: BTreeMap<u32, u64>,
+    emotes: VecDeque<u8>,
+}
+
+impl Default for Lockstep {
+    fn default() -> Self {
+        Lockstep::new()
+    }
+}
+
+impl Lockstep {
+    // 最初の INPUT_DELAY ティックは誰も入力を送れないので、空の入力で埋めておく
+    pub fn new() -> Self {
+        let idle: BTreeMap<u32, TickInput> = (0..INPUT_DELAY)
+            .map(|tick| (tick, TickInput::default()))
+            .collect();
+        Lockstep {
+            tick: 0,
+            local: idle.clone(),
+            remote: idle,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            emotes: VecDeque::new(),
+        }
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    // INPUT_DELAY 先のティックの入力をまだ送っていない。送った入力は相手が使っているかもしれないので送り直さない
+    pub fn needs_input(&self) -> bool {
+        !self.local.contains_key(&(self.tick + INPUT_DELAY))
+    }
+
+    // 手元の入力を INPUT_DELAY 先のティックに予約し、相手に送るメッセージを返す
+    pub fn submit(&mut self, input: TickInput) -> LockstepMessage {
+        let tick = self.tick + INPUT_DELAY;
+        self.local.insert(tick, input);
+        LockstepMessage::Input { tick, input }
+    }
+
+    pub fn receive(&mut self, message: LockstepMessage) -> Result<()> {
+        match message {
+            LockstepMessage::Input { tick, input } => {
+                self.remote.insert(tick, input);
+                Ok(())
+            }
+            LockstepMessage::Hash { tick, hash } => {
+                self.remote_hashes.insert(tick, hash);
+                self.check(tick)
+            }
+            LockstepMessage::Emote { emote, .. } => {
+                self.emotes.push_back(emote);
+                Ok(())
+            }
+        }
+    }
+
+    // 両者の入力がそろっていれば (自分, 相手) を返して次のティックへ進む。
+    // そろっていなければシミュレーションは止めて待つ
+    pub fn advance(&mut self) -> Option<(TickInput, TickInput)> {
+        if !self.local.contains_key(&self.tick) || !self.remote.contains_key(&self.tick) {
+            return None;
+        }
+        let local = self.local.remove(&self.tick)?;
+        let remote = self.remote.remove(&self.tick)?;
+        self.tick += 1;
+        Some((local, remote))
+    }
+
+    pub fn take_emote(&mut self) -> Option<u8> {
+        self.emotes.pop_front()
+    }
+
+    // advance の後に呼ぶ。HASH_INTERVAL ごとに状態のハッシュを相手へ送る
+    pub fn record_hash(&mut self, hash: u64) -> Result<Option<LockstepMessage>> {
+        let tick = self.tick;
+        if !tick.is_multiple_of(HASH_INTERVAL) {
+            return Ok(None);
+        }
+        self.local_hashes.insert(tick, hash);
+        self.check(tick)?;
+        Ok(Some(LockstepMessage::Hash { tick, hash }))
+    }
+
+    fn check(&mut self, tick: u32) -> Result<()> {
+        match (self.local_hashes.get(&tick), self.remote_hashes.get(&tick)) {
+            (Some(local), Some(remote)) if local != remote => Err(anyhow!(
+                "Desync at tick {}: {:016x} != {:016x}",
+                tick,
+                local,
+                remote
+            )),
+            (Some(_), Some(_)) => {
+                self.local_hashes.remove(&tick);
+                self.remote_hashes.remove(&tick);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockstep_waits_for_remote_input_and_detects_desync() {
+        let mut lockstep = Lockstep::new();
+        let jump = TickInput {
+            jump: true,
+            ..TickInput::default()
+        };
+
+        for _ in 0..INPUT_DELAY {
+            lockstep.submit(jump);
+            assert!(lockstep.advance().is_some());
+        }
+        assert_eq!(lockstep.advance(), None);
+
+        lockstep
+            .receive(LockstepMessage::Input {
+                tick: INPUT_DELAY,
+                input: TickInput::default(),
+            })
+            .unwrap();
+        assert_eq!(lockstep.advance(), Some((jump, TickInput::default())));
+
+        let mut lockstep = Lockstep::new();
+        lockstep.record_hash(1).unwrap();
+        assert!(lockstep
+            .receive(LockstepMessage::Hash { tick: 0, hash: 2 })
+            .is_err());
+    }
+}
@@ -0,0 +1,75 @@
+// 音はどれもいずれかのバスを通って master に集まる。バスごとに音量を決められる
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bus {
+    Music,
+    Ambience,
+    Sfx,
+    Ui,
+}
+
+pub const BUSES: [Bus; 4] = [Bus::Music, Bus::Ambience, Bus::Sfx, Bus::Ui];
+
+// ノードの組み方を決めるもの。音を鳴らさないデスクトップ版では使わない
+#[cfg_attr(feature = "desktop", allow(dead_code))]
+impl Bus {
+    pub fn index(&self) -> usize {
+        BUSES.iter().position(|bus| bus == self).unwrap_or(0)
+    }
+
+    // タブが隠れたときに絞るバス
+    pub fn follows_focus(&self) -> bool {
+        matches!(self, Bus::Music | Bus::Ambience)
+    }
+
+    // 大きな効果音が鳴ったときに絞るバス
+    pub fn is_ducked(&self) -> bool {
+        matches!(self, Bus::Music | Bus::Ambience)
+    }
+}
+
+// 大きな効果音が鳴ったら、音楽と環境音を depth_db だけ下げて duration_ms の間そのままにする
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DuckingRule {
+    pub depth_db: f32,
+    pub duration_ms: f32,
+}
+
+pub const DEFAULT_DUCKING: DuckingRule = DuckingRule {
+    depth_db: -9.0,
+    duration_ms: 150.0,
+};
+
+#[cfg_attr(feature = "desktop", allow(dead_code))]
+impl DuckingRule {
+    // 下げたときの音量の倍率
+    pub fn gain(&self) -> f32 {
+        10f32.powf(self.depth_db.min(0.0) / 20.0)
+    }
+
+    pub fn hold(&self) -> f64 {
+        f64::from(self.duration_ms.max(0.0)) / 1000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ducking_depth_is_given_in_decibels() {
+        let rule = DuckingRule {
+            depth_db: -6.0,
+            duration_ms: 250.0,
+        };
+        assert!((rule.gain() - 0.501).abs() < 0.001);
+        assert_eq!(rule.hold(), 0.25);
+
+        let boost = DuckingRule {
+            depth_db: 6.0,
+            duration_ms: -10.0,
+        };
+        assert_eq!(boost.gain(), 1.0);
+        assert_eq!(boost.hold(), 0.0);
+        assert_eq!(Bus::Ui.index(), 3);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::config::GameConfig;
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{Point, Rect, Renderer};
+use rand::rngs::StdRng;
+use rand::Rng;
+
+// 500m ごとに 30 秒だけ効く
+const INTERVAL_METERS: u32 = 500;
+const DURATION_FRAMES: u32 = 30 * 60;
+const BANNER_FRAMES: u32 = 120;
+const BANNER: Rect = Rect {
+    position: Point { x: 150, y: 84 },
+    width: 300,
+    height: 40,
+};
+const BANNER_COLOR: &str = "rgba(0, 0, 0, 0.6)";
+const BANNER_TEXT_COLOR: &str = "#FFD23F";
+const GIANT_SCALE: f64 = 2.0;
+const TINY_SCALE: f64 = 0.5;
+
+// ランの途中で config を書き換える変化。apply で元の値を覚えておき、revert で戻す
+pub trait Mutator {
+    fn name(&self) -> &'static str;
+    fn apply(&mut self, config: &mut GameConfig);
+    fn revert(&self, config: &mut GameConfig);
+}
+
+// 重力は整数なので、弱められないときは踏み切りを強くして落ちる速さの上限を下げる
+#[derive(Default)]
+struct LowGravity {
+    previous: (i16, i16, i16),
+}
+
+impl Mutator for LowGravity {
+    fn name(&self) -> &'static str {
+        "Low gravity"
+    }
+
+    fn apply(&mut self, config: &mut GameConfig) {
+        self.previous = (config.gravity, config.jump_speed, config.terminal_speed);
+        config.gravity = (config.gravity / 2).max(1);
+        config.jump_speed = config.jump_speed * 6 / 5;
+        config.terminal_speed = (config.terminal_speed / 2).max(1);
+    }
+
+    fn revert(&self, config: &mut GameConfig) {
+        (config.gravity, config.jump_speed, config.terminal_speed) = self.previous;
+    }
+}
+
+#[derive(Default)]
+struct DoubleSpeed {
+    previous: i16,
+}
+
+impl Mutator for DoubleSpeed {
+    fn name(&self) -> &'static str {
+        "Double speed"
+    }
+
+    fn apply(&mut self, config: &mut GameConfig) {
+        self.previous = config.running_speed;
+        config.running_speed *= 2;
+    }
+
+    fn revert(&self, config: &mut GameConfig) {
+        config.running_speed = self.previous;
+    }
+}
+
+// ジャンプとスライディングのキーを入れ替える
+struct InvertedControls;
+
+impl Mutator for InvertedControls {
+    fn name(&self) -> &'static str {
+        "Inverted controls"
+    }
+
+    fn apply(&mut self, config: &mut GameConfig) {
+        config.inverted_controls = true;
+    }
+
+    fn revert(&self, config: &mut GameConfig) {
+        config.inverted_controls = false;
+    }
+}
+
+// 少年の大きさを変える。当たり判定も同じだけ変わる
+struct Resize {
+    name: &'static str,
+    scale: f64,
+    previous: f64,
+}
+
+impl Resize {
+    fn new(name: &'static str, scale: f64) -> Self {
+        Resize {
+            name,
+            scale,
+            previous: 1.0,
+        }
+    }
+}
+
+impl Mutator for Resize {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn apply(&mut self, config: &mut GameConfig) {
+        self.previous = config.boy_scale;
+        config.boy_scale = self.scale;
+    }
+
+    fn revert(&self, config: &mut GameConfig) {
+        config.boy_scale = self.previous;
+    }
+}
+
+const MUTATORS: usize = 5;
+
+fn mutator(index: usize) -> Box<dyn Mutator> {
+    match index {
+        0 => Box::new(LowGravity::default()),
+        1 => Box::new(DoubleSpeed::default()),
+        2 => Box::new(InvertedControls),
+        3 => Box::new(Resize::new("Giant boy", GIANT_SCALE)),
+        _ => Box::new(Resize::new("Tiny boy", TINY_SCALE)),
+    }
+}
+
+// 走った距離を見て変化を選び、時間が来たら元に戻す
+pub struct MutatorRoulette {
+    rng: StdRng,
+    next_distance: u32,
+    active: Option<Box<dyn Mutator>>,
+    remaining_frames: u32,
+    banner_frames: u32,
+}
+
+impl MutatorRoulette {
+    pub fn new(rng: StdRng) -> Self {
+        MutatorRoulette {
+            rng,
+            next_distance: INTERVAL_METERS,
+            active: None,
+            remaining_frames: 0,
+            banner_frames: 0,
+        }
+    }
+
+    // config を書き換えたら true を返す
+    pub fn update(&mut self, distance: u32, config: &mut GameConfig) -> bool {
+        self.banner_frames = self.banner_frames.saturating_sub(1);
+        let mut changed = false;
+        if self.active.is_some() {
+            self.remaining_frames = self.remaining_frames.saturating_sub(1);
+            if self.remaining_frames == 0 {
+                changed = self.revert(config);
+            }
+        }
+        if distance >= self.next_distance {
+            self.next_distance = (distance / INTERVAL_METERS + 1) * INTERVAL_METERS;
+            self.revert(config);
+            let mut next = mutator(self.rng.gen_range(0..MUTATORS));
+            next.apply(config);
+            self.active = Some(next);
+            self.remaining_frames = DURATION_FRAMES;
+            self.banner_frames = BANNER_FRAMES;
+            changed = true;
+        }
+        changed
+    }
+
+    // 効いている変化があれば戻して true を返す
+    pub fn revert(&mut self, config: &mut GameConfig) -> bool {
+        match self.active.take() {
+            Some(active) => {
+                active.revert(config);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let Some(active) = self.active.as_ref().filter(|_| self.banner_frames > 0) else {
+            return;
+        };
+        renderer.fill_rect(&BANNER, BANNER_COLOR);
+        renderer.draw_text(
+            active.name(),
+            &Point {
+                x: BANNER.x() + BANNER.width / 2,
+                y: BANNER.y() + 28,
+            },
+            TextStyle::new(BANNER_TEXT_COLOR).align(TextAlign::Center),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn mutators_start_every_interval_and_revert_on_expiry() {
+        let base = GameConfig::default();
+        let mut config = base.clone();
+        let mut roulette = MutatorRoulette::new(StdRng::seed_from_u64(1));
+
+        assert!(!roulette.update(INTERVAL_METERS - 1, &mut config));
+        assert!(roulette.update(INTERVAL_METERS, &mut config));
+        assert!(roulette.active.is_some());
+        (1..DURATION_FRAMES).for_each(|_| {
+            assert!(!roulette.update(INTERVAL_METERS + 1, &mut config));
+        });
+        assert!(roulette.update(INTERVAL_METERS + 1, &mut config));
+        assert!(roulette.active.is_none());
+        assert_eq!(config.running_speed, base.running_speed);
+        assert_eq!(config.jump_speed, base.jump_speed);
+        assert_eq!(config.boy_scale, base.boy_scale);
+        assert!(!config.inverted_controls);
+
+        (0..MUTATORS).for_each(|index| {
+            let mut mutator = mutator(index);
+            mutator.apply(&mut config);
+            mutator.revert(&mut config);
+        });
+        assert_eq!(config.terminal_speed, base.terminal_speed);
+    }
+}
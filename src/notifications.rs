@@ -0,0 +1,129 @@
+use crate::arena::{Arena, ArenaStats, OverflowPolicy};
+use crate::engine::{Point, Rect, Renderer};
+use crate::tween::{lerp, Easing};
+use std::cell::RefCell;
+
+pub const DEFAULT_DURATION: u32 = 180;
+const SLIDE_FRAMES: u32 = 15;
+const QUEUE_CAPACITY: usize = 8;
+const TOAST_WIDTH: i16 = 240;
+const TOAST_HEIGHT: i16 = 36;
+const TOAST_MARGIN: i16 = 12;
+const CANVAS_WIDTH: i16 = 600;
+const TOAST_COLOR: &str = "rgba(0, 0, 0, 0.75)";
+const TEXT_COLOR: &str = "#FFFFFF";
+
+thread_local! {
+    static NOTIFICATIONS: RefCell<Notifications> = RefCell::new(Notifications::new());
+}
+
+// どのサブシステムからでも通知を積めるようにする
+pub fn push(message: &str) {
+    push_for(message, DEFAULT_DURATION);
+}
+
+pub fn push_for(message: &str, duration: u32) {
+    NOTIFICATIONS.with(|notifications| notifications.borrow_mut().push(message, duration));
+}
+
+pub fn update() {
+    NOTIFICATIONS.with(|notifications| notifications.borrow_mut().update());
+}
+
+pub fn stats() -> ArenaStats {
+    NOTIFICATIONS.with(|notifications| notifications.borrow().queue.stats())
+}
+
+pub fn draw(renderer: &Renderer) {
+    NOTIFICATIONS.with(|notifications| notifications.borrow().draw(renderer));
+}
+
+struct Toast {
+    message: String,
+    duration: u32,
+    age: u32,
+}
+
+impl Toast {
+    // 右上の画面外から滑り込み、時間が来たら画面外へ戻る
+    fn offset(&self) -> i16 {
+        let remaining = self.duration.saturating_sub(self.age);
+        let visible = self.age.min(remaining).min(SLIDE_FRAMES);
+        let t = Easing::EaseOut.apply(visible as f32 / SLIDE_FRAMES as f32);
+        lerp((TOAST_WIDTH + TOAST_MARGIN) as f32, 0.0, t) as i16
+    }
+
+    fn finished(&self) -> bool {
+        self.age >= self.duration
+    }
+}
+
+pub struct Notifications {
+    queue: Arena<Toast>,
+}
+
+impl Notifications {
+    fn new() -> Self {
+        Notifications {
+            queue: Arena::with_capacity(QUEUE_CAPACITY, OverflowPolicy::DropNewest),
+        }
+    }
+
+    fn push(&mut self, message: &str, duration: u32) {
+        self.queue.push(Toast {
+            message: message.into(),
+            duration: duration.max(SLIDE_FRAMES * 2),
+            age: 0,
+        });
+    }
+
+    // 一度に表示するのは先頭の一件だけ
+    fn update(&mut self) {
+        if let Some(toast) = self.queue.first_mut() {
+            toast.age += 1;
+            if toast.finished() {
+                self.queue.remove_first();
+            }
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if let Some(toast) = self.queue.first() {
+            let bounds = Rect::new_from_x_y(
+                CANVAS_WIDTH - TOAST_WIDTH - TOAST_MARGIN + toast.offset(),
+                TOAST_MARGIN,
+                TOAST_WIDTH,
+                TOAST_HEIGHT,
+            );
+            renderer.fill_rect(&bounds, TOAST_COLOR);
+            renderer.draw_text(
+                &toast.message,
+                &Point {
+                    x: bounds.x() + TOAST_MARGIN,
+                    y: bounds.y() + TOAST_HEIGHT / 2,
+                },
+                TEXT_COLOR,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toasts_are_shown_one_after_another() {
+        let mut notifications = Notifications::new();
+        notifications.push("First", 40);
+        notifications.push("Second", 40);
+
+        (0..39).for_each(|_| notifications.update());
+        assert_eq!(notifications.queue.first().unwrap().message, "First");
+
+        notifications.update();
+        let toast = notifications.queue.first().unwrap();
+        assert_eq!(toast.message, "Second");
+        assert_eq!(toast.offset(), TOAST_WIDTH + TOAST_MARGIN);
+    }
+}
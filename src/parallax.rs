@@ -0,0 +1,233 @@
+use crate::arena::{Arena, OverflowPolicy};
+use crate::engine::{Point, Rect, Renderer};
+use crate::random;
+use rand::Rng;
+
+const LAYER_WIDTH: i16 = 1200;
+const SKYLINE_BASE: i16 = 430;
+const SKYLINE_COLOR: &str = "rgba(60, 70, 100, 0.35)";
+const CLOUD_COLOR: &str = "rgba(255, 255, 255, 0.6)";
+const BIRD_COLOR: &str = "rgba(30, 30, 30, 0.8)";
+const BALLOON_COLOR: &str = "rgba(220, 60, 60, 0.9)";
+const SKYLINE_FACTOR: f32 = 0.2;
+const CLOUD_FACTOR: f32 = 0.1;
+const DRIFTER_CAPACITY: usize = 4;
+// 1 フレームあたりに鳥や気球が現れる確率
+const DRIFTER_CHANCE: f64 = 1.0 / 600.0;
+
+enum Shape {
+    Rect(Rect, &'static str),
+    Circle(Point, i16, &'static str),
+}
+
+impl Shape {
+    fn draw(&self, renderer: &Renderer, offset: i16) {
+        match self {
+            Shape::Rect(rect, color) => {
+                let mut rect = *rect;
+                rect.set_x(rect.x() + offset);
+                renderer.fill_rect(&rect, *color);
+            }
+            Shape::Circle(center, radius, color) => renderer.fill_circle(
+                &Point {
+                    x: center.x + offset,
+                    y: center.y,
+                },
+                *radius,
+                *color,
+            ),
+        }
+    }
+}
+
+// 手前の地面よりゆっくり流れる遠景。幅ごとに繰り返して描く
+pub struct ParallaxLayer {
+    factor: f32,
+    offset: f32,
+    shapes: Vec<Shape>,
+}
+
+impl ParallaxLayer {
+    fn new(factor: f32, shapes: Vec<Shape>) -> Self {
+        ParallaxLayer {
+            factor,
+            offset: 0.0,
+            shapes,
+        }
+    }
+
+    pub fn skyline() -> Self {
+        let mut rng = random::rng();
+        let mut x = 0;
+        let mut shapes = vec![];
+        while x < LAYER_WIDTH {
+            let width = rng.gen_range(40..90);
+            let height = rng.gen_range(60..200);
+            shapes.push(Shape::Rect(
+                Rect::new_from_x_y(x, SKYLINE_BASE - height, width, height),
+                SKYLINE_COLOR,
+            ));
+            x += width + rng.gen_range(0..20);
+        }
+        ParallaxLayer::new(SKYLINE_FACTOR, shapes)
+    }
+
+    pub fn clouds() -> Self {
+        let mut rng = random::rng();
+        let shapes = (0..5)
+            .flat_map(|index| {
+                let x = index * LAYER_WIDTH / 5 + rng.gen_range(0..120);
+                let y = rng.gen_range(40..160);
+                vec![
+                    Shape::Circle(Point { x, y }, 22, CLOUD_COLOR),
+                    Shape::Circle(
+                        Point {
+                            x: x + 24,
+                            y: y - 10,
+                        },
+                        28,
+                        CLOUD_COLOR,
+                    ),
+                    Shape::Circle(Point { x: x + 52, y }, 20, CLOUD_COLOR),
+                ]
+            })
+            .collect();
+        ParallaxLayer::new(CLOUD_FACTOR, shapes)
+    }
+
+    fn scroll(&mut self, velocity: i16) {
+        self.offset = (self.offset + velocity as f32 * self.factor) % LAYER_WIDTH as f32;
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        let offset = self.offset as i16;
+        [offset, offset + LAYER_WIDTH].iter().for_each(|offset| {
+            self.shapes
+                .iter()
+                .for_each(|shape| shape.draw(renderer, *offset));
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DrifterKind {
+    Bird,
+    Balloon,
+}
+
+// 空をときどき横切る鳥や気球
+struct Drifter {
+    kind: DrifterKind,
+    position: Point,
+    speed: i16,
+}
+
+impl Drifter {
+    fn draw(&self, renderer: &Renderer) {
+        match self.kind {
+            DrifterKind::Bird => {
+                renderer.fill_circle(&self.position, 3, BIRD_COLOR);
+                renderer.fill_circle(
+                    &Point {
+                        x: self.position.x + 6,
+                        y: self.position.y - 3,
+                    },
+                    3,
+                    BIRD_COLOR,
+                );
+            }
+            DrifterKind::Balloon => {
+                renderer.fill_circle(&self.position, 12, BALLOON_COLOR);
+                renderer.fill_rect(
+                    &Rect::new_from_x_y(self.position.x - 3, self.position.y + 14, 6, 6),
+                    BIRD_COLOR,
+                );
+            }
+        }
+    }
+}
+
+pub struct Scenery {
+    layers: Vec<ParallaxLayer>,
+    drifters: Arena<Drifter>,
+    detail: bool,
+}
+
+impl Scenery {
+    pub fn new() -> Self {
+        Scenery {
+            layers: vec![ParallaxLayer::clouds(), ParallaxLayer::skyline()],
+            drifters: Arena::with_capacity(DRIFTER_CAPACITY, OverflowPolicy::DropNewest),
+            detail: true,
+        }
+    }
+
+    // 低画質では遠景を一枚にして、鳥や気球も飛ばさない
+    pub fn set_detail(&mut self, detail: bool) {
+        self.detail = detail;
+        if !detail {
+            self.drifters.clear();
+        }
+    }
+
+    pub fn update(&mut self, velocity: i16) {
+        self.layers
+            .iter_mut()
+            .for_each(|layer| layer.scroll(velocity));
+
+        self.drifters.iter_mut().for_each(|drifter| {
+            drifter.position.x += drifter.speed + velocity / 4;
+        });
+        self.drifters.retain(|drifter| drifter.position.x > -40);
+
+        let mut rng = random::rng();
+        if self.detail && rng.gen_bool(DRIFTER_CHANCE) {
+            let kind = if rng.gen_bool(0.7) {
+                DrifterKind::Bird
+            } else {
+                DrifterKind::Balloon
+            };
+            self.drifters.push(Drifter {
+                kind,
+                position: Point {
+                    x: 640,
+                    y: rng.gen_range(30..200),
+                },
+                speed: match kind {
+                    DrifterKind::Bird => -2,
+                    DrifterKind::Balloon => 0,
+                },
+            });
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let skipped = if self.detail {
+            0
+        } else {
+            self.layers.len().saturating_sub(1)
+        };
+        self.layers
+            .iter()
+            .skip(skipped)
+            .for_each(|layer| layer.draw(renderer));
+        self.drifters
+            .iter()
+            .for_each(|drifter| drifter.draw(renderer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layer_scrolls_slower_than_the_ground_and_wraps() {
+        let mut layer = ParallaxLayer::new(0.5, vec![]);
+        layer.scroll(-10);
+        assert_eq!(layer.offset, -5.0);
+
+        (0..240).for_each(|_| layer.scroll(-10));
+        assert!(layer.offset > -(LAYER_WIDTH as f32));
+    }
+}
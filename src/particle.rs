@@ -0,0 +1,101 @@
+use crate::engine::{Point, Rect, Renderer};
+use crate::random;
+use rand::Rng;
+use std::f32::consts::TAU;
+
+const CONFETTI_COLORS: [&str; 5] = ["#FF4F6D", "#FFD700", "#4FC3F7", "#7CFC00", "#FFFFFF"];
+const CONFETTI_SIZE: f32 = 8.0;
+const CONFETTI_LIFE: u32 = 180;
+// 1 フレームに加わる下向きの速さと、紙がひらひら落ちる速さの上限
+const CONFETTI_GRAVITY: f32 = 0.2;
+const CONFETTI_TERMINAL_SPEED: f32 = 2.5;
+const CONFETTI_DRAG: f32 = 0.98;
+const CONFETTI_FLUTTER: f32 = 0.15;
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    x: f32,
+    y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    // 裏返りながら落ちるように、横幅を揺らす位相
+    phase: f32,
+    color: &'static str,
+    life: u32,
+}
+
+// 画面の座標で飛ばす紙吹雪。寿命が来たものから消える
+#[derive(Default)]
+pub struct Particles {
+    particles: Vec<Particle>,
+}
+
+impl Particles {
+    // origin から上に向けて count 枚を撒く
+    pub fn confetti(&mut self, origin: Point, count: usize) {
+        let mut rng = random::rng();
+        self.particles.extend((0..count).map(|_| Particle {
+            x: f32::from(origin.x),
+            y: f32::from(origin.y),
+            velocity_x: rng.gen_range(-4.0..4.0),
+            velocity_y: rng.gen_range(-12.0..-6.0),
+            phase: rng.gen_range(0.0..TAU),
+            color: CONFETTI_COLORS[rng.gen_range(0..CONFETTI_COLORS.len())],
+            life: CONFETTI_LIFE,
+        }));
+    }
+
+    pub fn update(&mut self) {
+        self.particles.iter_mut().for_each(|particle| {
+            particle.velocity_x *= CONFETTI_DRAG;
+            particle.velocity_y =
+                (particle.velocity_y + CONFETTI_GRAVITY).min(CONFETTI_TERMINAL_SPEED);
+            particle.x += particle.velocity_x;
+            particle.y += particle.velocity_y;
+            particle.phase += CONFETTI_FLUTTER;
+            particle.life -= 1;
+        });
+        self.particles.retain(|particle| particle.life > 0);
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        self.particles.iter().for_each(|particle| {
+            let width = (CONFETTI_SIZE * particle.phase.cos().abs()).max(1.0);
+            renderer.fill_rect(
+                &Rect::new_from_x_y(
+                    (particle.x - width / 2.0) as i16,
+                    particle.y as i16,
+                    width as i16,
+                    CONFETTI_SIZE as i16,
+                ),
+                particle.color,
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confetti_flies_up_drifts_down_and_expires() {
+        let mut particles = Particles::default();
+        particles.confetti(Point { x: 300, y: 600 }, 20);
+        particles.update();
+        assert!(particles
+            .particles
+            .iter()
+            .all(|particle| particle.y < 600.0));
+
+        (0..CONFETTI_LIFE / 2).for_each(|_| particles.update());
+        assert!(particles
+            .particles
+            .iter()
+            .all(|particle| particle.velocity_y > 0.0
+                && particle.velocity_y <= CONFETTI_TERMINAL_SPEED));
+
+        (0..CONFETTI_LIFE).for_each(|_| particles.update());
+        assert!(particles.particles.is_empty());
+    }
+}
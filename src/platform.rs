@@ -0,0 +1,54 @@
+use crate::browser::LoopClosure;
+use crate::draw::{Context, Surface};
+use crate::engine::InputEvent;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::channel::mpsc::UnboundedReceiver;
+use serde::de::DeserializeOwned;
+
+mod native;
+mod web;
+
+// ゲームのロジックから使うブラウザ依存の処理。
+// ネイティブ向けの実装に差し替えて、wasm 以外でもテストやツールから動かせるようにする
+#[async_trait(?Send)]
+pub trait Platform {
+    fn log(&self, message: &str);
+    fn now(&self) -> Result<f64>;
+    fn timestamp(&self) -> f64;
+    // 月は 1 始まりで返す
+    fn month_and_day(&self) -> (u8, u8);
+    fn get_item(&self, key: &str) -> Result<Option<String>>;
+    fn set_item(&self, key: &str, value: &str) -> Result<()>;
+    // ゲームを描く画面。ブラウザではページのキャンバス、デスクトップではウィンドウの Framebuffer
+    fn context(&self) -> Result<Context>;
+    fn create_surface(&self, width: u32, height: u32) -> Result<Surface>;
+    fn context_of(&self, surface: &Surface) -> Result<Context>;
+    // 画面へのキーとポインターの入力を、届いた時刻と一緒に受け取る
+    fn input(&self) -> Result<UnboundedReceiver<InputEvent>>;
+    fn request_animation_frame(&self, callback: &LoopClosure) -> Result<i32>;
+    // 画面が隠れたら true、また見えるようになったら false を渡す
+    fn on_visibility_change(&self, callback: Box<dyn FnMut(bool)>) -> Result<()>;
+    // ページを離れるかウィンドウを閉じる直前に呼ぶ
+    fn on_unload(&self, callback: Box<dyn FnMut()>) -> Result<()>;
+    async fn fetch_text(&self, path: &str) -> Result<String>;
+    // 画像や音のファイルをそのままのバイト列で読む。いまはデスクトップ版だけが使う
+    #[cfg_attr(not(feature = "desktop"), allow(dead_code))]
+    async fn fetch_bytes(&self, path: &str) -> Result<Vec<u8>>;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn current() -> &'static dyn Platform {
+    &web::WebPlatform
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn current() -> &'static dyn Platform {
+    &native::NativePlatform
+}
+
+// JS の値を経由せずに読むので、ネイティブでも同じように使える
+pub async fn fetch_json<T: DeserializeOwned>(path: &str) -> Result<T> {
+    let text = current().fetch_text(path).await?;
+    serde_json::from_str(&text).map_err(|err| anyhow!("Could not parse {} {:#?}", path, err))
+}
@@ -0,0 +1,279 @@
+use super::Platform;
+use crate::browser::LoopClosure;
+use crate::draw::{Context, Surface};
+use crate::engine::InputEvent;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::channel::mpsc::UnboundedReceiver;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// 静的ファイルは webpack と同じく static/ から読む
+const STATIC_DIR: &str = "static";
+// localStorage の代わりに、ユーザーのデータの置き場所にある 1 つの JSON に保存する
+#[cfg_attr(test, allow(dead_code))]
+const APP_DIR: &str = "walk-the-dog";
+const STORAGE_FILE: &str = "storage.json";
+
+type Storage = BTreeMap<String, String>;
+
+thread_local! {
+    // 最初に使うときにファイルから読み、書くたびにファイルへ書き戻す
+    static STORAGE: RefCell<Option<Storage>> = const { RefCell::new(None) };
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct NativePlatform;
+
+fn started_at() -> Instant {
+    static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+    *STARTED_AT.get_or_init(Instant::now)
+}
+
+// Linux は XDG_DATA_HOME、macOS は Application Support、Windows は APPDATA
+#[cfg_attr(test, allow(dead_code))]
+fn data_dir() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".local").join("share")))
+    }
+}
+
+// テストでは手元のセーブを書き換えないよう、プロセスの間だけ覚えておく
+#[cfg(test)]
+fn storage_file() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(not(test))]
+fn storage_file() -> Option<PathBuf> {
+    Some(data_dir()?.join(APP_DIR).join(STORAGE_FILE))
+}
+
+// 読めないファイルは空として扱い、次に書くときに作り直す
+fn read_storage(path: &Path) -> Storage {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Storage::new();
+    };
+    serde_json::from_str(&text).unwrap_or_else(|err| {
+        log!("Could not parse {} {:#?}", path.display(), err);
+        Storage::new()
+    })
+}
+
+// 書いている途中で落ちても元のファイルが壊れないよう、別のファイルに書いてから置き換える
+fn write_storage(path: &Path, storage: &Storage) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| anyhow!("Could not create {} {:#?}", dir.display(), err))?;
+    }
+    let json = serde_json::to_string_pretty(storage)?;
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, json)
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|err| anyhow!("Could not write {} {:#?}", path.display(), err))
+}
+
+fn with_storage<T>(f: impl FnOnce(&mut Storage) -> T) -> T {
+    STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let storage = storage.get_or_insert_with(|| {
+            storage_file()
+                .map(|path| read_storage(&path))
+                .unwrap_or_default()
+        });
+        f(storage)
+    })
+}
+
+// 1970-01-01 からの日数を (月, 日) に直す
+fn month_and_day_of(days: i64) -> (u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    (month as u8, day as u8)
+}
+
+// デスクトップ版でなければ、描く先のウィンドウがない
+#[cfg(not(feature = "desktop"))]
+fn no_window<T>() -> Result<T> {
+    Err(anyhow!("No window without the desktop feature"))
+}
+
+#[async_trait(?Send)]
+impl Platform for NativePlatform {
+    fn log(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+
+    fn now(&self) -> Result<f64> {
+        Ok(started_at().elapsed().as_secs_f64() * 1000.0)
+    }
+
+    fn timestamp(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64() * 1000.0)
+            .unwrap_or_default()
+    }
+
+    fn month_and_day(&self) -> (u8, u8) {
+        const MILLISECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+        month_and_day_of((self.timestamp() / MILLISECONDS_PER_DAY) as i64)
+    }
+
+    fn get_item(&self, key: &str) -> Result<Option<String>> {
+        Ok(with_storage(|storage| storage.get(key).cloned()))
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<()> {
+        with_storage(|storage| {
+            storage.insert(key.into(), value.into());
+            storage_file().map_or(Ok(()), |path| write_storage(&path, storage))
+        })
+    }
+
+    #[cfg(feature = "desktop")]
+    fn context(&self) -> Result<Context> {
+        Ok(crate::desktop::screen())
+    }
+
+    // Framebuffer は描画先とコンテキストを兼ねる
+    #[cfg(feature = "desktop")]
+    fn create_surface(&self, width: u32, height: u32) -> Result<Surface> {
+        Ok(std::rc::Rc::new(crate::draw::software::Framebuffer::new(
+            width as usize,
+            height as usize,
+        )))
+    }
+
+    #[cfg(feature = "desktop")]
+    fn context_of(&self, surface: &Surface) -> Result<Context> {
+        Ok(surface.clone())
+    }
+
+    #[cfg(feature = "desktop")]
+    fn input(&self) -> Result<UnboundedReceiver<InputEvent>> {
+        Ok(crate::desktop::input())
+    }
+
+    // ウィンドウの次の描画で呼ぶ
+    #[cfg(feature = "desktop")]
+    fn request_animation_frame(&self, callback: &LoopClosure) -> Result<i32> {
+        crate::desktop::request_frame(callback.clone());
+        Ok(0)
+    }
+
+    #[cfg(feature = "desktop")]
+    fn on_visibility_change(&self, callback: Box<dyn FnMut(bool)>) -> Result<()> {
+        crate::desktop::on_visibility_change(callback);
+        Ok(())
+    }
+
+    #[cfg(feature = "desktop")]
+    fn on_unload(&self, callback: Box<dyn FnMut()>) -> Result<()> {
+        crate::desktop::on_unload(callback);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn context(&self) -> Result<Context> {
+        no_window()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn create_surface(&self, _width: u32, _height: u32) -> Result<Surface> {
+        no_window()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn context_of(&self, _surface: &Surface) -> Result<Context> {
+        no_window()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn input(&self) -> Result<UnboundedReceiver<InputEvent>> {
+        no_window()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn request_animation_frame(&self, _callback: &LoopClosure) -> Result<i32> {
+        no_window()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn on_visibility_change(&self, _callback: Box<dyn FnMut(bool)>) -> Result<()> {
+        no_window()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn on_unload(&self, _callback: Box<dyn FnMut()>) -> Result<()> {
+        no_window()
+    }
+
+    async fn fetch_text(&self, path: &str) -> Result<String> {
+        std::fs::read_to_string(Path::new(STATIC_DIR).join(path))
+            .map_err(|err| anyhow!("Could not read {} {:#?}", path, err))
+    }
+
+    async fn fetch_bytes(&self, path: &str) -> Result<Vec<u8>> {
+        std::fs::read(Path::new(STATIC_DIR).join(path))
+            .map_err(|err| anyhow!("Could not read {} {:#?}", path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_platform_keeps_storage_and_dates() {
+        let platform = NativePlatform;
+        platform.set_item("walk_the_dog.test", "value").unwrap();
+
+        assert_eq!(
+            platform.get_item("walk_the_dog.test").unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(month_and_day_of(0), (1, 1));
+        assert_eq!(month_and_day_of(19_783), (3, 1));
+    }
+
+    #[test]
+    fn storage_survives_in_a_file() {
+        let dir = env::temp_dir().join(format!("walk-the-dog-{}", std::process::id()));
+        let path = dir.join("saves").join(STORAGE_FILE);
+        assert!(read_storage(&path).is_empty());
+
+        let mut storage = Storage::new();
+        storage.insert("walk_the_dog.test".into(), "saved".into());
+        write_storage(&path, &storage).unwrap();
+        assert_eq!(read_storage(&path), storage);
+
+        fs::write(&path, "not json").unwrap();
+        assert!(read_storage(&path).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
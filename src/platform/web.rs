@@ -0,0 +1,250 @@
+use super::Platform;
+use crate::browser::{self, LoopClosure};
+use crate::draw::{Context, Surface};
+use crate::engine::InputEvent;
+#[cfg(not(feature = "desktop"))]
+use crate::engine::{KeyPress, Point};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+#[cfg(not(feature = "desktop"))]
+use futures::channel::mpsc::unbounded;
+use futures::channel::mpsc::UnboundedReceiver;
+#[cfg(not(feature = "desktop"))]
+use std::cell::RefCell;
+#[cfg(not(feature = "desktop"))]
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::Uint8Array;
+use web_sys::Storage;
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub struct WebPlatform;
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn local_storage() -> Result<Storage> {
+    browser::window()?
+        .local_storage()
+        .map_err(|err| anyhow!("Could not access localStorage {:#?}", err))?
+        .ok_or_else(|| anyhow!("No localStorage Found"))
+}
+
+// デスクトップ版の描画先はウィンドウの Framebuffer なので、ページのキャンバスは使わない
+#[cfg(feature = "desktop")]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn no_canvas<T>() -> Result<T> {
+    Err(anyhow!("No page canvas with the desktop renderer"))
+}
+
+// キャンバスのキーとポインターのイベントを、届いた時刻と一緒に流す
+#[cfg(not(feature = "desktop"))]
+fn listen_to_canvas() -> Result<UnboundedReceiver<InputEvent>> {
+    let (keydown_sender, keyevent_receiver) = unbounded();
+    let keydown_sender = Rc::new(RefCell::new(keydown_sender));
+    let keyup_sender = Rc::clone(&keydown_sender);
+    let pointerdown_sender = Rc::clone(&keydown_sender);
+    let pointermove_sender = Rc::clone(&keydown_sender);
+    let pointerup_sender = Rc::clone(&keydown_sender);
+
+    let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
+        let _ = keydown_sender
+            .borrow_mut()
+            .start_send((keycode.time_stamp(), KeyPress::KeyDown(keycode.code())));
+    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+
+    let onkeyup = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
+        let _ = keyup_sender
+            .borrow_mut()
+            .start_send((keycode.time_stamp(), KeyPress::KeyUp(keycode.code())));
+    }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+
+    // マウスとタッチはどちらも PointerEvent として受け取る
+    let onpointerdown = browser::closure_wrap(Box::new(move |event: web_sys::PointerEvent| {
+        let _ = pointerdown_sender.borrow_mut().start_send((
+            event.time_stamp(),
+            KeyPress::PointerDown {
+                position: pointer_position(&event),
+                touch: event.pointer_type() == "touch",
+            },
+        ));
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+
+    let onpointermove = browser::closure_wrap(Box::new(move |event: web_sys::PointerEvent| {
+        let _ = pointermove_sender.borrow_mut().start_send((
+            event.time_stamp(),
+            KeyPress::PointerMove(pointer_position(&event)),
+        ));
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+
+    let onpointerup = browser::closure_wrap(Box::new(move |event: web_sys::PointerEvent| {
+        let _ = pointerup_sender
+            .borrow_mut()
+            .start_send((event.time_stamp(), KeyPress::PointerUp));
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+
+    let canvas = browser::canvas()?;
+    canvas.set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
+    canvas.set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
+    canvas.set_onpointerdown(Some(onpointerdown.as_ref().unchecked_ref()));
+    canvas.set_onpointermove(Some(onpointermove.as_ref().unchecked_ref()));
+    canvas.set_onpointerup(Some(onpointerup.as_ref().unchecked_ref()));
+
+    onkeydown.forget();
+    onkeyup.forget();
+    onpointerdown.forget();
+    onpointermove.forget();
+    onpointerup.forget();
+
+    Ok(keyevent_receiver)
+}
+
+#[cfg(not(feature = "desktop"))]
+fn pointer_position(event: &web_sys::PointerEvent) -> Point {
+    Point {
+        x: event.offset_x() as i16,
+        y: event.offset_y() as i16,
+    }
+}
+
+#[async_trait(?Send)]
+impl Platform for WebPlatform {
+    fn log(&self, message: &str) {
+        web_sys::console::log_1(&message.into());
+    }
+
+    fn now(&self) -> Result<f64> {
+        Ok(browser::window()?
+            .performance()
+            .ok_or_else(|| anyhow!("Performance object not found"))?
+            .now())
+    }
+
+    fn timestamp(&self) -> f64 {
+        web_sys::js_sys::Date::now()
+    }
+
+    fn month_and_day(&self) -> (u8, u8) {
+        let date = web_sys::js_sys::Date::new_0();
+        ((date.get_month() + 1) as u8, date.get_date() as u8)
+    }
+
+    fn get_item(&self, key: &str) -> Result<Option<String>> {
+        local_storage()?
+            .get_item(key)
+            .map_err(|err| anyhow!("Could not read {} from localStorage {:#?}", key, err))
+    }
+
+    fn set_item(&self, key: &str, value: &str) -> Result<()> {
+        local_storage()?
+            .set_item(key, value)
+            .map_err(|err| anyhow!("Could not write {} to localStorage {:#?}", key, err))
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn context(&self) -> Result<Context> {
+        self.context_of(&browser::canvas()?)
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn create_surface(&self, width: u32, height: u32) -> Result<Surface> {
+        let canvas = browser::document()?
+            .create_element("canvas")
+            .map_err(|err| anyhow!("Could not create canvas element {:#?}", err))?
+            .dyn_into::<Surface>()
+            .map_err(|element| anyhow!("Error converting {:#?} to HtmlCanvasElement", element))?;
+        canvas.set_width(width);
+        canvas.set_height(height);
+        Ok(canvas)
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn context_of(&self, surface: &Surface) -> Result<Context> {
+        surface
+            .get_context("2d")
+            .map_err(|js_value| anyhow!("Error getting 2d context {:#?}", js_value))?
+            .ok_or_else(|| anyhow!("No 2d context found"))?
+            .dyn_into::<Context>()
+            .map_err(|element| {
+                anyhow!(
+                    "Error converting {:#?} to CanvasRenderingContext2d",
+                    element
+                )
+            })
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn input(&self) -> Result<UnboundedReceiver<InputEvent>> {
+        listen_to_canvas()
+    }
+
+    #[cfg(not(feature = "desktop"))]
+    fn request_animation_frame(&self, callback: &LoopClosure) -> Result<i32> {
+        browser::window()?
+            .request_animation_frame(callback.as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Cannot request animation frame {:#?}", err))
+    }
+
+    #[cfg(feature = "desktop")]
+    fn context(&self) -> Result<Context> {
+        no_canvas()
+    }
+
+    #[cfg(feature = "desktop")]
+    fn create_surface(&self, _width: u32, _height: u32) -> Result<Surface> {
+        no_canvas()
+    }
+
+    #[cfg(feature = "desktop")]
+    fn context_of(&self, _surface: &Surface) -> Result<Context> {
+        no_canvas()
+    }
+
+    #[cfg(feature = "desktop")]
+    fn input(&self) -> Result<UnboundedReceiver<InputEvent>> {
+        no_canvas()
+    }
+
+    #[cfg(feature = "desktop")]
+    fn request_animation_frame(&self, _callback: &LoopClosure) -> Result<i32> {
+        no_canvas()
+    }
+
+    fn on_visibility_change(&self, mut callback: Box<dyn FnMut(bool)>) -> Result<()> {
+        let document = browser::document()?;
+        let hidden_document = document.clone();
+        let listener = browser::closure_wrap(
+            Box::new(move || callback(hidden_document.hidden())) as Box<dyn FnMut()>
+        );
+        document
+            .add_event_listener_with_callback("visibilitychange", listener.as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Could not listen to visibilitychange {:#?}", err))?;
+        listener.forget();
+        Ok(())
+    }
+
+    // unload より確実に届く pagehide を使う
+    fn on_unload(&self, callback: Box<dyn FnMut()>) -> Result<()> {
+        let listener = browser::closure_wrap(callback);
+        browser::window()?
+            .add_event_listener_with_callback("pagehide", listener.as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Could not listen to pagehide {:#?}", err))?;
+        listener.forget();
+        Ok(())
+    }
+
+    async fn fetch_text(&self, path: &str) -> Result<String> {
+        let response = browser::fetch_response(path).await?;
+        browser::response_text(&response).await
+    }
+
+    async fn fetch_bytes(&self, path: &str) -> Result<Vec<u8>> {
+        let response = browser::fetch_response(path).await?;
+        let array_buffer = response
+            .array_buffer()
+            .map_err(|err| anyhow!("Error loading array buffer {:#?}", err))?;
+        let array_buffer = JsFuture::from(array_buffer)
+            .await
+            .map_err(|err| anyhow!("Error reading {} {:#?}", path, err))?;
+        Ok(Uint8Array::new(&array_buffer).to_vec())
+    }
+}
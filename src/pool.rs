@@ -0,0 +1,83 @@
+// 使い終わった箱を取っておき、次に同じ種類を作るときは中身だけを書き換えて使い回す
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolStats {
+    pub free: usize,
+    pub allocated: u32,
+    pub reused: u32,
+}
+
+pub struct Pool<T> {
+    free: Vec<Box<T>>,
+    capacity: usize,
+    allocated: u32,
+    reused: u32,
+}
+
+impl<T> Pool<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Pool {
+            free: Vec::with_capacity(capacity),
+            capacity,
+            allocated: 0,
+            reused: 0,
+        }
+    }
+
+    // 空いている箱があれば reset で書き換えて返す。なければ create で新しく作る
+    pub fn take(&mut self, create: impl FnOnce() -> T, reset: impl FnOnce(&mut T)) -> Box<T> {
+        match self.free.pop() {
+            Some(mut item) => {
+                self.reused += 1;
+                reset(&mut item);
+                item
+            }
+            None => {
+                self.allocated += 1;
+                Box::new(create())
+            }
+        }
+    }
+
+    // 取っておく数には上限がある。溢れた分はそのまま捨てる
+    pub fn give_back(&mut self, item: Box<T>) {
+        if self.free.len() < self.capacity {
+            self.free.push(item);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            free: self.free.len(),
+            allocated: self.allocated,
+            reused: self.reused,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returned_boxes_are_reset_and_reused_in_place() {
+        let mut pool: Pool<Vec<i16>> = Pool::with_capacity(1);
+        let mut first = pool.take(|| Vec::with_capacity(8), |_| {});
+        first.extend([1, 2, 3]);
+        let address: *const Vec<i16> = &*first;
+        pool.give_back(first);
+        pool.give_back(Box::default());
+
+        let second = pool.take(Vec::new, |items| items.clear());
+        assert_eq!(&*second as *const Vec<i16>, address);
+        assert!(second.is_empty());
+        assert_eq!(second.capacity(), 8);
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                free: 0,
+                allocated: 1,
+                reused: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{Point, Rect, Renderer};
+use serde::{Deserialize, Serialize};
+
+// 拾ってから効いているフレーム数。60 で 1 秒
+const SHIELD_TICKS: u32 = 60 * 15;
+const MAGNET_TICKS: u32 = 60 * 8;
+const SPEED_BOOST_TICKS: u32 = 60 * 4;
+// 加速中に足す走る速さ
+pub const SPEED_BOOST: i16 = 3;
+
+// スコアの下に右揃えで一つずつ並べる
+const HUD_POSITION: Point = Point { x: 580, y: 84 };
+const HUD_LINE_HEIGHT: i16 = 22;
+const HUD_BAR_WIDTH: i16 = 60;
+const HUD_BAR_HEIGHT: i16 = 6;
+const HUD_COLOR: &str = "#FFFFFF";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PowerUpKind {
+    Shield,
+    Magnet,
+    SpeedBoost,
+}
+
+pub const POWER_UP_KINDS: [PowerUpKind; 3] = [
+    PowerUpKind::Shield,
+    PowerUpKind::Magnet,
+    PowerUpKind::SpeedBoost,
+];
+
+impl PowerUpKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "Shield",
+            PowerUpKind::Magnet => "Magnet",
+            PowerUpKind::SpeedBoost => "Speed",
+        }
+    }
+
+    // 拾い物と HUD で同じ色を使う
+    pub fn color(&self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "#5FB8FF",
+            PowerUpKind::Magnet => "#FF5FA2",
+            PowerUpKind::SpeedBoost => "#7CFF6B",
+        }
+    }
+
+    // 走り出すときに持って出るときのコインの値段
+    pub fn price(&self) -> u32 {
+        match self {
+            PowerUpKind::Shield => 30,
+            PowerUpKind::Magnet => 20,
+            PowerUpKind::SpeedBoost => 20,
+        }
+    }
+
+    // プロフィールの unlocks に入っていれば、ただで持って出られる
+    pub fn unlock(&self) -> &'static str {
+        match self {
+            PowerUpKind::Shield => "loadout.shield",
+            PowerUpKind::Magnet => "loadout.magnet",
+            PowerUpKind::SpeedBoost => "loadout.speedBoost",
+        }
+    }
+
+    fn duration(&self) -> u32 {
+        match self {
+            PowerUpKind::Shield => SHIELD_TICKS,
+            PowerUpKind::Magnet => MAGNET_TICKS,
+            PowerUpKind::SpeedBoost => SPEED_BOOST_TICKS,
+        }
+    }
+}
+
+// 持って出るものを選び直す。なしから順に、選べるものだけを回る
+pub fn next_loadout(
+    current: Option<PowerUpKind>,
+    available: impl Fn(PowerUpKind) -> bool,
+) -> Option<PowerUpKind> {
+    let choices: Vec<Option<PowerUpKind>> = std::iter::once(None)
+        .chain(POWER_UP_KINDS.iter().copied().map(Some))
+        .collect();
+    let start = choices
+        .iter()
+        .position(|choice| *choice == current)
+        .unwrap_or(0);
+    choices
+        .iter()
+        .cycle()
+        .skip(start + 1)
+        .take(choices.len())
+        .copied()
+        .find(|choice| choice.is_none_or(&available))
+        .flatten()
+}
+
+// 効いているパワーアップと残りフレーム。同じものを拾い直すと残りが満タンに戻る
+#[derive(Clone, Default)]
+pub struct PowerUps {
+    ticks: [u32; 3],
+}
+
+impl PowerUps {
+    fn index(kind: PowerUpKind) -> usize {
+        POWER_UP_KINDS
+            .iter()
+            .position(|candidate| *candidate == kind)
+            .unwrap_or(0)
+    }
+
+    pub fn activate(&mut self, kind: PowerUpKind) {
+        self.ticks[PowerUps::index(kind)] = kind.duration();
+    }
+
+    pub fn is_active(&self, kind: PowerUpKind) -> bool {
+        self.ticks[PowerUps::index(kind)] > 0
+    }
+
+    // シールドが倒れるのを一回肩代わりしたときなど、時間より先に切らす
+    pub fn consume(&mut self, kind: PowerUpKind) {
+        self.ticks[PowerUps::index(kind)] = 0;
+    }
+
+    pub fn update(&mut self) {
+        self.ticks
+            .iter_mut()
+            .for_each(|ticks| *ticks = ticks.saturating_sub(1));
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let style = TextStyle::new(HUD_COLOR).align(TextAlign::Right);
+        POWER_UP_KINDS
+            .iter()
+            .zip(self.ticks)
+            .filter(|(_, ticks)| *ticks > 0)
+            .enumerate()
+            .for_each(|(line, (kind, ticks))| {
+                let y = HUD_POSITION.y + line as i16 * HUD_LINE_HEIGHT;
+                let left = HUD_POSITION.x - HUD_BAR_WIDTH;
+                let filled = (HUD_BAR_WIDTH as u32 * ticks / kind.duration()) as i16;
+                renderer.fill_rect(
+                    &Rect::new_from_x_y(left, y - HUD_BAR_HEIGHT, filled, HUD_BAR_HEIGHT),
+                    kind.color(),
+                );
+                renderer.stroke_rect(
+                    &Rect::new_from_x_y(left, y - HUD_BAR_HEIGHT, HUD_BAR_WIDTH, HUD_BAR_HEIGHT),
+                    HUD_COLOR,
+                );
+                renderer.draw_text(kind.label(), &Point { x: left - 8, y }, style.clone());
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_ups_run_out_and_the_shield_can_be_spent() {
+        let mut power_ups = PowerUps::default();
+        power_ups.activate(PowerUpKind::SpeedBoost);
+        power_ups.activate(PowerUpKind::Shield);
+        assert!(power_ups.is_active(PowerUpKind::SpeedBoost));
+        assert!(!power_ups.is_active(PowerUpKind::Magnet));
+
+        (0..SPEED_BOOST_TICKS).for_each(|_| power_ups.update());
+        assert!(!power_ups.is_active(PowerUpKind::SpeedBoost));
+        assert!(power_ups.is_active(PowerUpKind::Shield));
+
+        power_ups.consume(PowerUpKind::Shield);
+        assert!(!power_ups.is_active(PowerUpKind::Shield));
+    }
+
+    #[test]
+    fn loadout_choices_skip_what_cannot_be_taken() {
+        let affordable = |kind| kind != PowerUpKind::Magnet;
+        assert_eq!(next_loadout(None, affordable), Some(PowerUpKind::Shield));
+        assert_eq!(
+            next_loadout(Some(PowerUpKind::Shield), affordable),
+            Some(PowerUpKind::SpeedBoost)
+        );
+        assert_eq!(
+            next_loadout(Some(PowerUpKind::SpeedBoost), affordable),
+            None
+        );
+        assert_eq!(next_loadout(None, |_| false), None);
+    }
+}
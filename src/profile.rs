@@ -0,0 +1,256 @@
+use crate::browser::{self, storage};
+use crate::difficulty::Difficulty;
+use crate::leaderboard::HighScore;
+use crate::power_up::PowerUpKind;
+use crate::settings::Settings;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const MAX_PROFILES: usize = 3;
+// レースで見せる姿。ロビーで選び、部屋の全員にこの名前で伝える
+pub const SKINS: [&str; 3] = ["rhb", "blue", "green"];
+const ACTIVE_PROFILE_KEY: &str = "walk_the_dog.active_profile";
+const PLAYER_ID_KEY: &str = "walk_the_dog.player_id";
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Stats {
+    pub runs: u32,
+    pub frames_survived: u64,
+    pub best_run_frames: u32,
+    pub best_by_difficulty: BTreeMap<Difficulty, u32>,
+    pub best_distance: u32,
+    // 難易度ごとの、区切りの距離 (m) までの最速時間 (ms)
+    pub best_splits: BTreeMap<Difficulty, BTreeMap<u32, f64>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    pub settings: Settings,
+    pub stats: Stats,
+    pub unlocks: Vec<String>,
+    // ランで拾ったコインの合計。走り出すときのパワーアップに使う
+    pub coins: u32,
+    pub high_score: u32,
+    // スコアの上位。名前入力で入れた 3 文字と一緒に残す
+    pub high_scores: Vec<HighScore>,
+    // 次の名前入力で最初から選んでおく文字
+    pub initials: String,
+    pub skin: String,
+    // レベルの名前ごとの、いちばん多く取った星の数
+    pub medals: BTreeMap<String, u8>,
+    // 解いた実績の id
+    pub achievements: Vec<String>,
+    pub updated_at: f64,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: "Player".into(),
+            settings: Settings::default(),
+            stats: Stats::default(),
+            unlocks: vec![],
+            coins: 0,
+            high_score: 0,
+            high_scores: vec![],
+            initials: String::new(),
+            skin: SKINS[0].into(),
+            medals: BTreeMap::new(),
+            achievements: vec![],
+            updated_at: 0.0,
+        }
+    }
+}
+
+impl Profile {
+    // 自己ベストを更新したら true を返す
+    // 自己ベストは難易度ごとに比べる
+    pub fn record_run(&mut self, frames: u32, difficulty: Difficulty) -> bool {
+        self.stats.runs += 1;
+        self.stats.frames_survived += frames as u64;
+        self.stats.best_run_frames = self.stats.best_run_frames.max(frames);
+        let best = self.stats.best_by_difficulty.entry(difficulty).or_default();
+        let is_best = frames > *best;
+        *best = (*best).max(frames);
+        is_best
+    }
+
+    // スコアの最高記録を更新したら true を返す。距離は別に最長を残す
+    pub fn record_score(&mut self, score: u32, distance: u32) -> bool {
+        self.stats.best_distance = self.stats.best_distance.max(distance);
+        let is_best = score > self.high_score;
+        self.high_score = self.high_score.max(score);
+        is_best
+    }
+
+    // SKINS の次の姿にする。知らない名前なら最初に戻る
+    pub fn next_skin(&mut self) {
+        let next = SKINS
+            .iter()
+            .position(|skin| *skin == self.skin)
+            .map_or(0, |index| (index + 1) % SKINS.len());
+        self.skin = SKINS[next].into();
+    }
+
+    pub fn has_unlocked(&self, kind: PowerUpKind) -> bool {
+        self.unlocks.iter().any(|unlock| unlock == kind.unlock())
+    }
+
+    pub fn can_start_with(&self, kind: PowerUpKind) -> bool {
+        self.has_unlocked(kind) || self.coins >= kind.price()
+    }
+
+    // 解放済みならただ。そうでなければコインで払う。払えなければ false
+    pub fn buy_loadout(&mut self, kind: PowerUpKind) -> bool {
+        if self.has_unlocked(kind) {
+            return true;
+        }
+        match self.coins.checked_sub(kind.price()) {
+            Some(coins) => {
+                self.coins = coins;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn medal(&self, level: &str) -> Option<u8> {
+        self.medals.get(level).copied()
+    }
+
+    // 前より多く星を取ったときだけ残して true を返す
+    pub fn record_medal(&mut self, level: &str, stars: u8) -> bool {
+        if self.medal(level).is_some_and(|best| best >= stars) {
+            return false;
+        }
+        self.medals.insert(level.into(), stars);
+        true
+    }
+
+    pub fn best_splits(&self, difficulty: Difficulty) -> BTreeMap<u32, f64> {
+        self.stats
+            .best_splits
+            .get(&difficulty)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn record_splits(&mut self, difficulty: Difficulty, splits: BTreeMap<u32, f64>) {
+        self.stats.best_splits.insert(difficulty, splits);
+    }
+}
+
+// 家族で共有する端末向けに、スロットごとに別のキーへ保存する
+fn storage_key(slot: usize) -> String {
+    format!("walk_the_dog.profile.{}", slot)
+}
+
+pub fn load(slot: usize) -> Profile {
+    storage::get_item(&storage_key(slot))
+        .ok()
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(|| Profile {
+            name: format!("Player {}", slot + 1),
+            ..Profile::default()
+        })
+}
+
+pub fn save(slot: usize, profile: &mut Profile) {
+    profile.updated_at = browser::timestamp();
+    store(slot, profile);
+}
+
+// 更新時刻を変えずに書き込む (クラウドから取得したデータの反映用)
+pub fn store(slot: usize, profile: &Profile) {
+    let result = serde_json::to_string(profile)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| storage::set_item(&storage_key(slot), &json));
+    if let Err(err) = result {
+        log!("Could not save profile {} {:#?}", slot, err);
+    }
+}
+
+pub fn active_slot() -> usize {
+    storage::get_item(ACTIVE_PROFILE_KEY)
+        .ok()
+        .flatten()
+        .and_then(|slot| slot.parse().ok())
+        .filter(|slot| *slot < MAX_PROFILES)
+        .unwrap_or(0)
+}
+
+pub fn set_active_slot(slot: usize) {
+    if let Err(err) = storage::set_item(ACTIVE_PROFILE_KEY, &slot.to_string()) {
+        log!("Could not store active profile {:#?}", err);
+    }
+}
+
+pub fn player_id() -> String {
+    if let Ok(Some(id)) = storage::get_item(PLAYER_ID_KEY) {
+        return id;
+    }
+
+    let id = format!("{:016x}", thread_rng().gen::<u64>());
+    if let Err(err) = storage::set_item(PLAYER_ID_KEY, &id) {
+        log!("Could not store player id {:#?}", err);
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_score_keeps_the_best_score_and_distance() {
+        let mut profile = Profile::default();
+
+        assert!(profile.record_score(120, 80));
+        assert!(!profile.record_score(90, 100));
+        assert_eq!(profile.high_score, 120);
+        assert_eq!(profile.stats.best_distance, 100);
+    }
+
+    #[test]
+    fn loadouts_are_bought_with_coins_unless_unlocked() {
+        let mut profile = Profile {
+            coins: 45,
+            unlocks: vec![PowerUpKind::Magnet.unlock().into()],
+            ..Profile::default()
+        };
+
+        assert!(profile.buy_loadout(PowerUpKind::Shield));
+        assert_eq!(profile.coins, 15);
+        assert!(!profile.can_start_with(PowerUpKind::SpeedBoost));
+        assert!(!profile.buy_loadout(PowerUpKind::SpeedBoost));
+        assert!(profile.buy_loadout(PowerUpKind::Magnet));
+        assert_eq!(profile.coins, 15);
+    }
+
+    #[test]
+    fn medals_keep_the_most_stars_per_level() {
+        let mut profile = Profile::default();
+
+        assert!(profile.record_medal("Park Loop", 2));
+        assert!(!profile.record_medal("Park Loop", 1));
+        assert!(profile.record_medal("Park Loop", 3));
+        assert_eq!(profile.medal("Park Loop"), Some(3));
+        assert_eq!(profile.medal("Riverside"), None);
+    }
+
+    #[test]
+    fn skins_cycle_back_to_the_first() {
+        let mut profile = Profile::default();
+        (0..SKINS.len()).for_each(|_| profile.next_skin());
+        assert_eq!(profile.skin, SKINS[0]);
+
+        profile.skin = "retired".into();
+        profile.next_skin();
+        assert_eq!(profile.skin, SKINS[0]);
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+// 最初の 2 秒ほどのフレームで端末の速さを測る
+const SAMPLE_FRAMES: u32 = 120;
+// 1 フレームの処理がこれより重ければ古い端末とみなす
+const LOW_TIER_FRAME_TIME: f32 = 12.0;
+pub const LOW_TIER_FRAME_RATE: f64 = 30.0;
+
+// オプションで選ぶ画質。Auto なら測った結果に任せる
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Quality {
+    #[default]
+    Auto,
+    High,
+    Low,
+}
+
+pub const QUALITIES: [Quality; 3] = [Quality::Auto, Quality::High, Quality::Low];
+
+impl Quality {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Quality::Auto => "Auto",
+            Quality::High => "High",
+            Quality::Low => "Low",
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        QUALITIES
+            .iter()
+            .position(|quality| quality == self)
+            .unwrap_or_default()
+    }
+
+    // 測り終わるまでの Auto は High として扱う
+    pub fn is_low(&self, detected: Option<bool>) -> bool {
+        match self {
+            Quality::Auto => detected.unwrap_or(false),
+            Quality::High => false,
+            Quality::Low => true,
+        }
+    }
+}
+
+// 起動直後のフレーム時間を平均し、低画質にするべきかを一度だけ決める
+#[derive(Default)]
+pub struct QualityDetector {
+    frames: u32,
+    total: f32,
+    low: Option<bool>,
+}
+
+impl QualityDetector {
+    pub fn new() -> Self {
+        QualityDetector::default()
+    }
+
+    // 決まったフレームでだけ Some を返す
+    pub fn record(&mut self, frame_time: f32) -> Option<bool> {
+        if self.low.is_some() {
+            return None;
+        }
+        self.frames += 1;
+        self.total += frame_time;
+        if self.frames < SAMPLE_FRAMES {
+            return None;
+        }
+        let low = self.total / self.frames as f32 > LOW_TIER_FRAME_TIME;
+        self.low = Some(low);
+        self.low
+    }
+
+    pub fn detected(&self) -> Option<bool> {
+        self.low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_frames_pick_the_low_tier_once() {
+        let mut detector = QualityDetector::new();
+        let decisions: Vec<bool> = (0..SAMPLE_FRAMES * 2)
+            .filter_map(|_| detector.record(20.0))
+            .collect();
+
+        assert_eq!(decisions, vec![true]);
+        assert!(Quality::Auto.is_low(detector.detected()));
+        assert!(!Quality::High.is_low(detector.detected()));
+        assert!(!Quality::Auto.is_low(None));
+        assert!(Quality::Low.is_low(None));
+    }
+}
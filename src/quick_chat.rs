@@ -0,0 +1,154 @@
+use crate::engine::{KeyState, Point, Rect, Renderer};
+use crate::lockstep::LockstepMessage;
+
+// 数字キー 1〜4 で送れる定型メッセージ
+pub const EMOTES: [&str; 4] = ["GG!", "NICE!", "OOPS", "HURRY!"];
+const EMOTE_KEYS: [&str; 4] = ["Digit1", "Digit2", "Digit3", "Digit4"];
+// 連打で相手の画面を埋めないよう、送ってから 1.5 秒は次を送れない。
+// レースのティックは相手を待つあいだ止まるので、自分の端末のフレームで数える
+const SEND_COOLDOWN_FRAMES: u32 = 90;
+// 行儀の悪い相手が連打してきても、吹き出しはこの間隔より速く切り替えない
+const RECEIVE_COOLDOWN_FRAMES: u32 = 60;
+const BUBBLE_FRAMES: u8 = 120;
+const BUBBLE_WIDTH: i16 = 90;
+const BUBBLE_HEIGHT: i16 = 28;
+const BUBBLE_COLOR: &str = "rgba(255, 255, 255, 0.6)";
+const BUBBLE_TEXT_COLOR: &str = "rgba(30, 30, 30, 0.8)";
+
+#[derive(Default)]
+pub struct QuickChat {
+    frame: u32,
+    last_sent: Option<u32>,
+    last_received: Option<u32>,
+}
+
+impl QuickChat {
+    pub fn new() -> Self {
+        QuickChat::default()
+    }
+
+    // 毎フレーム呼ぶ。tick は送るメッセージに載せるだけで、間隔には使わない
+    pub fn update(&mut self, keystate: &KeyState, tick: u32) -> Option<LockstepMessage> {
+        self.frame = self.frame.wrapping_add(1);
+        let emote = EMOTE_KEYS
+            .iter()
+            .position(|code| keystate.is_just_pressed(code))?;
+        self.send(emote as u8, tick)
+    }
+
+    pub fn send(&mut self, emote: u8, tick: u32) -> Option<LockstepMessage> {
+        if usize::from(emote) >= EMOTES.len() {
+            return None;
+        }
+        if !cooled_down(self.last_sent, self.frame, SEND_COOLDOWN_FRAMES) {
+            return None;
+        }
+        self.last_sent = Some(self.frame);
+        Some(LockstepMessage::Emote { tick, emote })
+    }
+
+    // 相手から届いた定型メッセージ。間隔が詰まりすぎているものは捨てる
+    pub fn receive(&mut self, emote: u8) -> Option<EmoteBubble> {
+        if !cooled_down(self.last_received, self.frame, RECEIVE_COOLDOWN_FRAMES) {
+            return None;
+        }
+        let bubble = EmoteBubble::new(emote)?;
+        self.last_received = Some(self.frame);
+        Some(bubble)
+    }
+}
+
+fn cooled_down(last: Option<u32>, frame: u32, cooldown: u32) -> bool {
+    last.is_none_or(|last| frame.wrapping_sub(last) >= cooldown)
+}
+
+// 相手のマーカーの上にしばらく出す吹き出し
+pub struct EmoteBubble {
+    text: &'static str,
+    frames: u8,
+}
+
+impl EmoteBubble {
+    pub fn new(emote: u8) -> Option<Self> {
+        EMOTES.get(usize::from(emote)).map(|text| EmoteBubble {
+            text,
+            frames: BUBBLE_FRAMES,
+        })
+    }
+
+    // 表示し終わったら false を返す
+    pub fn update(&mut self) -> bool {
+        self.frames = self.frames.saturating_sub(1);
+        self.frames > 0
+    }
+
+    // anchor はマーカーの上端の中央
+    pub fn draw(&self, renderer: &Renderer, anchor: &Point) {
+        let bubble = Rect::new_from_x_y(
+            anchor.x - BUBBLE_WIDTH / 2,
+            anchor.y - BUBBLE_HEIGHT - 8,
+            BUBBLE_WIDTH,
+            BUBBLE_HEIGHT,
+        );
+        renderer.fill_rect(&bubble, BUBBLE_COLOR);
+        renderer.draw_text(
+            self.text,
+            &Point {
+                x: bubble.x() + 8,
+                y: bubble.y() + BUBBLE_HEIGHT / 2,
+            },
+            BUBBLE_TEXT_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idle() -> KeyState {
+        KeyState::injected(std::iter::empty())
+    }
+
+    #[test]
+    fn quick_chat_is_rate_limited() {
+        let mut chat = QuickChat::new();
+
+        assert_eq!(
+            chat.send(1, 10),
+            Some(LockstepMessage::Emote { tick: 10, emote: 1 })
+        );
+        (1..SEND_COOLDOWN_FRAMES).for_each(|_| {
+            chat.update(&idle(), 10);
+        });
+        assert_eq!(chat.send(2, 10), None);
+        chat.update(&idle(), 10);
+        assert!(chat.send(2, 10).is_some());
+        assert_eq!(chat.send(EMOTES.len() as u8, 1000), None);
+    }
+
+    #[test]
+    fn cooldown_runs_while_the_race_tick_is_stalled() {
+        let mut chat = QuickChat::new();
+        let press = || KeyState::injected(["Digit1"].iter().copied());
+
+        assert!(chat.update(&press(), 5).is_some());
+        (0..SEND_COOLDOWN_FRAMES).for_each(|_| {
+            chat.update(&idle(), 5);
+        });
+        assert!(chat.update(&press(), 5).is_some());
+    }
+
+    #[test]
+    fn received_emotes_are_rate_limited() {
+        let mut chat = QuickChat::new();
+
+        assert!(chat.receive(0).is_some());
+        assert!(chat.receive(1).is_none());
+        (0..RECEIVE_COOLDOWN_FRAMES).for_each(|_| {
+            chat.update(&idle(), 0);
+        });
+        assert!(chat.receive(EMOTES.len() as u8).is_none());
+        assert!(chat.receive(1).is_some());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::engine::{KeyState, Point, Rect, Renderer};
+use crate::game::Walk;
+use crate::golden::Fnv1a;
+use crate::lockstep::{Lockstep, LockstepMessage, TickInput};
+use crate::profile::SKINS;
+use crate::quick_chat::{EmoteBubble, QuickChat};
+use crate::random;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::cmp::{Ordering, Reverse};
+use std::hash::{Hash, Hasher};
+
+const JUMP_KEY: &str = "Space";
+const SLIDE_KEY: &str = "ArrowDown";
+const ATTACK_KEY: &str = "KeyX";
+
+// これだけ走った方が勝ち。両方倒れたら遠くまで走った方が勝つ
+pub const FINISH_DISTANCE: u32 = 300;
+
+const TEXT_COLOR: &str = "#FFFFFF";
+// profile::SKINS と同じ並びのランナーの色
+const SKIN_COLORS: [&str; 3] = ["#FF0000", "#3A6EA5", "#3AA55A"];
+const TRACK_COLOR: &str = "rgba(0, 0, 0, 0.4)";
+const TRACK: Rect = Rect::new_from_x_y(40, 90, 400, 8);
+const TRACK_GAP: i16 = 14;
+
+// 送った入力を Walk に渡すキーの入力に戻す。押し始めは前のティックの入力と比べて決める
+fn keystate(input: TickInput, previous: TickInput) -> KeyState {
+    let keys = [
+        (input.jump, previous.jump, JUMP_KEY),
+        (input.slide, previous.slide, SLIDE_KEY),
+    ];
+    let held = keys
+        .iter()
+        .filter(|(pressed, _, _)| *pressed)
+        .map(|(_, _, code)| *code);
+    let just = keys
+        .iter()
+        .filter(|(pressed, was_pressed, _)| *pressed && !was_pressed)
+        .map(|(_, _, code)| *code)
+        .chain(input.attack.then_some(ATTACK_KEY));
+    KeyState::replayed(held, just)
+}
+
+fn skin_color(skin: &str) -> &'static str {
+    SKINS
+        .iter()
+        .position(|known| *known == skin)
+        .map_or(SKIN_COLORS[0], |index| SKIN_COLORS[index])
+}
+
+// レースの一人分。本編と同じ Walk を、自分専用の乱数で進める
+pub struct Runner {
+    walk: Walk,
+    rng: StdRng,
+    previous: TickInput,
+    // ゴールしたか倒れたティック
+    finished_at: Option<u32>,
+    color: &'static str,
+}
+
+impl Runner {
+    pub fn new(mut walk: Walk, seed: u64, skin: &str) -> Self {
+        walk.start_race();
+        Runner {
+            walk,
+            rng: StdRng::seed_from_u64(seed),
+            previous: TickInput::default(),
+            finished_at: None,
+            color: skin_color(skin),
+        }
+    }
+
+    // Walk はコースを共有の乱数で作るので、このランナーの乱数に差し替えてから 1 ティック進める
+    pub fn step(&mut self, input: TickInput, tick: u32) {
+        if self.finished_at.is_some() {
+            return;
+        }
+        let shared = random::snapshot();
+        random::restore(self.rng.clone());
+        self.walk.step(&keystate(input, self.previous));
+        self.rng = random::snapshot();
+        random::restore(shared);
+        self.previous = input;
+        if self.walk.knocked_out() || self.has_finished() {
+            self.finished_at = Some(tick);
+        }
+    }
+
+    pub fn distance(&self) -> u32 {
+        self.walk.distance()
+    }
+
+    fn has_finished(&self) -> bool {
+        self.distance() >= FINISH_DISTANCE
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.finished_at.is_some()
+    }
+
+    pub fn color(&self) -> &'static str {
+        self.color
+    }
+
+    pub fn walk(&self) -> &Walk {
+        &self.walk
+    }
+
+    pub fn into_walk(self) -> Walk {
+        self.walk
+    }
+
+    // 先にゴールした方が上。どちらもゴールしていなければ遠くまで走っている方が上
+    fn standing(&self) -> (bool, Reverse<u32>, u32) {
+        let finished_at = self
+            .finished_at
+            .filter(|_| self.has_finished())
+            .unwrap_or(u32::MAX);
+        (self.has_finished(), Reverse(finished_at), self.distance())
+    }
+
+    pub fn compare(&self, other: &Runner) -> Ordering {
+        self.standing().cmp(&other.standing())
+    }
+
+    pub fn hash_state(&self, hasher: &mut impl Hasher) {
+        self.walk.hash_state(hasher);
+        self.finished_at.hash(hasher);
+    }
+}
+
+// 二人の走った距離をゴールまでの線で見せる。index は上からの並び
+pub fn draw_track(renderer: &Renderer, runners: &[Runner], top: i16) {
+    for (index, runner) in runners.iter().enumerate() {
+        let track = Rect::new_from_x_y(
+            TRACK.x(),
+            top + index as i16 * TRACK_GAP,
+            TRACK.width,
+            TRACK.height,
+        );
+        renderer.fill_rect(&track, TRACK_COLOR);
+        let progress = runner.distance().min(FINISH_DISTANCE) as i32 * i32::from(TRACK.width)
+            / FINISH_DISTANCE as i32;
+        renderer.fill_rect(
+            &Rect::new_from_x_y(track.x(), track.y(), progress as i16, track.height),
+            runner.color(),
+        );
+    }
+}
+
+// 両方のランナーを同じティックまで進めたときの状態のハッシュ
+pub fn hash_runners(tick: u32, runners: &[Runner]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    tick.hash(&mut hasher);
+    runners
+        .iter()
+        .for_each(|runner| runner.hash_state(&mut hasher));
+    hasher.finish()
+}
+
+// ロビーのカウントダウンのあとに走る二人のレース。
+// 両方の端末が同じシードで二人分の Walk を動かし、やり取りするのは入力とハッシュだけ
+pub struct Race {
+    lockstep: Lockstep,
+    runners: [Runner; 2],
+    local: usize,
+    // 入力を送れないフレームに押したキーを、次に送る入力まで覚えておく
+    pending: TickInput,
+    desync: Option<String>,
+    chat: QuickChat,
+    // 相手から届いた定型メッセージ。相手のランナーの上に出す
+    bubble: Option<EmoteBubble>,
+}
+
+impl Race {
+    // local は自分が何番目のランナーか。両方の端末で同じ順になるように決めて渡す。
+    // mine と rival は読み込んだままの Walk で、どちらも同じシードから同じコースを作る
+    pub fn new(seed: u64, local: usize, mine: Walk, rival: Walk, skins: [&str; 2]) -> Self {
+        let walks = if local == 0 {
+            [mine, rival]
+        } else {
+            [rival, mine]
+        };
+        let [first, second] = walks;
+        Race {
+            lockstep: Lockstep::new(),
+            runners: [
+                Runner::new(first, seed, skins[0]),
+                Runner::new(second, seed, skins[1]),
+            ],
+            local,
+            pending: TickInput::default(),
+            desync: None,
+            chat: QuickChat::new(),
+            bubble: None,
+        }
+    }
+
+    // 次のレースで使えるよう、走らせた Walk を (自分, 相手) で返す
+    pub fn into_walks(self) -> (Walk, Walk) {
+        let [first, second] = self.runners.map(Runner::into_walk);
+        if self.local == 0 {
+            (first, second)
+        } else {
+            (second, first)
+        }
+    }
+
+    pub fn receive(&mut self, message: LockstepMessage) {
+        if let Err(err) = self.lockstep.receive(message) {
+            log!("Race desynced {:#?}", err);
+            self.desync = Some(err.to_string());
+        }
+    }
+
+    // 相手に送るメッセージを返す。相手の入力が届いていなければシミュレーションは止めて待つ
+    pub fn update(&mut self, keystate: &KeyState) -> Vec<LockstepMessage> {
+        let mut outgoing = vec![];
+        // 数字キーの定型メッセージは走り終わったあとも送れる
+        outgoing.extend(self.chat.update(keystate, self.lockstep.tick()));
+        if !self.bubble.as_mut().is_some_and(EmoteBubble::update) {
+            self.bubble = None;
+        }
+        while let Some(emote) = self.lockstep.take_emote() {
+            if let Some(bubble) = self.chat.receive(emote) {
+                self.bubble = Some(bubble);
+            }
+        }
+        if self.is_over() {
+            return outgoing;
+        }
+        self.pending = TickInput {
+            jump: self.pending.jump
+                || keystate.is_pressed(JUMP_KEY)
+                || keystate.is_just_pressed(JUMP_KEY),
+            slide: self.pending.slide
+                || keystate.is_pressed(SLIDE_KEY)
+                || keystate.is_just_pressed(SLIDE_KEY),
+            attack: self.pending.attack || keystate.is_just_pressed(ATTACK_KEY),
+        };
+        if self.lockstep.needs_input() {
+            let input = std::mem::take(&mut self.pending);
+            outgoing.push(self.lockstep.submit(input));
+        }
+        if let Some((local, remote)) = self.lockstep.advance() {
+            let inputs = if self.local == 0 {
+                [local, remote]
+            } else {
+                [remote, local]
+            };
+            let tick = self.lockstep.tick();
+            self.runners
+                .iter_mut()
+                .zip(inputs)
+                .for_each(|(runner, input)| runner.step(input, tick));
+            match self.lockstep.record_hash(hash_runners(tick, &self.runners)) {
+                Ok(Some(message)) => outgoing.push(message),
+                Ok(None) => {}
+                Err(err) => {
+                    log!("Race desynced {:#?}", err);
+                    self.desync = Some(err.to_string());
+                }
+            }
+        }
+        outgoing
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.desync.is_some() || self.runners.iter().all(Runner::is_done)
+    }
+
+    fn status(&self) -> String {
+        let local = &self.runners[self.local];
+        let remote = &self.runners[1 - self.local];
+        match (&self.desync, local.is_done(), remote.is_done()) {
+            (Some(_), _, _) => "DESYNC - press Space".into(),
+            (None, true, true) if local.compare(remote) == Ordering::Less => {
+                "YOU LOSE - press Space".into()
+            }
+            (None, true, true) => "YOU WIN - press Space".into(),
+            (None, true, false) => "Waiting for the other runner".into(),
+            (None, false, _) => format!(
+                "{}m to go",
+                FINISH_DISTANCE.saturating_sub(local.distance())
+            ),
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let local = self.runners[self.local].walk();
+        let remote = &self.runners[1 - self.local];
+        local.draw(renderer);
+        local.draw_rival(renderer, remote.walk(), remote.color());
+        if let Some(bubble) = &self.bubble {
+            bubble.draw(renderer, &local.rival_anchor(remote.walk()));
+        }
+        draw_track(renderer, &self.runners, TRACK.y());
+        renderer.draw_text(&self.status(), &Point { x: 40, y: 60 }, TEXT_COLOR);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    #[cfg(feature = "desktop")]
+    use crate::{analytics::Analytics, config::GameConfig, engine::Audio};
+    #[cfg(feature = "desktop")]
+    use futures::executor::block_on;
+
+    // static/ から本編と同じ Walk を読み込む。画像をデコードできるデスクトップ版でだけ動く
+    #[cfg(feature = "desktop")]
+    pub fn load_walk() -> Walk {
+        block_on(Walk::load(
+            GameConfig::default(),
+            Analytics::new(None, None),
+            Audio::new().unwrap(),
+        ))
+        .unwrap()
+    }
+
+    #[cfg(feature = "desktop")]
+    fn race(local: usize, skins: [&str; 2]) -> Race {
+        Race::new(7, local, load_walk(), load_walk(), skins)
+    }
+
+    #[cfg(feature = "desktop")]
+    fn exchange(first: &mut Race, second: &mut Race, to_second: Vec<LockstepMessage>) {
+        let to_first = second.update(&KeyState::injected(std::iter::empty()));
+        to_second
+            .into_iter()
+            .for_each(|message| second.receive(message));
+        to_first
+            .into_iter()
+            .for_each(|message| first.receive(message));
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn both_sides_of_a_race_run_the_same_walks() {
+        let mut first = race(0, [SKINS[0], SKINS[1]]);
+        let mut second = race(1, [SKINS[0], SKINS[1]]);
+
+        for frame in 0..240 {
+            let jump = if frame % 40 < 20 {
+                vec!["Space"]
+            } else {
+                vec![]
+            };
+            let to_second = first.update(&KeyState::injected(jump.into_iter()));
+            exchange(&mut first, &mut second, to_second);
+        }
+
+        assert!(first.desync.is_none() && second.desync.is_none());
+        assert_eq!(
+            hash_runners(first.lockstep.tick(), &first.runners),
+            hash_runners(second.lockstep.tick(), &second.runners)
+        );
+        assert!(first.runners.iter().all(|runner| runner.distance() > 0));
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn emotes_pop_up_over_the_other_runner() {
+        let mut first = race(0, ["rhb", "rhb"]);
+        let mut second = race(1, ["rhb", "rhb"]);
+
+        let sent = first.update(&KeyState::injected(["Digit2"].iter().copied()));
+        assert!(sent.contains(&LockstepMessage::Emote { tick: 0, emote: 1 }));
+        sent.into_iter().for_each(|message| second.receive(message));
+
+        assert!(second.bubble.is_none());
+        second.update(&KeyState::injected(std::iter::empty()));
+        assert!(second.bubble.is_some());
+    }
+
+    #[test]
+    fn inputs_become_the_keys_the_walk_reads() {
+        let jump = TickInput {
+            jump: true,
+            ..TickInput::default()
+        };
+        assert!(keystate(jump, TickInput::default()).is_just_pressed(JUMP_KEY));
+        assert!(!keystate(jump, jump).is_just_pressed(JUMP_KEY));
+        assert!(keystate(jump, jump).is_pressed(JUMP_KEY));
+    }
+}
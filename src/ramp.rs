@@ -0,0 +1,98 @@
+use crate::config::GameConfig;
+use crate::tween::{lerp, Easing};
+use serde::{Deserialize, Serialize};
+
+// 走った距離 (m) に応じて 0 から max まで上がる量。start までは 0、full から先は max のまま
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RampCurve {
+    pub start: u32,
+    pub full: u32,
+    pub max: i16,
+    pub easing: Easing,
+}
+
+impl RampCurve {
+    // 上がらない曲線。Kid モードなどで使う
+    pub const FLAT: RampCurve = RampCurve {
+        start: 0,
+        full: 0,
+        max: 0,
+        easing: Easing::Linear,
+    };
+
+    pub fn value(&self, distance: u32) -> i16 {
+        if self.max == 0 || distance <= self.start {
+            return 0;
+        }
+        let length = self.full.saturating_sub(self.start).max(1);
+        let t = (distance - self.start) as f32 / length as f32;
+        lerp(0.0, self.max as f32, self.easing.apply(t)).round() as i16
+    }
+}
+
+// ランが進むほど速く、障害物の間を詰めていく
+#[derive(Clone)]
+pub struct DifficultyRamp {
+    speed: RampCurve,
+    spacing: RampCurve,
+    distance: u32,
+}
+
+impl DifficultyRamp {
+    pub fn new(config: &GameConfig) -> Self {
+        DifficultyRamp {
+            speed: config.speed_ramp,
+            spacing: config.spacing_ramp,
+            distance: 0,
+        }
+    }
+
+    pub fn update(&mut self, distance: u32) {
+        self.distance = distance;
+    }
+
+    pub fn running_speed(&self, base: i16) -> i16 {
+        base + self.speed.value(self.distance)
+    }
+
+    pub fn obstacle_buffer(&self, base: i16) -> i16 {
+        (base - self.spacing.value(self.distance)).max(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_ramp_speeds_up_and_tightens_with_distance() {
+        let config = GameConfig {
+            speed_ramp: RampCurve {
+                start: 100,
+                full: 300,
+                max: 4,
+                easing: Easing::Linear,
+            },
+            spacing_ramp: RampCurve {
+                start: 0,
+                full: 400,
+                max: 30,
+                easing: Easing::EaseIn,
+            },
+            ..GameConfig::default()
+        };
+        let mut ramp = DifficultyRamp::new(&config);
+        assert_eq!(ramp.running_speed(4), 4);
+        assert_eq!(ramp.obstacle_buffer(20), 20);
+
+        ramp.update(200);
+        assert_eq!(ramp.running_speed(4), 6);
+        assert_eq!(ramp.obstacle_buffer(20), 16);
+
+        ramp.update(5000);
+        assert_eq!(ramp.running_speed(4), 8);
+        assert_eq!(ramp.obstacle_buffer(20), 0);
+        assert_eq!(RampCurve::FLAT.value(5000), 0);
+    }
+}
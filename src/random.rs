@@ -0,0 +1,81 @@
+use crate::browser;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::cell::RefCell;
+
+const SEED_PARAM: &str = "seed";
+const DAY_MS: f64 = 86_400_000.0;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+// ゴールデンテストでは固定のシードを与えて毎回同じ展開にする
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+// ?seed=1234 (0x で始めれば 16 進) のように付けて開くと、毎回同じ展開のランになる
+pub fn requested_seed() -> Option<u64> {
+    parse_seed(&browser::query_param(SEED_PARAM)?)
+}
+
+// UTC の日付ごとに決まるシード。その日のデイリーは誰が走っても同じ展開になる
+pub fn daily_seed(timestamp: f64) -> u64 {
+    (timestamp / DAY_MS).floor() as u64
+}
+
+fn parse_seed(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// 練習モードのしおり用。戻したあとも同じ順でセグメントが出てくる
+pub fn snapshot() -> StdRng {
+    RNG.with(|rng| rng.borrow().clone())
+}
+
+pub fn restore(snapshot: StdRng) {
+    RNG.with(|rng| *rng.borrow_mut() = snapshot);
+}
+
+// thread_rng の代わりに使う。共有の乱数から派生させるので、シードを固定すれば結果も固定される
+pub fn rng() -> StdRng {
+    RNG.with(|rng| {
+        StdRng::from_rng(&mut *rng.borrow_mut()).expect("Could not derive a random generator")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn restored_snapshot_repeats_the_sequence() {
+        seed(7);
+        let snapshot = snapshot();
+        let first: Vec<u32> = (0..4).map(|_| rng().gen_range(0..100)).collect();
+        restore(snapshot);
+        let second: Vec<u32> = (0..4).map(|_| rng().gen_range(0..100)).collect();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn seeds_are_read_as_decimal_or_hex() {
+        assert_eq!(parse_seed("1234"), Some(1234));
+        assert_eq!(parse_seed(" 0x5EED "), Some(0x5EED));
+        assert_eq!(parse_seed("walk"), None);
+    }
+
+    #[test]
+    fn daily_seed_changes_at_utc_midnight() {
+        let midnight = 20_000.0 * DAY_MS;
+        assert_eq!(daily_seed(midnight), daily_seed(midnight + DAY_MS - 1.0));
+        assert_ne!(daily_seed(midnight), daily_seed(midnight - 1.0));
+    }
+}
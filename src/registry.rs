@@ -0,0 +1,163 @@
+use crate::config::Physics;
+use crate::draw::Bitmap;
+use crate::engine::{self, AlphaMask, Insets, Point, SpriteSheet};
+use crate::segment::{
+    platform_at, BarrierTemplate, Coin, CoinTrail, Enemy, Obstacle, ObstaclePool, Projectile, Saw,
+    Telegraph, FLOATING_PLATFORM,
+};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const COIN_ARC_COUNT: u16 = 7;
+// 種類ごとの速さと予告。速いものほど早めに知らせる
+const FIREBALL_SPEED: i16 = 4;
+const FIREBALL_TELEGRAPH: Telegraph = Telegraph {
+    lead: 360,
+    color: "#FF6A00",
+};
+const ARROW_SPEED: i16 = 9;
+const ARROW_TELEGRAPH: Telegraph = Telegraph {
+    lead: 720,
+    color: "#FF3030",
+};
+// 犬は地面の流れとは別に、この幅を行き来する
+const DOG_PATROL_RANGE: i16 = 160;
+const DOG_SPEED: i16 = 2;
+// 画像から余白を測れなかったときに使う Stone.png の余白
+const STONE_INSETS: Insets = Insets {
+    left: 4,
+    top: 8,
+    right: 4,
+    bottom: 0,
+};
+
+const MASK_CELL_SIZE: i16 = 4;
+
+// 透明な余白は画像のアルファ値から測り、当たり判定を見た目に合わせる
+pub fn stone_template(image: Bitmap) -> BarrierTemplate {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    match engine::image_alpha(&image) {
+        Ok(alpha) => {
+            // 石は形がいびつなので、既定でマスクでも判定する。
+            // "stone_box" で置いた石だけは矩形で判定する
+            BarrierTemplate::new(image, Insets::from_alpha(&alpha, width, height))
+                .with_mask(AlphaMask::from_alpha(&alpha, width, height, MASK_CELL_SIZE))
+                .pixel_perfect(true)
+        }
+        Err(err) => {
+            log!("Could not measure stone insets {:#?}", err);
+            BarrierTemplate::new(image, STONE_INSETS)
+        }
+    }
+}
+
+// 障害物を作るときに参照できる読み込み済みの素材
+pub struct SpawnContext<'a> {
+    pub pool: &'a ObstaclePool,
+    pub stone: &'a BarrierTemplate,
+    pub sheet: &'a Rc<SpriteSheet>,
+    pub enemy_sheet: &'a Rc<SpriteSheet>,
+    pub physics: Physics,
+}
+
+type ObstacleFactory = Box<dyn Fn(&SpawnContext, Point) -> Vec<Box<dyn Obstacle>>>;
+
+// 種類名から障害物を生成する。新しい障害物はここに登録すれば名前で呼び出せる
+pub struct ObstacleRegistry {
+    factories: HashMap<String, ObstacleFactory>,
+}
+
+impl ObstacleRegistry {
+    pub fn new() -> Self {
+        ObstacleRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    pub fn with_builtins<'a>(composites: impl Iterator<Item = &'a str>) -> Self {
+        let mut registry = ObstacleRegistry::new();
+        registry.register("stone", |context, position| {
+            vec![context.pool.barrier(context.stone, position)]
+        });
+        registry.register("stone_box", |context, position| {
+            vec![context.pool.barrier_with(context.stone, position, false)]
+        });
+        registry.register("platform", |context, position| {
+            vec![platform_at(
+                context.pool,
+                context.sheet.clone(),
+                position,
+                FLOATING_PLATFORM,
+            )]
+        });
+        registry.register("saw", |_context, position| {
+            vec![Box::new(Saw::new(position))]
+        });
+        registry.register("coin", |_context, position| {
+            vec![Box::new(Coin::new(position))]
+        });
+        registry.register("fireball", |_context, position| {
+            vec![Box::new(Projectile::new(
+                position,
+                FIREBALL_SPEED,
+                Some(FIREBALL_TELEGRAPH),
+            ))]
+        });
+        registry.register("arrow", |_context, position| {
+            vec![Box::new(Projectile::new(
+                position,
+                ARROW_SPEED,
+                Some(ARROW_TELEGRAPH),
+            ))]
+        });
+        registry.register("dog", |context, position| {
+            vec![Box::new(Enemy::new(
+                context.enemy_sheet.clone(),
+                position,
+                DOG_PATROL_RANGE,
+                DOG_SPEED,
+            ))]
+        });
+        registry.register("coin_arc", |context, position| {
+            CoinTrail::Arc {
+                from: position,
+                count: COIN_ARC_COUNT,
+            }
+            .coins(&context.physics)
+        });
+        // タイルのメタデータにある足場の形はそのままの名前で置ける
+        composites.for_each(|composite| {
+            let name = composite.to_string();
+            registry.register(composite, move |context, position| {
+                vec![platform_at(
+                    context.pool,
+                    context.sheet.clone(),
+                    position,
+                    &name,
+                )]
+            });
+        });
+        registry
+    }
+
+    pub fn register(
+        &mut self,
+        name: &str,
+        factory: impl Fn(&SpawnContext, Point) -> Vec<Box<dyn Obstacle>> + 'static,
+    ) {
+        self.factories.insert(name.into(), Box::new(factory));
+    }
+
+    pub fn spawn(
+        &self,
+        name: &str,
+        context: &SpawnContext,
+        position: Point,
+    ) -> Result<Vec<Box<dyn Obstacle>>> {
+        self.factories
+            .get(name)
+            .map(|factory| factory(context, position))
+            .ok_or_else(|| anyhow!("Unknown obstacle type {}", name))
+    }
+}
@@ -0,0 +1,133 @@
+use crate::engine::KeyState;
+use serde::{Deserialize, Serialize};
+
+// 記録するキーと、押し始めだけを見るかどうか。ビットの位置は並び順のまま
+const KEYS: [(&str, bool); 3] = [("Space", false), ("ArrowDown", false), ("KeyX", true)];
+
+// 押しているキーが変わったフレームと、そのときのキーのビット
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct InputChange {
+    pub frame: u32,
+    pub keys: u8,
+}
+
+fn keys_of(keystate: &KeyState) -> u8 {
+    KEYS.iter()
+        .enumerate()
+        .filter(|(_, (code, just))| {
+            if *just {
+                keystate.is_just_pressed(code)
+            } else {
+                keystate.is_pressed(code)
+            }
+        })
+        .fold(0, |keys, (bit, _)| keys | 1 << bit)
+}
+
+// 走っている間の入力を、変わったところだけ残す
+#[derive(Clone, Default)]
+pub struct InputRecorder {
+    frame: u32,
+    keys: u8,
+    changes: Vec<InputChange>,
+}
+
+impl InputRecorder {
+    pub fn record(&mut self, keystate: &KeyState) {
+        let keys = keys_of(keystate);
+        if keys != self.keys {
+            self.changes.push(InputChange {
+                frame: self.frame,
+                keys,
+            });
+            self.keys = keys;
+        }
+        self.frame += 1;
+    }
+
+    pub fn changes(&self) -> &[InputChange] {
+        &self.changes
+    }
+}
+
+// 記録した入力を同じシードのランへ流し直す
+pub struct Replay {
+    seed: u64,
+    changes: Vec<InputChange>,
+    frame: u32,
+    next: usize,
+    keys: u8,
+}
+
+impl Replay {
+    pub fn new(seed: u64, changes: Vec<InputChange>) -> Self {
+        Replay {
+            seed,
+            changes,
+            frame: 0,
+            next: 0,
+            keys: 0,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // このフレームで押されていたキーだけを押したことにした入力。
+    // 押しっぱなしのキーは、押し始めたフレームだけ押した瞬間として扱う
+    pub fn next_keystate(&mut self) -> KeyState {
+        let previous = self.keys;
+        while let Some(change) = self
+            .changes
+            .get(self.next)
+            .filter(|change| change.frame <= self.frame)
+        {
+            self.keys = change.keys;
+            self.next += 1;
+        }
+        self.frame += 1;
+        let keys = self.keys;
+        let pressed = KEYS
+            .iter()
+            .enumerate()
+            .filter(move |(bit, _)| keys & 1 << bit != 0);
+        KeyState::replayed(
+            pressed.clone().map(|(_, (code, _))| *code),
+            pressed
+                .filter(|(bit, (_, just))| *just || previous & 1 << bit == 0)
+                .map(|(_, (code, _))| *code),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_repeats_the_recorded_keys() {
+        let frames: [&[&str]; 5] = [&[], &["Space"], &["Space"], &["ArrowDown", "KeyX"], &[]];
+        let mut recorder = InputRecorder::default();
+        frames
+            .iter()
+            .for_each(|codes| recorder.record(&KeyState::injected(codes.iter().copied())));
+        assert_eq!(recorder.changes().len(), 3);
+
+        let mut replay = Replay::new(7, recorder.changes().to_vec());
+        frames.iter().for_each(|codes| {
+            let keystate = replay.next_keystate();
+            KEYS.iter().for_each(|(code, _)| {
+                assert_eq!(keystate.is_pressed(code), codes.contains(code));
+            });
+        });
+
+        // 押し続けた Space は、押し始めのフレームだけ押した瞬間になる
+        let mut replay = Replay::new(7, recorder.changes().to_vec());
+        let just: Vec<bool> = frames
+            .iter()
+            .map(|_| replay.next_keystate().is_just_pressed("Space"))
+            .collect();
+        assert_eq!(just, vec![false, true, false, false, false]);
+    }
+}
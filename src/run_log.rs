@@ -0,0 +1,138 @@
+use crate::engine::{Point, Rect, Renderer};
+
+// 障害物の上をこれより低く越えたらきわどかったことにする
+pub const NEAR_MISS_CLEARANCE: i16 = 24;
+
+const STRIP_COLOR: &str = "rgba(0, 0, 0, 0.6)";
+const FRAME_COLOR: &str = "#FFFFFF";
+const BUCKETS: usize = 40;
+const MARKER_HEIGHT: i16 = 6;
+const DEATH_RADIUS: i16 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunEventKind {
+    Jump,
+    Slide,
+    NearMiss,
+    Death,
+}
+
+impl RunEventKind {
+    fn color(&self) -> &'static str {
+        match self {
+            RunEventKind::Jump => "#7FD8FF",
+            RunEventKind::Slide => "#FFD23F",
+            RunEventKind::NearMiss => "#FF9F40",
+            RunEventKind::Death => "#FF5050",
+        }
+    }
+
+    // 帯の中でどの段に印を付けるか
+    fn row(&self) -> i16 {
+        match self {
+            RunEventKind::Jump => 0,
+            RunEventKind::Slide => 1,
+            RunEventKind::NearMiss | RunEventKind::Death => 2,
+        }
+    }
+}
+
+// distance は起きたときに走っていた距離 (m)
+#[derive(Clone, Copy, Debug)]
+pub struct RunEvent {
+    pub kind: RunEventKind,
+    pub distance: u32,
+}
+
+// ランの途中で何が起きたかの記録。終わったあとに縮めた帯にして見せる
+#[derive(Clone, Default)]
+pub struct RunLog {
+    events: Vec<RunEvent>,
+}
+
+impl RunLog {
+    pub fn push(&mut self, kind: RunEventKind, distance: u32) {
+        self.events.push(RunEvent { kind, distance });
+    }
+
+    // length までを buckets 等分して、それぞれで起きた出来事を数える。障害物の多かったところほど多くなる
+    fn heat(&self, length: u32, buckets: usize) -> Vec<u32> {
+        let mut heat = vec![0; buckets];
+        let length = length.max(1);
+        self.events.iter().for_each(|event| {
+            let bucket = (event.distance.min(length - 1) as usize * buckets) / length as usize;
+            heat[bucket] += 1;
+        });
+        heat
+    }
+
+    pub fn draw_strip(&self, renderer: &Renderer, strip: &Rect, length: u32) {
+        renderer.fill_rect(strip, STRIP_COLOR);
+        let heat = self.heat(length, BUCKETS);
+        let hottest = heat.iter().copied().max().unwrap_or(0).max(1);
+        let bucket_width = strip.width / BUCKETS as i16;
+        heat.iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .for_each(|(index, count)| {
+                renderer.fill_rect(
+                    &Rect::new_from_x_y(
+                        strip.x() + index as i16 * bucket_width,
+                        strip.y(),
+                        bucket_width,
+                        strip.height,
+                    ),
+                    format!(
+                        "rgba(255, 80, 80, {:.2})",
+                        0.5 * *count as f32 / hottest as f32
+                    ),
+                );
+            });
+
+        let x_at = |distance: u32| {
+            strip.x()
+                + (distance.min(length) as f32 / length.max(1) as f32 * strip.width as f32) as i16
+        };
+        let row_height = strip.height / 3;
+        self.events.iter().for_each(|event| {
+            let x = x_at(event.distance);
+            match event.kind {
+                RunEventKind::Death => renderer.fill_circle(
+                    &Point {
+                        x,
+                        y: strip.y() + strip.height / 2,
+                    },
+                    DEATH_RADIUS,
+                    event.kind.color(),
+                ),
+                kind => renderer.fill_rect(
+                    &Rect::new_from_x_y(
+                        x,
+                        strip.y() + kind.row() * row_height + (row_height - MARKER_HEIGHT) / 2,
+                        1,
+                        MARKER_HEIGHT,
+                    ),
+                    kind.color(),
+                ),
+            }
+        });
+        renderer.stroke_rect(strip, FRAME_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heat_counts_events_along_the_run() {
+        let mut log = RunLog::default();
+        log.push(RunEventKind::Jump, 5);
+        log.push(RunEventKind::NearMiss, 8);
+        log.push(RunEventKind::Slide, 55);
+        log.push(RunEventKind::Death, 100);
+
+        assert_eq!(log.heat(100, 4), vec![2, 0, 1, 1]);
+        assert_eq!(RunLog::default().heat(0, 2), vec![0, 0]);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{Point, Renderer};
+
+// 背景が 1 メートル分流れるのにかかるピクセル数
+const PIXELS_PER_METER: u32 = 50;
+// 右端に揃える
+const HUD_POSITION: Point = Point { x: 580, y: 30 };
+const HUD_LINE_HEIGHT: i16 = 24;
+const HUD_COLOR: &str = "#FFFFFF";
+
+// 走った距離と、障害物を抜けたときのボーナスを合わせたスコア
+#[derive(Clone, Default)]
+pub struct Score {
+    traveled: u32,
+    bonus: u32,
+}
+
+impl Score {
+    pub fn new() -> Self {
+        Score::default()
+    }
+
+    // velocity は背景の流れる向き。左へ流れている分だけ進んだことにする
+    pub fn travel(&mut self, velocity: i16) {
+        self.traveled += (-velocity).max(0) as u32;
+    }
+
+    pub fn add_bonus(&mut self, points: u32) {
+        self.bonus += points;
+    }
+
+    // 背景が流れたピクセル数
+    pub fn traveled(&self) -> u32 {
+        self.traveled
+    }
+
+    pub fn distance(&self) -> u32 {
+        self.traveled / PIXELS_PER_METER
+    }
+
+    pub fn total(&self) -> u32 {
+        self.distance() + self.bonus
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let style = TextStyle::new(HUD_COLOR).align(TextAlign::Right);
+        renderer.draw_text(
+            &format!("Score {}", self.total()),
+            &HUD_POSITION,
+            style.clone(),
+        );
+        renderer.draw_text(
+            &format!("{} m", self.distance()),
+            &Point {
+                x: HUD_POSITION.x,
+                y: HUD_POSITION.y + HUD_LINE_HEIGHT,
+            },
+            style,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_adds_distance_and_bonus() {
+        let mut score = Score::new();
+        (0..25).for_each(|_| score.travel(-4));
+        score.travel(3);
+        score.add_bonus(10);
+
+        assert_eq!(score.distance(), 2);
+        assert_eq!(score.total(), 12);
+    }
+}
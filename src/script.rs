@@ -0,0 +1,180 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+// スクリプトから見えるゲームの様子。毎ティック、タスクを進める前に書き込む
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScriptView {
+    pub distance: u32,
+    pub boy_x: i16,
+    // 少年の走る速さ。止まっていれば 0
+    pub speed: i16,
+    pub running: bool,
+}
+
+// スクリプトからゲームへの頼みごと。ゲームループがまとめて処理する
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScriptCommand {
+    // 登録名の障害物を画面の右の外、height の高さに置く
+    Spawn { name: &'static str, height: i16 },
+    // 少年の足をだんだん緩めて止める
+    Decelerate,
+    // 止まった少年を待機の姿にして喜ばせる
+    Celebrate,
+    Confetti,
+    // 走りを終えて結果の画面に移る
+    ShowResults,
+}
+
+#[derive(Default)]
+struct Shared {
+    tick: Cell<u32>,
+    view: Cell<ScriptView>,
+    commands: RefCell<Vec<ScriptCommand>>,
+}
+
+// タスクに渡す窓口。待つ・見る・頼むだけができる
+#[derive(Clone)]
+pub struct Script {
+    shared: Rc<Shared>,
+}
+
+impl Script {
+    pub fn view(&self) -> ScriptView {
+        self.shared.view.get()
+    }
+
+    // ゲームのティックで数える。一時停止中は進まない
+    pub fn wait(&self, ticks: u32) -> impl Future<Output = ()> {
+        let until = self.shared.tick.get() + ticks;
+        let shared = self.shared.clone();
+        WaitUntil {
+            shared: self.shared.clone(),
+            condition: move |_view: &ScriptView| shared.tick.get() >= until,
+        }
+    }
+
+    pub fn wait_until(
+        &self,
+        condition: impl FnMut(&ScriptView) -> bool + Unpin,
+    ) -> impl Future<Output = ()> {
+        WaitUntil {
+            shared: self.shared.clone(),
+            condition,
+        }
+    }
+
+    pub fn spawn(&self, name: &'static str, height: i16) {
+        self.request(ScriptCommand::Spawn { name, height });
+    }
+
+    pub fn request(&self, command: ScriptCommand) {
+        self.shared.commands.borrow_mut().push(command);
+    }
+}
+
+struct WaitUntil<F> {
+    shared: Rc<Shared>,
+    condition: F,
+}
+
+impl<F: FnMut(&ScriptView) -> bool + Unpin> Future for WaitUntil<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _context: &mut Context) -> Poll<()> {
+        let view = self.shared.view.get();
+        if (self.condition)(&view) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+// 「30 ティック待って鳥を出し、少年が 500 を越えたら…」のような流れを、
+// 状態のフラグを持たずに async のまま書けるようにする。
+// 起こされるのを待つのではなく、ゲームループが毎ティック全部のタスクを進める
+#[derive(Default)]
+pub struct Scheduler {
+    shared: Rc<Shared>,
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    pub fn start<F: Future<Output = ()> + 'static>(&mut self, script: impl FnOnce(Script) -> F) {
+        let task = script(Script {
+            shared: self.shared.clone(),
+        });
+        self.tasks.push(Box::pin(task));
+    }
+
+    // ゲーム時間を 1 ティック進めてタスクを走らせ、その間に頼まれたことを返す
+    pub fn tick(&mut self, view: ScriptView) -> Vec<ScriptCommand> {
+        self.shared.tick.set(self.shared.tick.get() + 1);
+        self.shared.view.set(view);
+        let waker = noop_waker();
+        let mut context = Context::from_waker(&waker);
+        self.tasks
+            .retain_mut(|task| task.as_mut().poll(&mut context).is_pending());
+        self.shared.commands.take()
+    }
+
+    // 途中のタスクは写し取れないので、しおりに戻るときは捨てる
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+        self.shared.commands.take();
+    }
+}
+
+// 毎ティック全部を進めるので、起こされても何もしなくてよい
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn noop_waker() -> Waker {
+    Waker::from(Arc::new(NoopWake))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_advance_with_game_ticks_and_conditions() {
+        let mut scheduler = Scheduler::default();
+        scheduler.start(|script| async move {
+            script.wait(2).await;
+            script.spawn("fireball", 500);
+            script.wait_until(|view| view.boy_x > 300).await;
+            script.spawn("arrow", 450);
+        });
+        let view = ScriptView {
+            distance: 0,
+            boy_x: 100,
+            ..ScriptView::default()
+        };
+
+        // 最初に進めたティックから数える
+        assert!(scheduler.tick(view).is_empty());
+        assert!(scheduler.tick(view).is_empty());
+        assert_eq!(
+            scheduler.tick(view),
+            vec![ScriptCommand::Spawn {
+                name: "fireball",
+                height: 500
+            }]
+        );
+        assert!(scheduler.tick(view).is_empty());
+
+        let past = ScriptView { boy_x: 301, ..view };
+        assert_eq!(scheduler.tick(past).len(), 1);
+        assert!(scheduler.tasks.is_empty());
+    }
+}
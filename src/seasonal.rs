@@ -0,0 +1,69 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, PartialOrd)]
+pub struct MonthDay {
+    pub month: u8,
+    pub day: u8,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SkinAssets {
+    pub sheet: String,
+    pub image: String,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SeasonalEvent {
+    pub name: String,
+    pub start: MonthDay,
+    pub end: MonthDay,
+    pub background: Option<String>,
+    pub skin: Option<SkinAssets>,
+    pub obstacle_skin: Option<SkinAssets>,
+    #[serde(default = "default_multiplier")]
+    pub collectible_multiplier: u32,
+}
+
+fn default_multiplier() -> u32 {
+    1
+}
+
+impl SeasonalEvent {
+    // 年をまたぐ期間 (12/20 〜 1/5 など) も扱う
+    fn is_active(&self, today: MonthDay) -> bool {
+        if self.start <= self.end {
+            self.start <= today && today <= self.end
+        } else {
+            self.start <= today || today <= self.end
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct SeasonalCalendar {
+    events: Vec<SeasonalEvent>,
+}
+
+impl SeasonalCalendar {
+    pub fn active(&self, today: MonthDay) -> Option<&SeasonalEvent> {
+        self.events.iter().find(|event| event.is_active(today))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_spanning_new_year_are_active_on_both_sides() {
+        let calendar: SeasonalCalendar = serde_json::from_str(
+            r#"{"events": [{"name": "winter", "start": {"month": 12, "day": 20}, "end": {"month": 1, "day": 5}}]}"#,
+        )
+        .unwrap();
+
+        assert!(calendar.active(MonthDay { month: 12, day: 31 }).is_some());
+        assert!(calendar.active(MonthDay { month: 1, day: 2 }).is_some());
+        assert!(calendar.active(MonthDay { month: 6, day: 1 }).is_none());
+    }
+}
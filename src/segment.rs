@@ -1,60 +1,230 @@
-use crate::engine::{Cell, Image, Point, Rect, Renderer, SpriteSheet};
+use crate::beat_map::{BeatEventKind, BeatMap};
+use crate::config::Physics;
+use crate::draw::Bitmap;
+use crate::engine::{
+    AlphaMask, Animation, Image, Insets, Layer, Point, Rect, Renderer, SpriteHandle, SpriteSheet,
+};
+use crate::pool::{Pool, PoolStats};
+use crate::power_up::PowerUpKind;
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use web_sys::HtmlImageElement;
 
 // 障害物とインタラクトするオブジェクトが実装するトレイト
 pub trait Disturbee {
+    // 体の当たり判定。ここに障害物が触れると倒れる
     fn bounding_box(&self) -> Rect;
     fn velocity_y(&self) -> i16;
     fn pos_y(&self) -> i16;
     fn land_on(&mut self, pos: i16);
     fn knock_out(&mut self);
+    fn collect(&mut self) {}
+    fn power_up(&mut self, _kind: PowerUpKind) {}
+    // レベルの終わりのゴールの線を越えた
+    fn cross_finish(&mut self) {}
+    // 攻撃の当たり判定。攻撃中だけ返し、触れた障害物を壊す
+    fn hitbox(&self) -> Option<Rect> {
+        None
+    }
+}
+
+// 攻撃と体のどちらが障害物に触れたか。両方触れていれば攻撃を優先し、体は傷を受けない
+#[derive(Debug, PartialEq)]
+pub enum Contact {
+    Hit,
+    Hurt,
+}
+
+impl Contact {
+    pub fn between(disturbee: &dyn Disturbee, touches: impl Fn(&Rect) -> bool) -> Option<Contact> {
+        if disturbee.hitbox().is_some_and(|hitbox| touches(&hitbox)) {
+            Some(Contact::Hit)
+        } else if touches(&disturbee.bounding_box()) {
+            Some(Contact::Hurt)
+        } else {
+            None
+        }
+    }
 }
 
 pub trait Obstacle {
     fn check_intersection(&self, disturbee: &mut dyn Disturbee);
     fn draw(&self, renderer: &Renderer);
     fn move_horizontally(&mut self, x: i16);
+    // 当たり判定の届く横の範囲。左右に動くものは動く範囲全体を返す
+    fn left(&self) -> i16;
     fn right(&self) -> i16;
+    fn set_sheet(&mut self, _sheet: Rc<SpriteSheet>) {}
+    // x の位置での地面の高さ。起伏のない障害物は None
+    fn ground_at(&self, _x: i16) -> Option<i16> {
+        None
+    }
+    // デバッグ用。point に重なっていれば状態を説明する文字列を返す
+    fn inspect(&self, _point: &Point) -> Option<String> {
+        None
+    }
+    // 画面外から速く飛んでくるものは、入ってくる前に当たり判定と予告の設定を返す
+    fn telegraph(&self) -> Option<(Rect, Telegraph)> {
+        None
+    }
+    // 画面の左端から出ていくときに入る点
+    fn bonus(&self) -> u32 {
+        0
+    }
+    // 練習モードのしおり用に今の状態を丸ごと写す
+    fn snapshot(&self) -> Box<dyn Obstacle>;
+    // 跳び越えないと倒れる、地面に置かれた障害物の当たり判定
+    fn ground_hazard(&self) -> Option<Rect> {
+        None
+    }
+    // 履歴に残す、少年を倒したものの名前
+    fn cause(&self) -> &'static str {
+        "Obstacle"
+    }
+    // 重なり順。ふつうは少年の後ろに描く
+    fn layer(&self) -> Layer {
+        Layer::Obstacles
+    }
+    // 磁石が効いている間、target の近くにある拾い物を引き寄せる
+    fn attract(&mut self, _target: &Point) {}
+    // 拍に合わせて動くものへ、毎フレーム音の時計 (秒) を渡す
+    fn sync(&mut self, _clock: f64) {}
+    // 画面から出て使い終わった。使い回せるものはプールに返す
+    fn recycle(self: Box<Self>, _pool: &ObstaclePool) {}
+}
+
+// セグメントを作るたびに箱と当たり判定の Vec を確保し直さないよう、石と足場を取っておく
+const POOLED_OBSTACLES: usize = 16;
+
+pub struct ObstaclePool {
+    barriers: RefCell<Pool<Barrier>>,
+    platforms: RefCell<Pool<Platform>>,
+}
+
+impl Default for ObstaclePool {
+    fn default() -> Self {
+        ObstaclePool {
+            barriers: RefCell::new(Pool::with_capacity(POOLED_OBSTACLES)),
+            platforms: RefCell::new(Pool::with_capacity(POOLED_OBSTACLES)),
+        }
+    }
 }
 
+impl ObstaclePool {
+    pub fn barrier(&self, template: &BarrierTemplate, position: Point) -> Box<dyn Obstacle> {
+        self.barrier_with(template, position, template.pixel_perfect)
+    }
+
+    // 雛形の既定とは別に、この障害物だけ判定の細かさを決める
+    pub fn barrier_with(
+        &self,
+        template: &BarrierTemplate,
+        position: Point,
+        pixel_perfect: bool,
+    ) -> Box<dyn Obstacle> {
+        let build = || {
+            let mut barrier = template.at(position);
+            barrier.set_pixel_perfect(pixel_perfect);
+            barrier
+        };
+        self.barriers
+            .borrow_mut()
+            .take(build, |barrier| *barrier = build())
+    }
+
+    fn platform(
+        &self,
+        sheet: Rc<SpriteSheet>,
+        position: Point,
+        composite: &str,
+    ) -> Box<dyn Obstacle> {
+        self.platforms.borrow_mut().take(
+            || Platform::new(sheet.clone(), position, composite),
+            |platform| platform.reset(sheet.clone(), position, composite),
+        )
+    }
+
+    pub fn recycle(&self, obstacle: Box<dyn Obstacle>) {
+        obstacle.recycle(self);
+    }
+
+    // 石と足場の順
+    pub fn stats(&self) -> (PoolStats, PoolStats) {
+        (
+            self.barriers.borrow().stats(),
+            self.platforms.borrow().stats(),
+        )
+    }
+}
+
+// 右端に出す「!」の設定。lead は画面の右端から何ピクセル先にいるうちに知らせるか
+#[derive(Clone, Copy, Debug)]
+pub struct Telegraph {
+    pub lead: i16,
+    pub color: &'static str,
+}
+
+const TELEGRAPH_MARGIN: i16 = 20;
+
+impl Telegraph {
+    // view は画面に映っている範囲。印の位置は画面上の座標で、高さは target に合わせる
+    pub fn marker(&self, target: &Rect, view: &Rect) -> Option<Point> {
+        let distance = target.x() - view.right();
+        (distance > 0 && distance <= self.lead).then(|| Point {
+            x: view.width - TELEGRAPH_MARGIN,
+            y: (target.y() + target.height / 2 - view.y())
+                .clamp(TELEGRAPH_MARGIN, view.height - TELEGRAPH_MARGIN),
+        })
+    }
+}
+
+#[derive(Clone)]
 struct Platform {
     sheet: Rc<SpriteSheet>,
     position: Point,
     bounding_boxes: Vec<Rect>,
-    sprites: Vec<Cell>,
+    composite: String,
+    sprites: Vec<SpriteHandle>,
 }
 
 impl Platform {
-    fn new(
-        sheet: Rc<SpriteSheet>,
-        position: Point,
-        sprite_names: &[&str],
-        bounding_boxes: &[Rect],
-    ) -> Self {
-        let sprites = sprite_names
-            .iter()
-            .filter_map(|sprite_name| sheet.cell(sprite_name).cloned())
-            .collect();
-
-        let bounding_boxes = bounding_boxes
-            .iter()
-            .map(|bounding_box| {
-                Rect::new_from_x_y(
-                    bounding_box.x() + position.x,
-                    bounding_box.y() + position.y,
-                    bounding_box.width,
-                    bounding_box.height,
-                )
-            })
-            .collect();
+    fn new(sheet: Rc<SpriteSheet>, position: Point, composite: &str) -> Self {
+        let mut platform = Platform {
+            sheet: sheet.clone(),
+            position,
+            composite: String::new(),
+            sprites: vec![],
+            bounding_boxes: vec![],
+        };
+        platform.reset(sheet, position, composite);
+        platform
+    }
 
-        Platform {
-            sheet: sheet,
-            position: position,
-            sprites: sprites,
-            bounding_boxes: bounding_boxes,
+    // プールから出したときに置き直す。確保済みの Vec と String はそのまま使う
+    fn reset(&mut self, sheet: Rc<SpriteSheet>, position: Point, composite: &str) {
+        self.sprites.clear();
+        self.bounding_boxes.clear();
+        match sheet.composite(composite) {
+            Some(found) => {
+                self.sprites.extend_from_slice(found.handles());
+                self.bounding_boxes
+                    .extend(found.bounding_boxes().iter().map(|bounding_box| {
+                        Rect::new_from_x_y(
+                            bounding_box.x() + position.x,
+                            bounding_box.y() + position.y,
+                            bounding_box.width,
+                            bounding_box.height,
+                        )
+                    }));
+            }
+            None => {
+                log!("Sheet is missing composite {}", composite);
+            }
         }
+        self.composite.clear();
+        self.composite.push_str(composite);
+        self.sheet = sheet;
+        self.position = position;
     }
 
     fn bounding_boxes(&self) -> &Vec<Rect> {
@@ -76,22 +246,13 @@ impl Obstacle for Platform {
     fn draw(&self, renderer: &Renderer) {
         let mut x = 0;
         self.sprites.iter().for_each(|sprite| {
-            self.sheet.draw(
+            let frame = &self.sheet.cell_at(*sprite).frame;
+            self.sheet.draw_handle(
                 renderer,
-                &Rect::new_from_x_y(
-                    sprite.frame.x,
-                    sprite.frame.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-                &Rect::new_from_x_y(
-                    self.position.x + x,
-                    self.position.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
+                *sprite,
+                &Rect::new_from_x_y(self.position.x + x, self.position.y, frame.w, frame.h),
             );
-            x += sprite.frame.w;
+            x += frame.w;
         });
 
         self.bounding_boxes.iter().for_each(|bb| {
@@ -116,112 +277,1476 @@ impl Obstacle for Platform {
         }
     }
 
+    // 同じ形のないシートに差し替えるとハンドルがずれるので、その場合は元のシートを使い続ける
+    fn set_sheet(&mut self, sheet: Rc<SpriteSheet>) {
+        if let Some(composite) = sheet.composite(&self.composite) {
+            self.sprites = composite.handles().to_vec();
+            self.sheet = sheet;
+        }
+    }
+
+    fn left(&self) -> i16 {
+        self.bounding_boxes
+            .iter()
+            .map(|bounding_box| bounding_box.x())
+            .min()
+            .unwrap_or(self.position.x)
+    }
+
     fn right(&self) -> i16 {
         self.bounding_boxes()
             .last()
             .unwrap_or(&Rect::default())
             .right()
     }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn cause(&self) -> &'static str {
+        "Platform"
+    }
+
+    fn recycle(self: Box<Self>, pool: &ObstaclePool) {
+        pool.platforms.borrow_mut().give_back(self);
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.bounding_boxes
+            .iter()
+            .any(|bounding_box| bounding_box.contains(point))
+            .then(|| {
+                format!(
+                    "Platform {} at {:?} boxes {:?}",
+                    self.composite, self.position, self.bounding_boxes
+                )
+            })
+    }
 }
 
+#[derive(Clone)]
 pub struct Barrier {
     image: Image,
+    insets: Insets,
+    mask: Option<Rc<AlphaMask>>,
+    // true ならマスクでも当たり判定をする。マスクがなければ矩形だけで判定する
+    pixel_perfect: bool,
+    broken: Cell<bool>,
 }
 
 impl Barrier {
-    pub fn new(image: Image) -> Self {
-        Barrier { image }
+    pub fn new(image: Image, insets: Insets) -> Self {
+        Barrier {
+            image,
+            insets,
+            mask: None,
+            pixel_perfect: false,
+            broken: Cell::new(false),
+        }
+    }
+
+    pub fn set_pixel_perfect(&mut self, pixel_perfect: bool) {
+        self.pixel_perfect = pixel_perfect;
+    }
+
+    fn hitbox(&self) -> Rect {
+        self.image.bounding_box().inset(&self.insets)
+    }
+
+    // 画素単位の判定では、矩形が重なったあとに不透明なマスとの重なりも確かめる
+    fn hits(&self, rect: &Rect) -> bool {
+        if !rect.intersects(&self.hitbox()) {
+            return false;
+        }
+        match (&self.mask, self.pixel_perfect) {
+            (Some(mask), true) => mask.overlaps(&self.image.bounding_box().position, rect),
+            _ => true,
+        }
+    }
+}
+
+// 同じ画像と当たり判定の余白を持つ Barrier をいくつも置くための雛形
+#[derive(Clone)]
+pub struct BarrierTemplate {
+    image: Bitmap,
+    insets: Insets,
+    mask: Option<Rc<AlphaMask>>,
+    // 作る Barrier の pixel_perfect の初期値
+    pixel_perfect: bool,
+}
+
+impl BarrierTemplate {
+    pub fn new(image: Bitmap, insets: Insets) -> Self {
+        BarrierTemplate {
+            image,
+            insets,
+            mask: None,
+            pixel_perfect: false,
+        }
+    }
+
+    // 読み込み時に作ったマスクを、この雛形から作る Barrier で共有する
+    pub fn with_mask(self, mask: AlphaMask) -> Self {
+        BarrierTemplate {
+            mask: Some(Rc::new(mask)),
+            ..self
+        }
+    }
+
+    // 形の入り組んだ障害物向けに、画素単位に近い当たり判定を既定にする
+    pub fn pixel_perfect(self, pixel_perfect: bool) -> Self {
+        BarrierTemplate {
+            pixel_perfect,
+            ..self
+        }
+    }
+
+    pub fn at(&self, position: Point) -> Barrier {
+        Barrier {
+            mask: self.mask.clone(),
+            pixel_perfect: self.pixel_perfect,
+            ..Barrier::new(Image::new(self.image.clone(), position), self.insets)
+        }
     }
 }
 
 impl Obstacle for Barrier {
     fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
-        if disturbee
-            .bounding_box()
-            .intersects(self.image.bounding_box())
-        {
-            disturbee.knock_out();
+        if self.broken.get() {
+            return;
+        }
+        match Contact::between(disturbee, |rect| self.hits(rect)) {
+            Some(Contact::Hit) => self.broken.set(true),
+            Some(Contact::Hurt) => disturbee.knock_out(),
+            None => {}
         }
     }
 
     fn draw(&self, renderer: &Renderer) {
-        self.image.draw(renderer);
+        if !self.broken.get() {
+            self.image.draw(renderer);
+        }
     }
 
     fn move_horizontally(&mut self, x: i16) {
         self.image.move_horizontally(x)
     }
 
+    fn left(&self) -> i16 {
+        self.image.bounding_box().x()
+    }
+
     fn right(&self) -> i16 {
         self.image.right()
     }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn bonus(&self) -> u32 {
+        BARRIER_BONUS
+    }
+
+    fn cause(&self) -> &'static str {
+        "Barrier"
+    }
+
+    fn recycle(self: Box<Self>, pool: &ObstaclePool) {
+        pool.barriers.borrow_mut().give_back(self);
+    }
+
+    fn ground_hazard(&self) -> Option<Rect> {
+        (!self.broken.get()).then(|| self.hitbox())
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.image.bounding_box().contains(point).then(|| {
+            format!(
+                "Barrier at {:?} hitbox {:?} mask {} pixel perfect {} broken {}",
+                self.image.bounding_box().position,
+                self.hitbox(),
+                self.mask.is_some(),
+                self.pixel_perfect,
+                self.broken.get()
+            )
+        })
+    }
+}
+
+const GROUND_LEVEL: i16 = 600;
+const GROUND_COLOR: &str = "#6B4A2B";
+const GROUND_STRIP_WIDTH: i16 = 8;
+
+// 地面の起伏。x は区間の先頭からの距離、rise は通常の地面からの高さ
+#[derive(Clone)]
+pub struct GroundProfile {
+    points: Vec<(i16, i16)>,
+}
+
+impl GroundProfile {
+    pub fn new(points: Vec<(i16, i16)>) -> Self {
+        GroundProfile { points }
+    }
+
+    // 点の間は直線で補間する。同じ x に二点あれば段差として後の点を使う
+    fn rise_at(&self, x: i16) -> Option<i16> {
+        let first = self.points.first()?;
+        let last = self.points.last()?;
+        if x < first.0 || x > last.0 {
+            return None;
+        }
+
+        self.points
+            .windows(2)
+            .rev()
+            .find(|pair| pair[0].0 <= x && x <= pair[1].0)
+            .map(|pair| {
+                let (left, right) = (pair[0], pair[1]);
+                if right.0 == left.0 {
+                    right.1
+                } else {
+                    left.1 + (right.1 - left.1) * (x - left.0) / (right.0 - left.0)
+                }
+            })
+            .or(Some(first.1))
+    }
+
+    fn width(&self) -> i16 {
+        self.points.last().map(|point| point.0).unwrap_or(0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Ground {
+    x: i16,
+    profile: GroundProfile,
+}
+
+impl Ground {
+    pub fn new(x: i16, profile: GroundProfile) -> Self {
+        Ground { x, profile }
+    }
+}
+
+impl Obstacle for Ground {
+    fn check_intersection(&self, _disturbee: &mut dyn Disturbee) {}
+
+    fn draw(&self, renderer: &Renderer) {
+        (0..self.profile.width())
+            .step_by(GROUND_STRIP_WIDTH as usize)
+            .filter_map(|x| self.profile.rise_at(x).map(|rise| (x, rise)))
+            .filter(|(_, rise)| *rise > 0)
+            .for_each(|(x, rise)| {
+                renderer.fill_rect(
+                    &Rect::new_from_x_y(self.x + x, GROUND_LEVEL - rise, GROUND_STRIP_WIDTH, rise),
+                    GROUND_COLOR,
+                );
+            });
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.x += x;
+    }
+
+    fn left(&self) -> i16 {
+        self.x
+    }
+
+    fn right(&self) -> i16 {
+        self.x + self.profile.width()
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn ground_at(&self, x: i16) -> Option<i16> {
+        self.profile
+            .rise_at(x - self.x)
+            .map(|rise| GROUND_LEVEL - rise)
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.ground_at(point.x)
+            .filter(|ground| point.y >= *ground)
+            .map(|ground| format!("Ground at x {} height {}", self.x, ground))
+    }
+}
+
+const COIN_RADIUS: i16 = 10;
+const COIN_COLOR: &str = "#FFD700";
+const COIN_SHINE_COLOR: &str = "#FFF3A0";
+// 一回転にかかるフレーム数
+const COIN_SPIN_FRAMES: u8 = 48;
+// 一回転の間に二度、光が強くなったり弱くなったりする
+const COIN_GLOW_COLOR: &str = "rgba(255, 215, 0, 0.8)";
+const COIN_GLOW_BLUR: f64 = 8.0;
+const COIN_GLOW_PULSE: f64 = 5.0;
+
+// 触れると消えて、その場で点が入る。少年は倒れない。
+// 障害物の判定は &self なので取得済みかどうかは Cell で持つ
+#[derive(Clone)]
+pub struct Coin {
+    position: Point,
+    collected: Cell<bool>,
+    spin: u8,
+}
+
+impl Coin {
+    pub fn new(position: Point) -> Self {
+        Coin {
+            position,
+            collected: Cell::new(false),
+            spin: 0,
+        }
+    }
+
+    // 回っているように、横幅だけを縮めて描く
+    fn width(&self) -> i16 {
+        let angle = f64::from(self.spin) / f64::from(COIN_SPIN_FRAMES) * std::f64::consts::TAU;
+        ((f64::from(COIN_RADIUS) * angle.cos().abs()).round() as i16).max(1)
+    }
+
+    fn glow(&self) -> i16 {
+        let angle = f64::from(self.spin) / f64::from(COIN_SPIN_FRAMES) * std::f64::consts::TAU;
+        (COIN_GLOW_BLUR + COIN_GLOW_PULSE * (angle * 2.0).sin()).round() as i16
+    }
+
+    fn bounding_box(&self) -> Rect {
+        Rect::new_from_x_y(
+            self.position.x - COIN_RADIUS,
+            self.position.y - COIN_RADIUS,
+            COIN_RADIUS * 2,
+            COIN_RADIUS * 2,
+        )
+    }
+}
+
+impl Obstacle for Coin {
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if !self.collected.get() && disturbee.bounding_box().intersects(&self.bounding_box()) {
+            self.collected.set(true);
+            disturbee.collect();
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if !self.collected.get() {
+            let width = self.width();
+            renderer.with_glow(COIN_GLOW_COLOR, self.glow(), |renderer| {
+                renderer.fill_ellipse(&self.position, width, COIN_RADIUS, COIN_COLOR);
+            });
+            renderer.fill_ellipse(&self.position, width / 2, COIN_RADIUS / 2, COIN_SHINE_COLOR);
+            renderer.draw_bounding_box(&self.bounding_box());
+        }
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.position.x += x;
+        self.spin = (self.spin + 1) % COIN_SPIN_FRAMES;
+    }
+
+    fn left(&self) -> i16 {
+        self.position.x - COIN_RADIUS
+    }
+
+    fn right(&self) -> i16 {
+        self.position.x + COIN_RADIUS
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.bounding_box().contains(point).then(|| {
+            format!(
+                "Coin at {:?} collected {}",
+                self.position,
+                self.collected.get()
+            )
+        })
+    }
+
+    fn attract(&mut self, target: &Point) {
+        let dx = target.x - self.position.x;
+        let dy = target.y - self.position.y;
+        if !self.collected.get() && dx.abs() <= MAGNET_RADIUS && dy.abs() <= MAGNET_RADIUS {
+            self.position.x += dx.clamp(-MAGNET_SPEED, MAGNET_SPEED);
+            self.position.y += dy.clamp(-MAGNET_SPEED, MAGNET_SPEED);
+        }
+    }
+}
+
+// 磁石がコインを引き寄せる範囲と、1 フレームに寄せる距離
+const MAGNET_RADIUS: i16 = 150;
+const MAGNET_SPEED: i16 = 8;
+
+const POWER_UP_SIZE: i16 = 28;
+const POWER_UP_FRAME_COLOR: &str = "#FFFFFF";
+const POWER_UP_GLOW_BLUR: i16 = 12;
+// 上下に揺れる周期と幅
+const POWER_UP_BOB_FRAMES: u8 = 60;
+const POWER_UP_BOB_HEIGHT: f64 = 4.0;
+
+// 触れると効果が少年に渡る箱。コインと同じく少年は倒れない
+#[derive(Clone)]
+pub struct PowerUp {
+    kind: PowerUpKind,
+    position: Point,
+    collected: Cell<bool>,
+    bob: u8,
+}
+
+impl PowerUp {
+    // position は箱の中心
+    pub fn new(kind: PowerUpKind, position: Point) -> Self {
+        PowerUp {
+            kind,
+            position,
+            collected: Cell::new(false),
+            bob: 0,
+        }
+    }
+
+    fn bounding_box(&self) -> Rect {
+        let angle = f64::from(self.bob) / f64::from(POWER_UP_BOB_FRAMES) * std::f64::consts::TAU;
+        let offset = (angle.sin() * POWER_UP_BOB_HEIGHT).round() as i16;
+        Rect::new_from_x_y(
+            self.position.x - POWER_UP_SIZE / 2,
+            self.position.y - POWER_UP_SIZE / 2 + offset,
+            POWER_UP_SIZE,
+            POWER_UP_SIZE,
+        )
+    }
+}
+
+impl Obstacle for PowerUp {
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if !self.collected.get() && disturbee.bounding_box().intersects(&self.bounding_box()) {
+            self.collected.set(true);
+            disturbee.power_up(self.kind);
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if !self.collected.get() {
+            let bounding_box = self.bounding_box();
+            renderer.with_glow(self.kind.color(), POWER_UP_GLOW_BLUR, |renderer| {
+                renderer.fill_rect(&bounding_box, self.kind.color());
+            });
+            renderer.stroke_rect(&bounding_box, POWER_UP_FRAME_COLOR);
+        }
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.position.x += x;
+        self.bob = (self.bob + 1) % POWER_UP_BOB_FRAMES;
+    }
+
+    fn left(&self) -> i16 {
+        self.position.x - POWER_UP_SIZE / 2
+    }
+
+    fn right(&self) -> i16 {
+        self.position.x + POWER_UP_SIZE / 2
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.bounding_box().contains(point).then(|| {
+            format!(
+                "{} power-up at {:?} collected {}",
+                self.kind.label(),
+                self.position,
+                self.collected.get()
+            )
+        })
+    }
+}
+
+const FINISH_POLE_WIDTH: i16 = 8;
+const FINISH_POLE_HEIGHT: i16 = 240;
+const FINISH_SQUARE: i16 = 16;
+const FINISH_FLAG_COLUMNS: i16 = 4;
+const FINISH_FLAG_ROWS: i16 = 3;
+const FINISH_LIGHT_COLOR: &str = "#FFFFFF";
+const FINISH_DARK_COLOR: &str = "#222222";
+
+// レベルの終わりに立つ市松模様の旗。ぶつかりはせず、越えたことを一度だけ少年に知らせる
+#[derive(Clone)]
+pub struct FinishLine {
+    x: i16,
+    crossed: Cell<bool>,
+}
+
+impl FinishLine {
+    // x は竿の中心
+    pub fn new(x: i16) -> Self {
+        FinishLine {
+            x,
+            crossed: Cell::new(false),
+        }
+    }
+
+    fn pole(&self) -> Rect {
+        Rect::new_from_x_y(
+            self.x - FINISH_POLE_WIDTH / 2,
+            GROUND_LEVEL - FINISH_POLE_HEIGHT,
+            FINISH_POLE_WIDTH,
+            FINISH_POLE_HEIGHT,
+        )
+    }
+}
+
+impl Obstacle for FinishLine {
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if !self.crossed.get() && disturbee.bounding_box().right() >= self.x {
+            self.crossed.set(true);
+            disturbee.cross_finish();
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        let pole = self.pole();
+        renderer.fill_rect(&pole, FINISH_LIGHT_COLOR);
+        (0..FINISH_FLAG_ROWS).for_each(|row| {
+            (0..FINISH_FLAG_COLUMNS).for_each(|column| {
+                let color = if (row + column) % 2 == 0 {
+                    FINISH_DARK_COLOR
+                } else {
+                    FINISH_LIGHT_COLOR
+                };
+                renderer.fill_rect(
+                    &Rect::new_from_x_y(
+                        pole.right() + column * FINISH_SQUARE,
+                        pole.y() + row * FINISH_SQUARE,
+                        FINISH_SQUARE,
+                        FINISH_SQUARE,
+                    ),
+                    color,
+                );
+            });
+        });
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.x += x;
+    }
+
+    fn left(&self) -> i16 {
+        self.x - FINISH_POLE_WIDTH / 2
+    }
+
+    fn right(&self) -> i16 {
+        self.x + FINISH_POLE_WIDTH / 2 + FINISH_FLAG_COLUMNS * FINISH_SQUARE
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.pole()
+            .contains(point)
+            .then(|| format!("Finish line at {} crossed {}", self.x, self.crossed.get()))
+    }
 }
 
-const STONE_ON_GROUND: i16 = 546;
-const LOW_PLATFORM: i16 = 420;
+const BARRIER_BONUS: u32 = 10;
+const PROJECTILE_BONUS: u32 = 20;
+pub const COIN_BONUS: u32 = 5;
+const ENEMY_BONUS: u32 = 30;
+
+const PROJECTILE_RADIUS: i16 = 12;
+const PROJECTILE_COLOR: &str = "#FF6A00";
+
+// 地面の流れより速く左へ飛んでくる障害物
+#[derive(Clone)]
+pub struct Projectile {
+    position: Point,
+    speed: i16,
+    telegraph: Option<Telegraph>,
+    broken: Cell<bool>,
+}
+
+impl Projectile {
+    pub fn new(position: Point, speed: i16, telegraph: Option<Telegraph>) -> Self {
+        Projectile {
+            position,
+            speed,
+            telegraph,
+            broken: Cell::new(false),
+        }
+    }
+
+    fn bounding_box(&self) -> Rect {
+        Rect::new_from_x_y(
+            self.position.x - PROJECTILE_RADIUS,
+            self.position.y - PROJECTILE_RADIUS,
+            PROJECTILE_RADIUS * 2,
+            PROJECTILE_RADIUS * 2,
+        )
+    }
+}
+
+impl Obstacle for Projectile {
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if self.broken.get() {
+            return;
+        }
+        match Contact::between(disturbee, |rect| rect.intersects(&self.bounding_box())) {
+            Some(Contact::Hit) => self.broken.set(true),
+            Some(Contact::Hurt) => disturbee.knock_out(),
+            None => {}
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if !self.broken.get() {
+            renderer.fill_circle(&self.position, PROJECTILE_RADIUS, PROJECTILE_COLOR);
+            renderer.draw_bounding_box(&self.bounding_box());
+        }
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.position.x += x - self.speed;
+    }
+
+    fn left(&self) -> i16 {
+        self.position.x - PROJECTILE_RADIUS
+    }
+
+    fn right(&self) -> i16 {
+        self.position.x + PROJECTILE_RADIUS
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn bonus(&self) -> u32 {
+        PROJECTILE_BONUS
+    }
+
+    fn cause(&self) -> &'static str {
+        "Projectile"
+    }
+
+    // 飛んでくるものは少年より手前を通す
+    fn layer(&self) -> Layer {
+        Layer::Foreground
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.bounding_box()
+            .contains(point)
+            .then(|| format!("Projectile at {:?} speed {}", self.position, self.speed))
+    }
+
+    fn telegraph(&self) -> Option<(Rect, Telegraph)> {
+        if self.broken.get() {
+            return None;
+        }
+        self.telegraph
+            .map(|telegraph| (self.bounding_box(), telegraph))
+    }
+}
+
+const SAW_RADIUS: i16 = 28;
+// 刃の半分ほどを地面に埋めて置く
+const SAW_SINK: i16 = 12;
+const SAW_COLOR: &str = "#B8C2CC";
+const SAW_HUB_COLOR: &str = "#5A6570";
+const SAW_TEETH: u8 = 6;
+const SAW_TOOTH_RADIUS: i16 = 5;
+// 1 フレームで回る角度 (ラジアン)
+const SAW_SPIN: f64 = 0.2;
+const SAW_BONUS: u32 = 15;
+
+// 地面で回り続ける丸鋸。攻撃では壊れないので跳び越えるしかない
+#[derive(Clone)]
+pub struct Saw {
+    center: Point,
+    tick: u8,
+}
+
+impl Saw {
+    // position は刃の真下の地面
+    pub fn new(position: Point) -> Self {
+        Saw {
+            center: Point {
+                x: position.x,
+                y: position.y - SAW_RADIUS + SAW_SINK,
+            },
+            tick: 0,
+        }
+    }
+
+    fn bounding_box(&self) -> Rect {
+        Rect::new_from_x_y(
+            self.center.x - SAW_RADIUS,
+            self.center.y - SAW_RADIUS,
+            SAW_RADIUS * 2,
+            SAW_RADIUS * 2 - SAW_SINK,
+        )
+    }
+}
+
+impl Obstacle for Saw {
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if Contact::between(disturbee, |rect| rect.intersects(&self.bounding_box()))
+            == Some(Contact::Hurt)
+        {
+            disturbee.knock_out();
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        let angle = f64::from(self.tick) * SAW_SPIN;
+        (0..SAW_TEETH).for_each(|tooth| {
+            let theta = angle + f64::from(tooth) * std::f64::consts::TAU / f64::from(SAW_TEETH);
+            let tip = Point {
+                x: self.center.x + (theta.cos() * f64::from(SAW_RADIUS)) as i16,
+                y: self.center.y + (theta.sin() * f64::from(SAW_RADIUS)) as i16,
+            };
+            renderer.fill_circle(&tip, SAW_TOOTH_RADIUS, SAW_COLOR);
+        });
+        renderer.fill_circle(&self.center, SAW_RADIUS - SAW_TOOTH_RADIUS, SAW_COLOR);
+        renderer.fill_circle(&self.center, SAW_TOOTH_RADIUS, SAW_HUB_COLOR);
+        renderer.draw_bounding_box(&self.bounding_box());
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.center.x += x;
+        self.tick = self.tick.wrapping_add(1);
+    }
+
+    fn left(&self) -> i16 {
+        self.center.x - SAW_RADIUS
+    }
+
+    fn right(&self) -> i16 {
+        self.center.x + SAW_RADIUS
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn ground_hazard(&self) -> Option<Rect> {
+        Some(self.bounding_box())
+    }
+
+    fn bonus(&self) -> u32 {
+        SAW_BONUS
+    }
+
+    fn cause(&self) -> &'static str {
+        "Saw"
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.bounding_box()
+            .contains(point)
+            .then(|| format!("Saw at {:?}", self.center))
+    }
+}
+
+const ENEMY_WALK_LEFT: &str = "walk_left";
+const ENEMY_WALK_RIGHT: &str = "walk_right";
+// 絵の余白を除いた体の部分
+const ENEMY_INSETS: Insets = Insets {
+    left: 4,
+    top: 8,
+    right: 4,
+    bottom: 0,
+};
+
+pub fn enemy_animation_names() -> impl Iterator<Item = &'static str> {
+    [ENEMY_WALK_LEFT, ENEMY_WALK_RIGHT].iter().copied()
+}
+
+// 自分のシートのアニメーションで歩く敵。地面と一緒に流されながら、
+// home から range の幅を speed で行ったり来たりする
+#[derive(Clone)]
+pub struct Enemy {
+    sheet: Rc<SpriteSheet>,
+    walk_left: Option<Rc<Animation>>,
+    walk_right: Option<Rc<Animation>>,
+    position: Point,
+    home: i16,
+    range: i16,
+    speed: i16,
+    tick: u8,
+    defeated: Cell<bool>,
+}
+
+impl Enemy {
+    // position は左端の足元
+    pub fn new(sheet: Rc<SpriteSheet>, position: Point, range: i16, speed: i16) -> Self {
+        Enemy {
+            walk_left: sheet.animation(ENEMY_WALK_LEFT),
+            walk_right: sheet.animation(ENEMY_WALK_RIGHT),
+            sheet,
+            position,
+            home: position.x,
+            range,
+            speed: -speed.abs(),
+            tick: 0,
+            defeated: Cell::new(false),
+        }
+    }
+
+    fn animation(&self) -> Option<&Rc<Animation>> {
+        if self.speed < 0 {
+            self.walk_left.as_ref()
+        } else {
+            self.walk_right.as_ref()
+        }
+    }
+
+    fn sprite(&self) -> Option<SpriteHandle> {
+        self.animation()
+            .and_then(|animation| animation.frame_at(self.tick))
+    }
+
+    fn destination(&self) -> Rect {
+        let (width, height) = self
+            .sprite()
+            .map(|handle| {
+                let frame = &self.sheet.cell_at(handle).frame;
+                (frame.w, frame.h)
+            })
+            .unwrap_or_default();
+        Rect::new_from_x_y(self.position.x, self.position.y - height, width, height)
+    }
+
+    fn bounding_box(&self) -> Rect {
+        self.destination().inset(&ENEMY_INSETS)
+    }
+}
+
+impl Obstacle for Enemy {
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if self.defeated.get() {
+            return;
+        }
+        match Contact::between(disturbee, |rect| rect.intersects(&self.bounding_box())) {
+            Some(Contact::Hit) => self.defeated.set(true),
+            Some(Contact::Hurt) => disturbee.knock_out(),
+            None => {}
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if self.defeated.get() {
+            return;
+        }
+        if let Some(handle) = self.sprite() {
+            self.sheet
+                .draw_handle(renderer, handle, &self.destination());
+        }
+        renderer.draw_bounding_box(&self.bounding_box());
+    }
+
+    // 流れる分に加えて自分でも歩く。端まで来たら向きを変える
+    fn move_horizontally(&mut self, x: i16) {
+        self.home += x;
+        self.position.x += x;
+        if self.defeated.get() {
+            return;
+        }
+        self.position.x += self.speed;
+        if self.position.x <= self.home || self.position.x >= self.home + self.range {
+            self.position.x = self.position.x.clamp(self.home, self.home + self.range);
+            self.speed = -self.speed;
+        }
+        self.tick = self.tick.wrapping_add(1);
+        if self.sprite().is_none() {
+            self.tick = 0;
+        }
+    }
+
+    fn left(&self) -> i16 {
+        self.home.min(self.position.x)
+    }
+
+    fn right(&self) -> i16 {
+        self.home + self.range + self.destination().width
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn bonus(&self) -> u32 {
+        ENEMY_BONUS
+    }
+
+    fn cause(&self) -> &'static str {
+        "Enemy"
+    }
+
+    fn ground_hazard(&self) -> Option<Rect> {
+        (!self.defeated.get()).then(|| self.bounding_box())
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.destination().contains(point).then(|| {
+            format!(
+                "Enemy at {:?} home {} range {} speed {} defeated {}",
+                self.position,
+                self.home,
+                self.range,
+                self.speed,
+                self.defeated.get()
+            )
+        })
+    }
+}
+
+const DEFAULT_COIN_SPACING: i16 = 40;
+
+// セグメントの定義で座標を一つずつ並べずに済むよう、コインの並べ方を形で指定する
+// 例: {"shape": "arc", "from": {"x": 100, "y": 500}, "count": 7}
+#[derive(Deserialize, Clone)]
+#[serde(tag = "shape", rename_all = "camelCase")]
+pub enum CoinTrail {
+    Line {
+        from: Point,
+        to: Point,
+        count: u16,
+    },
+    // ジャンプの放物線に沿って並べる
+    Arc {
+        from: Point,
+        count: u16,
+    },
+    Sine {
+        from: Point,
+        count: u16,
+        #[serde(default = "default_coin_spacing")]
+        spacing: i16,
+        amplitude: i16,
+        wavelength: i16,
+    },
+}
+
+fn default_coin_spacing() -> i16 {
+    DEFAULT_COIN_SPACING
+}
+
+impl CoinTrail {
+    pub fn positions(&self, physics: &Physics) -> Vec<Point> {
+        match self {
+            CoinTrail::Line { from, to, count } => spread(*count)
+                .map(|t| Point {
+                    x: from.x + ((to.x - from.x) as f32 * t) as i16,
+                    y: from.y + ((to.y - from.y) as f32 * t) as i16,
+                })
+                .collect(),
+            CoinTrail::Arc { from, count } => {
+                // 地面を離れてから同じ高さに戻るまでのフレーム数
+                let airtime = -2.0 * physics.jump_speed as f32 / physics.gravity.max(1) as f32;
+                spread(*count)
+                    .map(|t| {
+                        let frame = airtime * t;
+                        Point {
+                            x: from.x + (physics.running_speed as f32 * frame) as i16,
+                            y: from.y
+                                + (physics.jump_speed as f32 * frame
+                                    + physics.gravity as f32 * frame * frame / 2.0)
+                                    as i16,
+                        }
+                    })
+                    .collect()
+            }
+            CoinTrail::Sine {
+                from,
+                count,
+                spacing,
+                amplitude,
+                wavelength,
+            } => (0..*count as i16)
+                .map(|index| {
+                    let x = index * spacing;
+                    let phase = x as f32 / (*wavelength).max(1) as f32 * std::f32::consts::TAU;
+                    Point {
+                        x: from.x + x,
+                        y: from.y - (phase.sin() * *amplitude as f32) as i16,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn coins(&self, physics: &Physics) -> Vec<Box<dyn Obstacle>> {
+        self.positions(physics)
+            .into_iter()
+            .map(|position| Box::new(Coin::new(position)) as Box<dyn Obstacle>)
+            .collect()
+    }
+}
+
+// 0.0 から 1.0 までを count 個に等分した値
+fn spread(count: u16) -> impl Iterator<Item = f32> {
+    let last = count.saturating_sub(1).max(1) as f32;
+    (0..count).map(move |index| index as f32 / last)
+}
+
+pub const STONE_ON_GROUND: i16 = 546;
+pub const LOW_PLATFORM: i16 = 420;
 const HIGH_PLATFORM: i16 = 375;
 
 pub fn stone_and_platform(
-    stone: HtmlImageElement,
+    pool: &ObstaclePool,
+    stone: &BarrierTemplate,
     sprite_sheet: Rc<SpriteSheet>,
     offset_x: i16,
 ) -> Vec<Box<dyn Obstacle>> {
     const INITIAL_STONE_OFFSET: i16 = 150;
     const FIRST_PLATFORM: i16 = 370;
     vec![
-        Box::new(Barrier::new(Image::new(
+        pool.barrier(
             stone,
             Point {
                 x: offset_x + INITIAL_STONE_OFFSET,
                 y: STONE_ON_GROUND,
             },
-        ))),
-        Box::new(create_floating_platform(
+        ),
+        create_floating_platform(
+            pool,
             sprite_sheet,
             Point {
                 x: offset_x + FIRST_PLATFORM,
                 y: LOW_PLATFORM,
             },
-        )),
+        ),
     ]
 }
 
 pub fn platform_and_stone(
-    stone: HtmlImageElement,
+    pool: &ObstaclePool,
+    stone: &BarrierTemplate,
     sprite_sheet: Rc<SpriteSheet>,
     offset_x: i16,
 ) -> Vec<Box<dyn Obstacle>> {
     const INITIAL_PLATFORM_OFFSET: i16 = 150;
     const FIRST_STONE: i16 = 370;
     vec![
-        Box::new(create_floating_platform(
+        create_floating_platform(
+            pool,
             sprite_sheet,
             Point {
                 x: offset_x + INITIAL_PLATFORM_OFFSET,
                 y: HIGH_PLATFORM,
             },
-        )),
-        Box::new(Barrier::new(Image::new(
+        ),
+        pool.barrier(
             stone,
             Point {
                 x: offset_x + FIRST_STONE,
                 y: STONE_ON_GROUND,
             },
-        ))),
+        ),
     ]
 }
 
-fn create_floating_platform(sprite_sheet: Rc<SpriteSheet>, position: Point) -> Platform {
-    const FLOATING_PLATFORM_SPRITES: &[&str] = &["13.png", "14.png", "15.png"];
-    const FLOATING_PLATFORM_BOUNDING_BOXES: &[Rect] = &[
-        Rect::new_from_x_y(0, 0, 60, 54),
-        Rect::new_from_x_y(60, 0, 384 - (60 * 2), 93),
-        Rect::new_from_x_y(384 - 60, 0, 60, 54),
-    ];
-    Platform::new(
-        sprite_sheet,
-        position,
-        &FLOATING_PLATFORM_SPRITES,
-        &FLOATING_PLATFORM_BOUNDING_BOXES,
-    )
+// 石を飛び越えるジャンプの軌道上にコインを並べる
+pub fn stone_with_coin_arc(
+    pool: &ObstaclePool,
+    stone: &BarrierTemplate,
+    physics: &Physics,
+    offset_x: i16,
+) -> Vec<Box<dyn Obstacle>> {
+    const STONE_OFFSET: i16 = 250;
+    const ARC_START: i16 = 150;
+    const BOY_CENTER_ON_GROUND: i16 = 540;
+    let mut obstacles = vec![pool.barrier(
+        stone,
+        Point {
+            x: offset_x + STONE_OFFSET,
+            y: STONE_ON_GROUND,
+        },
+    )];
+    let trail = CoinTrail::Arc {
+        from: Point {
+            x: offset_x + ARC_START,
+            y: BOY_CENTER_ON_GROUND,
+        },
+        count: 7,
+    };
+    obstacles.extend(trail.coins(physics));
+    obstacles
+}
+
+// 音の時計が appears_at を過ぎると乗れるようになる足場。それまでは枠だけを描く
+#[derive(Clone)]
+pub struct BeatPlatform {
+    bounding_box: Rect,
+    appears_at: f64,
+    clock: f64,
+}
+
+pub const BEAT_PLATFORM_WIDTH: i16 = 120;
+const BEAT_PLATFORM_HEIGHT: i16 = 20;
+const BEAT_PLATFORM_COLOR: &str = "#B388FF";
+const BEAT_PLATFORM_OUTLINE_COLOR: &str = "rgba(179, 136, 255, 0.5)";
+
+impl BeatPlatform {
+    pub fn new(position: Point, appears_at: f64) -> Self {
+        BeatPlatform {
+            bounding_box: Rect::new(position, BEAT_PLATFORM_WIDTH, BEAT_PLATFORM_HEIGHT),
+            appears_at,
+            clock: 0.0,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.clock >= self.appears_at
+    }
+}
+
+impl Obstacle for BeatPlatform {
+    // ボーナスの区間なので、横や下からぶつかっても倒れずにすり抜ける
+    fn check_intersection(&self, disturbee: &mut dyn Disturbee) {
+        if self.is_active()
+            && disturbee.bounding_box().intersects(&self.bounding_box)
+            && disturbee.velocity_y() > 0
+            && disturbee.pos_y() < self.bounding_box.y()
+        {
+            disturbee.land_on(self.bounding_box.y());
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if self.is_active() {
+            renderer.fill_rect(&self.bounding_box, BEAT_PLATFORM_COLOR);
+        } else {
+            renderer.stroke_rect(&self.bounding_box, BEAT_PLATFORM_OUTLINE_COLOR);
+        }
+        renderer.draw_bounding_box(&self.bounding_box);
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.bounding_box.set_x(self.bounding_box.x() + x);
+    }
+
+    fn left(&self) -> i16 {
+        self.bounding_box.x()
+    }
+
+    fn right(&self) -> i16 {
+        self.bounding_box.right()
+    }
+
+    fn snapshot(&self) -> Box<dyn Obstacle> {
+        Box::new(self.clone())
+    }
+
+    fn sync(&mut self, clock: f64) {
+        self.clock = clock;
+    }
+
+    fn inspect(&self, point: &Point) -> Option<String> {
+        self.bounding_box.contains(point).then(|| {
+            format!(
+                "Beat platform at {:?} appears at {:.2}s (clock {:.2}s)",
+                self.bounding_box.position, self.appears_at, self.clock
+            )
+        })
+    }
+}
+
+// 譜面の出来事を、少年の足元に来る時刻に合わせて並べる。clock は今の音の時計で、
+// 区間の頭は offset_x に着いたあとの最初の拍に合わせる。足場は着く一拍前の拍で現れるように、
+// フレーム数ではなく音の時計で予約する
+pub fn rhythm_segment(
+    beat_map: &BeatMap,
+    physics: &Physics,
+    offset_x: i16,
+    clock: f64,
+) -> Vec<Box<dyn Obstacle>> {
+    const INITIAL_STEP_OFFSET: i16 = 200;
+    const BOY_X: i16 = 0;
+    const LEAD_BEATS: f64 = 1.0;
+    let pixels_per_second = f64::from(physics.running_speed.max(1)) * 60.0;
+    let seconds_per_beat = beat_map.seconds_per_beat();
+    let reaches_offset =
+        clock + f64::from(offset_x + INITIAL_STEP_OFFSET - BOY_X) / pixels_per_second;
+    let first_beat = (reaches_offset / seconds_per_beat).ceil() * seconds_per_beat;
+    beat_map
+        .events
+        .iter()
+        .map(|event| {
+            let arrives_at = first_beat + event.time;
+            let x = BOY_X + ((arrives_at - clock) * pixels_per_second).round() as i16;
+            let y = GROUND_LEVEL - event.height;
+            match event.kind {
+                BeatEventKind::Platform => Box::new(BeatPlatform::new(
+                    Point { x, y },
+                    arrives_at - LEAD_BEATS * seconds_per_beat,
+                )) as Box<dyn Obstacle>,
+                BeatEventKind::Coin => Box::new(Coin::new(Point {
+                    x: x + BEAT_PLATFORM_WIDTH / 2,
+                    y,
+                })),
+            }
+        })
+        .collect()
+}
+
+pub const FLOATING_PLATFORM: &str = "floating_platform";
+
+pub fn required_obstacle_composites() -> impl Iterator<Item = &'static str> {
+    std::iter::once(FLOATING_PLATFORM)
+}
+
+fn create_floating_platform(
+    pool: &ObstaclePool,
+    sprite_sheet: Rc<SpriteSheet>,
+    position: Point,
+) -> Box<dyn Obstacle> {
+    pool.platform(sprite_sheet, position, FLOATING_PLATFORM)
+}
+
+// なだらかな坂と段差の続く区間
+pub fn rolling_ground(offset_x: i16) -> Vec<Box<dyn Obstacle>> {
+    const INITIAL_GROUND_OFFSET: i16 = 100;
+    vec![Box::new(Ground::new(
+        offset_x + INITIAL_GROUND_OFFSET,
+        GroundProfile::new(vec![
+            (0, 0),
+            (160, 40),
+            (320, 40),
+            (320, 64),
+            (480, 64),
+            (640, 0),
+        ]),
+    ))]
+}
+
+// 足場を階段状に積み上げ、画面の上へ登っていく区間
+pub fn vertical_climb(
+    pool: &ObstaclePool,
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+) -> Vec<Box<dyn Obstacle>> {
+    const INITIAL_PLATFORM_OFFSET: i16 = 150;
+    const STEP_WIDTH: i16 = 420;
+    const STEP_HEIGHT: i16 = 130;
+    const STEPS: i16 = 4;
+    (0..STEPS)
+        .map(|step| {
+            create_floating_platform(
+                pool,
+                sprite_sheet.clone(),
+                Point {
+                    x: offset_x + INITIAL_PLATFORM_OFFSET + step * STEP_WIDTH,
+                    y: LOW_PLATFORM - step * STEP_HEIGHT,
+                },
+            )
+        })
+        .collect()
+}
+
+// タイルのメタデータに定義された任意の形の足場を置く
+pub fn platform_at(
+    pool: &ObstaclePool,
+    sprite_sheet: Rc<SpriteSheet>,
+    position: Point,
+    kind: &str,
+) -> Box<dyn Obstacle> {
+    pool.platform(sprite_sheet, position, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ground_profile_interpolates_slopes_and_steps() {
+        let profile = GroundProfile::new(vec![(0, 0), (100, 50), (100, 80), (200, 80)]);
+
+        assert_eq!(profile.rise_at(50), Some(25));
+        assert_eq!(profile.rise_at(100), Some(80));
+        assert_eq!(profile.rise_at(150), Some(80));
+        assert_eq!(profile.rise_at(201), None);
+    }
+
+    #[test]
+    fn coin_arc_follows_the_jump_parabola() {
+        let physics = Physics {
+            gravity: 1,
+            jump_speed: -20,
+            running_speed: 4,
+            terminal_speed: 20,
+        };
+        let trail: CoinTrail =
+            serde_json::from_str(r#"{"shape": "arc", "from": {"x": 100, "y": 500}, "count": 5}"#)
+                .unwrap();
+        let positions = trail.positions(&physics);
+
+        assert_eq!(positions.len(), 5);
+        assert_eq!((positions[0].x, positions[0].y), (100, 500));
+        assert_eq!((positions[2].x, positions[2].y), (180, 300));
+        assert_eq!((positions[4].x, positions[4].y), (260, 500));
+    }
+
+    struct Swinging {
+        body: Rect,
+        swing: Option<Rect>,
+        knocked_out: bool,
+    }
+
+    impl Disturbee for Swinging {
+        fn bounding_box(&self) -> Rect {
+            self.body
+        }
+        fn velocity_y(&self) -> i16 {
+            0
+        }
+        fn pos_y(&self) -> i16 {
+            self.body.y()
+        }
+        fn land_on(&mut self, _pos: i16) {}
+        fn knock_out(&mut self) {
+            self.knocked_out = true;
+        }
+        fn hitbox(&self) -> Option<Rect> {
+            self.swing
+        }
+    }
+
+    #[test]
+    fn finish_line_is_crossed_once_without_knocking_out() {
+        let mut boy = Swinging {
+            body: Rect::new_from_x_y(0, 500, 50, 100),
+            swing: None,
+            knocked_out: false,
+        };
+        let mut finish = FinishLine::new(80);
+        finish.check_intersection(&mut boy);
+        assert!(!finish.crossed.get());
+
+        finish.move_horizontally(-40);
+        finish.check_intersection(&mut boy);
+        assert!(finish.crossed.get());
+        assert!(!boy.knocked_out);
+    }
+
+    #[test]
+    fn attack_breaks_projectiles_without_hurting_the_body() {
+        let mut boy = Swinging {
+            body: Rect::new_from_x_y(0, 500, 50, 100),
+            swing: Some(Rect::new_from_x_y(50, 500, 40, 100)),
+            knocked_out: false,
+        };
+        let projectile = Projectile::new(Point { x: 60, y: 560 }, 5, None);
+        projectile.check_intersection(&mut boy);
+        assert!(!boy.knocked_out);
+        assert!(projectile.broken.get());
+
+        boy.swing = None;
+        let projectile = Projectile::new(Point { x: 40, y: 560 }, 5, None);
+        projectile.check_intersection(&mut boy);
+        assert!(boy.knocked_out);
+        assert!(!projectile.broken.get());
+    }
+
+    #[test]
+    fn saws_cannot_be_broken_by_an_attack() {
+        let mut boy = Swinging {
+            body: Rect::new_from_x_y(0, 500, 50, 100),
+            swing: Some(Rect::new_from_x_y(50, 500, 40, 100)),
+            knocked_out: false,
+        };
+        let saw = Saw::new(Point { x: 110, y: 600 });
+        saw.check_intersection(&mut boy);
+        assert!(!boy.knocked_out);
+
+        boy.swing = None;
+        boy.body = Rect::new_from_x_y(60, 500, 50, 100);
+        saw.check_intersection(&mut boy);
+        assert!(boy.knocked_out);
+        assert_eq!(saw.ground_hazard().map(|rect| rect.bottom()), Some(600));
+    }
+
+    #[test]
+    fn coins_disappear_without_knocking_out_and_spin_edge_on() {
+        let mut boy = Swinging {
+            body: Rect::new_from_x_y(0, 500, 50, 100),
+            swing: None,
+            knocked_out: false,
+        };
+        let mut coin = Coin::new(Point { x: 40, y: 550 });
+        assert_eq!(coin.width(), COIN_RADIUS);
+        (0..COIN_SPIN_FRAMES / 4).for_each(|_| coin.move_horizontally(0));
+        assert_eq!(coin.width(), 1);
+
+        coin.check_intersection(&mut boy);
+        assert!(coin.collected.get());
+        assert!(!boy.knocked_out);
+    }
+
+    #[test]
+    fn telegraph_marks_projectiles_just_outside_the_view() {
+        let telegraph = Telegraph {
+            lead: 300,
+            color: "#FF0000",
+        };
+        let view = Rect::new_from_x_y(0, 0, 600, 600);
+        let mut projectile = Projectile::new(Point { x: 1000, y: 560 }, 5, Some(telegraph));
+
+        let (target, _) = projectile.telegraph().unwrap();
+        assert!(telegraph.marker(&target, &view).is_none());
+
+        projectile.move_horizontally(-150);
+        let (target, _) = projectile.telegraph().unwrap();
+        let marker = telegraph.marker(&target, &view).unwrap();
+        assert_eq!((marker.x, marker.y), (580, 560));
+
+        projectile.move_horizontally(-300);
+        let (target, _) = projectile.telegraph().unwrap();
+        assert!(telegraph.marker(&target, &view).is_none());
+    }
+
+    #[test]
+    fn rhythm_platforms_appear_on_the_beat_before_the_boy_arrives() {
+        let physics = Physics {
+            gravity: 1,
+            jump_speed: -20,
+            running_speed: 5,
+            terminal_speed: 20,
+        };
+        let beat_map = BeatMap::from_bpm("song.mp3", 120.0);
+        let obstacles = rhythm_segment(&beat_map, &physics, 100, 10.2);
+        assert_eq!(obstacles.len(), beat_map.events.len());
+        // x 300 に着くのは 11.2 秒なので、区間の頭は次の拍の 11.5 秒。一段目はその一拍前の 11.0 秒に現れる
+        let description = obstacles[0]
+            .inspect(&Point {
+                x: 400,
+                y: GROUND_LEVEL - 175,
+            })
+            .unwrap();
+        assert!(description.contains("appears at 11.00s"));
+
+        let mut platform = BeatPlatform::new(
+            Point {
+                x: 300,
+                y: LOW_PLATFORM,
+            },
+            10.5,
+        );
+        platform.sync(10.4);
+        assert!(!platform.is_active());
+        platform.sync(10.5);
+        assert!(platform.is_active());
+    }
 }
@@ -0,0 +1,98 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use std::collections::VecDeque;
+
+// 段階が上がる距離 (m)。TIER_DISTANCES[n] を越えると段階 n のセグメントも出てくる
+const TIER_DISTANCES: [u32; 3] = [0, 300, 800];
+// 最近出たものとして覚えておく数。この中にあるものは出にくくする
+const HISTORY_LENGTH: usize = 3;
+
+// セグメントの名前と出やすさ、出始める段階
+pub struct SegmentEntry {
+    pub name: &'static str,
+    pub weight: u32,
+    pub tier: usize,
+}
+
+// 出やすさの表から次のセグメントを選ぶ。直前と同じものは続けて出さない
+#[derive(Clone, Default)]
+pub struct SegmentPicker {
+    recent: VecDeque<usize>,
+}
+
+impl SegmentPicker {
+    pub fn pick(&mut self, table: &[SegmentEntry], distance: u32, rng: &mut impl Rng) -> usize {
+        let tier = TIER_DISTANCES
+            .iter()
+            .take_while(|tier_distance| distance >= **tier_distance)
+            .count()
+            .saturating_sub(1);
+        let weights = table.iter().enumerate().map(|(index, entry)| {
+            if entry.tier > tier || self.recent.front() == Some(&index) {
+                0
+            } else if self.recent.contains(&index) {
+                entry.weight.div_ceil(2)
+            } else {
+                entry.weight
+            }
+        });
+        // 選べるものがなければ (表に一つしかないときなど) 直前と同じものを出す
+        let index = match WeightedIndex::new(weights) {
+            Ok(weights) => weights.sample(rng),
+            Err(_) => self.recent.front().copied().unwrap_or_default(),
+        };
+        self.recent.push_front(index);
+        self.recent.truncate(HISTORY_LENGTH);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const TABLE: [SegmentEntry; 4] = [
+        SegmentEntry {
+            name: "a",
+            weight: 10,
+            tier: 0,
+        },
+        SegmentEntry {
+            name: "b",
+            weight: 10,
+            tier: 0,
+        },
+        SegmentEntry {
+            name: "c",
+            weight: 5,
+            tier: 1,
+        },
+        SegmentEntry {
+            name: "d",
+            weight: 5,
+            tier: 2,
+        },
+    ];
+
+    #[test]
+    fn segments_never_repeat_and_unlock_with_distance() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut picker = SegmentPicker::default();
+
+        let early: Vec<usize> = (0..50).map(|_| picker.pick(&TABLE, 0, &mut rng)).collect();
+        assert!(early.windows(2).all(|pair| pair[0] != pair[1]));
+        assert!(early.iter().all(|index| *index < 2));
+
+        let late: Vec<usize> = (0..200)
+            .map(|_| picker.pick(&TABLE, 1000, &mut rng))
+            .collect();
+        assert!(late.windows(2).all(|pair| pair[0] != pair[1]));
+        assert!(late.contains(&2) && late.contains(&3));
+
+        let mut single = SegmentPicker::default();
+        assert_eq!(single.pick(&TABLE[..1], 0, &mut rng), 0);
+        assert_eq!(single.pick(&TABLE[..1], 0, &mut rng), 0);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::mixer::{Bus, DuckingRule, DEFAULT_DUCKING};
+use crate::quality::Quality;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+
+pub const RETRO_FILTER_KEY: &str = "F2";
+pub const OPTIONS_KEY: &str = "KeyO";
+pub const MUTE_KEY: &str = "KeyM";
+
+pub const MINIMUM_GAME_SPEED: f32 = 0.5;
+pub const MAXIMUM_GAME_SPEED: f32 = 1.5;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct Settings {
+    pub retro_filter: bool,
+    pub master_volume: f32,
+    pub muted: bool,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    // 風や鳥の声など、景色に合わせた環境音
+    pub ambience_volume: f32,
+    // 結果画面のカウントなど、画面の合図の音
+    pub ui_volume: f32,
+    // 大きな効果音が鳴ったときに音楽と環境音を下げる dB と、下げたままにするミリ秒
+    pub duck_depth_db: f32,
+    pub duck_ms: f32,
+    pub game_speed: f32,
+    pub quality: Quality,
+    // タブが隠れている間も BGM を流し続けるか
+    pub music_in_background: bool,
+    pub speedrun_timer: bool,
+    // 地面にいる間、跳んだらどこへ着くかを薄く描く
+    pub jump_preview: bool,
+    // 地面の障害物の前で自動で跳ぶ。スライディングと台の乗り降りは自分で行う
+    pub auto_jump: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            retro_filter: false,
+            master_volume: 1.0,
+            muted: false,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            ambience_volume: 1.0,
+            ui_volume: 1.0,
+            duck_depth_db: DEFAULT_DUCKING.depth_db,
+            duck_ms: DEFAULT_DUCKING.duration_ms,
+            game_speed: 1.0,
+            quality: Quality::Auto,
+            music_in_background: false,
+            speedrun_timer: false,
+            jump_preview: false,
+            auto_jump: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn toggle_retro_filter(&mut self) {
+        self.retro_filter = !self.retro_filter;
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    pub fn bus_volume(&self, bus: Bus) -> f32 {
+        match bus {
+            Bus::Music => self.music_volume,
+            Bus::Ambience => self.ambience_volume,
+            Bus::Sfx => self.sfx_volume,
+            Bus::Ui => self.ui_volume,
+        }
+    }
+
+    pub fn bus_volume_mut(&mut self, bus: Bus) -> &mut f32 {
+        match bus {
+            Bus::Music => &mut self.music_volume,
+            Bus::Ambience => &mut self.ambience_volume,
+            Bus::Sfx => &mut self.sfx_volume,
+            Bus::Ui => &mut self.ui_volume,
+        }
+    }
+
+    pub fn ducking(&self) -> DuckingRule {
+        DuckingRule {
+            depth_db: self.duck_depth_db,
+            duration_ms: self.duck_ms,
+        }
+    }
+}
+
+thread_local! {
+    static PAGE_MUTE: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+// ページ側のミュートボタンから呼ばれる。次のフレームでゲームが拾う
+pub fn request_mute(muted: bool) {
+    PAGE_MUTE.with(|request| request.set(Some(muted)));
+}
+
+// 頼まれていたときに一度だけ Some を返す
+pub fn take_mute_request() -> Option<bool> {
+    PAGE_MUTE.with(|request| request.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_mute_request_is_taken_once() {
+        assert_eq!(take_mute_request(), None);
+        request_mute(true);
+        request_mute(false);
+
+        assert_eq!(take_mute_request(), Some(false));
+        assert_eq!(take_mute_request(), None);
+    }
+}
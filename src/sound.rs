@@ -2,7 +2,10 @@ use anyhow::{anyhow, Result};
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::js_sys::ArrayBuffer;
-use web_sys::{AudioBuffer, AudioBufferSourceNode, AudioContext, AudioDestinationNode, AudioNode};
+use web_sys::{
+    AudioBuffer, AudioBufferSourceNode, AudioContext, AudioNode, AudioScheduledSourceNode,
+    GainNode, OscillatorType,
+};
 
 pub fn create_audio_context() -> Result<AudioContext> {
     AudioContext::new().map_err(|err| anyhow!("Could not create audio context: {:#?}", err))
@@ -13,19 +16,70 @@ fn create_buffer_source(ctx: &AudioContext) -> Result<AudioBufferSourceNode> {
         .map_err(|err| anyhow!("Error creating buffer source {:#?}", err))
 }
 
-fn connect_with_audio_node(
-    buffer_source: &AudioBufferSourceNode,
-    destination: &AudioDestinationNode,
-) -> Result<AudioNode> {
-    buffer_source
+fn connect_with_audio_node(source: &AudioNode, destination: &AudioNode) -> Result<AudioNode> {
+    source
         .connect_with_audio_node(&destination)
         .map_err(|err| anyhow!("Error connecting audio source to destination {:#?}", err))
 }
 
-fn create_track_source(ctx: &AudioContext, buffer: &AudioBuffer) -> Result<AudioBufferSourceNode> {
+// 音量調整用のノード。出力先に繋いだ状態で返す
+pub fn create_gain(ctx: &AudioContext) -> Result<GainNode> {
+    create_gain_to(ctx, &ctx.destination())
+}
+
+pub fn create_gain_to(ctx: &AudioContext, destination: &AudioNode) -> Result<GainNode> {
+    let gain = ctx
+        .create_gain()
+        .map_err(|err| anyhow!("Error creating gain node {:#?}", err))?;
+    connect_with_audio_node(&gain, destination)?;
+    Ok(gain)
+}
+
+// 今の音量から fade 秒かけて value まで変える
+pub fn fade_to(ctx: &AudioContext, gain: &GainNode, value: f32, fade: f64) -> Result<()> {
+    let now = ctx.current_time();
+    let param = gain.gain();
+    param
+        .cancel_scheduled_values(now)
+        .and_then(|_param| param.set_value_at_time(param.value(), now))
+        .and_then(|_param| param.linear_ramp_to_value_at_time(value, now + fade))
+        .map(|_param| ())
+        .map_err(|err| anyhow!("Could not schedule fade {:#?}", err))
+}
+
+// ダッキングで下げきるまでの秒数。効果音の頭に間に合うよう短くする
+const DUCK_ATTACK: f64 = 0.02;
+
+// 大きな効果音の間だけ depth まで下げ、hold 秒おいてから release 秒かけて元に戻す
+pub fn duck(
+    ctx: &AudioContext,
+    gain: &GainNode,
+    depth: f32,
+    hold: f64,
+    release: f64,
+) -> Result<()> {
+    let now = ctx.current_time();
+    let param = gain.gain();
+    param
+        .cancel_scheduled_values(now)
+        .and_then(|_param| param.set_value_at_time(param.value(), now))
+        .and_then(|_param| param.linear_ramp_to_value_at_time(depth, now + DUCK_ATTACK))
+        .and_then(|_param| param.set_value_at_time(depth, now + DUCK_ATTACK + hold))
+        .and_then(|_param| {
+            param.linear_ramp_to_value_at_time(1.0, now + DUCK_ATTACK + hold + release)
+        })
+        .map(|_param| ())
+        .map_err(|err| anyhow!("Could not schedule ducking {:#?}", err))
+}
+
+fn create_track_source(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+) -> Result<AudioBufferSourceNode> {
     let track_source = create_buffer_source(ctx)?;
     track_source.set_buffer(Some(&buffer));
-    connect_with_audio_node(&track_source, &ctx.destination())?;
+    connect_with_audio_node(&track_source, destination)?;
     Ok(track_source)
 }
 
@@ -34,15 +88,94 @@ pub enum LOOPING {
     YES,
 }
 
-pub fn play_sound(ctx: &AudioContext, buffer: &AudioBuffer, looping: LOOPING) -> Result<()> {
-    let track_source = create_track_source(ctx, buffer)?;
+pub fn play_sound(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+    looping: LOOPING,
+) -> Result<()> {
+    start_track(ctx, buffer, destination, looping).map(|_track_source| ())
+}
+
+fn start_track(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+    looping: LOOPING,
+) -> Result<AudioBufferSourceNode> {
+    let track_source = create_track_source(ctx, buffer, destination)?;
     if matches!(looping, LOOPING::YES) {
         track_source.set_loop(true);
     }
 
     track_source
         .start()
-        .map_err(|err| anyhow!("Could not start sound!{:#?}", err))
+        .map_err(|err| anyhow!("Could not start sound!{:#?}", err))?;
+    Ok(track_source)
+}
+
+// 効果音ファイルを用意するほどでもない短い合図を合成して鳴らす
+pub fn play_tone(
+    ctx: &AudioContext,
+    destination: &AudioNode,
+    frequency: f32,
+    duration: f64,
+) -> Result<()> {
+    let oscillator = ctx
+        .create_oscillator()
+        .map_err(|err| anyhow!("Error creating oscillator {:#?}", err))?;
+    oscillator.set_type(OscillatorType::Square);
+    oscillator.frequency().set_value(frequency);
+
+    let envelope = ctx
+        .create_gain()
+        .map_err(|err| anyhow!("Error creating gain node {:#?}", err))?;
+    let now = ctx.current_time();
+    envelope
+        .gain()
+        .exponential_ramp_to_value_at_time(0.001, now + duration)
+        .map_err(|err| anyhow!("Could not schedule tone envelope {:#?}", err))?;
+    connect_with_audio_node(&oscillator, &envelope)?;
+    connect_with_audio_node(&envelope, destination)?;
+
+    oscillator
+        .start()
+        .and_then(|_unit| oscillator.stop_with_when(now + duration))
+        .map_err(|err| anyhow!("Could not play tone {:#?}", err))
+}
+
+// BGM や環境音を一曲ループで流す。曲ごとに音量ノードを挟み、fade 秒かけて大きくする
+pub fn play_music(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+    fade: f64,
+) -> Result<(AudioBufferSourceNode, GainNode)> {
+    let fader = ctx
+        .create_gain()
+        .map_err(|err| anyhow!("Error creating gain node {:#?}", err))?;
+    connect_with_audio_node(&fader, destination)?;
+
+    let now = ctx.current_time();
+    fader
+        .gain()
+        .set_value_at_time(0.0, now)
+        .and_then(|_param| fader.gain().linear_ramp_to_value_at_time(1.0, now + fade))
+        .map_err(|err| anyhow!("Could not schedule music fade in {:#?}", err))?;
+    let track_source = start_track(ctx, buffer, &fader, LOOPING::YES)?;
+    Ok((track_source, fader))
+}
+
+// 今の音量から fade 秒かけて消し、消えたところで止める
+pub fn fade_out(
+    ctx: &AudioContext,
+    track_source: &AudioBufferSourceNode,
+    fader: &GainNode,
+    fade: f64,
+) -> Result<()> {
+    fade_to(ctx, fader, 0.0, fade)?;
+    AudioScheduledSourceNode::stop_with_when(track_source, ctx.current_time() + fade)
+        .map_err(|err| anyhow!("Could not stop music {:#?}", err))
 }
 
 pub async fn decode_audio_data(
@@ -0,0 +1,472 @@
+use crate::analytics::Analytics;
+use crate::browser::{self, socket::Socket};
+use crate::config::{self, GameConfig};
+use crate::engine::{Audio, Game, KeyState, Point, Rect, Renderer};
+use crate::game::Walk;
+use crate::lobby::{self, LobbyMessage, LobbyState};
+use crate::lockstep::{LockstepMessage, TickInput, HASH_INTERVAL, INPUT_DELAY};
+use crate::quick_chat::EmoteBubble;
+use crate::race::{draw_track, hash_runners, Runner};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+// ?spectator=ABCD でその部屋のレースを観戦する。?spectator=ABCD&transparent で背景を透明にし、
+// 線とテロップだけを重ねる (OBS のブラウザソース向け)
+const SPECTATOR_PARAM: &str = "spectator";
+const TRANSPARENT_PARAM: &str = "transparent";
+const RETRY_KEY: &str = "KeyR";
+const RACE_PLAYERS: usize = 2;
+
+const SCREEN: Rect = Rect::new_from_x_y(0, 0, 600, 600);
+const SKY_COLOR: &str = "rgb(70, 80, 160)";
+const TEXT_COLOR: &str = "#FFFFFF";
+const TRACK_TOP: i16 = 500;
+const TICKER: Rect = Rect::new_from_x_y(0, 540, 600, 60);
+const TICKER_COLOR: &str = "rgba(0, 0, 0, 0.6)";
+
+pub struct SpectatorOptions {
+    pub code: String,
+    pub transparent: bool,
+}
+
+pub fn requested() -> Option<SpectatorOptions> {
+    let code = browser::query_param(SPECTATOR_PARAM).filter(|code| !code.is_empty())?;
+    Some(SpectatorOptions {
+        code: code.to_uppercase(),
+        transparent: browser::query_param(TRANSPARENT_PARAM).is_some(),
+    })
+}
+
+// 観戦中のレース。二人から届いた入力で、プレイヤーと同じシードの Walk を二人分動かす
+struct RaceFeed {
+    players: [String; 2],
+    runners: [Runner; 2],
+    inputs: [BTreeMap<u32, TickInput>; 2],
+    tick: u32,
+    hashes: BTreeMap<u32, u64>,
+    // プレイヤーが送ってきた (ティック, ハッシュ)。こちらがそのティックまで進んだら突き合わせる
+    reported: Vec<(u32, u64)>,
+    verified: u32,
+    desync: Option<u32>,
+    bubbles: [Option<EmoteBubble>; 2],
+}
+
+impl RaceFeed {
+    fn new(seed: u64, players: [String; 2], walks: [Walk; 2], skins: [&str; 2]) -> Self {
+        let [first, second] = walks;
+        // プレイヤーの Lockstep と同じく、最初の INPUT_DELAY ティックは空の入力で埋めておく
+        let idle: BTreeMap<u32, TickInput> = (0..INPUT_DELAY)
+            .map(|tick| (tick, TickInput::default()))
+            .collect();
+        RaceFeed {
+            players,
+            runners: [
+                Runner::new(first, seed, skins[0]),
+                Runner::new(second, seed, skins[1]),
+            ],
+            inputs: [idle.clone(), idle],
+            tick: 0,
+            hashes: BTreeMap::new(),
+            reported: vec![],
+            verified: 0,
+            desync: None,
+            bubbles: [None, None],
+        }
+    }
+
+    fn into_walks(self) -> [Walk; 2] {
+        self.runners.map(Runner::into_walk)
+    }
+
+    fn receive(&mut self, player: &str, message: LockstepMessage) {
+        let Some(index) = self.players.iter().position(|id| id == player) else {
+            return;
+        };
+        match message {
+            LockstepMessage::Input { tick, input } => {
+                self.inputs[index].insert(tick, input);
+            }
+            LockstepMessage::Hash { tick, hash } => self.reported.push((tick, hash)),
+            LockstepMessage::Emote { emote, .. } => self.bubbles[index] = EmoteBubble::new(emote),
+        }
+    }
+
+    // 二人の入力がそろっているティックまで一気に進める
+    fn update(&mut self) {
+        for bubble in self.bubbles.iter_mut() {
+            if !bubble.as_mut().is_some_and(EmoteBubble::update) {
+                *bubble = None;
+            }
+        }
+        while self
+            .inputs
+            .iter()
+            .all(|inputs| inputs.contains_key(&self.tick))
+        {
+            let tick = self.tick;
+            let inputs = self
+                .inputs
+                .each_mut()
+                .map(|inputs| inputs.remove(&tick).unwrap_or_default());
+            self.tick += 1;
+            let tick = self.tick;
+            self.runners
+                .iter_mut()
+                .zip(inputs)
+                .for_each(|(runner, input)| runner.step(input, tick));
+            if tick.is_multiple_of(HASH_INTERVAL) {
+                self.hashes.insert(tick, hash_runners(tick, &self.runners));
+            }
+        }
+        self.check();
+    }
+
+    // プレイヤーのハッシュが観戦側で動かした結果と違えば、どちらかがずれている
+    fn check(&mut self) {
+        let hashes = &self.hashes;
+        let desync = &mut self.desync;
+        let verified = &mut self.verified;
+        self.reported.retain(|(tick, hash)| match hashes.get(tick) {
+            Some(own) => {
+                if own == hash {
+                    *verified += 1;
+                } else {
+                    desync.get_or_insert(*tick);
+                }
+                false
+            }
+            None => true,
+        });
+    }
+
+    fn leader(&self) -> usize {
+        let [first, second] = &self.runners;
+        if first.compare(second) == Ordering::Less {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn ticker(&self) -> String {
+        let [first, second] = &self.runners;
+        let over = self.runners.iter().all(Runner::is_done);
+        let status = match (self.desync, first.compare(second)) {
+            (Some(tick), _) => format!("DESYNC @{}", tick),
+            (None, Ordering::Equal) => "TIED".into(),
+            (None, _) if over => format!("P{} WINS", self.leader() + 1),
+            (None, _) => format!("P{} LEADS", self.leader() + 1),
+        };
+        format!(
+            "P1 {}m   P2 {}m   {}",
+            first.distance(),
+            second.distance(),
+            status
+        )
+    }
+
+    // 先頭のランナーの画面に、もう一人を重ねて見せる
+    fn draw(&self, renderer: &Renderer, transparent: bool) {
+        let leader = self.runners[self.leader()].walk();
+        let trailer = &self.runners[1 - self.leader()];
+        if !transparent {
+            leader.draw(renderer);
+            leader.draw_rival(renderer, trailer.walk(), trailer.color());
+        }
+        for (runner, bubble) in self.runners.iter().zip(&self.bubbles) {
+            if let Some(bubble) = bubble {
+                bubble.draw(renderer, &leader.rival_anchor(runner.walk()));
+            }
+        }
+        draw_track(renderer, &self.runners, TRACK_TOP);
+        renderer.fill_rect(&TICKER, TICKER_COLOR);
+        renderer.draw_text(
+            &self.ticker(),
+            &Point {
+                x: TICKER.x() + 20,
+                y: TICKER.y() + TICKER.height / 2,
+            },
+            TEXT_COLOR,
+        );
+    }
+}
+
+// 部屋のソケットに読むだけでつなぐ。観戦者からは何も送らないので、プレイヤーの部屋には現れない
+pub struct Spectator {
+    options: SpectatorOptions,
+    url: Option<String>,
+    socket: Option<Socket>,
+    offline: bool,
+    state: LobbyState,
+    // 次のレースで走らせる二人分の Walk。観戦中は feed が持っている
+    walks: Option<[Walk; 2]>,
+    feed: Option<RaceFeed>,
+}
+
+impl Spectator {
+    pub fn new(options: SpectatorOptions) -> Self {
+        Spectator {
+            options,
+            url: None,
+            socket: None,
+            offline: false,
+            state: LobbyState::default(),
+            walks: None,
+            feed: None,
+        }
+    }
+
+    fn connect(&mut self) {
+        let Some(url) = &self.url else {
+            return;
+        };
+        self.socket = match Socket::connect(url) {
+            Ok(socket) => Some(socket),
+            Err(err) => {
+                log!("Could not connect to the lobby {:#?}", err);
+                None
+            }
+        };
+        self.offline = false;
+    }
+
+    // 送り主の id で、どちらのランナーのメッセージかを決める
+    fn receive(&mut self, text: &str) {
+        let message = match serde_json::from_str::<LobbyMessage>(text) {
+            Ok(message) => message,
+            Err(err) => {
+                log!("Bad spectator message {} {:#?}", text, err);
+                return;
+            }
+        };
+        if let Some(player) = message.player() {
+            self.state.heard_from(player, browser::timestamp());
+        }
+        match message {
+            LobbyMessage::Race { player, message } => {
+                if let Some(feed) = &mut self.feed {
+                    feed.receive(&player, message);
+                }
+            }
+            message => {
+                self.state.apply(message);
+                if let Some(starts_at) = self.state.starts_at() {
+                    self.start_feed(starts_at);
+                }
+            }
+        }
+    }
+
+    // 途中から観戦し始めたレースは最初の入力が分からないので、次のカウントダウンから追いかける
+    fn start_feed(&mut self, starts_at: f64) {
+        self.state.start_race();
+        self.end_feed();
+        let Some(walks) = self.walks.take() else {
+            log!("Spectator: the race walks are still loading");
+            return;
+        };
+        let players = self.state.players();
+        if players.len() != RACE_PLAYERS {
+            self.walks = Some(walks);
+            return;
+        }
+        let ids = [players[0].id.clone(), players[1].id.clone()];
+        let skins = [players[0].skin.as_str(), players[1].skin.as_str()];
+        let seed = lobby::race_seed(&self.options.code, starts_at);
+        self.feed = Some(RaceFeed::new(seed, ids, walks, skins));
+    }
+
+    fn end_feed(&mut self) {
+        if let Some(feed) = self.feed.take() {
+            self.walks = Some(feed.into_walks().map(Walk::reset));
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        if self.url.is_none() {
+            "Online play is not configured"
+        } else if self.offline {
+            "Offline - press R to retry"
+        } else {
+            "Waiting for the next race"
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Game for Spectator {
+    async fn initialize(&self) -> Result<Box<dyn Game>> {
+        let options = SpectatorOptions {
+            code: self.options.code.clone(),
+            transparent: self.options.transparent,
+        };
+        let config = config::load_game_config().await;
+        let Some(url) = config.lobby_url else {
+            log!("lobbyUrl is not configured");
+            return Ok(Box::new(Spectator::new(options)));
+        };
+        // プレイヤーと同じ既定の設定で走らせる。観戦者の画面では音を鳴らさない
+        let load = || async {
+            let audio = Audio::new()?;
+            audio.set_muted(true);
+            Walk::load(GameConfig::default(), Analytics::new(None, None), audio).await
+        };
+        let walks = [load().await?, load().await?];
+        let mut spectator = Spectator {
+            url: Some(lobby::room_url(&url, &options.code)),
+            walks: Some(walks),
+            ..Spectator::new(options)
+        };
+        spectator.connect();
+        Ok(Box::new(spectator))
+    }
+
+    fn update(&mut self, keystate: &KeyState) {
+        if self.url.is_none() {
+            return;
+        }
+        self.offline = self.socket.as_ref().is_none_or(Socket::is_closed);
+        if self.offline {
+            if keystate.is_just_pressed(RETRY_KEY) {
+                self.connect();
+            }
+            return;
+        }
+        let mut received = vec![];
+        if let Some(socket) = &mut self.socket {
+            while let Some(text) = socket.try_receive() {
+                received.push(text);
+            }
+        }
+        for text in received {
+            self.receive(&text);
+        }
+        self.state.drop_silent("", browser::timestamp());
+        if let Some(feed) = &mut self.feed {
+            feed.update();
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if self.options.transparent {
+            renderer.clear(&SCREEN);
+        } else {
+            renderer.fill_rect(&SCREEN, SKY_COLOR);
+        }
+        if let Some(feed) = &self.feed {
+            feed.draw(renderer, self.options.transparent);
+            return;
+        }
+        renderer.draw_text(
+            &format!("ROOM {}", self.options.code),
+            &Point { x: 40, y: 60 },
+            TEXT_COLOR,
+        );
+        renderer.draw_text(self.status(), &Point { x: 40, y: 100 }, TEXT_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectator() -> Spectator {
+        Spectator::new(SpectatorOptions {
+            code: "ABCD".into(),
+            transparent: true,
+        })
+    }
+
+    fn lobby_json(message: LobbyMessage) -> String {
+        serde_json::to_string(&message).unwrap()
+    }
+
+    fn present(player: &str) -> String {
+        lobby_json(LobbyMessage::Present {
+            player: player.into(),
+            name: player.into(),
+            skin: "rhb".into(),
+            ready: true,
+        })
+    }
+
+    #[test]
+    fn spectator_waits_for_walks_and_two_players() {
+        let mut spectator = spectator();
+        spectator.receive(&present("a"));
+        spectator.receive(&lobby_json(LobbyMessage::Countdown { starts_at: 10.0 }));
+        assert!(spectator.feed.is_none());
+        assert_eq!(spectator.state.starts_at(), None);
+
+        // 知らないメッセージは読み捨てる
+        spectator.receive("{\"type\":\"input\",\"tick\":3}");
+        assert!(spectator.feed.is_none());
+    }
+
+    #[cfg(feature = "desktop")]
+    #[test]
+    fn spectator_replays_the_race_from_the_lobby_socket() {
+        use crate::race::{tests::load_walk, Race};
+
+        let starts_at = 5000.0;
+        let seed = lobby::race_seed("ABCD", starts_at);
+        let mut spectator = Spectator {
+            walks: Some([load_walk(), load_walk()]),
+            ..spectator()
+        };
+        spectator.receive(&present("b"));
+        spectator.receive(&present("a"));
+        spectator.receive(&lobby_json(LobbyMessage::Countdown { starts_at }));
+        assert!(spectator.feed.is_some());
+
+        // ロビーと同じく、id の順で a が 1 番目のランナー
+        let ids = ["a", "b"];
+        let mut players = [
+            Race::new(seed, 0, load_walk(), load_walk(), ["rhb", "rhb"]),
+            Race::new(seed, 1, load_walk(), load_walk(), ["rhb", "rhb"]),
+        ];
+        for frame in 0..240 {
+            for index in 0..RACE_PLAYERS {
+                let keys = if index == 0 && frame % 40 < 20 {
+                    vec!["Space"]
+                } else {
+                    vec![]
+                };
+                let sent = players[index].update(&KeyState::injected(keys.into_iter()));
+                for message in sent {
+                    players[1 - index].receive(message);
+                    spectator.receive(&lobby_json(LobbyMessage::Race {
+                        player: ids[index].into(),
+                        message,
+                    }));
+                }
+            }
+            spectator.feed.as_mut().unwrap().update();
+        }
+
+        let feed = spectator.feed.as_ref().unwrap();
+        assert!(feed.tick > 200);
+        assert_eq!(feed.desync, None);
+        assert!(feed.verified > 0);
+        assert!(feed.runners.iter().all(|runner| runner.distance() > 0));
+        assert!(feed.ticker().starts_with("P1 "));
+
+        // ずれたハッシュが届いたらテロップに出す
+        let mut spectator = spectator;
+        spectator.receive(&lobby_json(LobbyMessage::Race {
+            player: "b".into(),
+            message: LockstepMessage::Hash {
+                tick: HASH_INTERVAL,
+                hash: 1,
+            },
+        }));
+        let feed = spectator.feed.as_mut().unwrap();
+        feed.update();
+        assert_eq!(feed.desync, Some(HASH_INTERVAL));
+        assert!(feed
+            .ticker()
+            .ends_with(&format!("DESYNC @{}", HASH_INTERVAL)));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{Point, Renderer};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const TIMER_POSITION: Point = Point { x: 300, y: 30 };
+const SPLIT_POSITION: Point = Point { x: 300, y: 56 };
+const TIMER_SIZE: u16 = 24;
+const TIMER_COLOR: &str = "#FFFFFF";
+const AHEAD_COLOR: &str = "#40E040";
+const BEHIND_COLOR: &str = "#FF5050";
+// 区切りを通ってからこのミリ秒の間だけ、その区切りの結果を出す
+const SPLIT_SHOWN_MS: f64 = 3000.0;
+
+thread_local! {
+    static LAST_SPLITS: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// 最後に終わったランの区切りを JSON で返す。ページ側から書き出しに使う
+pub fn export() -> String {
+    LAST_SPLITS.with(|splits| splits.borrow().clone())
+}
+
+// 区切りの距離 (m) と、スタートからの時間 (ms)。delta は自己ベストとの差
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Split {
+    pub distance: u32,
+    pub time: f64,
+    pub delta: Option<f64>,
+}
+
+// スピードラン用のタイマー。時間は performance.now の値で測り、ゲームの update の回数には頼らない
+pub struct SpeedrunTimer {
+    started_at: f64,
+    now: f64,
+    distances: Vec<u32>,
+    splits: Vec<Split>,
+    best: BTreeMap<u32, f64>,
+}
+
+impl SpeedrunTimer {
+    pub fn new(started_at: f64, distances: &[u32], best: BTreeMap<u32, f64>) -> Self {
+        let mut distances = distances.to_vec();
+        distances.sort_unstable();
+        distances.dedup();
+        SpeedrunTimer {
+            started_at,
+            now: started_at,
+            distances,
+            splits: vec![],
+            best,
+        }
+    }
+
+    pub fn update(&mut self, now: f64, distance: u32) {
+        self.now = now;
+        let time = self.elapsed();
+        let reached = self
+            .distances
+            .iter()
+            .take_while(|split| **split <= distance)
+            .count();
+        let best = &self.best;
+        self.splits
+            .extend(self.distances.drain(..reached).map(|distance| Split {
+                distance,
+                time,
+                delta: best.get(&distance).map(|best| time - best),
+            }));
+    }
+
+    pub fn elapsed(&self) -> f64 {
+        self.now - self.started_at
+    }
+
+    // 今回の区切りで速くなったところだけを書き換えた自己ベスト
+    pub fn best_splits(&self) -> BTreeMap<u32, f64> {
+        let mut best = self.best.clone();
+        self.splits.iter().for_each(|split| {
+            let time = best.entry(split.distance).or_insert(split.time);
+            *time = time.min(split.time);
+        });
+        best
+    }
+
+    // 書き出せるように残しておく
+    pub fn finish(&self) {
+        match serde_json::to_string(&self.splits) {
+            Ok(json) => {
+                LAST_SPLITS.with(|splits| splits.replace(json));
+            }
+            Err(err) => {
+                log!("Could not export splits {:#?}", err);
+            }
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.draw_text(
+            &format_time(self.elapsed()),
+            &TIMER_POSITION,
+            TextStyle::new(TIMER_COLOR)
+                .size(TIMER_SIZE)
+                .align(TextAlign::Center),
+        );
+        let Some(split) = self
+            .splits
+            .last()
+            .filter(|split| self.elapsed() - split.time < SPLIT_SHOWN_MS)
+        else {
+            return;
+        };
+        let (delta, color) = match split.delta {
+            Some(delta) if delta <= 0.0 => (format!(" (-{})", format_time(-delta)), AHEAD_COLOR),
+            Some(delta) => (format!(" (+{})", format_time(delta)), BEHIND_COLOR),
+            None => (String::new(), TIMER_COLOR),
+        };
+        renderer.draw_text(
+            &format!("{} m {}{}", split.distance, format_time(split.time), delta),
+            &SPLIT_POSITION,
+            TextStyle::new(color).align(TextAlign::Center),
+        );
+    }
+}
+
+// m:ss.mmm
+fn format_time(ms: f64) -> String {
+    let total = ms.max(0.0).round() as u64;
+    format!(
+        "{}:{:02}.{:03}",
+        total / 60_000,
+        total / 1000 % 60,
+        total % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_compare_against_the_personal_best() {
+        let best = vec![(100, 20_000.0)].into_iter().collect();
+        let mut timer = SpeedrunTimer::new(1000.0, &[250, 100], best);
+        timer.update(5000.0, 40);
+        assert!(timer.splits.is_empty());
+
+        timer.update(19_500.0, 260);
+        assert_eq!(
+            timer.splits,
+            vec![
+                Split {
+                    distance: 100,
+                    time: 18_500.0,
+                    delta: Some(-1500.0),
+                },
+                Split {
+                    distance: 250,
+                    time: 18_500.0,
+                    delta: None,
+                },
+            ]
+        );
+        assert_eq!(timer.best_splits().get(&100), Some(&18_500.0));
+        assert_eq!(format_time(83_456.0), "1:23.456");
+    }
+}
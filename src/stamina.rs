@@ -0,0 +1,94 @@
+use crate::engine::{Point, Rect, Renderer};
+
+pub const MAX_STAMINA: f32 = 100.0;
+// 何も拾わなければ 20 秒で尽きる
+const DRAIN_PER_TICK: f32 = MAX_STAMINA / (60.0 * 20.0);
+const REFILL_PER_COIN: f32 = 8.0;
+// 尽きてからはこの update ごとに走る速さが 1 ずつ落ちる
+const DECAY_INTERVAL: u32 = 45;
+
+const BAR: Rect = Rect {
+    position: Point { x: 10, y: 10 },
+    width: 150,
+    height: 12,
+};
+const BAR_COLOR: &str = "#7FD8FF";
+const EXHAUSTED_COLOR: &str = "#FF5050";
+const FRAME_COLOR: &str = "#FFFFFF";
+
+// マラソンモードのスタミナ。時間で減り、コインで回復する
+#[derive(Clone)]
+pub struct Stamina {
+    value: f32,
+    exhausted_ticks: u32,
+}
+
+impl Stamina {
+    pub fn new() -> Self {
+        Stamina {
+            value: MAX_STAMINA,
+            exhausted_ticks: 0,
+        }
+    }
+
+    pub fn update(&mut self, pickups: u32) {
+        self.value = (self.value - DRAIN_PER_TICK + pickups as f32 * REFILL_PER_COIN)
+            .clamp(0.0, MAX_STAMINA);
+        if self.is_exhausted() {
+            self.exhausted_ticks += 1;
+        } else {
+            self.exhausted_ticks = 0;
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.value <= 0.0
+    }
+
+    // 0 になったら走れずに倒れる
+    pub fn running_speed(&self, full_speed: i16) -> i16 {
+        let lost = (self.exhausted_ticks / DECAY_INTERVAL) as i16;
+        (full_speed - lost).max(0)
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        let filled = (BAR.width as f32 * self.value / MAX_STAMINA) as i16;
+        let color = if self.is_exhausted() || self.value < MAX_STAMINA / 5.0 {
+            EXHAUSTED_COLOR
+        } else {
+            BAR_COLOR
+        };
+        renderer.fill_rect(&Rect::new(BAR.position, filled, BAR.height), color);
+        renderer.stroke_rect(&BAR, FRAME_COLOR);
+    }
+}
+
+impl Default for Stamina {
+    fn default() -> Self {
+        Stamina::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamina_drains_refills_and_then_slows_the_runner() {
+        let mut stamina = Stamina::new();
+        stamina.update(0);
+        assert!(stamina.value < MAX_STAMINA);
+        stamina.update(1);
+        assert_eq!(stamina.value, MAX_STAMINA);
+
+        (0..20 * 60).for_each(|_| stamina.update(0));
+        assert!(stamina.is_exhausted());
+        assert_eq!(stamina.running_speed(4), 4);
+
+        (0..DECAY_INTERVAL * 4).for_each(|_| stamina.update(0));
+        assert_eq!(stamina.running_speed(4), 0);
+
+        stamina.update(1);
+        assert_eq!(stamina.running_speed(4), 4);
+    }
+}
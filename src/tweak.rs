@@ -0,0 +1,145 @@
+use crate::config::GameConfig;
+use crate::engine::{KeyState, Point, Renderer};
+use crate::ui::{Button, Label, Panel, Slider, WidgetEvent};
+use serde_json::{Map, Value};
+
+pub const TWEAK_PANEL_KEY: &str = "F8";
+
+// 実行中に調整できる定数。key は GameConfig の JSON 上の名前
+struct Tunable {
+    key: &'static str,
+    label: &'static str,
+    min: f32,
+    max: f32,
+    step: f32,
+    get: fn(&GameConfig) -> i16,
+    set: fn(&mut GameConfig, i16),
+}
+
+const TUNABLES: [Tunable; 4] = [
+    Tunable {
+        key: "gravity",
+        label: "Gravity",
+        min: 1.0,
+        max: 4.0,
+        step: 1.0,
+        get: |config| config.gravity,
+        set: |config, value| config.gravity = value,
+    },
+    Tunable {
+        key: "jumpSpeed",
+        label: "Jump",
+        min: -40.0,
+        max: -10.0,
+        step: 1.0,
+        get: |config| config.jump_speed,
+        set: |config, value| config.jump_speed = value,
+    },
+    Tunable {
+        key: "runningSpeed",
+        label: "Run",
+        min: 1.0,
+        max: 12.0,
+        step: 1.0,
+        get: |config| config.running_speed,
+        set: |config, value| config.running_speed = value,
+    },
+    Tunable {
+        key: "obstacleBuffer",
+        label: "Spawn gap",
+        min: 0.0,
+        max: 300.0,
+        step: 10.0,
+        get: |config| config.obstacle_buffer,
+        set: |config, value| config.obstacle_buffer = value,
+    },
+];
+
+// 見出しの Label の次から TUNABLES を並べ、その後ろにボタンを置く
+const FIRST_TUNABLE: usize = 1;
+const EXPORT_BUTTON: usize = FIRST_TUNABLE + TUNABLES.len();
+const CLOSE_BUTTON: usize = EXPORT_BUTTON + 1;
+
+pub enum TweakEndState {
+    Continue,
+    Changed(Box<GameConfig>),
+    Export(String),
+    Close,
+}
+
+// 開発用の調整パネル。動かした値はそのまま走行中の設定に反映する
+pub struct TweakPanel {
+    panel: Panel,
+    config: GameConfig,
+}
+
+impl TweakPanel {
+    pub fn new(config: GameConfig) -> Self {
+        let mut panel = Panel::new(Point { x: 150, y: 100 }, 300);
+        panel.add(Box::new(Label::new("Tweaks")));
+        TUNABLES.iter().for_each(|tunable| {
+            panel.add(Box::new(Slider::new(
+                tunable.label,
+                (tunable.get)(&config) as f32,
+                tunable.min,
+                tunable.max,
+                tunable.step,
+            )));
+        });
+        panel.add(Box::new(Button::new("Export JSON")));
+        panel.add(Box::new(Button::new("Close")));
+        TweakPanel { panel, config }
+    }
+
+    pub fn update(&mut self, keystate: &KeyState) -> TweakEndState {
+        if keystate.is_just_pressed("Escape") || keystate.is_just_pressed(TWEAK_PANEL_KEY) {
+            return TweakEndState::Close;
+        }
+
+        match self.panel.update(keystate) {
+            Some((EXPORT_BUTTON, WidgetEvent::Pressed)) => TweakEndState::Export(self.export()),
+            Some((CLOSE_BUTTON, WidgetEvent::Pressed)) => TweakEndState::Close,
+            Some((index, WidgetEvent::Changed(value))) => {
+                match TUNABLES.get(index.wrapping_sub(FIRST_TUNABLE)) {
+                    Some(tunable) => {
+                        (tunable.set)(&mut self.config, value.round() as i16);
+                        TweakEndState::Changed(Box::new(self.config.clone()))
+                    }
+                    None => TweakEndState::Continue,
+                }
+            }
+            _ => TweakEndState::Continue,
+        }
+    }
+
+    // remote_config.json にそのまま貼り付けられる形で書き出す
+    fn export(&self) -> String {
+        let values: Map<String, Value> = TUNABLES
+            .iter()
+            .map(|tunable| (tunable.key.into(), (tunable.get)(&self.config).into()))
+            .collect();
+        Value::Object(values).to_string()
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        self.panel.draw(renderer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_lists_every_tunable_by_config_key() {
+        let config = GameConfig {
+            jump_speed: -30,
+            ..GameConfig::default()
+        };
+        let exported: Map<String, Value> =
+            serde_json::from_str(&TweakPanel::new(config).export()).unwrap();
+
+        assert_eq!(exported.len(), TUNABLES.len());
+        assert_eq!(exported["jumpSpeed"], -30);
+    }
+}
@@ -0,0 +1,96 @@
+// メニューやカメラ、スコア表示などのアニメーションで共通に使う補間
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    // 0.0 から 1.0 の進み具合を曲線に沿って変換する
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+pub fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+// 毎フレーム update を呼ぶと from から to へ duration フレームかけて進む
+#[derive(Clone, Copy, Debug)]
+pub struct Tween {
+    from: f32,
+    to: f32,
+    duration: u32,
+    elapsed: u32,
+    easing: Easing,
+}
+
+impl Tween {
+    pub fn new(from: f32, to: f32, duration: u32, easing: Easing) -> Self {
+        Tween {
+            from,
+            to,
+            duration,
+            elapsed: 0,
+            easing,
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.elapsed = (self.elapsed + 1).min(self.duration);
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.duration == 0 {
+            1.0
+        } else {
+            self.elapsed as f32 / self.duration as f32
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        lerp(self.from, self.to, self.easing.apply(self.progress()))
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tween_eases_from_start_to_end() {
+        let mut tween = Tween::new(10.0, 20.0, 4, Easing::EaseOut);
+        assert_eq!(tween.value(), 10.0);
+
+        tween.update();
+        tween.update();
+        assert!(tween.value() > 15.0);
+
+        (0..10).for_each(|_| tween.update());
+        assert!(tween.finished());
+        assert_eq!(tween.value(), 20.0);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+}
@@ -0,0 +1,556 @@
+use crate::browser;
+use crate::engine::{KeyState, Point, Rect, Renderer};
+
+const FOCUSED_CLASS: &str = "focused";
+
+const WIDGET_HEIGHT: i16 = 32;
+const WIDGET_SPACING: i16 = 8;
+const PANEL_PADDING: i16 = 12;
+const PANEL_COLOR: &str = "rgba(0, 0, 0, 0.7)";
+const WIDGET_COLOR: &str = "#3A6EA5";
+const TEXT_COLOR: &str = "#FFFFFF";
+const FOCUS_COLOR: &str = "#FFD800";
+
+pub enum MenuAction {
+    Select(usize),
+    Cancel,
+}
+
+pub struct MenuItem {
+    id: String,
+    label: String,
+}
+
+impl MenuItem {
+    pub fn new(id: &str, label: &str) -> Self {
+        MenuItem {
+            id: id.into(),
+            label: label.into(),
+        }
+    }
+}
+
+// 上下キーでフォーカスを移動し、Enter で決定、Escape でキャンセルするメニュー
+pub struct MenuList {
+    items: Vec<MenuItem>,
+    focused: usize,
+}
+
+impl MenuList {
+    pub fn new(items: Vec<MenuItem>) -> Self {
+        MenuList { items, focused: 0 }
+    }
+
+    pub fn html(&self) -> String {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                format!(
+                    "<button id='{}' class='{}'>{}</button>",
+                    item.id,
+                    self.class_of(index),
+                    item.label
+                )
+            })
+            .collect()
+    }
+
+    pub fn update(&mut self, keystate: &KeyState) -> Option<MenuAction> {
+        if keystate.is_just_pressed("ArrowDown") {
+            self.move_focus(1);
+        }
+        if keystate.is_just_pressed("ArrowUp") {
+            self.move_focus(-1);
+        }
+
+        if keystate.is_just_pressed("Enter") {
+            Some(MenuAction::Select(self.focused))
+        } else if keystate.is_just_pressed("Escape") {
+            Some(MenuAction::Cancel)
+        } else {
+            None
+        }
+    }
+
+    fn move_focus(&mut self, offset: isize) {
+        if self.step_focus(offset) {
+            self.draw_focus();
+        }
+    }
+
+    // 端まで行ったら反対側へ回り込む
+    fn step_focus(&mut self, offset: isize) -> bool {
+        let len = self.items.len() as isize;
+        if len == 0 {
+            return false;
+        }
+
+        let focused = (self.focused as isize + offset).rem_euclid(len) as usize;
+        let changed = focused != self.focused;
+        self.focused = focused;
+        changed
+    }
+
+    fn draw_focus(&self) {
+        self.items.iter().enumerate().for_each(|(index, item)| {
+            if let Err(err) = browser::set_class_by_id(&item.id, self.class_of(index)) {
+                log!("Could not update menu focus {:#?}", err);
+            }
+        });
+    }
+
+    fn class_of(&self, index: usize) -> &'static str {
+        if index == self.focused {
+            FOCUSED_CLASS
+        } else {
+            ""
+        }
+    }
+}
+
+pub enum WidgetInput {
+    Activate,
+    Decrease,
+    Increase,
+    Press(Point),
+    Drag(Point),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WidgetEvent {
+    Pressed,
+    Toggled(bool),
+    Changed(f32),
+    Selected(usize),
+}
+
+// キャンバス上に描画する UI 部品。配置は Panel が決める
+pub trait Widget {
+    fn focusable(&self) -> bool {
+        true
+    }
+    fn handle(&mut self, input: WidgetInput, bounds: &Rect) -> Option<WidgetEvent>;
+    fn draw(&self, renderer: &Renderer, bounds: &Rect, focused: bool);
+}
+
+fn draw_frame(renderer: &Renderer, bounds: &Rect, focused: bool) {
+    renderer.fill_rect(bounds, WIDGET_COLOR);
+    if focused {
+        renderer.stroke_rect(bounds, FOCUS_COLOR);
+    }
+}
+
+fn text_position(bounds: &Rect) -> Point {
+    Point {
+        x: bounds.x() + WIDGET_SPACING,
+        y: bounds.y() + bounds.height / 2,
+    }
+}
+
+pub struct Label {
+    text: String,
+}
+
+impl Label {
+    pub fn new(text: &str) -> Self {
+        Label { text: text.into() }
+    }
+}
+
+impl Widget for Label {
+    fn focusable(&self) -> bool {
+        false
+    }
+
+    fn handle(&mut self, _input: WidgetInput, _bounds: &Rect) -> Option<WidgetEvent> {
+        None
+    }
+
+    fn draw(&self, renderer: &Renderer, bounds: &Rect, _focused: bool) {
+        renderer.draw_text(&self.text, &text_position(bounds), TEXT_COLOR);
+    }
+}
+
+pub struct Button {
+    label: String,
+}
+
+impl Button {
+    pub fn new(label: &str) -> Self {
+        Button {
+            label: label.into(),
+        }
+    }
+}
+
+impl Widget for Button {
+    fn handle(&mut self, input: WidgetInput, _bounds: &Rect) -> Option<WidgetEvent> {
+        match input {
+            WidgetInput::Activate | WidgetInput::Press(_) => Some(WidgetEvent::Pressed),
+            _ => None,
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer, bounds: &Rect, focused: bool) {
+        draw_frame(renderer, bounds, focused);
+        renderer.draw_text(&self.label, &text_position(bounds), TEXT_COLOR);
+    }
+}
+
+// 決まった選択肢を順に切り替える。右・決定で次へ、左で前へ
+pub struct Choice {
+    label: String,
+    options: Vec<String>,
+    selected: usize,
+}
+
+impl Choice {
+    pub fn new(label: &str, options: Vec<String>, selected: usize) -> Self {
+        Choice {
+            label: label.into(),
+            selected: selected.min(options.len().saturating_sub(1)),
+            options,
+        }
+    }
+}
+
+impl Widget for Choice {
+    fn handle(&mut self, input: WidgetInput, _bounds: &Rect) -> Option<WidgetEvent> {
+        let count = self.options.len();
+        if count == 0 {
+            return None;
+        }
+        self.selected = match input {
+            WidgetInput::Activate | WidgetInput::Press(_) | WidgetInput::Increase => {
+                (self.selected + 1) % count
+            }
+            WidgetInput::Decrease => (self.selected + count - 1) % count,
+            WidgetInput::Drag(_) => return None,
+        };
+        Some(WidgetEvent::Selected(self.selected))
+    }
+
+    fn draw(&self, renderer: &Renderer, bounds: &Rect, focused: bool) {
+        draw_frame(renderer, bounds, focused);
+        let option = self
+            .options
+            .get(self.selected)
+            .map(String::as_str)
+            .unwrap_or_default();
+        renderer.draw_text(
+            &format!("{}: {}", self.label, option),
+            &text_position(bounds),
+            TEXT_COLOR,
+        );
+    }
+}
+
+pub struct Toggle {
+    label: String,
+    value: bool,
+}
+
+impl Toggle {
+    pub fn new(label: &str, value: bool) -> Self {
+        Toggle {
+            label: label.into(),
+            value,
+        }
+    }
+}
+
+impl Widget for Toggle {
+    fn handle(&mut self, input: WidgetInput, _bounds: &Rect) -> Option<WidgetEvent> {
+        let value = match input {
+            WidgetInput::Activate | WidgetInput::Press(_) => !self.value,
+            WidgetInput::Decrease => false,
+            WidgetInput::Increase => true,
+            WidgetInput::Drag(_) => return None,
+        };
+        if value == self.value {
+            return None;
+        }
+        self.value = value;
+        Some(WidgetEvent::Toggled(value))
+    }
+
+    fn draw(&self, renderer: &Renderer, bounds: &Rect, focused: bool) {
+        draw_frame(renderer, bounds, focused);
+        let state = if self.value { "ON" } else { "OFF" };
+        renderer.draw_text(
+            &format!("{}: {}", self.label, state),
+            &text_position(bounds),
+            TEXT_COLOR,
+        );
+    }
+}
+
+pub struct Slider {
+    label: String,
+    value: f32,
+    min: f32,
+    max: f32,
+    step: f32,
+}
+
+impl Slider {
+    pub fn new(label: &str, value: f32, min: f32, max: f32, step: f32) -> Self {
+        Slider {
+            label: label.into(),
+            value: value.max(min).min(max),
+            min,
+            max,
+            step,
+        }
+    }
+
+    fn set_value(&mut self, value: f32) -> Option<WidgetEvent> {
+        let value = value.max(self.min).min(self.max);
+        if value == self.value {
+            return None;
+        }
+        self.value = value;
+        Some(WidgetEvent::Changed(value))
+    }
+
+    // バーはウィジェットの右半分に置く
+    fn track(bounds: &Rect) -> Rect {
+        let width = bounds.width / 2 - WIDGET_SPACING;
+        Rect::new_from_x_y(
+            bounds.right() - width - WIDGET_SPACING,
+            bounds.y() + bounds.height / 2 - 2,
+            width,
+            4,
+        )
+    }
+
+    fn ratio(&self) -> f32 {
+        (self.value - self.min) / (self.max - self.min)
+    }
+}
+
+impl Widget for Slider {
+    fn handle(&mut self, input: WidgetInput, bounds: &Rect) -> Option<WidgetEvent> {
+        match input {
+            WidgetInput::Decrease => self.set_value(self.value - self.step),
+            WidgetInput::Increase => self.set_value(self.value + self.step),
+            WidgetInput::Press(point) | WidgetInput::Drag(point) => {
+                let track = Slider::track(bounds);
+                let ratio = (point.x - track.x()) as f32 / track.width as f32;
+                self.set_value(self.min + ratio * (self.max - self.min))
+            }
+            WidgetInput::Activate => None,
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer, bounds: &Rect, focused: bool) {
+        draw_frame(renderer, bounds, focused);
+        renderer.draw_text(&self.label, &text_position(bounds), TEXT_COLOR);
+
+        let track = Slider::track(bounds);
+        renderer.fill_rect(&track, TEXT_COLOR);
+        let knob_x = track.x() + (track.width as f32 * self.ratio()) as i16;
+        renderer.fill_rect(
+            &Rect::new_from_x_y(knob_x - 4, bounds.y() + 6, 8, bounds.height - 12),
+            FOCUS_COLOR,
+        );
+    }
+}
+
+// 子ウィジェットを縦に並べ、フォーカス移動と入力の振り分けを行う
+pub struct Panel {
+    position: Point,
+    width: i16,
+    children: Vec<(Rect, Box<dyn Widget>)>,
+    focused: Option<usize>,
+    dragging: Option<usize>,
+}
+
+impl Panel {
+    pub fn new(position: Point, width: i16) -> Self {
+        Panel {
+            position,
+            width,
+            children: vec![],
+            focused: None,
+            dragging: None,
+        }
+    }
+
+    pub fn add(&mut self, widget: Box<dyn Widget>) -> usize {
+        let index = self.children.len();
+        let bounds = Rect::new_from_x_y(
+            self.position.x + PANEL_PADDING,
+            self.position.y + PANEL_PADDING + index as i16 * (WIDGET_HEIGHT + WIDGET_SPACING),
+            self.width - PANEL_PADDING * 2,
+            WIDGET_HEIGHT,
+        );
+        if self.focused.is_none() && widget.focusable() {
+            self.focused = Some(index);
+        }
+        self.children.push((bounds, widget));
+        index
+    }
+
+    // 作り直したパネルに差し替える。フォーカスとドラッグ中の子はそのまま残す
+    pub fn refresh(&mut self, fresh: Panel) {
+        let (focused, dragging) = (self.focused, self.dragging);
+        *self = fresh;
+        let len = self.children.len();
+        if focused.is_some_and(|index| index < len) {
+            self.focused = focused;
+        }
+        self.dragging = dragging.filter(|index| *index < len);
+    }
+
+    fn bounds(&self) -> Rect {
+        let height = self
+            .children
+            .last()
+            .map(|(bounds, _)| bounds.bottom() - self.position.y)
+            .unwrap_or(0)
+            + PANEL_PADDING;
+        Rect::new(self.position, self.width, height)
+    }
+
+    // フォーカスできない子は飛ばし、端では反対側へ回り込む
+    fn move_focus(&mut self, offset: isize) {
+        let len = self.children.len() as isize;
+        let mut index = match self.focused {
+            Some(index) => index as isize,
+            None => return,
+        };
+        for _ in 0..len {
+            index = (index + offset).rem_euclid(len);
+            if self.children[index as usize].1.focusable() {
+                self.focused = Some(index as usize);
+                return;
+            }
+        }
+    }
+
+    fn send(&mut self, index: usize, input: WidgetInput) -> Option<(usize, WidgetEvent)> {
+        let (bounds, widget) = &mut self.children[index];
+        widget.handle(input, bounds).map(|event| (index, event))
+    }
+
+    pub fn update(&mut self, keystate: &KeyState) -> Option<(usize, WidgetEvent)> {
+        if keystate.is_just_pressed("ArrowDown") {
+            self.move_focus(1);
+        }
+        if keystate.is_just_pressed("ArrowUp") {
+            self.move_focus(-1);
+        }
+
+        if !keystate.is_pointer_down() {
+            self.dragging = None;
+        }
+        if let Some(point) = keystate.pointer() {
+            if keystate.is_pointer_just_pressed() {
+                let hit = self
+                    .children
+                    .iter()
+                    .position(|(bounds, widget)| widget.focusable() && bounds.contains(&point));
+                if let Some(index) = hit {
+                    self.focused = Some(index);
+                    self.dragging = Some(index);
+                    return self.send(index, WidgetInput::Press(point));
+                }
+            } else if let Some(index) = self.dragging {
+                // 押したまま動かすとウィジェットの外でも追従する
+                return self.send(index, WidgetInput::Drag(point));
+            }
+        }
+
+        let focused = self.focused?;
+        if keystate.is_just_pressed("Enter") || keystate.is_just_pressed("Space") {
+            self.send(focused, WidgetInput::Activate)
+        } else if keystate.is_just_pressed("ArrowLeft") {
+            self.send(focused, WidgetInput::Decrease)
+        } else if keystate.is_just_pressed("ArrowRight") {
+            self.send(focused, WidgetInput::Increase)
+        } else {
+            None
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.fill_rect(&self.bounds(), PANEL_COLOR);
+        self.children
+            .iter()
+            .enumerate()
+            .for_each(|(index, (bounds, widget))| {
+                widget.draw(renderer, bounds, self.focused == Some(index));
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focus_wraps_around() {
+        let mut menu = MenuList::new(vec![
+            MenuItem::new("first", "First"),
+            MenuItem::new("second", "Second"),
+            MenuItem::new("third", "Third"),
+        ]);
+
+        assert!(menu.step_focus(-1));
+        assert_eq!(menu.focused, 2);
+        assert!(menu.step_focus(1));
+        assert_eq!(menu.focused, 0);
+
+        let mut single = MenuList::new(vec![MenuItem::new("only", "Only")]);
+        assert!(!single.step_focus(1));
+        assert_eq!(single.focused, 0);
+    }
+
+    #[test]
+    fn panel_focus_skips_labels_and_wraps() {
+        let mut panel = Panel::new(Point { x: 0, y: 0 }, 200);
+        panel.add(Box::new(Label::new("Options")));
+        panel.add(Box::new(Button::new("First")));
+        panel.add(Box::new(Button::new("Second")));
+
+        assert_eq!(panel.focused, Some(1));
+        panel.move_focus(1);
+        assert_eq!(panel.focused, Some(2));
+        panel.move_focus(1);
+        assert_eq!(panel.focused, Some(1));
+        panel.move_focus(-1);
+        assert_eq!(panel.focused, Some(2));
+    }
+
+    #[test]
+    fn refreshed_panel_keeps_the_focus() {
+        let build = |label: &str| {
+            let mut panel = Panel::new(Point { x: 0, y: 0 }, 200);
+            panel.add(Box::new(Label::new("Options")));
+            panel.add(Box::new(Toggle::new(label, false)));
+            panel.add(Box::new(Button::new("Close")));
+            panel
+        };
+        let mut panel = build("Mute");
+        panel.move_focus(1);
+        assert_eq!(panel.focused, Some(2));
+
+        panel.refresh(build("Muted"));
+        assert_eq!(panel.focused, Some(2));
+        assert_eq!(panel.children.len(), 3);
+    }
+
+    #[test]
+    fn slider_clamps_to_range() {
+        let bounds = Rect::new_from_x_y(0, 0, 200, WIDGET_HEIGHT);
+        let mut slider = Slider::new("Volume", 0.9, 0.0, 1.0, 0.25);
+
+        assert_eq!(
+            slider.handle(WidgetInput::Increase, &bounds),
+            Some(WidgetEvent::Changed(1.0))
+        );
+        assert_eq!(slider.handle(WidgetInput::Increase, &bounds), None);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::draw::{TextAlign, TextStyle};
+use crate::engine::{Camera, KeyState, Point, Rect, Renderer};
+use crate::level::{self, Level};
+use crate::notifications;
+use crate::profile::Profile;
+use crate::tween::{lerp, Easing, Tween};
+
+const VIEW_WIDTH: i16 = 600;
+const MAP_MARGIN: i16 = 120;
+const NODE_SPACING: i16 = 200;
+// ノードを交互に上下させて道をうねらせる
+const NODE_HEIGHTS: [i16; 2] = [380, 280];
+const NODE_RADIUS: i16 = 18;
+const PATH_DOT_SPACING: i16 = 16;
+const PATH_DOT_RADIUS: i16 = 3;
+const HOP_FRAMES: u32 = 30;
+const HOP_HEIGHT: f32 = 60.0;
+const SCROLL_FRAMES: u32 = 30;
+
+const SHADE: Rect = Rect::new_from_x_y(0, 0, 600, 600);
+const SHADE_COLOR: &str = "rgba(20, 40, 30, 0.85)";
+const PATH_COLOR: &str = "#E8D8A8";
+const LOCKED_COLOR: &str = "#606060";
+const OPEN_COLOR: &str = "#3A6EA5";
+const CLEARED_COLOR: &str = "#FFD23F";
+const TEXT_COLOR: &str = "#FFFFFF";
+const TITLE_POSITION: Point = Point { x: 300, y: 60 };
+const HELP_POSITION: Point = Point { x: 300, y: 560 };
+
+pub enum WorldMapEndState {
+    Continue,
+    Play(usize),
+    Close,
+}
+
+struct MapNode {
+    name: String,
+    position: Point,
+    // 最初のレベルと、ひとつ前のレベルでメダルを取ったレベルだけ遊べる
+    unlocked: bool,
+    medal: Option<u8>,
+}
+
+struct Hop {
+    from: Point,
+    to: Point,
+    target: usize,
+    progress: Tween,
+}
+
+// レベルを道でつないだ地図。左右で解放済みのノードへ跳んで移り、カメラが後を追う
+pub struct WorldMap {
+    nodes: Vec<MapNode>,
+    current: usize,
+    hop: Option<Hop>,
+    camera: Camera,
+    scroll: Option<Tween>,
+}
+
+impl WorldMap {
+    // いちばん先の解放済みのノードから始める
+    pub fn new(levels: &[Level], profile: &Profile) -> Self {
+        let nodes: Vec<MapNode> = levels
+            .iter()
+            .enumerate()
+            .map(|(index, level)| MapNode {
+                name: level.name.clone(),
+                position: Point {
+                    x: MAP_MARGIN + index as i16 * NODE_SPACING,
+                    y: NODE_HEIGHTS[index % NODE_HEIGHTS.len()],
+                },
+                unlocked: index == 0 || profile.medal(&levels[index - 1].name).is_some(),
+                medal: profile.medal(&level.name),
+            })
+            .collect();
+        let current = nodes.iter().rposition(|node| node.unlocked).unwrap_or(0);
+        let mut map = WorldMap {
+            nodes,
+            current,
+            hop: None,
+            camera: Camera::new(Point { x: 0, y: 0 }),
+            scroll: None,
+        };
+        map.camera.position.x = map.scroll_target(current);
+        map
+    }
+
+    // 跳んでいる間は入力を受け付けない
+    pub fn update(&mut self, keystate: &KeyState) -> WorldMapEndState {
+        if let Some(scroll) = &mut self.scroll {
+            scroll.update();
+            self.camera.position.x = scroll.value().round() as i16;
+            if scroll.finished() {
+                self.scroll = None;
+            }
+        }
+        if let Some(hop) = &mut self.hop {
+            hop.progress.update();
+            if hop.progress.finished() {
+                self.current = hop.target;
+                self.hop = None;
+            }
+            return WorldMapEndState::Continue;
+        }
+
+        if keystate.is_just_pressed("Escape") {
+            return WorldMapEndState::Close;
+        }
+        if keystate.is_just_pressed("Enter") || keystate.is_just_pressed("Space") {
+            return WorldMapEndState::Play(self.current);
+        }
+        let target = if keystate.is_just_pressed("ArrowRight") {
+            Some(self.current + 1)
+        } else if keystate.is_just_pressed("ArrowLeft") {
+            self.current.checked_sub(1)
+        } else {
+            None
+        };
+        match target.and_then(|target| self.nodes.get(target).map(|node| (target, node))) {
+            Some((target, node)) if node.unlocked => self.hop_to(target),
+            Some(_) => notifications::push(&format!(
+                "Clear {} to unlock",
+                self.nodes[self.current].name
+            )),
+            None => {}
+        }
+        WorldMapEndState::Continue
+    }
+
+    fn hop_to(&mut self, target: usize) {
+        self.hop = Some(Hop {
+            from: self.nodes[self.current].position,
+            to: self.nodes[target].position,
+            target,
+            progress: Tween::new(0.0, 1.0, HOP_FRAMES, Easing::Linear),
+        });
+        self.scroll = Some(Tween::new(
+            f32::from(self.camera.position.x),
+            f32::from(self.scroll_target(target)),
+            SCROLL_FRAMES,
+            Easing::EaseInOut,
+        ));
+    }
+
+    // ノードを画面の真ん中に映す。地図の端より外は映さない
+    fn scroll_target(&self, index: usize) -> i16 {
+        let width = self
+            .nodes
+            .last()
+            .map_or(0, |node| node.position.x + MAP_MARGIN);
+        let x = self.nodes.get(index).map_or(0, |node| node.position.x) - VIEW_WIDTH / 2;
+        x.clamp(0, (width - VIEW_WIDTH).max(0))
+    }
+
+    // 跳んでいる間は二つのノードの間を放物線で結ぶ
+    fn player_position(&self) -> Point {
+        match &self.hop {
+            Some(hop) => {
+                let t = hop.progress.value();
+                let arc = HOP_HEIGHT * 4.0 * t * (1.0 - t);
+                Point {
+                    x: lerp(f32::from(hop.from.x), f32::from(hop.to.x), t).round() as i16,
+                    y: (lerp(f32::from(hop.from.y), f32::from(hop.to.y), t) - arc).round() as i16,
+                }
+            }
+            None => self
+                .nodes
+                .get(self.current)
+                .map_or(Point { x: 0, y: 0 }, |node| node.position),
+        }
+    }
+
+    // player にはワールド座標で足元の位置を渡す
+    pub fn draw(&self, renderer: &Renderer, player: impl FnOnce(&Renderer, Point)) {
+        renderer.fill_rect(&SHADE, SHADE_COLOR);
+        renderer.with_camera(&self.camera, |renderer| {
+            self.nodes
+                .windows(2)
+                .for_each(|pair| draw_path(renderer, &pair[0].position, &pair[1].position));
+            self.nodes
+                .iter()
+                .enumerate()
+                .for_each(|(index, node)| draw_node(renderer, index, node));
+            player(renderer, self.player_position());
+        });
+
+        if let Some(node) = self.nodes.get(self.current) {
+            renderer.draw_text(
+                &node.name,
+                &TITLE_POSITION,
+                TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+            );
+        }
+        renderer.draw_text(
+            "Left/Right: move  Enter: play  Esc: close",
+            &HELP_POSITION,
+            TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+        );
+    }
+}
+
+fn draw_path(renderer: &Renderer, from: &Point, to: &Point) {
+    let steps = ((to.x - from.x).abs().max((to.y - from.y).abs()) / PATH_DOT_SPACING).max(1);
+    (1..steps).for_each(|step| {
+        let t = f32::from(step) / f32::from(steps);
+        renderer.fill_circle(
+            &Point {
+                x: lerp(f32::from(from.x), f32::from(to.x), t).round() as i16,
+                y: lerp(f32::from(from.y), f32::from(to.y), t).round() as i16,
+            },
+            PATH_DOT_RADIUS,
+            PATH_COLOR,
+        );
+    });
+}
+
+fn draw_node(renderer: &Renderer, index: usize, node: &MapNode) {
+    let color = match (node.unlocked, node.medal) {
+        (false, _) => LOCKED_COLOR,
+        (true, None) => OPEN_COLOR,
+        (true, Some(_)) => CLEARED_COLOR,
+    };
+    renderer.fill_circle(&node.position, NODE_RADIUS, color);
+    renderer.draw_text(
+        &(index + 1).to_string(),
+        &node.position,
+        TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+    );
+    let label = match (node.unlocked, node.medal) {
+        (false, _) => "locked".to_string(),
+        (true, medal) => level::stars_text(medal.unwrap_or(0)),
+    };
+    renderer.draw_text(
+        &label,
+        &Point {
+            x: node.position.x,
+            y: node.position.y + NODE_RADIUS * 2,
+        },
+        TextStyle::new(TEXT_COLOR).align(TextAlign::Center),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels() -> Vec<Level> {
+        ["Morning Stroll", "Park Loop", "Market Street"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| Level {
+                name: name.to_string(),
+                length: 100 * (index as u32 + 1),
+                seed: index as u64,
+                stars: Default::default(),
+            })
+            .collect()
+    }
+
+    fn press(key: &str) -> KeyState {
+        KeyState::injected(std::iter::once(key))
+    }
+
+    #[test]
+    fn nodes_unlock_after_the_previous_level_has_a_medal() {
+        let mut profile = Profile::default();
+        profile.record_medal("Morning Stroll", 2);
+        let map = WorldMap::new(&levels(), &profile);
+
+        let unlocked: Vec<bool> = map.nodes.iter().map(|node| node.unlocked).collect();
+        assert_eq!(unlocked, vec![true, true, false]);
+        assert_eq!(map.current, 1);
+    }
+
+    #[test]
+    fn the_player_hops_in_an_arc_and_stops_at_locked_nodes() {
+        let mut profile = Profile::default();
+        profile.record_medal("Morning Stroll", 1);
+        let mut map = WorldMap::new(&levels(), &profile);
+        let idle = KeyState::injected(std::iter::empty());
+
+        map.update(&press("ArrowLeft"));
+        (0..HOP_FRAMES / 2).for_each(|_| {
+            map.update(&idle);
+        });
+        let middle = map.player_position();
+        assert!(middle.y < NODE_HEIGHTS[0].min(NODE_HEIGHTS[1]));
+        (0..HOP_FRAMES).for_each(|_| {
+            map.update(&idle);
+        });
+        assert_eq!(map.current, 0);
+        assert_eq!(map.camera.position.x, 0);
+
+        map.update(&press("ArrowRight"));
+        (0..HOP_FRAMES).for_each(|_| {
+            map.update(&idle);
+        });
+        map.update(&press("ArrowRight"));
+        assert!(map.hop.is_none());
+        assert_eq!(map.current, 1);
+        assert!(matches!(
+            map.update(&press("Enter")),
+            WorldMapEndState::Play(1)
+        ));
+    }
+}